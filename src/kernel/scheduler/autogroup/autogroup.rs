@@ -1,15 +1,213 @@
-// File for the autogroup scheduler module.
-use crate::kernel::scheduler::autogroup::autogroup_impl::AutogroupImpl;
-use crate::kernel::scheduler::autogroup::autogroup_impl::AutogroupImplTrait;
-use crate::kernel::scheduler::autogroup::autogroup_impl::AutogroupImplTraitV1;
-use crate::kernel::scheduler::autogroup::autogroup_impl::AutogroupImplTraitV2;
-use crate::kernel::scheduler::autogroup::autogroup_impl::AutogroupImplTraitV3;
-use crate::kernel::scheduler::autogroup::autogroup_impl::AutogroupImplTraitV4;
-use crate::kernel::scheduler::autogroup::autogroup_impl::AutogroupImplTraitV5;
-use crate::kernel::scheduler::autogroup::autogroup_impl::AutogroupImplTraitV6;
-use crate::kernel::scheduler::autogroup::autogroup_impl::AutogroupImplTraitV7;
-use crate::kernel::scheduler::autogroup::autogroup_impl::AutogroupImplTraitV8;
-use crate::kernel::scheduler::autogroup::autogroup_impl::AutogroupImplTraitV9;
-use crate::kernel::scheduler::autogroup::autogroup_impl::AutogroupImplTraitV10;
-use crate::kernel::scheduler::autogroup::autogroup_impl::AutogroupImplTraitV11;
+//! # Autogroup Scheduler
+//!
+//! This module groups fair tasks by session automatically, so that a
+//! `make -j64` in one terminal gets the same share of the CPU as the shell
+//! in another instead of one share per compiler process. Each session is a
+//! task group on the fair runqueues; CPU time is divided between groups by
+//! their weight first and only then between the tasks of a group.
+//!
+//! ## Features
+//! - One task group per session
+//! - Per-autogroup nice value
+//! - Runtime enable switch; when disabled all tasks share the root group
+//!
+//! ## Usage
+//! ```rust
+//! use crate::kernel::scheduler::autogroup::AutoGroupScheduler;
+//!
+//! let autogroup = AutoGroupScheduler::new();
+//! autogroup.attach_task(task.id(), session_id);
+//! autogroup.set_autogroup_nice(session_id, 5)?;
+//!
+//! fair.enqueue_task(&task, autogroup.task_group(task.id()))?;
+//! ```
 
+use crate::kernel::scheduler::fair::{nice_to_weight, TaskGroup, ROOT_TASK_GROUP};
+use crate::kernel::task::TaskId;
+use crate::kernel::error::{KernelResult, SchedulerError};
+use crate::kernel::sync::RwLock;
+use crate::kernel::log::kernel_info;
+
+use alloc::collections::BTreeMap;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// State of one session's autogroup
+#[derive(Debug, Clone, Copy, Default)]
+struct AutoGroup {
+    /// Nice value of the group as a whole
+    nice: i8,
+    /// Number of attached tasks
+    nr_tasks: usize,
+}
+
+/// Autogroup scheduler component
+pub struct AutoGroupScheduler {
+    enabled: AtomicBool,
+    /// Session of every attached task
+    tasks: RwLock<BTreeMap<TaskId, u64>>,
+    /// Autogroups by session
+    groups: RwLock<BTreeMap<u64, AutoGroup>>,
+}
+
+impl AutoGroupScheduler {
+    /// Create an autogroup scheduler with autogrouping enabled
+    pub fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(true),
+            tasks: RwLock::new(BTreeMap::new()),
+            groups: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// Enable or disable autogrouping
+    ///
+    /// Takes effect for each task the next time it is enqueued.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+        kernel_info!("Autogroup {}", if enabled { "enabled" } else { "disabled" });
+    }
+
+    /// Check whether autogrouping is enabled
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Attach a task to the autogroup of a session
+    ///
+    /// Session 0 is the root group. A session's autogroup is created with
+    /// nice 0 when its first task attaches and destroyed with its last.
+    pub fn attach_task(&self, task: TaskId, session_id: u64) {
+        let previous = self.tasks.write().insert(task, session_id);
+        let mut groups = self.groups.write();
+        if let Some(previous) = previous {
+            Self::put_group(&mut groups, previous);
+        }
+        groups.entry(session_id).or_default().nr_tasks += 1;
+    }
+
+    /// Detach an exiting task from its autogroup
+    pub fn detach_task(&self, task: TaskId) {
+        if let Some(session_id) = self.tasks.write().remove(&task) {
+            Self::put_group(&mut self.groups.write(), session_id);
+        }
+    }
+
+    /// Set the nice value of a session's autogroup
+    ///
+    /// # Returns
+    /// - `Ok(())` on success
+    /// - `Err(SchedulerError::InvalidParameter)` if `nice` is outside
+    ///   -20..=19 or the session has no autogroup
+    pub fn set_autogroup_nice(&self, session_id: u64, nice: i8) -> KernelResult<()> {
+        if !(-20..=19).contains(&nice) {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        let mut groups = self.groups.write();
+        let group = groups.get_mut(&session_id).ok_or(SchedulerError::InvalidParameter)?;
+        group.nice = nice;
+        Ok(())
+    }
+
+    /// Nice value of a session's autogroup
+    pub fn autogroup_nice(&self, session_id: u64) -> Option<i8> {
+        self.groups.read().get(&session_id).map(|g| g.nice)
+    }
+
+    /// Task group of a session's autogroup
+    pub fn session_group(&self, session_id: u64) -> Option<TaskGroup> {
+        self.groups.read().get(&session_id).map(|g| TaskGroup {
+            id: session_id,
+            parent: ROOT_TASK_GROUP,
+            weight: nice_to_weight(g.nice),
+        })
+    }
+
+    /// Task group a task should be enqueued in
+    ///
+    /// The root group when autogrouping is disabled or the task is not
+    /// attached to a session.
+    pub fn task_group(&self, task: TaskId) -> TaskGroup {
+        if !self.is_enabled() {
+            return TaskGroup::ROOT;
+        }
+        self.tasks.read().get(&task)
+            .and_then(|&session_id| self.session_group(session_id))
+            .unwrap_or(TaskGroup::ROOT)
+    }
+
+    /// Log autogroup state
+    pub fn print_autogroup_info(&self) {
+        kernel_info!("Autogroup enabled: {}", self.is_enabled());
+        for (session_id, group) in self.groups.read().iter() {
+            kernel_info!("Autogroup {}: nice {}, {} tasks", session_id, group.nice, group.nr_tasks);
+        }
+    }
+
+    /// Drop a task's reference to an autogroup
+    fn put_group(groups: &mut BTreeMap<u64, AutoGroup>, session_id: u64) {
+        if let Some(group) = groups.get_mut(&session_id) {
+            group.nr_tasks -= 1;
+            if group.nr_tasks == 0 {
+                groups.remove(&session_id);
+            }
+        }
+    }
+}
+
+impl Default for AutoGroupScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kernel::scheduler::fair::{CfsRq, NICE_0_LOAD};
+
+    /// Run a CFS runqueue for `slices` 1ms slices; returns the slices each task got
+    fn run(rq: &mut CfsRq, slices: u64) -> BTreeMap<TaskId, u64> {
+        let mut ran = BTreeMap::new();
+        let mut now = 0;
+        for _ in 0..slices {
+            let next = rq.leftmost().unwrap();
+            rq.set_curr(next, now);
+            now += 1_000_000;
+            rq.put_prev(next, now);
+            *ran.entry(next).or_insert(0) += 1;
+        }
+        ran
+    }
+
+    #[test]
+    fn test_groups_share_cpu_equally_regardless_of_task_count() {
+        let autogroup = AutoGroupScheduler::new();
+        let mut rq = CfsRq::new();
+        autogroup.attach_task(TaskId::new(1), 100);
+        for id in 2..=5 {
+            autogroup.attach_task(TaskId::new(id), 200);
+        }
+        for id in 1..=5 {
+            let task = TaskId::new(id);
+            rq.enqueue(task, NICE_0_LOAD, false, 0, autogroup.task_group(task));
+        }
+
+        let ran = run(&mut rq, 1000);
+        let shell = ran[&TaskId::new(1)];
+        let build: u64 = (2..=5).map(|id| ran[&TaskId::new(id)]).sum();
+        assert!(shell.abs_diff(500) <= 10, "shell got {} of 1000", shell);
+        assert!(build.abs_diff(500) <= 10, "build got {} of 1000", build);
+    }
+
+    #[test]
+    fn test_disabled_puts_all_tasks_in_root_group() {
+        let autogroup = AutoGroupScheduler::new();
+        autogroup.attach_task(TaskId::new(1), 100);
+        autogroup.set_autogroup_nice(100, 10).unwrap();
+        assert_eq!(autogroup.task_group(TaskId::new(1)).weight, nice_to_weight(10));
+
+        autogroup.set_enabled(false);
+        assert_eq!(autogroup.task_group(TaskId::new(1)), TaskGroup::ROOT);
+        assert!(autogroup.set_autogroup_nice(100, 20).is_err());
+    }
+}
@@ -0,0 +1,322 @@
+//! # Scheduler Clock and Tick Management
+//!
+//! This module owns the per-CPU scheduler tick. Each CPU normally programs
+//! its next tick one period ahead; on adaptive-tick (`nohz_full`) CPUs the
+//! tick is stopped while a single task runs and restarted as soon as a
+//! second task needs to share the CPU.
+//!
+//! It also maintains the per-CPU runqueue clocks. `rq_clock` follows wall
+//! time, while `rq_clock_task` only advances while the CPU actually ran
+//! guest code: on virtualized systems, time the hypervisor stole from the
+//! vCPU is subtracted so that PELT and CFS don't charge it to tasks.
+//!
+//! Three time bases are provided. `sched_clock(cpu)` is derived from the
+//! CPU-local counter and is cheap, but local counters (e.g. TSCs) may be
+//! unsynchronized, so it is clamped to stay within `MAX_CLOCK_SKEW_NS` of
+//! the globally coherent `clock_monotonic()`; differences between CPUs thus
+//! never exceed the skew bound. `clock_boottime()` additionally includes
+//! time spent in suspend.
+//!
+//! ## Features
+//! - Configurable tick frequency
+//! - Per-CPU one-shot tick programming
+//! - Tick stop/restart for adaptive-tick CPUs
+//! - Skipped tick accounting for stopped ticks
+//! - Steal-time-aware task clock per CPU
+//! - Per-CPU `sched_clock` with bounded cross-CPU skew
+//! - Globally coherent monotonic and boottime clocks
+//!
+//! ## Usage
+//! ```rust
+//! use crate::kernel::scheduler::clock::ClockScheduler;
+//!
+//! let clock = ClockScheduler::with_tick_frequency(1000);
+//!
+//! // At the end of each tick
+//! clock.program_next_tick(cpu, now, stop_tick);
+//!
+//! // Before charging runtime
+//! let now_task = clock.update_rq_clock(cpu, steal_clock(cpu));
+//! ```
+
+use crate::kernel::cpu::{CpuId, CpuMask};
+use crate::kernel::time::Timestamp;
+use crate::kernel::error::{KernelResult, SchedulerError};
+use crate::kernel::sync::SpinLock;
+use crate::kernel::log::{kernel_info, kernel_debug};
+use crate::kernel::memory::percpu::PerCpu;
+use crate::arch::cpu::current_cpu_id;
+use crate::arch::timer::{read_local_clock, set_next_event};
+use crate::arch::smp::send_reschedule_ipi;
+
+use core::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+
+/// Nanoseconds per second
+const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+/// Default bound on the `sched_clock` difference between any two CPUs
+pub const MAX_CLOCK_SKEW_NS: u64 = 100_000; // 100us
+
+/// Per-CPU `sched_clock` state
+#[derive(Debug, Default)]
+struct SchedClockState {
+    /// Correction added to the raw local counter
+    offset: AtomicI64,
+    /// Last value returned for the CPU
+    last: AtomicU64,
+}
+
+/// Per-CPU tick state
+#[derive(Debug, Default)]
+struct TickState {
+    /// Tick is currently stopped
+    stopped: AtomicBool,
+    /// Time the tick was stopped
+    stopped_at: AtomicU64,
+    /// Expiry of the next programmed tick
+    next_tick: AtomicU64,
+}
+
+/// Per-CPU runqueue clocks
+#[derive(Debug, Default)]
+struct RqClock {
+    /// Wall time of the last update (0 before the first)
+    clock: u64,
+    /// Time the CPU spent running guest code
+    clock_task: u64,
+    /// Hypervisor steal counter at the last update
+    prev_steal: u64,
+    /// Total steal time subtracted so far
+    steal_total: u64,
+}
+
+/// Clock scheduler component
+pub struct ClockScheduler {
+    tick_period_ns: AtomicU64,
+    ticks: PerCpu<TickState>,
+    rq_clocks: PerCpu<SpinLock<RqClock>>,
+    sched_clocks: PerCpu<SchedClockState>,
+    max_skew_ns: AtomicU64,
+    /// Last value returned by `clock_monotonic`
+    mono_last: AtomicU64,
+    /// Total time spent in suspend
+    suspend_ns: AtomicU64,
+}
+
+impl ClockScheduler {
+    /// Create a clock with a 1000 Hz tick
+    pub fn new() -> Self {
+        Self::with_tick_frequency(1000)
+    }
+
+    /// Create a clock with the given tick frequency (Hz)
+    pub fn with_tick_frequency(hz: u32) -> Self {
+        Self {
+            tick_period_ns: AtomicU64::new(NSEC_PER_SEC / hz.max(1) as u64),
+            ticks: PerCpu::new(TickState::default()),
+            rq_clocks: PerCpu::new(SpinLock::new(RqClock::default())),
+            sched_clocks: PerCpu::new(SchedClockState::default()),
+            max_skew_ns: AtomicU64::new(MAX_CLOCK_SKEW_NS),
+            mono_last: AtomicU64::new(0),
+            suspend_ns: AtomicU64::new(0),
+        }
+    }
+
+    /// Change the tick frequency (Hz)
+    pub fn set_tick_frequency(&self, hz: u32) -> KernelResult<()> {
+        if hz == 0 || hz as u64 > NSEC_PER_SEC {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        self.tick_period_ns.store(NSEC_PER_SEC / hz as u64, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Length of one tick in nanoseconds
+    pub fn tick_period_ns(&self) -> u64 {
+        self.tick_period_ns.load(Ordering::Relaxed)
+    }
+
+    /// Program the next tick of the local CPU, or stop it
+    ///
+    /// With `stop` set, no further tick is programmed until the tick is
+    /// restarted. Returns the expiry of the programmed tick, if any.
+    pub fn program_next_tick(&self, cpu: CpuId, now: u64, stop: bool) -> Option<u64> {
+        let tick = self.ticks.get(cpu);
+        if stop {
+            if !tick.stopped.swap(true, Ordering::AcqRel) {
+                tick.stopped_at.store(now, Ordering::Release);
+                kernel_debug!("CPU {} tick stopped", cpu.as_u32());
+            }
+            return None;
+        }
+
+        tick.stopped.store(false, Ordering::Release);
+        let next = now + self.tick_period_ns();
+        tick.next_tick.store(next, Ordering::Release);
+        set_next_event(next);
+        Some(next)
+    }
+
+    /// Restart a stopped tick, e.g. because a second task became runnable
+    ///
+    /// A remote CPU is kicked with an IPI and reprograms its tick on the
+    /// way out of the interrupt.
+    pub fn restart_tick(&self, cpu: CpuId, now: u64) {
+        if !self.ticks.get(cpu).stopped.load(Ordering::Acquire) {
+            return;
+        }
+        kernel_debug!("CPU {} tick restarted", cpu.as_u32());
+        if cpu == current_cpu_id() {
+            self.program_next_tick(cpu, now, false);
+        } else {
+            send_reschedule_ipi(cpu);
+        }
+    }
+
+    /// Set the bound on the `sched_clock` difference between CPUs
+    pub fn set_max_clock_skew(&self, skew_ns: u64) -> KernelResult<()> {
+        if skew_ns == 0 {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        self.max_skew_ns.store(skew_ns, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Bound on the `sched_clock` difference between CPUs
+    pub fn max_clock_skew(&self) -> u64 {
+        self.max_skew_ns.load(Ordering::Relaxed)
+    }
+
+    /// Globally coherent monotonic time, excluding suspend
+    ///
+    /// Never goes backwards, even if read on different CPUs.
+    pub fn clock_monotonic(&self) -> u64 {
+        let now = Timestamp::now().as_nanos();
+        let prev = self.mono_last.fetch_max(now, Ordering::AcqRel);
+        prev.max(now)
+    }
+
+    /// Monotonic time including time spent in suspend
+    pub fn clock_boottime(&self) -> u64 {
+        self.clock_monotonic() + self.suspend_ns.load(Ordering::Acquire)
+    }
+
+    /// Account time the system spent suspended
+    pub fn add_suspend_time(&self, suspended_ns: u64) {
+        self.suspend_ns.fetch_add(suspended_ns, Ordering::AcqRel);
+    }
+
+    /// Fast per-CPU scheduler clock
+    ///
+    /// On the local CPU this is the raw local counter plus a per-CPU
+    /// correction. The result is clamped to within half the skew bound of
+    /// `clock_monotonic()` (recalibrating the correction when the clamp
+    /// hits), so any two CPUs differ by at most `max_clock_skew()`. A
+    /// remote CPU's counter can't be read, so its clock is advanced to the
+    /// coherent time instead. Per CPU the clock never goes backwards.
+    pub fn sched_clock(&self, cpu: CpuId) -> u64 {
+        let state = self.sched_clocks.get(cpu);
+        let mono = self.clock_monotonic();
+        let half_skew = self.max_clock_skew() / 2;
+        let (low, high) = (mono.saturating_sub(half_skew), mono + half_skew);
+
+        let value = if cpu == current_cpu_id() {
+            let raw = read_local_clock();
+            let local = raw.wrapping_add(state.offset.load(Ordering::Relaxed) as u64);
+            let clamped = local.clamp(low, high);
+            if clamped != local {
+                state.offset.store(clamped.wrapping_sub(raw) as i64, Ordering::Relaxed);
+            }
+            clamped
+        } else {
+            low
+        };
+
+        // `last` never exceeds an earlier `high`, so it stays in bounds
+        let prev = state.last.fetch_max(value, Ordering::AcqRel);
+        prev.max(value)
+    }
+
+    /// Time elapsed between two `sched_clock` readings, possibly taken on
+    /// different CPUs
+    ///
+    /// Cross-CPU readings may appear out of order by up to the skew bound;
+    /// such a difference is reported as 0 rather than negative.
+    pub fn sched_clock_delta(&self, start: u64, end: u64) -> u64 {
+        end.saturating_sub(start)
+    }
+
+    /// Advance a CPU's runqueue clocks to the current time
+    ///
+    /// # Arguments
+    /// * `cpu` - CPU whose clocks to update
+    /// * `steal_ns` - The hypervisor's cumulative steal time for the CPU
+    ///   (0 on bare metal)
+    ///
+    /// # Returns
+    /// The updated `rq_clock_task`, which advanced by the elapsed time minus
+    /// the time stolen since the last update.
+    pub fn update_rq_clock(&self, cpu: CpuId, steal_ns: u64) -> u64 {
+        let now = Timestamp::now().as_nanos();
+        let mut rq = self.rq_clocks.get(cpu).lock();
+        if rq.clock == 0 {
+            rq.clock = now;
+            rq.clock_task = now;
+            rq.prev_steal = steal_ns;
+            return rq.clock_task;
+        }
+
+        let delta = now.saturating_sub(rq.clock);
+        rq.clock = rq.clock.max(now);
+        // Steal beyond the elapsed time is carried over to the next update
+        let steal = steal_ns.saturating_sub(rq.prev_steal).min(delta);
+        rq.prev_steal += steal;
+        rq.steal_total += steal;
+        rq.clock_task += delta - steal;
+        rq.clock_task
+    }
+
+    /// Wall-clock runqueue time of a CPU at its last update
+    pub fn rq_clock(&self, cpu: CpuId) -> u64 {
+        self.rq_clocks.get(cpu).lock().clock
+    }
+
+    /// Task clock of a CPU at its last update, excluding stolen time
+    pub fn rq_clock_task(&self, cpu: CpuId) -> u64 {
+        self.rq_clocks.get(cpu).lock().clock_task
+    }
+
+    /// Total time stolen from a CPU by the hypervisor
+    pub fn steal_time(&self, cpu: CpuId) -> u64 {
+        self.rq_clocks.get(cpu).lock().steal_total
+    }
+
+    /// Log the runqueue clocks and steal time of every CPU
+    pub fn print_clock_info(&self) {
+        for cpu in CpuMask::online().iter() {
+            let rq = self.rq_clocks.get(cpu).lock();
+            kernel_info!("CPU {} rq_clock={} rq_clock_task={} steal={} ns",
+                        cpu.as_u32(), rq.clock, rq.clock_task, rq.steal_total);
+        }
+    }
+
+    /// Check whether a CPU's tick is stopped
+    pub fn is_tick_stopped(&self, cpu: CpuId) -> bool {
+        self.ticks.get(cpu).stopped.load(Ordering::Acquire)
+    }
+
+    /// Number of ticks a CPU skipped since its tick was stopped
+    pub fn skipped_ticks(&self, cpu: CpuId, now: u64) -> u64 {
+        let tick = self.ticks.get(cpu);
+        if !tick.stopped.load(Ordering::Acquire) {
+            return 0;
+        }
+        now.saturating_sub(tick.stopped_at.load(Ordering::Acquire)) / self.tick_period_ns()
+    }
+}
+
+impl Default for ClockScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
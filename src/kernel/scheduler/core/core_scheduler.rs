@@ -23,6 +23,11 @@
 //! - Real-time scheduling with priority inheritance
 //! - Deadline scheduling with bandwidth isolation
 //! - Automatic load balancing and task migration
+//! - Core scheduling: SMT siblings only co-run tasks of the same trust group
+//! - Pressure-aware wakeups driven by PSI scheduling hints
+//! - Suspend and resume, holding wakeups that arrive while suspended
+//! - CPU offlining that waits for the CPU's per-CPU kthreads to park
+//! - Lock-free reads of the scheduler configuration, updated by read-copy-update
 //! - Comprehensive debugging and statistics
 //! - Memory barrier coordination for SMP safety
 //!
@@ -53,6 +58,7 @@ use crate::kernel::scheduler::domains::*;
 use crate::kernel::scheduler::fair::*;
 use crate::kernel::scheduler::idle::*;
 use crate::kernel::scheduler::isolation::*;
+use crate::kernel::scheduler::kthread::*;
 use crate::kernel::scheduler::loadavg::*;
 use crate::kernel::scheduler::membarrier::*;
 use crate::kernel::scheduler::migration::*;
@@ -64,22 +70,27 @@ use crate::kernel::scheduler::swait::*;
 use crate::kernel::scheduler::wait::*;
 use crate::kernel::scheduler::pelt::*;
 use crate::kernel::scheduler::preempt::*;
+use crate::kernel::scheduler::psi::*;
+use crate::kernel::scheduler::report::*;
 use crate::kernel::scheduler::topology::*;
+use crate::kernel::scheduler::trace::*;
 
 use crate::kernel::task::{Task, TaskId, TaskPriority, TaskState};
 use crate::kernel::cpu::{CpuId, CpuMask};
-use crate::kernel::time::{Timestamp, Duration};
+use crate::kernel::time::{Timestamp, Duration, get_current_time_us};
 use crate::kernel::error::{KernelResult, SchedulerError};
 use crate::kernel::sync::{SpinLock, RwLock, Mutex};
 use crate::kernel::log::{kernel_info, kernel_warn, kernel_error, kernel_debug};
 use crate::kernel::memory::percpu::PerCpu;
 use crate::arch::context::Context;
 use crate::arch::cpu::current_cpu_id;
+use crate::arch::paravirt::steal_clock;
+use crate::arch::smp::send_reschedule_ipi;
 
 use alloc::vec::Vec;
 use alloc::collections::{BTreeMap, VecDeque};
 use alloc::sync::Arc;
-use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU32, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU32, AtomicUsize, AtomicPtr, Ordering};
 use core::time::Duration as CoreDuration;
 
 /// Core scheduler state with enhanced state machine
@@ -126,7 +137,10 @@ pub enum SchedPolicy {
     RoundRobin = 2,
     /// Batch processing (lower priority than normal)
     Batch = 3,
-    /// Idle tasks (lowest priority)
+    /// `SCHED_IDLE`: fair tasks that only run while no other fair task
+    /// wants the CPU. These are regular tasks with a near-zero weight; the
+    /// per-CPU idle threads the idle scheduler runs have no policy of
+    /// their own
     Idle = 4,
     /// Deadline scheduling with timing guarantees
     Deadline = 5,
@@ -137,7 +151,22 @@ pub enum SchedPolicy {
 }
 
 impl SchedPolicy {
+    /// Every policy, in index order
+    pub const ALL: [SchedPolicy; NR_SCHED_POLICIES] = [
+        SchedPolicy::Normal,
+        SchedPolicy::Fifo,
+        SchedPolicy::RoundRobin,
+        SchedPolicy::Batch,
+        SchedPolicy::Idle,
+        SchedPolicy::Deadline,
+        SchedPolicy::Interactive,
+        SchedPolicy::Background,
+    ];
+
     /// Get the base priority class for this policy
+    ///
+    /// RT tasks are further ordered within their class by their RT
+    /// priority (1-99).
     pub fn priority_class(&self) -> u32 {
         match self {
             SchedPolicy::Fifo | SchedPolicy::RoundRobin => 100,     // RT priority
@@ -154,19 +183,111 @@ impl SchedPolicy {
     pub fn is_realtime(&self) -> bool {
         matches!(self, SchedPolicy::Fifo | SchedPolicy::RoundRobin | SchedPolicy::Deadline)
     }
+
+    /// Check if tasks of this policy are scheduled by the fair class
+    pub fn is_fair(&self) -> bool {
+        matches!(self, SchedPolicy::Normal | SchedPolicy::Interactive | SchedPolicy::Batch
+                 | SchedPolicy::Background | SchedPolicy::Idle)
+    }
     
     /// Get the scheduler responsible for this policy
     pub fn scheduler_name(&self) -> &'static str {
         match self {
-            SchedPolicy::Normal | SchedPolicy::Interactive => "CFS",
+            SchedPolicy::Normal | SchedPolicy::Interactive | SchedPolicy::Idle => "CFS",
             SchedPolicy::Fifo | SchedPolicy::RoundRobin => "RT",
             SchedPolicy::Deadline => "DL",
             SchedPolicy::Batch | SchedPolicy::Background => "BATCH",
-            SchedPolicy::Idle => "IDLE",
         }
     }
 }
 
+/// Number of scheduling policies
+pub const NR_SCHED_POLICIES: usize = 8;
+
+/// Context switch and preemption counters of one scheduling policy
+#[derive(Debug, Default)]
+pub struct PolicyStats {
+    /// Context switches to a task of the policy
+    pub switches_in: AtomicU64,
+    /// Context switches away from a task of the policy
+    pub switches_out: AtomicU64,
+    /// Preemptions in favour of a task of the policy
+    pub preemptions: AtomicU64,
+    /// Times a running task of the policy was preempted
+    pub preempted: AtomicU64,
+}
+
+impl PolicyStats {
+    /// Copy of the counters
+    pub fn snapshot(&self) -> PolicyStatsSnapshot {
+        PolicyStatsSnapshot {
+            switches_in: self.switches_in.load(Ordering::Relaxed),
+            switches_out: self.switches_out.load(Ordering::Relaxed),
+            preemptions: self.preemptions.load(Ordering::Relaxed),
+            preempted: self.preempted.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Reset the counters
+    pub fn reset(&self) {
+        self.switches_in.store(0, Ordering::Relaxed);
+        self.switches_out.store(0, Ordering::Relaxed);
+        self.preemptions.store(0, Ordering::Relaxed);
+        self.preempted.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Counters of one scheduling policy at a point in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PolicyStatsSnapshot {
+    /// Context switches to a task of the policy
+    pub switches_in: u64,
+    /// Context switches away from a task of the policy
+    pub switches_out: u64,
+    /// Preemptions in favour of a task of the policy
+    pub preemptions: u64,
+    /// Times a running task of the policy was preempted
+    pub preempted: u64,
+}
+
+/// Number of context switch reasons
+pub const NR_SWITCH_REASONS: usize = 5;
+
+/// Why a CPU switched away from its running task
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SwitchReason {
+    /// The task blocked or yielded, or the idle task found work
+    Voluntary = 0,
+    /// A higher priority or better placed task preempted the task
+    Preempted = 1,
+    /// The task used up its slice, runtime or quota
+    SliceExpired = 2,
+    /// The task was migrated off the CPU
+    Migration = 3,
+    /// Nothing was left to run and the CPU went idle
+    IdleEntry = 4,
+}
+
+impl SwitchReason {
+    /// Every reason, in index order
+    pub const ALL: [SwitchReason; NR_SWITCH_REASONS] = [
+        SwitchReason::Voluntary,
+        SwitchReason::Preempted,
+        SwitchReason::SliceExpired,
+        SwitchReason::Migration,
+        SwitchReason::IdleEntry,
+    ];
+
+    /// Check whether the task was switched out against its will
+    pub fn is_involuntary(&self) -> bool {
+        matches!(self, SwitchReason::Preempted | SwitchReason::SliceExpired | SwitchReason::Migration)
+    }
+}
+
+/// Longest scheduling or context switch latency taken as a real sample;
+/// anything longer comes from clocks out of step between CPUs
+pub const MAX_LATENCY_SAMPLE_NS: u64 = 1_000_000_000; // 1s
+
 /// Comprehensive scheduler statistics with performance metrics
 #[derive(Debug, Default)]
 pub struct SchedulerStats {
@@ -190,14 +311,34 @@ pub struct SchedulerStats {
     pub rt_throttled: AtomicU64,
     /// Deadline misses
     pub deadline_misses: AtomicU64,
-    /// CPU idle time (microseconds)
+    /// RT tasks flagged by the stall watchdog
+    pub rt_stalls: AtomicU64,
+    /// CPU idle time (microseconds), as charged to the cpuidle states
     pub cpu_idle_time: AtomicU64,
     /// Average scheduling latency (nanoseconds)
     pub avg_schedule_latency: AtomicU64,
     /// Peak scheduling latency (nanoseconds)
     pub peak_schedule_latency: AtomicU64,
+    /// Average context switch latency (nanoseconds)
+    pub avg_switch_latency: AtomicU64,
+    /// Peak context switch latency (nanoseconds)
+    pub peak_switch_latency: AtomicU64,
+    /// Latency samples dropped because the clock went backwards or they
+    /// exceeded `MAX_LATENCY_SAMPLE_NS`
+    pub discarded_latency_samples: AtomicU64,
     /// System load (fixed point, multiplied by 1000)
     pub system_load: AtomicU32,
+    /// Time CPUs were kept idle by core scheduling (nanoseconds)
+    pub force_idle_time: AtomicU64,
+    /// New tasks held back because of PSI pressure
+    pub psi_deferred_tasks: AtomicU64,
+    /// Deferred work refused because the CPU's queue was full
+    pub deferred_work_overflows: AtomicU64,
+    /// Context switches and preemptions by task policy, indexed by
+    /// `SchedPolicy`
+    pub per_policy: [PolicyStats; NR_SCHED_POLICIES],
+    /// Context switches by reason, indexed by `SwitchReason`
+    pub switch_reasons: [AtomicU64; NR_SWITCH_REASONS],
 }
 
 impl SchedulerStats {
@@ -212,6 +353,82 @@ impl SchedulerStats {
         self.system_load.load(Ordering::Relaxed) as f64 / 10.0
     }
     
+    /// Counters of one policy
+    pub fn policy(&self, policy: SchedPolicy) -> &PolicyStats {
+        &self.per_policy[policy as usize]
+    }
+    
+    /// Account a context switch from `prev` to `next`; a still running
+    /// `prev` was preempted
+    pub fn account_switch(&self, prev: Option<(SchedPolicy, bool)>, next: SchedPolicy) {
+        let next_stats = self.policy(next);
+        next_stats.switches_in.fetch_add(1, Ordering::Relaxed);
+        if let Some((prev, preempted)) = prev {
+            let prev_stats = self.policy(prev);
+            prev_stats.switches_out.fetch_add(1, Ordering::Relaxed);
+            if preempted {
+                prev_stats.preempted.fetch_add(1, Ordering::Relaxed);
+                next_stats.preemptions.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+    
+    /// Copy of the counters of every policy, in `SchedPolicy::ALL` order
+    pub fn policy_snapshot(&self) -> [PolicyStatsSnapshot; NR_SCHED_POLICIES] {
+        SchedPolicy::ALL.map(|policy| self.policy(policy).snapshot())
+    }
+    
+    /// Account the scheduling latency of a `schedule` call that started
+    /// at `start_ns` and ended at `end_ns`
+    ///
+    /// Returns false if the sample was discarded as bogus.
+    pub fn record_schedule_latency(&self, start_ns: u64, end_ns: u64) -> bool {
+        self.record_latency(&self.avg_schedule_latency, &self.peak_schedule_latency, start_ns, end_ns)
+    }
+    
+    /// Account the latency of a context switch that started at `start_ns`
+    /// and ended at `end_ns`
+    ///
+    /// Returns false if the sample was discarded as bogus.
+    pub fn record_switch_latency(&self, start_ns: u64, end_ns: u64) -> bool {
+        self.record_latency(&self.avg_switch_latency, &self.peak_switch_latency, start_ns, end_ns)
+    }
+    
+    /// Fold a latency sample into a moving average (1/8 weight) and a peak
+    ///
+    /// Start and end may be read on different CPUs, whose clocks need not
+    /// agree: a sample running backwards or longer than
+    /// `MAX_LATENCY_SAMPLE_NS` is counted as discarded instead.
+    fn record_latency(&self, avg: &AtomicU64, peak: &AtomicU64, start_ns: u64, end_ns: u64) -> bool {
+        let latency = match end_ns.checked_sub(start_ns) {
+            Some(latency) if latency <= MAX_LATENCY_SAMPLE_NS => latency,
+            _ => {
+                self.discarded_latency_samples.fetch_add(1, Ordering::Relaxed);
+                return false;
+            }
+        };
+        peak.fetch_max(latency, Ordering::Relaxed);
+        let _ = avg.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |avg| {
+            Some(if avg == 0 { latency } else { avg - avg / 8 + latency / 8 })
+        });
+        true
+    }
+    
+    /// Account an idle period of `idle_us` microseconds
+    pub fn account_idle(&self, idle_us: u64) {
+        self.cpu_idle_time.fetch_add(idle_us, Ordering::Relaxed);
+    }
+    
+    /// Account the reason of a context switch
+    pub fn account_switch_reason(&self, reason: SwitchReason) {
+        self.switch_reasons[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+    
+    /// Context switches of every reason, in `SwitchReason::ALL` order
+    pub fn switch_reason_snapshot(&self) -> [u64; NR_SWITCH_REASONS] {
+        SwitchReason::ALL.map(|reason| self.switch_reasons[reason as usize].load(Ordering::Relaxed))
+    }
+    
     /// Reset statistics counters
     pub fn reset(&self) {
         self.context_switches.store(0, Ordering::Relaxed);
@@ -221,16 +438,27 @@ impl SchedulerStats {
         self.schedule_failures.store(0, Ordering::Relaxed);
         self.rt_throttled.store(0, Ordering::Relaxed);
         self.deadline_misses.store(0, Ordering::Relaxed);
+        self.rt_stalls.store(0, Ordering::Relaxed);
         self.avg_schedule_latency.store(0, Ordering::Relaxed);
         self.peak_schedule_latency.store(0, Ordering::Relaxed);
+        self.avg_switch_latency.store(0, Ordering::Relaxed);
+        self.peak_switch_latency.store(0, Ordering::Relaxed);
+        self.discarded_latency_samples.store(0, Ordering::Relaxed);
+        self.force_idle_time.store(0, Ordering::Relaxed);
+        self.psi_deferred_tasks.store(0, Ordering::Relaxed);
+        self.deferred_work_overflows.store(0, Ordering::Relaxed);
+        for stats in &self.per_policy {
+            stats.reset();
+        }
+        for count in &self.switch_reasons {
+            count.store(0, Ordering::Relaxed);
+        }
     }
 }
 
 /// Per-CPU scheduler data for efficient SMP scaling
 #[derive(Debug, Default)]
 pub struct PerCpuSchedulerData {
-    /// CPU-specific runqueue statistics
-    pub runqueue_size: AtomicU32,
     /// Last scheduling decision timestamp
     pub last_schedule_time: AtomicU64,
     /// CPU utilization (0-1000 for 0-100.0%)
@@ -245,6 +473,178 @@ pub struct PerCpuSchedulerData {
     pub idle_state: AtomicU32,
     /// Local scheduling statistics
     pub local_stats: SchedulerStats,
+    /// Core scheduling cookie of the task running on this CPU
+    pub core_cookie: AtomicU64,
+    /// Whether a (non-idle) task is running on this CPU
+    pub core_busy: AtomicBool,
+    /// Start of the current forced idle period (0 if not forced idle)
+    pub force_idle_start: AtomicU64,
+    /// Start of the current idle period (0 if not idle)
+    pub idle_start: AtomicU64,
+    /// Most recent scheduling decision
+    pub last_decision: Mutex<Option<ScheduleResult>>,
+    /// Runqueue length and utilization of the most recent ticks
+    pub history: TickHistory,
+    /// Tasks that went into uninterruptible sleep on this CPU
+    pub nr_uninterruptible: AtomicU32,
+    /// Work to run on this CPU's next tick
+    pub deferred: SpinLock<VecDeque<DeferredWork>>,
+    /// The running task yielded since the last scheduling decision
+    pub yielded: AtomicBool,
+    /// Fair `min_vruntime` seen by the last invariant check
+    pub verified_min_vruntime: AtomicU64,
+    /// `last_schedule_time` of the last stall reported for this CPU
+    pub stall_reported: AtomicU64,
+}
+
+impl PerCpuSchedulerData {
+    /// Utilization change over the tick history window (0-1000 scale)
+    ///
+    /// Positive when utilization is rising, negative when it is falling.
+    pub fn util_trend(&self) -> i32 {
+        self.history.util_trend()
+    }
+
+    /// Check whether this CPU has not scheduled for longer than
+    /// `threshold_ns` at `now`
+    ///
+    /// An idle CPU, a CPU that never scheduled and a CPU whose tick is
+    /// stopped, running its only task without scheduling by design, are
+    /// not stalled. Returns how long the CPU has been stalled and whether
+    /// this is the first check to find this stall.
+    fn check_stall(&self, now: u64, threshold_ns: u64, tick_stopped: bool) -> Option<(u64, bool)> {
+        let last = self.last_schedule_time.load(Ordering::Acquire);
+        if last == 0 || tick_stopped || self.idle_start.load(Ordering::Acquire) != 0 {
+            return None;
+        }
+        let stalled_ns = now.saturating_sub(last);
+        if stalled_ns <= threshold_ns {
+            return None;
+        }
+        Some((stalled_ns, self.stall_reported.swap(last, Ordering::AcqRel) != last))
+    }
+}
+
+/// Deferred work items a CPU can have queued
+pub const DEFERRED_WORK_QUEUE_LEN: usize = 32;
+
+/// Time a tick spends on deferred work before leaving the rest for the
+/// next tick
+const DEFERRED_WORK_BUDGET_NS: u64 = 50_000; // 50us
+
+/// Short maintenance work run from a CPU's scheduler tick
+///
+/// `func` is called with the CPU and `data`. It runs in tick context: it
+/// must not sleep and should finish well within `DEFERRED_WORK_BUDGET_NS`.
+#[derive(Debug, Clone, Copy)]
+pub struct DeferredWork {
+    /// Function to run
+    pub func: fn(cpu: CpuId, data: usize),
+    /// Argument passed to `func`
+    pub data: usize,
+}
+
+impl DeferredWork {
+    /// Create a work item calling `func` with `data`
+    pub const fn new(func: fn(cpu: CpuId, data: usize), data: usize) -> Self {
+        Self { func, data }
+    }
+}
+
+/// Called with a CPU that has not scheduled for too long and how long
+/// ago it last did (ns)
+pub type CpuStallCallback = fn(cpu: CpuId, stalled_ns: u64);
+
+/// Maximum number of ticks a CPU's tick history can cover
+pub const MAX_TICK_HISTORY: usize = 64;
+
+/// Default number of ticks trends are computed over
+pub const DEFAULT_TICK_HISTORY: usize = 32;
+
+/// Fixed-size ring of per-tick runqueue length and utilization samples
+///
+/// Only the owning CPU's tick records samples and no lock is taken. A
+/// reader racing with the tick may see one sample of the next window,
+/// which moves a trend by at most one tick. Memory is always
+/// `MAX_TICK_HISTORY` samples; the window only limits how many are used.
+#[derive(Debug)]
+pub struct TickHistory {
+    /// Samples packed as runqueue length (high half) and utilization (low half)
+    samples: [AtomicU64; MAX_TICK_HISTORY],
+    /// Number of samples recorded so far
+    recorded: AtomicU64,
+    /// Number of most recent samples trends are computed over
+    window: AtomicU32,
+}
+
+impl TickHistory {
+    /// Create an empty history; the window is clamped to 2-`MAX_TICK_HISTORY` ticks
+    pub fn new(window: usize) -> Self {
+        Self {
+            samples: [const { AtomicU64::new(0) }; MAX_TICK_HISTORY],
+            recorded: AtomicU64::new(0),
+            window: AtomicU32::new(window.clamp(2, MAX_TICK_HISTORY) as u32),
+        }
+    }
+
+    /// Number of ticks trends are computed over
+    pub fn window(&self) -> usize {
+        self.window.load(Ordering::Relaxed) as usize
+    }
+
+    /// Set the number of ticks trends are computed over (2-`MAX_TICK_HISTORY`)
+    pub fn set_window(&self, ticks: usize) -> KernelResult<()> {
+        if !(2..=MAX_TICK_HISTORY).contains(&ticks) {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        self.window.store(ticks as u32, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Record the state of one tick; only called by the owning CPU
+    pub fn record(&self, nr_running: u32, util: u32) {
+        let slot = self.recorded.load(Ordering::Relaxed);
+        let sample = (nr_running as u64) << 32 | util as u64;
+        self.samples[slot as usize % MAX_TICK_HISTORY].store(sample, Ordering::Relaxed);
+        self.recorded.store(slot + 1, Ordering::Release);
+    }
+
+    /// Samples of the window, oldest first, as (runqueue length, utilization)
+    pub fn samples(&self) -> Vec<(u32, u32)> {
+        let recorded = self.recorded.load(Ordering::Acquire);
+        let count = recorded.min(self.window() as u64);
+        (recorded - count..recorded)
+            .map(|slot| {
+                let sample = self.samples[slot as usize % MAX_TICK_HISTORY].load(Ordering::Relaxed);
+                ((sample >> 32) as u32, sample as u32)
+            })
+            .collect()
+    }
+
+    /// Utilization change over the window of a least squares line through
+    /// the samples; 0 with fewer than two samples
+    pub fn util_trend(&self) -> i32 {
+        let samples = self.samples();
+        let n = samples.len() as i64;
+        if n < 2 {
+            return 0;
+        }
+        let sum_x = n * (n - 1) / 2;
+        let sum_xx = (n - 1) * n * (2 * n - 1) / 6;
+        let (sum_y, sum_xy) = samples.iter().enumerate()
+            .fold((0i64, 0i64), |(sy, sxy), (x, &(_, util))| {
+                (sy + util as i64, sxy + x as i64 * util as i64)
+            });
+        let slope_num = n * sum_xy - sum_x * sum_y;
+        let slope_den = n * sum_xx - sum_x * sum_x;
+        (slope_num * (n - 1) / slope_den) as i32
+    }
+}
+
+impl Default for TickHistory {
+    fn default() -> Self {
+        Self::new(DEFAULT_TICK_HISTORY)
+    }
 }
 
 /// Scheduling decision result
@@ -252,14 +652,111 @@ pub struct PerCpuSchedulerData {
 pub enum ScheduleResult {
     /// Continue running current task
     KeepCurrent,
-    /// Switch to new task
-    SwitchTo(TaskId),
+    /// Switch to new task, for the given reason
+    SwitchTo(TaskId, SwitchReason),
     /// CPU should go idle
     GoIdle,
     /// Reschedule immediately (high priority task arrived)
     RescheduleImmediate,
 }
 
+/// What a scheduling decision rests on besides the runqueues' content
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DecisionCause {
+    /// The class and core scheduling picks
+    Runqueues,
+    /// The CPU is in a preempt-disabled section
+    PreemptDisabled,
+    /// The current task was about to sleep with a signal pending
+    PendingSignal,
+    /// No task is compatible with the SMT siblings or the isolation
+    ForcedIdle,
+}
+
+/// State of one CPU captured by an emergency shutdown
+#[derive(Debug, Clone)]
+pub struct CpuErrorContext {
+    /// CPU described
+    pub cpu: CpuId,
+    /// Most recent scheduling decision
+    pub last_decision: Option<ScheduleResult>,
+    /// Time of that decision in nanoseconds
+    pub last_schedule_time: u64,
+    /// Task running on the CPU
+    pub current_task: Option<TaskId>,
+    /// Runnable tasks on the CPU
+    pub runqueue_size: u32,
+}
+
+/// Diagnostic snapshot of an emergency shutdown
+#[derive(Debug, Clone)]
+pub struct ErrorContext {
+    /// Time of the shutdown in nanoseconds
+    pub timestamp: u64,
+    /// CPU that shut scheduling down
+    pub cpu: CpuId,
+    /// Scheduler ticks at the shutdown
+    pub tick: u64,
+    /// State of every online CPU
+    pub cpus: Vec<CpuErrorContext>,
+    /// Why the scheduler is in the `Error` state; updated when a recovery
+    /// attempt fails
+    pub reason: &'static str,
+}
+
+/// Scheduler state found inconsistent by `verify_invariants`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvariantViolation {
+    /// A task is queued or running more than once across CPUs and classes
+    MultiplyQueued { task: TaskId, count: usize },
+    /// A queued task no longer exists
+    MissingTask { task: TaskId, cpu: CpuId },
+    /// A queued task is neither runnable nor running
+    NotRunnable { task: TaskId, cpu: CpuId, state: TaskState },
+    /// A task is queued on another CPU than its own
+    WrongCpu { task: TaskId, queued_on: CpuId, current_cpu: CpuId },
+    /// A task is queued or running on an offline CPU
+    OfflineCpu { task: TaskId, cpu: CpuId },
+    /// Deadline reservations exceed the deadline bandwidth limit (0-1000
+    /// per CPU, summed over online CPUs)
+    DeadlineOvercommit { reserved: u64, limit: u64 },
+    /// A CPU's fair `min_vruntime` went backwards
+    MinVruntimeRegressed { cpu: CpuId, previous: u64, current: u64 },
+}
+
+/// Deadline and RT bandwidth of one CPU (per mille of the CPU)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuBandwidth {
+    pub cpu: CpuId,
+    /// Bandwidth reserved by admitted deadline tasks and servers
+    pub deadline: u32,
+    /// Share of the last second RT tasks ran
+    pub rt: u32,
+    /// Bandwidth left under the cap
+    pub headroom: u32,
+    /// Deadline and RT together are above the safety margin
+    pub over_margin: bool,
+}
+
+/// Deadline and RT bandwidth use, per CPU and system-wide
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BandwidthReport {
+    /// Bandwidth cap of every CPU (per mille)
+    pub cap: u32,
+    /// Use above which a CPU is over the safety margin (per mille)
+    pub margin: u32,
+    /// Every online CPU
+    pub cpus: Vec<CpuBandwidth>,
+    /// Deadline bandwidth summed over all CPUs (per mille of one CPU)
+    pub deadline: u32,
+    /// RT bandwidth summed over all CPUs (per mille of one CPU)
+    pub rt: u32,
+    /// Headroom summed over all CPUs (per mille of one CPU)
+    pub headroom: u32,
+    /// Some CPU is over the safety margin
+    pub warning: bool,
+}
+
 /// Load balancing configuration
 #[derive(Debug, Clone)]
 pub struct LoadBalanceConfig {
@@ -269,10 +766,14 @@ pub struct LoadBalanceConfig {
     pub imbalance_threshold: u32,
     /// Maximum tasks to migrate per balance operation
     pub max_migrations_per_balance: u32,
-    /// Load balance interval in ticks
+    /// Longest interval between two balance attempts of a scheduling
+    /// domain in milliseconds; each domain balances at an interval derived
+    /// from its span, capped at this value
     pub balance_interval: u64,
     /// Enable NUMA-aware balancing
     pub numa_aware: bool,
+    /// Minimum time between two migrations of the same task in nanoseconds
+    pub min_migration_interval_ns: u64,
 }
 
 impl Default for LoadBalanceConfig {
@@ -281,9 +782,104 @@ impl Default for LoadBalanceConfig {
             aggressive_balance: false,
             imbalance_threshold: 25,
             max_migrations_per_balance: 4,
-            balance_interval: 100,
+            balance_interval: DEFAULT_MAX_BALANCE_INTERVAL_MS,
             numa_aware: true,
+            min_migration_interval_ns: 1_000_000, // 1ms
+        }
+    }
+}
+
+/// Actions enforcing PSI scheduling hints, each enabled separately
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PressureActions {
+    /// Under `ReduceLoad`, hold back waking `Background` and `Idle` tasks
+    pub hold_background: bool,
+    /// Under `ReduceLoad`, switch cpufreq to the powersave governor
+    pub powersave: bool,
+    /// From `PreferLightTasks` on, favor low-utilization tasks in fair picks
+    pub prefer_light: bool,
+}
+
+/// Action enforcing a PSI scheduling hint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PressureAction {
+    /// Waking `Background` and `Idle` tasks are held back
+    HoldBackground = 0,
+    /// cpufreq runs the powersave governor
+    Powersave = 1,
+    /// Fair picks favor low-utilization tasks
+    PreferLight = 2,
+}
+
+impl PressureAction {
+    /// Every action, in index order
+    pub const ALL: [PressureAction; 3] = [
+        PressureAction::HoldBackground,
+        PressureAction::Powersave,
+        PressureAction::PreferLight,
+    ];
+}
+
+/// State of the pressure enforcement actions
+#[derive(Debug, Default)]
+struct PressureEnforcement {
+    /// Start of the current activation of each action (0 while inactive)
+    since: [u64; 3],
+    /// Time each action was active in completed activations (ns)
+    total: [u64; 3],
+    /// Governor in effect before `Powersave` engaged
+    saved_governor: Option<Governor>,
+}
+
+/// Scheduler state kept across a suspend
+#[derive(Debug, Default)]
+struct SuspendState {
+    /// Whether wakeups are held
+    suspended: bool,
+    /// Start of the suspend
+    since: u64,
+    /// Governor in effect before the suspend
+    saved_governor: Option<Governor>,
+    /// Frequency in effect before the suspend
+    saved_frequency: Option<u64>,
+    /// Idle state in effect before the suspend
+    saved_idle_state: Option<u64>,
+    /// Tasks woken while suspended, in wakeup order
+    held: VecDeque<TaskId>,
+}
+
+impl SuspendState {
+    /// Start holding wakeups, suspended at `now` (ns)
+    fn begin(&mut self, now: u64) {
+        self.suspended = true;
+        self.since = now;
+    }
+
+    /// Hold the wakeup of a task until resume if suspended
+    ///
+    /// A task woken again before resume is held only once. Returns whether
+    /// the wakeup was held.
+    fn hold(&mut self, task: TaskId) -> bool {
+        if !self.suspended {
+            return false;
+        }
+        if !self.held.contains(&task) {
+            self.held.push_back(task);
+        }
+        true
+    }
+
+    /// Stop holding wakeups and return the state saved by the suspend
+    fn end(&mut self) -> SuspendState {
+        core::mem::take(self)
+    }
+
+    /// Deliver the held wakeups in the order they arrived
+    fn release<F: FnMut(TaskId) -> KernelResult<()>>(&mut self, mut wake: F) -> KernelResult<()> {
+        while let Some(task) = self.held.pop_front() {
+            wake(task)?;
         }
+        Ok(())
     }
 }
 
@@ -302,8 +898,28 @@ pub struct SchedulerConfig {
     pub power_aware: bool,
     /// Maximum RT bandwidth (percent of CPU time)
     pub rt_bandwidth_percent: u32,
+    /// Runtime the fair class is guaranteed per fair server period
+    /// (microseconds, 0 disables the fair server)
+    pub fair_server_runtime_us: u64,
+    /// Fair server period (microseconds)
+    pub fair_server_period_us: u64,
+    /// Share of the RT bandwidth cap above which `bandwidth_report` warns
+    /// about a CPU (percent)
+    pub bandwidth_margin_percent: u32,
     /// Enable scheduler debugging
     pub debug_enabled: bool,
+    /// Enable per-task scheduling statistics
+    pub schedstats_enabled: bool,
+    /// Let PSI scheduling hints steer task wakeups
+    pub psi_aware: bool,
+    /// Pick order of the fair class: classic vruntime or EEVDF
+    pub fair_mode: FairMode,
+    /// How a waking task's CPU is chosen when nothing else decides
+    pub wakeup_strategy: WakeupStrategy,
+    /// Ticks of runqueue and utilization history kept per CPU
+    pub tick_history_len: usize,
+    /// Actions enforcing PSI scheduling hints
+    pub pressure_actions: PressureActions,
 }
 
 impl Default for SchedulerConfig {
@@ -315,8 +931,94 @@ impl Default for SchedulerConfig {
             load_balance: LoadBalanceConfig::default(),
             power_aware: true,
             rt_bandwidth_percent: 95,
+            fair_server_runtime_us: 50_000, // 50ms
+            fair_server_period_us: 1_000_000, // every second
+            bandwidth_margin_percent: 90,
             debug_enabled: false,
+            schedstats_enabled: false,
+            psi_aware: true,
+            fair_mode: FairMode::Cfs,
+            wakeup_strategy: WakeupStrategy::WakeAffine,
+            tick_history_len: DEFAULT_TICK_HISTORY,
+            pressure_actions: PressureActions::default(),
+        }
+    }
+}
+
+/// Read-mostly value published as an atomically swapped `Arc`
+///
+/// Readers never block: `load` takes a reference to the current snapshot,
+/// which stays valid however often writers replace it. Writers are
+/// serialized and publish a new snapshot, RCU-style. A replaced snapshot
+/// is released once the readers that might still be taking a reference
+/// to it are done; readers are split into two slots by writer epoch, so a
+/// writer only waits for those of the previous epoch and new readers
+/// cannot hold it up.
+struct RcuCell<T> {
+    ptr: AtomicPtr<T>,
+    epoch: AtomicUsize,
+    readers: [AtomicUsize; 2],
+    writer: SpinLock<()>,
+}
+
+// SAFETY: the cell hands out `Arc<T>` to any thread, as `Arc<T>` would.
+unsafe impl<T: Send + Sync> Send for RcuCell<T> {}
+unsafe impl<T: Send + Sync> Sync for RcuCell<T> {}
+
+impl<T> RcuCell<T> {
+    fn new(value: T) -> Self {
+        Self {
+            ptr: AtomicPtr::new(Arc::into_raw(Arc::new(value)) as *mut T),
+            epoch: AtomicUsize::new(0),
+            readers: [AtomicUsize::new(0), AtomicUsize::new(0)],
+            writer: SpinLock::new(()),
+        }
+    }
+
+    /// Current snapshot
+    fn load(&self) -> Arc<T> {
+        loop {
+            let epoch = self.epoch.load(Ordering::SeqCst);
+            let readers = &self.readers[epoch & 1];
+            readers.fetch_add(1, Ordering::SeqCst);
+            // A reader that registered after a writer moved on could be
+            // missed by it; register again in the new epoch
+            if self.epoch.load(Ordering::SeqCst) != epoch {
+                readers.fetch_sub(1, Ordering::Release);
+                continue;
+            }
+            let ptr = self.ptr.load(Ordering::SeqCst);
+            // SAFETY: the writer that replaces `ptr` keeps its reference
+            // until this epoch's readers are done
+            let value = unsafe {
+                Arc::increment_strong_count(ptr);
+                Arc::from_raw(ptr)
+            };
+            readers.fetch_sub(1, Ordering::Release);
+            return value;
+        }
+    }
+
+    /// Publish a copy of the current value changed by `f`
+    fn update<F: FnOnce(&mut T)>(&self, f: F) where T: Clone {
+        let _writer = self.writer.lock();
+        // SAFETY: only writers release a snapshot, and they are serialized
+        let mut value = unsafe { (*self.ptr.load(Ordering::Acquire)).clone() };
+        f(&mut value);
+        let old = self.ptr.swap(Arc::into_raw(Arc::new(value)) as *mut T, Ordering::SeqCst);
+        let slot = self.epoch.fetch_add(1, Ordering::SeqCst) & 1;
+        while self.readers[slot].load(Ordering::Acquire) != 0 {
+            core::hint::spin_loop();
         }
+        // SAFETY: no reader can still be taking a reference to `old`
+        drop(unsafe { Arc::from_raw(old) });
+    }
+}
+
+impl<T> Drop for RcuCell<T> {
+    fn drop(&mut self) {
+        // SAFETY: the cell owns one reference to the published snapshot
+        drop(unsafe { Arc::from_raw(*self.ptr.get_mut()) });
     }
 }
 
@@ -334,6 +1036,7 @@ pub struct CoreScheduler {
     fair: FairScheduler,
     idle: IdleScheduler,
     isolation: IsolationScheduler,
+    kthread: KthreadScheduler,
     loadavg: LoadAvgScheduler,
     membarrier: MembarrierScheduler,
     migration: MigrationScheduler,
@@ -345,17 +1048,44 @@ pub struct CoreScheduler {
     wait: WaitScheduler,
     pelt: PeltScheduler,
     preempt: PreemptScheduler,
+    psi: RwLock<PSIScheduler>,
+    report: ReportScheduler,
     topology: TopologyScheduler,
+    trace: TraceScheduler,
     
     // Enhanced scheduler state
     state: AtomicU64,
-    config: RwLock<SchedulerConfig>,
+    config: RcuCell<SchedulerConfig>,
     global_stats: SchedulerStats,
     per_cpu_data: PerCpu<PerCpuSchedulerData>,
     tick_counter: AtomicU64,
     last_balance_time: AtomicU64,
     emergency_stop: AtomicBool,
     init_timestamp: AtomicU64,
+    
+    // Core scheduling: trust-group cookie per task (0 = untagged)
+    core_cookies: RwLock<BTreeMap<TaskId, u64>>,
+    core_sched_lock: SpinLock<()>,
+    
+    // Cache affinity group per task (0 = none)
+    cache_groups: RwLock<BTreeMap<TaskId, u32>>,
+    
+    // New tasks held back while PSI asks to limit new work
+    psi_held: SpinLock<VecDeque<TaskId>>,
+    pressure: SpinLock<PressureEnforcement>,
+    pressure_held: SpinLock<VecDeque<TaskId>>,
+    
+    // Uninterruptible sleepers: CPU they slept on and sleep start (ns)
+    uninterruptible: SpinLock<BTreeMap<TaskId, (CpuId, u64)>>,
+    
+    // Snapshot of the last emergency shutdown
+    error_context: SpinLock<Option<ErrorContext>>,
+    
+    // Told about CPUs `check_stalls` finds stuck
+    stall_callback: SpinLock<Option<CpuStallCallback>>,
+    
+    // Saved state and held wakeups while suspended
+    suspend: SpinLock<SuspendState>,
 }
 
 impl CoreScheduler {
@@ -367,42 +1097,74 @@ impl CoreScheduler {
     /// Create scheduler with custom configuration
     pub fn with_config(config: SchedulerConfig) -> Self {
         kernel_info!("Creating core scheduler with config: {:?}", config);
+        let history_len = config.tick_history_len;
+        let fair_server = (config.fair_server_runtime_us > 0).then(|| DlParams {
+            runtime_ns: config.fair_server_runtime_us * 1000,
+            deadline_ns: config.fair_server_period_us * 1000,
+            period_ns: config.fair_server_period_us * 1000,
+        });
+        let deadline = DeadlineScheduler::with_fair_server(config.rt_bandwidth_percent, fair_server);
+        deadline.set_history_enabled(config.debug_enabled);
         
         CoreScheduler {
             // Core scheduling components
-            clock: ClockScheduler::new(),
+            clock: ClockScheduler::with_tick_frequency(config.tick_frequency),
             autogroup: AutoGroupScheduler::new(),
             completion: CompletionScheduler::new(),
             cpufreq: CpuFreqScheduler::new(),
             cpuidle: CpuIdleScheduler::new(),
-            deadline: DeadlineScheduler::with_config(config.rt_bandwidth_percent),
+            deadline,
             debug: DebugScheduler::new(),
             domains: DomainsScheduler::new(),
-            fair: FairScheduler::with_timeslice(config.default_timeslice),
+            fair: FairScheduler::with_mode(config.default_timeslice, config.fair_mode),
             idle: IdleScheduler::new(),
             isolation: IsolationScheduler::new(),
+            kthread: KthreadScheduler::new(),
             loadavg: LoadAvgScheduler::new(),
             membarrier: MembarrierScheduler::new(),
             migration: MigrationScheduler::with_config(config.load_balance.clone()),
             features: FeaturesScheduler::new(),
-            rt: RtScheduler::with_bandwidth(config.rt_bandwidth_percent),
-            stats: StatsScheduler::new(),
+            rt: RtScheduler::with_config(config.rt_bandwidth_percent, config.default_timeslice),
+            stats: StatsScheduler::with_enabled(config.schedstats_enabled),
             stop_task: StopTaskScheduler::new(),
             swait: SwaitScheduler::new(),
             wait: WaitScheduler::new(),
             pelt: PeltScheduler::new(),
             preempt: PreemptScheduler::with_enabled(config.preemption_enabled),
+            psi: RwLock::new(PSIScheduler::new()),
+            report: ReportScheduler::new(),
             topology: TopologyScheduler::new(),
+            trace: TraceScheduler::new(),
             
             // Enhanced scheduler state
             state: AtomicU64::new(SchedulerState::Uninitialized as u64),
-            config: RwLock::new(config),
+            config: RcuCell::new(config),
             global_stats: SchedulerStats::default(),
-            per_cpu_data: PerCpu::new(PerCpuSchedulerData::default()),
+            per_cpu_data: PerCpu::new(PerCpuSchedulerData {
+                history: TickHistory::new(history_len),
+                ..Default::default()
+            }),
             tick_counter: AtomicU64::new(0),
             last_balance_time: AtomicU64::new(0),
             emergency_stop: AtomicBool::new(false),
             init_timestamp: AtomicU64::new(0),
+            
+            core_cookies: RwLock::new(BTreeMap::new()),
+            core_sched_lock: SpinLock::new(()),
+            
+            cache_groups: RwLock::new(BTreeMap::new()),
+            
+            psi_held: SpinLock::new(VecDeque::new()),
+            pressure: SpinLock::new(PressureEnforcement::default()),
+            pressure_held: SpinLock::new(VecDeque::new()),
+            
+            uninterruptible: SpinLock::new(BTreeMap::new()),
+            
+            error_context: SpinLock::new(None),
+            
+            stall_callback: SpinLock::new(None),
+            
+            suspend: SpinLock::new(SuspendState::default()),
         }
     }
 
@@ -420,6 +1182,12 @@ impl CoreScheduler {
         // Initialize components in dependency order
         self.init_core_infrastructure()?;
         self.init_cpu_management()?;
+        self.domains.set_max_balance_interval(self.config.load().load_balance.balance_interval)?;
+        if self.topology.known_cpus().is_empty() {
+            kernel_warn!("No CPU topology registered, balancing all online CPUs as one domain");
+            self.topology.add_flat_cpus(&CpuMask::online());
+        }
+        self.domains.build_from_topology(&self.topology)?;
         self.init_scheduling_policies()?;
         self.init_synchronization()?;
         self.init_load_tracking()?;
@@ -435,16 +1203,342 @@ impl CoreScheduler {
         // Set state to running
         self.set_state(SchedulerState::Running);
         
-        let init_time = Timestamp::now().as_nanos() - start_time.as_nanos();
+        let init_time = Timestamp::now().as_nanos().saturating_sub(start_time.as_nanos());
         kernel_info!("Core scheduler initialized successfully in {} μs", init_time / 1000);
         
         Ok(())
     }
 
+    /// Restart scheduling from the `Error` or `Stopped` state
+    ///
+    /// Re-validates the scheduler state, rebuilds the per-CPU data from the
+    /// class runqueues and checks that every CPU's running task still
+    /// exists. If all is consistent the emergency stop is cleared and the
+    /// scheduler runs again; otherwise it stays in `Error`, with the reason
+    /// recorded in `last_error_context`.
+    ///
+    /// # Returns
+    /// - `Ok(())` once the scheduler is `Running` again
+    /// - `Err(SchedulerError::InvalidParameter)` if the scheduler is in
+    ///   neither state
+    /// - `Err(SchedulerError::NotRunning)` if the state is inconsistent
+    pub fn recover_from_error(&self) -> KernelResult<()> {
+        let state = self.get_state();
+        if !matches!(state, SchedulerState::Error | SchedulerState::Stopped) {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        kernel_info!("Recovering scheduler from {:?} state", state);
+        self.set_state(SchedulerState::Initializing);
+
+        let consistent = match self.validate_scheduler_state() {
+            Ok(()) => {
+                self.rebuild_per_cpu_data();
+                self.check_running_tasks()
+            }
+            Err(_) => Err("scheduler state failed validation"),
+        };
+        if let Err(reason) = consistent {
+            kernel_error!("Scheduler recovery failed: {}", reason);
+            let mut context = self.error_context.lock();
+            match context.as_mut() {
+                Some(context) => context.reason = reason,
+                None => *context = Some(self.capture_error_context(reason)),
+            }
+            self.set_state(SchedulerState::Error);
+            return Err(SchedulerError::NotRunning.into());
+        }
+
+        self.emergency_stop.store(false, Ordering::Release);
+        self.set_state(SchedulerState::Running);
+        kernel_info!("Scheduler recovered");
+        Ok(())
+    }
+
+    /// Suspend scheduling for system sleep
+    ///
+    /// Saves the cpufreq governor and frequency and the idle state, stops
+    /// every CPU's tick and enters `Suspended`. Every CPU parks in its idle
+    /// task on its next `schedule()`; remote CPUs are kicked to get there.
+    /// Tasks woken while suspended are held and released by `resume`.
+    ///
+    /// # Returns
+    /// - `Ok(())` once the scheduler is `Suspended`
+    /// - `Err(SchedulerError::NotRunning)` if it was not `Running`
+    pub fn suspend(&self) -> KernelResult<()> {
+        if self.get_state() != SchedulerState::Running {
+            return Err(SchedulerError::NotRunning.into());
+        }
+        let now = Timestamp::now().as_nanos();
+        {
+            let mut suspend = self.suspend.lock();
+            suspend.begin(now);
+            suspend.saved_governor = get_current_governor().ok();
+            suspend.saved_frequency = get_current_frequency().ok();
+            suspend.saved_idle_state = get_current_idle_state().ok();
+            self.set_state(SchedulerState::Suspended);
+        }
+
+        let this_cpu = current_cpu_id();
+        for cpu in CpuMask::online().iter() {
+            self.clock.program_next_tick(cpu, now, true);
+            if cpu == this_cpu {
+                self.preempt.request_reschedule()?;
+            } else {
+                send_reschedule_ipi(cpu);
+            }
+        }
+        kernel_info!("Scheduler suspended");
+        Ok(())
+    }
+
+    /// Resume scheduling after `suspend`
+    ///
+    /// Restores the saved governor, frequency and idle state, leaves the
+    /// suspended interval out of PSI, the load average and uninterruptible
+    /// sleep accounting, restarts every CPU's tick and enters `Running`.
+    /// Wakeups held while suspended are then delivered in the order they
+    /// arrived.
+    ///
+    /// # Returns
+    /// - `Ok(())` once the scheduler is `Running` again
+    /// - `Err(SchedulerError::InvalidParameter)` if it was not `Suspended`
+    pub fn resume(&self) -> KernelResult<()> {
+        let now = Timestamp::now().as_nanos();
+        let mut saved = {
+            let mut suspend = self.suspend.lock();
+            if self.get_state() != SchedulerState::Suspended {
+                return Err(SchedulerError::InvalidParameter.into());
+            }
+            self.set_state(SchedulerState::Running);
+            suspend.end()
+        };
+        let suspended_ns = now.saturating_sub(saved.since);
+
+        if let Some(governor) = saved.saved_governor {
+            if let Err(e) = set_governor(governor) {
+                kernel_warn!("Resume: failed to restore {}: {:?}", governor.as_str(), e);
+            } else if governor == Governor::Userspace {
+                if let Some(frequency) = saved.saved_frequency {
+                    if let Err(e) = set_frequency(frequency) {
+                        kernel_warn!("Resume: failed to restore {} Hz: {:?}", frequency, e);
+                    }
+                }
+            }
+        }
+        if let Some(state) = saved.saved_idle_state {
+            if let Err(e) = set_idle_state(state) {
+                kernel_warn!("Resume: failed to restore idle state {}: {:?}", state, e);
+            }
+        }
+
+        self.psi.write().account_suspend(CoreDuration::from_nanos(suspended_ns));
+        self.loadavg.account_suspend(suspended_ns);
+        for sleep in self.uninterruptible.lock().values_mut() {
+            sleep.1 = sleep.1.saturating_add(suspended_ns);
+        }
+        for cpu in CpuMask::online().iter() {
+            self.clock.restart_tick(cpu, now);
+        }
+
+        kernel_info!("Scheduler resumed after {} ns, releasing {} held wakeups",
+                    suspended_ns, saved.held.len());
+        saved.release(|id| match Task::get_by_id(id) {
+            Some(task) => self.wake_up_task(&task),
+            None => Ok(()),
+        })
+    }
+
+    /// Hold a wakeup arriving while suspended until `resume`
+    ///
+    /// Returns whether the wakeup was held. Suspend and resume switch the
+    /// held state under the suspend lock, so a wakeup racing with `resume`
+    /// is either held and released by it or finds the scheduler running
+    /// again.
+    fn hold_if_suspended(&self, task: &Task) -> bool {
+        if !self.suspend.lock().hold(task.id()) {
+            return false;
+        }
+        kernel_debug!("Holding wakeup of task {} until resume", task.id().as_u64());
+        true
+    }
+
+    /// Switch the local CPU to its idle task while suspended
+    fn park_idle(&self) -> KernelResult<()> {
+        let idle_task = self.idle.get_idle_task(current_cpu_id())?;
+        if Task::current().map_or(false, |current| current.id() == idle_task.id()) {
+            return Ok(());
+        }
+        self.execute_schedule_result(ScheduleResult::GoIdle)
+    }
+
+    /// Snapshot taken by the last emergency shutdown, if any
+    pub fn last_error_context(&self) -> Option<ErrorContext> {
+        self.error_context.lock().clone()
+    }
+
+    /// Stop scheduling on an emergency stop request
+    ///
+    /// Captures the last scheduling decision and runqueue size of every
+    /// CPU for `last_error_context` and enters the `Error` state.
+    fn emergency_shutdown(&self) -> KernelResult<()> {
+        if self.get_state() != SchedulerState::Error {
+            let context = self.capture_error_context("emergency stop requested");
+            kernel_error!("Scheduler emergency shutdown on CPU {} at tick {}", 
+                         context.cpu.as_u32(), context.tick);
+            for cpu in &context.cpus {
+                kernel_error!("  CPU {}: {:?}, current {:?}, {} runnable", cpu.cpu.as_u32(), cpu.last_decision,
+                             cpu.current_task.map(|id| id.as_u64()), cpu.runqueue_size);
+            }
+            *self.error_context.lock() = Some(context);
+            self.set_state(SchedulerState::Error);
+        }
+        Err(SchedulerError::NotRunning.into())
+    }
+
+    /// Capture the scheduling state of every online CPU
+    fn capture_error_context(&self, reason: &'static str) -> ErrorContext {
+        let cpus = CpuMask::online().iter().map(|cpu| {
+            let data = self.per_cpu_data.get(cpu);
+            CpuErrorContext {
+                cpu,
+                last_decision: data.last_decision.lock().clone(),
+                last_schedule_time: data.last_schedule_time.load(Ordering::Acquire),
+                current_task: *data.current_task.lock(),
+                runqueue_size: self.class_nr_running(cpu),
+            }
+        }).collect();
+        ErrorContext {
+            timestamp: Timestamp::now().as_nanos(),
+            cpu: current_cpu_id(),
+            tick: self.tick_counter.load(Ordering::Relaxed),
+            cpus,
+            reason,
+        }
+    }
+
+    /// Drop per-CPU state that only lives while scheduling runs
+    fn rebuild_per_cpu_data(&self) {
+        for cpu in CpuMask::online().iter() {
+            let data = self.per_cpu_data.get(cpu);
+            *data.next_task.lock() = None;
+            data.core_busy.store(data.current_task.lock().is_some(), Ordering::Release);
+            data.force_idle_start.store(0, Ordering::Release);
+            data.idle_start.store(0, Ordering::Release);
+        }
+    }
+
+    /// Number of runnable tasks on a CPU, including the running one
+    ///
+    /// Counted from the class runqueues rather than kept alongside them, so
+    /// sleeps, exits and migrations, which dequeue from the classes, lower
+    /// it without bookkeeping of their own.
+    fn class_nr_running(&self, cpu: CpuId) -> u32 {
+        (self.fair.nr_running(cpu) + self.rt.nr_running(cpu) + self.deadline.deadline_tree(cpu).len()) as u32
+    }
+
+    /// Check the scheduler's bookkeeping for consistency
+    ///
+    /// Every queued or running task must be known once, be runnable and
+    /// sit on the online CPU it is queued on; deadline reservations must
+    /// fit the deadline bandwidth limit; and no CPU's fair `min_vruntime`
+    /// may have gone backwards since the previous check. Only tasks some
+    /// runqueue knows about can be checked.
+    ///
+    /// The CPUs are not stopped, so on a live system a check can race with
+    /// scheduling and report a transient mismatch; a violation that
+    /// persists across checks is real.
+    ///
+    /// # Returns
+    /// - `Ok(())` if every invariant holds
+    /// - `Err(violations)` listing every violation found
+    pub fn verify_invariants(&self) -> Result<(), Vec<InvariantViolation>> {
+        let online = CpuMask::online();
+        let mut cpus = self.topology.known_cpus();
+        for cpu in online.iter() {
+            cpus.set(cpu);
+        }
+        let mut violations = Vec::new();
+        let mut seen: BTreeMap<TaskId, usize> = BTreeMap::new();
+
+        for cpu in cpus.iter() {
+            let data = self.per_cpu_data.get(cpu);
+            let mut tasks = self.fair.queued_tasks(cpu);
+            tasks.extend(self.rt.run_lists(cpu).into_iter().flat_map(|(_, queue)| queue));
+            tasks.extend(self.deadline.deadline_tree(cpu).into_iter().map(|(task, _)| task));
+            let running = *data.current_task.lock();
+            tasks.extend(running);
+
+            for &id in &tasks {
+                *seen.entry(id).or_default() += 1;
+                if !online.contains(cpu) {
+                    violations.push(InvariantViolation::OfflineCpu { task: id, cpu });
+                    continue;
+                }
+                let task = match Task::get_by_id(id) {
+                    Some(task) => task,
+                    None => {
+                        violations.push(InvariantViolation::MissingTask { task: id, cpu });
+                        continue;
+                    }
+                };
+                let state = task.state();
+                if Some(id) != running && !matches!(state, TaskState::Runnable | TaskState::Running) {
+                    violations.push(InvariantViolation::NotRunnable { task: id, cpu, state });
+                }
+                let current_cpu = task.current_cpu();
+                if current_cpu != cpu {
+                    violations.push(InvariantViolation::WrongCpu { task: id, queued_on: cpu, current_cpu });
+                }
+            }
+            if !online.contains(cpu) {
+                continue;
+            }
+
+            let current = self.fair.min_vruntime(cpu);
+            let previous = data.verified_min_vruntime.swap(current, Ordering::AcqRel);
+            if previous != 0 && vruntime_before(current, previous) {
+                violations.push(InvariantViolation::MinVruntimeRegressed { cpu, previous, current });
+            }
+        }
+
+        violations.extend(seen.into_iter()
+            .filter(|&(_, count)| count > 1)
+            .map(|(task, count)| InvariantViolation::MultiplyQueued { task, count }));
+
+        let reserved = self.deadline.total_bandwidth();
+        let limit = self.deadline.bandwidth_percent() as u64 * 10 * online.weight() as u64;
+        if reserved > limit {
+            violations.push(InvariantViolation::DeadlineOvercommit { reserved, limit });
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    /// Check that the task recorded as running on each CPU still exists
+    fn check_running_tasks(&self) -> Result<(), &'static str> {
+        for cpu in CpuMask::online().iter() {
+            if let Some(id) = *self.per_cpu_data.get(cpu).current_task.lock() {
+                if Task::get_by_id(id).is_none() {
+                    return Err("a CPU's running task no longer exists");
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Main scheduler entry point with enhanced error handling and metrics
     pub fn schedule(&self) -> KernelResult<()> {
         let schedule_start = Timestamp::now();
         
+        // While suspended, every CPU parks in its idle task
+        if self.get_state() == SchedulerState::Suspended {
+            return self.park_idle();
+        }
+        
         // Quick state check
         if !self.is_running() {
             return Err(SchedulerError::NotRunning.into());
@@ -459,33 +1553,257 @@ impl CoreScheduler {
         let current_tick = self.tick_counter.fetch_add(1, Ordering::Relaxed);
         self.global_stats.scheduler_ticks.fetch_add(1, Ordering::Relaxed);
 
+        // Charge runtime and load by the steal-corrected task clock
+        self.update_task_clock_accounting(current_cpu_id());
+        self.enforce_task_quotas(current_cpu_id())?;
+
         // Update scheduler subsystems
         self.update_scheduler_subsystems(current_tick)?;
+        self.update_psi()?;
+        
+        // Periodic NUMA placement evaluation of the running task
+        if self.config.load().load_balance.numa_aware {
+            if let Some(current) = self.get_current_task(current_cpu_id()) {
+                self.fair.numa_tick(&current);
+            }
+        }
         
         // Perform load balancing if needed
-        self.maybe_load_balance(current_tick)?;
+        self.maybe_load_balance()?;
         
-        // Main scheduling decision
-        let schedule_result = self.make_scheduling_decision()?;
+        // Main scheduling decision; before idling, try to pull work
+        let mut schedule_result = self.make_scheduling_decision()?;
+        if matches!(schedule_result, ScheduleResult::GoIdle) {
+            let cpu = current_cpu_id();
+            if self.migration.newidle_balance(cpu, &self.domains.domains_of(cpu), self)? {
+                schedule_result = self.make_scheduling_decision()?;
+            }
+        }
+        
+        // Remember the decision for emergency diagnostics
+        let cpu_data = self.per_cpu_data.get(current_cpu_id());
+        *cpu_data.last_decision.lock() = Some(schedule_result.clone());
+        cpu_data.last_schedule_time.store(Timestamp::now().as_nanos(), Ordering::Release);
         
         // Execute scheduling decision
         self.execute_schedule_result(schedule_result)?;
         
+        // Program (or stop) the next tick and account load
+        self.update_tick(current_cpu_id(), Timestamp::now().as_nanos());
+        
         // Update scheduling latency metrics
-        let schedule_time = Timestamp::now().as_nanos() - schedule_start.as_nanos();
-        self.update_latency_stats(schedule_time);
+        self.global_stats.record_schedule_latency(schedule_start.as_nanos(), Timestamp::now().as_nanos());
         
         Ok(())
     }
 
-    /// Enhanced scheduling decision with policy-aware selection
+    /// Run `work` on a CPU's next scheduler tick
+    ///
+    /// Work runs in the order it was queued, at the end of the subsystem
+    /// updates of the tick. A CPU whose tick is stopped runs its work when
+    /// the tick restarts.
+    ///
+    /// # Returns
+    /// - `Ok(())` if the work was queued
+    /// - `Err(SchedulerError::Busy)` if the CPU already has
+    ///   `DEFERRED_WORK_QUEUE_LEN` items queued
+    pub fn queue_deferred(&self, cpu: CpuId, work: DeferredWork) -> KernelResult<()> {
+        let mut queue = self.per_cpu_data.get(cpu).deferred.lock();
+        if queue.len() >= DEFERRED_WORK_QUEUE_LEN {
+            self.global_stats.deferred_work_overflows.fetch_add(1, Ordering::Relaxed);
+            return Err(SchedulerError::Busy.into());
+        }
+        queue.push_back(work);
+        Ok(())
+    }
+
+    /// Per-tick upkeep of the scheduler subsystems, ending with the
+    /// deferred work queued for this CPU
+    fn update_scheduler_subsystems(&self, _tick: u64) -> KernelResult<()> {
+        self.run_deferred(current_cpu_id());
+        Ok(())
+    }
+
+    /// Run the deferred work queued for a CPU
+    ///
+    /// Stops once the tick has spent `DEFERRED_WORK_BUDGET_NS` on it; the
+    /// remaining items run on the next tick, as does work queued by the
+    /// items themselves. The queue lock is not held while an item runs.
+    fn run_deferred(&self, cpu: CpuId) {
+        let queue = &self.per_cpu_data.get(cpu).deferred;
+        let start = Timestamp::now().as_nanos();
+        let mut pending = queue.lock().len();
+        while pending > 0 && Timestamp::now().as_nanos().saturating_sub(start) < DEFERRED_WORK_BUDGET_NS {
+            let work = match queue.lock().pop_front() {
+                Some(work) => work,
+                None => break,
+            };
+            pending -= 1;
+            let item_start = Timestamp::now().as_nanos();
+            (work.func)(cpu, work.data);
+            let took = Timestamp::now().as_nanos().saturating_sub(item_start);
+            if took > DEFERRED_WORK_BUDGET_NS {
+                kernel_warn!("Deferred work on CPU {} overran its budget: {} ns", cpu.as_u32(), took);
+            }
+        }
+    }
+
+    /// Scheduling decision of the local CPU, applied to its state
+    ///
+    /// Takes the decision `make_scheduling_decision_for` would take for
+    /// this CPU, then records what it rests on: a reschedule deferred by a
+    /// preempt-disabled section, a sleeper kept running by a signal, the
+    /// core scheduling state of the chosen task and preemption counts.
     fn make_scheduling_decision(&self) -> KernelResult<ScheduleResult> {
         let current_cpu = current_cpu_id();
+        let constrained = self.core_constrained(current_cpu);
+        let _core = constrained.then(|| self.core_sched_lock.lock());
+        let (result, cause) = self.decide(current_cpu, constrained, false)?;
+        let data = self.per_cpu_data.get(current_cpu);
+        match cause {
+            DecisionCause::PreemptDisabled => {
+                if let Some(current) = self.get_current_task(current_cpu) {
+                    if matches!(current.state(), TaskState::InterruptibleSleep | TaskState::UninterruptibleSleep) {
+                        self.preempt.assert_may_sleep();
+                    }
+                }
+                self.preempt.defer_reschedule();
+            }
+            DecisionCause::PendingSignal => {
+                if let Some(current) = self.get_current_task(current_cpu) {
+                    current.set_state(TaskState::Running);
+                }
+            }
+            DecisionCause::ForcedIdle => {
+                data.core_busy.store(false, Ordering::Release);
+                if !self.core_cookies.read().is_empty() {
+                    // Force idle until the siblings run a compatible task
+                    let _ = data.force_idle_start.compare_exchange(0, Timestamp::now().as_nanos(),
+                                                                   Ordering::AcqRel, Ordering::Relaxed);
+                    kernel_debug!("CPU {} forced idle by core scheduling", current_cpu.as_u32());
+                }
+            }
+            DecisionCause::Runqueues => {
+                if constrained {
+                    match self.decision_candidate(current_cpu, &result) {
+                        Some(task) => self.mark_core_busy(current_cpu, task),
+                        None => data.core_busy.store(false, Ordering::Release),
+                    }
+                }
+                if matches!(result, ScheduleResult::SwitchTo(_, SwitchReason::Preempted)) {
+                    self.global_stats.preemptions.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Scheduling decision of any CPU, coordinated with isolation and SMT
+    /// siblings
+    ///
+    /// Runs the full decision logic on the runqueues of `cpu` without
+    /// changing any state, so it can be called speculatively, e.g. by a
+    /// balancer weighing what another CPU would run, or by tests. An
+    /// isolated CPU only runs tasks pinned to isolated CPUs. With core
+    /// scheduling, a CPU may only run a task whose cookie matches the tasks
+    /// running on its siblings. If neither the class pick nor any other
+    /// queued fair task is acceptable, the CPU goes idle. Preempt-disabled
+    /// sections are only known for the local CPU.
+    pub fn make_scheduling_decision_for(&self, cpu: CpuId) -> KernelResult<ScheduleResult> {
+        let constrained = self.core_constrained(cpu);
+        let _core = constrained.then(|| self.core_sched_lock.lock());
+        Ok(self.decide(cpu, constrained, true)?.0)
+    }
+
+    /// Check whether isolation or core scheduling constrains what a CPU runs
+    fn core_constrained(&self, cpu: CpuId) -> bool {
+        !self.core_cookies.read().is_empty() || self.isolation.is_isolated(cpu)
+    }
+
+    /// Scheduling decision of a CPU and what it rests on
+    ///
+    /// `constrained` is `core_constrained(cpu)`; the caller then holds the
+    /// core scheduling lock. A `speculative` decision leaves the fair
+    /// runqueues untouched, see `should_preempt_for_fair`.
+    fn decide(&self, cpu: CpuId, constrained: bool, speculative: bool)
+              -> KernelResult<(ScheduleResult, DecisionCause)> {
+        // Never switch inside a preempt-disabled section; the final
+        // preempt_enable() performs the deferred reschedule
+        if cpu == current_cpu_id() && self.preempt.preempt_count() > 0 {
+            return Ok((ScheduleResult::KeepCurrent, DecisionCause::PreemptDisabled));
+        }
+        
+        // A signal that arrived while an interruptible sleeper was about to
+        // block keeps it running, so its wait loop sees the signal
+        if let Some(current) = self.get_current_task(cpu) {
+            if signal_interrupts(current.state()) && has_pending_signal(&current) {
+                return Ok((ScheduleResult::KeepCurrent, DecisionCause::PendingSignal));
+            }
+        }
+        
+        let result = self.pick_class_decision(cpu, speculative)?;
+        if !constrained {
+            return Ok((result, DecisionCause::Runqueues));
+        }
+
+        let candidate = match self.decision_candidate(cpu, &result) {
+            Some(task) => task,
+            None => return Ok((result, DecisionCause::Runqueues)),
+        };
+        if self.can_run_here(cpu, candidate) {
+            return Ok((result, DecisionCause::Runqueues));
+        }
+        let compatible = self.fair.queued_tasks(cpu).into_iter()
+            .find(|&task| self.can_run_here(cpu, task));
+        if let Some(task) = compatible {
+            let reason = match result {
+                ScheduleResult::SwitchTo(_, reason) => reason,
+                _ => self.switch_reason(cpu, self.get_current_task(cpu).as_deref(), SwitchReason::Preempted),
+            };
+            return Ok((ScheduleResult::SwitchTo(task, reason), DecisionCause::Runqueues));
+        }
+        Ok((ScheduleResult::GoIdle, DecisionCause::ForcedIdle))
+    }
+
+    /// Task a CPU runs after a decision, if any
+    fn decision_candidate(&self, cpu: CpuId, result: &ScheduleResult) -> Option<TaskId> {
+        match result {
+            ScheduleResult::SwitchTo(task, _) => Some(*task),
+            ScheduleResult::KeepCurrent => *self.per_cpu_data.get(cpu).current_task.lock(),
+            ScheduleResult::GoIdle | ScheduleResult::RescheduleImmediate => None,
+        }
+    }
+
+    /// Check isolation and core scheduling constraints of a task on a CPU
+    fn can_run_here(&self, cpu: CpuId, task: TaskId) -> bool {
+        let isolation_ok = Task::get_by_id(task)
+            .map(|t| self.isolation.task_allowed_on(&t.cpu_affinity(), cpu))
+            .unwrap_or(false);
+        isolation_ok && self.siblings_allow(cpu, task)
+    }
+
+    /// Enhanced scheduling decision with policy-aware selection
+    fn pick_class_decision(&self, current_cpu: CpuId, speculative: bool) -> KernelResult<ScheduleResult> {
         let current_task = self.get_current_task(current_cpu);
         
         // Check for stop tasks first (highest priority)
         if let Some(stop_task) = self.stop_task.pick_next_task(current_cpu)? {
-            return Ok(ScheduleResult::SwitchTo(stop_task.id()));
+            let reason = self.switch_reason(current_cpu, current_task.as_deref(), SwitchReason::Preempted);
+            return Ok(ScheduleResult::SwitchTo(stop_task.id(), reason));
+        }
+        
+        // The fair server is paying out the fair class's reservation: fair
+        // tasks run ahead of RT tasks
+        if self.deadline.fair_server_active(current_cpu) {
+            if let Some(current) = &current_task {
+                if current.state() == TaskState::Running && current.sched_policy().is_fair() {
+                    return Ok(ScheduleResult::KeepCurrent);
+                }
+            }
+            if let Some(fair_task) = self.fair.pick_next_task(current_cpu)? {
+                let reason = self.switch_reason(current_cpu, current_task.as_deref(), SwitchReason::Preempted);
+                return Ok(ScheduleResult::SwitchTo(fair_task.id(), reason));
+            }
         }
         
         // Handle real-time tasks (second highest priority)
@@ -493,11 +1811,11 @@ impl CoreScheduler {
             // Check if we need to preempt current task
             if let Some(current) = current_task {
                 if self.should_preempt_for_rt(&current, &rt_task)? {
-                    self.global_stats.preemptions.fetch_add(1, Ordering::Relaxed);
-                    return Ok(ScheduleResult::SwitchTo(rt_task.id()));
+                    let reason = self.switch_reason(current_cpu, Some(&current), SwitchReason::Preempted);
+                    return Ok(ScheduleResult::SwitchTo(rt_task.id(), reason));
                 }
             } else {
-                return Ok(ScheduleResult::SwitchTo(rt_task.id()));
+                return Ok(ScheduleResult::SwitchTo(rt_task.id(), SwitchReason::Voluntary));
             }
         }
         
@@ -505,31 +1823,51 @@ impl CoreScheduler {
         if let Some(dl_task) = self.deadline.pick_next_task(current_cpu)? {
             if let Some(current) = current_task {
                 if self.should_preempt_for_deadline(&current, &dl_task)? {
-                    self.global_stats.preemptions.fetch_add(1, Ordering::Relaxed);
-                    return Ok(ScheduleResult::SwitchTo(dl_task.id()));
+                    let reason = self.switch_reason(current_cpu, Some(&current), SwitchReason::Preempted);
+                    return Ok(ScheduleResult::SwitchTo(dl_task.id(), reason));
                 }
             } else {
-                return Ok(ScheduleResult::SwitchTo(dl_task.id()));
+                return Ok(ScheduleResult::SwitchTo(dl_task.id(), SwitchReason::Voluntary));
+            }
+        }
+        
+        // Idle injection forces the CPU idle over fair work, but a running
+        // RT or deadline task keeps the CPU
+        if idle_injection_active(current_cpu) {
+            let realtime = current_task.as_ref().map_or(false, |current| {
+                current.state() == TaskState::Running
+                    && (current.sched_policy().is_realtime() || self.deadline.server_of(current.id()).is_some())
+            });
+            if !realtime {
+                return Ok(ScheduleResult::GoIdle);
             }
         }
         
         // Handle fair (CFS) tasks
-        if let Some(fair_task) = self.fair.pick_next_task(current_cpu)? {
+        let fair_next = if self.pressure_action_active(PressureAction::PreferLight) {
+            self.fair.pick_light_task(current_cpu, |task| self.pelt.task_util(task))?
+        } else {
+            self.fair.pick_next_task(current_cpu)?
+        };
+        if let Some(fair_task) = fair_next {
             // Check if current task should be preempted
             if let Some(current) = current_task {
-                if self.should_preempt_for_fair(&current, &fair_task)? {
-                    return Ok(ScheduleResult::SwitchTo(fair_task.id()));
+                if let Some(displaced) = self.should_preempt_for_fair(&current, &fair_task, speculative)? {
+                    let reason = self.switch_reason(current_cpu, Some(&current), displaced);
+                    return Ok(ScheduleResult::SwitchTo(fair_task.id(), reason));
                 } else {
                     return Ok(ScheduleResult::KeepCurrent);
                 }
             } else {
-                return Ok(ScheduleResult::SwitchTo(fair_task.id()));
+                return Ok(ScheduleResult::SwitchTo(fair_task.id(), SwitchReason::Voluntary));
             }
         }
         
         // No runnable tasks - check if current task can continue
+        // (unless its group or reservation ran out of CPU bandwidth)
         if let Some(current) = current_task {
-            if current.state() == TaskState::Running {
+            if current.state() == TaskState::Running && !self.fair.curr_throttled(current_cpu)
+                && !self.deadline.curr_throttled(current_cpu) && !self.stats.quota_throttled(current.id()) {
                 return Ok(ScheduleResult::KeepCurrent);
             }
         }
@@ -538,22 +1876,126 @@ impl CoreScheduler {
         Ok(ScheduleResult::GoIdle)
     }
 
+    /// Check whether the running task should give way to a queued RT task
+    ///
+    /// A running RT task only gives way to a higher RT priority, or to a
+    /// peer after yielding; tasks of every other class always give way.
+    fn should_preempt_for_rt(&self, current: &Task, rt_task: &Task) -> KernelResult<bool> {
+        if current.state() != TaskState::Running {
+            return Ok(true);
+        }
+        Ok(match current.sched_policy() {
+            SchedPolicy::Fifo | SchedPolicy::RoundRobin => {
+                self.rt.check_preempt_curr(current.current_cpu(), rt_task.id())
+            }
+            _ => true,
+        })
+    }
+
+    /// Check whether the running task should give way to a queued fair task
+    ///
+    /// Returns why it gives way: `SliceExpired` once it used up its slice,
+    /// runtime or quota, `Preempted` for a wakeup preemption, `Voluntary`
+    /// if it stopped running. `None` keeps it running.
+    ///
+    /// The checks charge the running task's runtime and set the wakeup
+    /// buddies; a `speculative` check does both on a snapshot of the fair
+    /// runqueue only.
+    fn should_preempt_for_fair(&self, current: &Task, fair_task: &Task, speculative: bool)
+                               -> KernelResult<Option<SwitchReason>> {
+        if current.state() != TaskState::Running {
+            return Ok(Some(SwitchReason::Voluntary));
+        }
+        if self.stats.quota_throttled(current.id()) {
+            return Ok(Some(SwitchReason::SliceExpired));
+        }
+        let cpu = current.current_cpu();
+        if self.deadline.server_of(current.id()).is_some() {
+            return Ok(None);
+        }
+        Ok(match current.sched_policy() {
+            SchedPolicy::Normal | SchedPolicy::Interactive | SchedPolicy::Batch
+            | SchedPolicy::Background | SchedPolicy::Idle => {
+                let now = self.clock.rq_clock_task(cpu);
+                let tick = if speculative {
+                    self.fair.would_preempt_tick(cpu, now)
+                } else {
+                    self.fair.check_preempt_tick(cpu, now)
+                };
+                let wakeup = || if speculative {
+                    self.fair.would_preempt_wakeup(cpu, fair_task.id(), now)
+                } else {
+                    self.fair.check_preempt_wakeup(cpu, fair_task.id(), now)
+                };
+                if tick {
+                    Some(SwitchReason::SliceExpired)
+                } else if wakeup() {
+                    Some(SwitchReason::Preempted)
+                } else {
+                    None
+                }
+            }
+            SchedPolicy::Deadline if self.deadline.curr_throttled(cpu) => Some(SwitchReason::SliceExpired),
+            _ => None,
+        })
+    }
+
+    /// Reason of switching away from the current task of `cpu`
+    ///
+    /// A task that blocked or yielded, and the idle task, leave
+    /// voluntarily, and a running task that already moved to another CPU
+    /// leaves because of the migration. Any other running task is
+    /// displaced for `displaced`.
+    fn switch_reason(&self, cpu: CpuId, current: Option<&Task>, displaced: SwitchReason) -> SwitchReason {
+        let current = match current {
+            Some(current) => current,
+            None => return SwitchReason::Voluntary,
+        };
+        let is_idle = self.idle.get_idle_task(cpu).map_or(false, |idle| idle.id() == current.id());
+        if current.state() != TaskState::Running || is_idle
+            || self.per_cpu_data.get(cpu).yielded.load(Ordering::Acquire) {
+            SwitchReason::Voluntary
+        } else if current.current_cpu() != cpu {
+            SwitchReason::Migration
+        } else {
+            displaced
+        }
+    }
+
     /// Execute the scheduling decision with comprehensive error handling
     fn execute_schedule_result(&self, result: ScheduleResult) -> KernelResult<()> {
+        // A yield only applies to the decision that follows it
+        self.per_cpu_data.get(current_cpu_id()).yielded.store(false, Ordering::Release);
         match result {
             ScheduleResult::KeepCurrent => {
                 // Nothing to do, continue current task
                 Ok(())
             }
-            ScheduleResult::SwitchTo(task_id) => {
+            ScheduleResult::SwitchTo(task_id, reason) => {
                 let task = Task::get_by_id(task_id)
                     .ok_or(SchedulerError::TaskNotFound)?;
-                self.switch_to_task(&task)
+                let current_cpu = current_cpu_id();
+                let idle_start = self.per_cpu_data.get(current_cpu).idle_start.swap(0, Ordering::AcqRel);
+                if idle_start != 0 {
+                    let idle_ns = Timestamp::now().as_nanos().saturating_sub(idle_start);
+                    self.migration.update_avg_idle(current_cpu, idle_ns);
+                    self.account_idle_exit(current_cpu);
+                }
+                self.switch_to_task(&task, reason)
             }
             ScheduleResult::GoIdle => {
                 let current_cpu = current_cpu_id();
+                let data = self.per_cpu_data.get(current_cpu);
+                self.prepare_idle_state(current_cpu);
+                let entered = data.idle_start.compare_exchange(
+                    0, Timestamp::now().as_nanos(), Ordering::AcqRel, Ordering::Relaxed).is_ok();
+                if entered {
+                    let state = data.idle_state.load(Ordering::Relaxed) as u64;
+                    idle_enter_at(current_cpu, state, get_current_time_us());
+                }
+                self.pelt.decay_blocked(current_cpu, self.clock.rq_clock_task(current_cpu));
                 let idle_task = self.idle.get_idle_task(current_cpu)?;
-                self.switch_to_task(&idle_task)
+                self.switch_to_task(&idle_task, SwitchReason::IdleEntry)
             }
             ScheduleResult::RescheduleImmediate => {
                 // Trigger immediate reschedule
@@ -563,8 +2005,56 @@ impl CoreScheduler {
         }
     }
 
+    /// Charge the idle period a CPU just left
+    ///
+    /// cpuidle measures the period and charges it to the state the CPU
+    /// idled in; the scheduler's idle time adds the same amount, so the
+    /// two cannot drift apart.
+    fn account_idle_exit(&self, cpu: CpuId) {
+        let idle_us = idle_exit_at(cpu, get_current_time_us());
+        self.global_stats.account_idle(idle_us);
+        self.per_cpu_data.get(cpu).local_stats.account_idle(idle_us);
+    }
+
+    /// Time a CPU spent idle in completed idle periods
+    ///
+    /// This is the sum of the CPU's idle state usage and injected idle
+    /// time as reported by cpuidle for the periods the scheduler started.
+    pub fn idle_time(&self, cpu: CpuId) -> Duration {
+        let idle_us = self.per_cpu_data.get(cpu).local_stats.cpu_idle_time.load(Ordering::Relaxed);
+        Duration::from_nanos(idle_us.saturating_mul(1_000))
+    }
+
+    /// Select the idle state the idle task of a CPU enters
+    ///
+    /// A pending timed wakeup bounds the idle period exactly, so it is the
+    /// predicted duration; only without one does cpuidle's statistical
+    /// prediction decide.
+    fn prepare_idle_state(&self, cpu: CpuId) {
+        let predicted_us = match self.next_wakeup_deadline(cpu) {
+            Some(wakeup) => wakeup.as_nanos() / 1_000,
+            None => predict_idle_duration(cpu),
+        };
+        match select_idle_state(cpu, predicted_us) {
+            Ok(state) => self.per_cpu_data.get(cpu).idle_state.store(state as u32, Ordering::Relaxed),
+            Err(e) => kernel_debug!("CPU {} idle state selection failed: {:?}", cpu.as_u32(), e),
+        }
+    }
+
+    /// Time until the next wakeup of a CPU the scheduler knows in advance
+    ///
+    /// That is the earliest replenishment of a throttled deadline task on
+    /// the CPU, when it starts its next period. `None` if no timed wakeup
+    /// is pending; one that is already due gives a zero duration.
+    pub fn next_wakeup_deadline(&self, cpu: CpuId) -> Option<Duration> {
+        let at = self.deadline.next_replenish(cpu)?;
+        Some(Duration::from_nanos(at.saturating_sub(Timestamp::now().as_nanos())))
+    }
+
     /// Enhanced task switching with comprehensive state management
-    fn switch_to_task(&self, new_task: &Task) -> KernelResult<()> {
+    ///
+    /// `reason` is why the current task gives up the CPU.
+    fn switch_to_task(&self, new_task: &Task, reason: SwitchReason) -> KernelResult<()> {
         let switch_start = Timestamp::now();
         let current_cpu = current_cpu_id();
         
@@ -576,11 +2066,32 @@ impl CoreScheduler {
         
         // Update statistics
         self.global_stats.context_switches.fetch_add(1, Ordering::Relaxed);
+        self.per_cpu_data.get(current_cpu).local_stats.context_switches.fetch_add(1, Ordering::Relaxed);
+        self.global_stats.account_switch_reason(reason);
+        
+        // Wakeup latency of a task running for the first time since its wakeup
+        let wake_time = new_task.wake_time().as_nanos();
+        if new_task.state() == TaskState::Runnable && wake_time > new_task.last_run().as_nanos() {
+            self.stats.on_wakeup_run(new_task.id(), switch_start.as_nanos().saturating_sub(wake_time));
+        }
+        
+        // Per-policy switch accounting; a still running task is preempted
+        self.global_stats.account_switch(
+            current_task.as_ref().map(|current| (current.sched_policy(), current.state() == TaskState::Running)),
+            new_task.sched_policy());
         
         // Handle preemption logic
         if let Some(current) = current_task.as_ref() {
             self.preempt.handle_task_preemption(current)?;
+            let state = current.state();
+            if state == TaskState::Running {
+                let preempt = SchedEvent::Preempt { prev: current.id(), next: new_task.id() };
+                self.deadline.record_event(current.id(), preempt);
+                self.trace.emit(|| preempt);
+            }
+            self.trace.emit(|| SchedEvent::SwitchOut { prev: current.id(), state });
         }
+        self.trace.emit(|| SchedEvent::SwitchIn { next: new_task.id() });
         
         // Notify schedulers about the switch
         self.notify_task_switch(current_task.as_ref(), new_task)?;
@@ -596,8 +2107,7 @@ impl CoreScheduler {
         new_task.set_last_run(Timestamp::now());
         
         // Update switch latency
-        let switch_time = Timestamp::now().as_nanos() - switch_start.as_nanos();
-        self.update_switch_latency(switch_time);
+        self.global_stats.record_switch_latency(switch_start.as_nanos(), Timestamp::now().as_nanos());
         
         kernel_debug!("Task switch: {} -> {} on CPU {}", 
                      current_task.map(|t| t.id().as_u64()).unwrap_or(0),
@@ -607,147 +2117,2314 @@ impl CoreScheduler {
         Ok(())
     }
 
+    /// Tell the sub-schedulers that `prev` is being replaced by `next`
+    ///
+    /// The switch-out notifiers of `prev` always run before the switch-in
+    /// notifiers of `next`.
+    fn notify_task_switch(&self, prev: Option<&Arc<Task>>, next: &Task) -> KernelResult<()> {
+        let cpu = current_cpu_id();
+        let now = self.clock.sched_clock(cpu);
+        let now_task = self.update_rq_clock(cpu);
+        if let Some(prev) = prev {
+            self.stats.on_switch_out(prev.id(), prev.state(), now);
+            self.preempt.fire_sched_out(prev.id(), cpu);
+            self.fair.put_prev_task(cpu, prev.id(), now_task);
+            self.rt.put_prev_task(cpu, prev.id(), now_task);
+            self.deadline.put_prev_task(cpu, prev.id(), now_task);
+            self.migration.record_task_run(prev.id(), cpu, Timestamp::now().as_nanos());
+            if matches!(prev.state(), TaskState::InterruptibleSleep | TaskState::UninterruptibleSleep) {
+                // Requeued by `put_prev_task` with its runtime charged; a
+                // sleeper leaves its runqueue until it is woken
+                self.dequeue_sleeper(prev)?;
+                self.pelt.util_est_dequeue(prev.id(), cpu);
+                self.pelt.dequeue_load(prev.id(), cpu, now_task, true);
+            }
+            if prev.state() == TaskState::UninterruptibleSleep {
+                self.per_cpu_data.get(cpu).nr_uninterruptible.fetch_add(1, Ordering::Relaxed);
+                self.uninterruptible.lock().insert(prev.id(), (cpu, Timestamp::now().as_nanos()));
+            }
+        }
+        self.fair.set_curr_task(cpu, next.id(), now_task);
+        self.rt.set_curr_task(cpu, next.id(), now_task);
+        self.deadline.set_curr_task(cpu, next.id(), now_task);
+        self.membarrier.on_task_switch(cpu, next);
+        self.stats.on_switch_in(next.id(), now);
+        self.preempt.fire_sched_in(next.id(), cpu);
+        Ok(())
+    }
+
     /// Enhanced task wake up with policy-aware handling
+    ///
+    /// While suspended the wakeup is held and delivered by `resume`.
     pub fn wake_up_task(&self, task: &Task) -> KernelResult<()> {
+        if self.hold_if_suspended(task) {
+            return Ok(());
+        }
         if !self.is_running() {
             return Err(SchedulerError::NotRunning.into());
         }
-        
+        if !self.prepare_wakeup(task)? {
+            return Ok(());
+        }
+        if self.enqueue_woken(task)? {
+            self.preempt.request_reschedule()?;
+        }
+        self.account_wakeup(task);
+        Ok(())
+    }
+
+    /// Wake a task a signal was just sent to
+    ///
+    /// Only an interruptible sleeper is woken; its wait loop then returns
+    /// `SchedulerError::Interrupted`. Returns whether the task was woken.
+    pub fn signal_wake_up(&self, task: &Task) -> KernelResult<bool> {
+        if !signal_interrupts(task.state()) || !has_pending_signal(task) {
+            return Ok(false);
+        }
+        self.wake_up_task(task)?;
+        Ok(true)
+    }
+
+    /// Wake several tasks at once, e.g. every waiter of a barrier
+    ///
+    /// Wakeups are grouped by the CPU each task is placed on: a CPU's fair
+    /// tasks, and its RT tasks, are enqueued under one runqueue lock each,
+    /// and the CPU gets at most one reschedule IPI. Tasks that fail to
+    /// wake are skipped. While suspended, wakeups are held as with
+    /// `wake_up_task`.
+    ///
+    /// Returns the number of tasks enqueued. Wakeups that are held, while
+    /// suspended or under pressure, and tasks that stay throttled by their
+    /// CPU quota are not counted.
+    pub fn wake_up_tasks(&self, tasks: &[&Task]) -> KernelResult<usize> {
+        let total = tasks.len();
+        let tasks: Vec<&Task> = tasks.iter().copied().filter(|task| !self.hold_if_suspended(task)).collect();
+        if tasks.is_empty() && total > 0 {
+            return Ok(0);
+        }
+        if !self.is_running() {
+            return Err(SchedulerError::NotRunning.into());
+        }
+        let mut batch = WakeBatch::new();
+        for &task in &tasks {
+            match self.prepare_wakeup(task) {
+                Ok(true) => batch.add(task.current_cpu(), task),
+                Ok(false) => {}
+                Err(_) => kernel_warn!("Failed to wake task {}", task.id().as_u64()),
+            }
+        }
+
+        let this_cpu = current_cpu_id();
+        let woken = batch.flush(|cpu, tasks| self.enqueue_woken_on(cpu, tasks), |cpu| {
+            if cpu == this_cpu {
+                let _ = self.preempt.request_reschedule();
+            } else {
+                send_reschedule_ipi(cpu);
+            }
+        });
+        Ok(woken)
+    }
+
+    /// Make a waking task runnable and place it on a CPU
+    ///
+    /// Returns whether it must be enqueued now: a task held back under PSI
+    /// pressure, or throttled by its CPU quota, is enqueued later.
+    fn prepare_wakeup(&self, task: &Task) -> KernelResult<bool> {
         kernel_debug!("Waking up task {} with policy {:?}", 
                      task.id().as_u64(), task.sched_policy());
         
+        // Under PSI pressure, hold back new background work until it eases
+        let hint = self.psi_hint();
+        let limit_new = matches!(hint, SchedulingHint::LimitNewTasks | SchedulingHint::ReduceLoad);
+        let background = matches!(task.sched_policy(), SchedPolicy::Batch | SchedPolicy::Background);
+        if limit_new && background && task.last_run().as_nanos() == 0 {
+            self.psi_held.lock().push_back(task.id());
+            self.global_stats.psi_deferred_tasks.fetch_add(1, Ordering::Relaxed);
+            kernel_debug!("Deferring new task {} under PSI pressure", task.id().as_u64());
+            return Ok(false);
+        }
+        if matches!(task.sched_policy(), SchedPolicy::Background | SchedPolicy::Idle)
+            && self.pressure_action_active(PressureAction::HoldBackground) {
+            self.pressure_held.lock().push_back(task.id());
+            kernel_debug!("Holding background task {} until pressure drops", task.id().as_u64());
+            return Ok(false);
+        }
+        
+        self.account_uninterruptible_wakeup(task);
+        
+        // A task throttled by its CPU quota is enqueued when its window ends
+        if self.stats.quota_throttled(task.id()) {
+            task.set_state(TaskState::Runnable);
+            return Ok(false);
+        }
+        
         // Update task state
         task.set_state(TaskState::Runnable);
         task.set_wake_time(Timestamp::now());
         
-        // Enqueue in appropriate scheduler
-        match task.sched_policy() {
+        // A task that moved recently stays on its CPU while that is allowed;
+        // the move is queued for the target to pull when it goes idle
+        let target_cpu = self.select_task_rq(task);
+        let prev_cpu = task.current_cpu();
+        if target_cpu != prev_cpu {
+            if task.cpu_affinity().contains(prev_cpu) && self.migration_throttled(task) {
+                self.migration.defer_migration(task.id(), target_cpu);
+            } else {
+                self.move_task_to(task, target_cpu)?;
+            }
+        }
+        
+        // A task waking from I/O wait boosts its CPU's frequency
+        if task.in_iowait() {
+            io_boost(task.current_cpu());
+        }
+        Ok(true)
+    }
+
+    /// End the uninterruptible sleep of a waking task, if it was in one
+    ///
+    /// The task stops counting toward the load of the CPU it slept on, and
+    /// its sleep is accounted to PSI as an io stall if it waited for I/O,
+    /// otherwise as a memory stall.
+    fn account_uninterruptible_wakeup(&self, task: &Task) {
+        let (cpu, start) = match self.uninterruptible.lock().remove(&task.id()) {
+            Some(sleep) => sleep,
+            None => return,
+        };
+        let _ = self.per_cpu_data.get(cpu).nr_uninterruptible
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1));
+        let slept = CoreDuration::from_nanos(Timestamp::now().as_nanos().saturating_sub(start));
+        self.psi.write().record_uninterruptible_sleep(task.id(), task.in_iowait(), slept);
+    }
+
+    /// Enqueue a woken task in its scheduling class
+    ///
+    /// Returns whether it should preempt the running task of its CPU.
+    fn enqueue_woken(&self, task: &Task) -> KernelResult<bool> {
+        let server = self.deadline.server_of(task.id());
+        Ok(match task.sched_policy() {
+            _ if server.is_some() => {
+                self.deadline.enqueue_task(task)?;
+                self.deadline.should_preempt_current(task)?
+            }
             SchedPolicy::Normal | SchedPolicy::Interactive => {
-                self.fair.enqueue_task(task)?;
+                self.fair.enqueue_task(task, self.fair_group(task.id()))?;
+                let now_task = self.update_rq_clock(task.current_cpu());
+                self.fair.check_preempt_wakeup(task.current_cpu(), task.id(), now_task)
             }
             SchedPolicy::Batch | SchedPolicy::Background => {
-                self.fair.enqueue_task_batch(task)?;
+                self.fair.enqueue_task_batch(task, self.fair_group(task.id()))?;
+                false
             }
             SchedPolicy::Fifo | SchedPolicy::RoundRobin => {
                 self.rt.enqueue_task(task)?;
                 // RT tasks may need immediate preemption
-                if self.rt.should_preempt_current(task)? {
-                    self.preempt.request_reschedule()?;
-                }
+                self.rt.should_preempt_current(task)?
             }
             SchedPolicy::Deadline => {
                 self.deadline.enqueue_task(task)?;
                 // Deadline tasks may need immediate preemption
-                if self.deadline.should_preempt_current(task)? {
-                    self.preempt.request_reschedule()?;
-                }
+                self.deadline.should_preempt_current(task)?
             }
             SchedPolicy::Idle => {
-                self.idle.enqueue_task(task)?;
+                self.fair.enqueue_task_idle(task, self.fair_group(task.id()))?;
+                let now_task = self.update_rq_clock(task.current_cpu());
+                self.fair.check_preempt_wakeup(task.current_cpu(), task.id(), now_task)
+            }
+        })
+    }
+
+    /// Take a task that went to sleep off the runqueue of its class
+    fn dequeue_sleeper(&self, task: &Task) -> KernelResult<()> {
+        if self.deadline.server_of(task.id()).is_some() {
+            return self.deadline.dequeue_task(task);
+        }
+        match task.sched_policy() {
+            SchedPolicy::Fifo | SchedPolicy::RoundRobin => self.rt.dequeue_task(task),
+            SchedPolicy::Deadline => self.deadline.dequeue_task(task),
+            _ => self.fair.dequeue_task(task),
+        }
+    }
+
+    /// Enqueue woken tasks placed on one CPU
+    ///
+    /// Fair and RT tasks are enqueued under one runqueue lock per class;
+    /// if a batch is refused its tasks are enqueued one at a time. Returns
+    /// how many tasks were enqueued and whether the CPU must reschedule.
+    fn enqueue_woken_on(&self, cpu: CpuId, tasks: &[&Task]) -> (usize, bool) {
+        let mut fair = Vec::new();
+        let mut rt = Vec::new();
+        let mut single = Vec::new();
+        for &task in tasks {
+            match task.sched_policy() {
+                _ if self.deadline.server_of(task.id()).is_some() => single.push(task),
+                SchedPolicy::Normal | SchedPolicy::Interactive => {
+                    fair.push((task, self.fair_group(task.id()), false));
+                }
+                SchedPolicy::Batch | SchedPolicy::Background => {
+                    fair.push((task, self.fair_group(task.id()), true));
+                }
+                SchedPolicy::Fifo | SchedPolicy::RoundRobin => rt.push(task),
+                SchedPolicy::Deadline | SchedPolicy::Idle => single.push(task),
+            }
+        }
+
+        let mut enqueued = Vec::with_capacity(tasks.len());
+        let mut resched = false;
+        if !fair.is_empty() {
+            let now_task = self.update_rq_clock(cpu);
+            match self.fair.enqueue_tasks(cpu, &fair, now_task) {
+                Ok(preempt) => {
+                    resched |= preempt;
+                    enqueued.extend(fair.iter().map(|&(task, _, _)| task));
+                }
+                Err(_) => single.extend(fair.iter().map(|&(task, _, _)| task)),
+            }
+        }
+        if !rt.is_empty() {
+            match self.rt.enqueue_tasks(cpu, &rt) {
+                Ok(preempt) => {
+                    resched |= preempt;
+                    enqueued.extend_from_slice(&rt);
+                }
+                Err(_) => single.extend_from_slice(&rt),
             }
         }
+        for task in single {
+            match self.enqueue_woken(task) {
+                Ok(preempt) => {
+                    resched |= preempt;
+                    enqueued.push(task);
+                }
+                Err(_) => kernel_warn!("Failed to wake task {}", task.id().as_u64()),
+            }
+        }
+
+        for &task in &enqueued {
+            self.account_wakeup(task);
+        }
+        (enqueued.len(), resched)
+    }
+
+    /// Account a woken task that was enqueued
+    fn account_wakeup(&self, task: &Task) {
+        self.trace.emit(|| SchedEvent::Wakeup { task: task.id(), target_cpu: task.current_cpu() });
+        self.pelt.util_est_enqueue(task.id(), task.current_cpu());
+        self.pelt.enqueue_load(task.id(), task.current_cpu(), self.clock.rq_clock_task(task.current_cpu()));
+        self.stats.on_enqueue(task.id(), self.clock.sched_clock(current_cpu_id()));
+        
+        // A second runnable task needs the tick to share the CPU
+        let target_cpu = task.current_cpu();
+        if self.class_nr_running(target_cpu) >= 2 {
+            self.clock.restart_tick(target_cpu, Timestamp::now().as_nanos());
+        }
         
         // Update statistics
         self.update_wakeup_stats(task);
-        
+    }
+
+    /// Give up the CPU to the other runnable tasks of the current task's class
+    ///
+    /// A fair task moves behind the queued tasks of its group, a `Fifo` or
+    /// `RoundRobin` task to the tail of its priority's run list. Deadline
+    /// and idle tasks, and tasks of a CBS server, only reschedule. A task
+    /// with no peer to yield to keeps running. With `YIELD_RESCHEDULE`
+    /// disabled the task is only requeued and keeps the CPU until the next
+    /// preemption point.
+    pub fn task_yield(&self) -> KernelResult<()> {
+        if !self.is_running() {
+            return Err(SchedulerError::NotRunning.into());
+        }
+        let current = Task::current().ok_or(SchedulerError::TaskNotFound)?;
+        if self.yield_current(&current) {
+            self.per_cpu_data.get(current.current_cpu()).yielded.store(true, Ordering::Release);
+            if sched_feat(SchedFeature::YieldReschedule) {
+                self.preempt.request_reschedule()?;
+            }
+        }
         Ok(())
     }
 
-    /// Intelligent load balancing with NUMA awareness
-    pub fn load_balance(&self) -> KernelResult<()> {
+    /// Yield the CPU in favour of `target`
+    ///
+    /// The current task yields as with `task_yield`. If both tasks are
+    /// fair tasks on the same CPU, `target` becomes the next pick and gets
+    /// the rest of the current task's slice; if both are RT tasks of the
+    /// same priority on the same CPU, `target` moves to the head of the run
+    /// list. Returns whether `target` was boosted.
+    pub fn yield_to(&self, target: &Task) -> KernelResult<bool> {
         if !self.is_running() {
-            return Ok(());
+            return Err(SchedulerError::NotRunning.into());
         }
+        let current = Task::current().ok_or(SchedulerError::TaskNotFound)?;
+        let cpu = current.current_cpu();
+        let fair = |task: &Task| self.deadline.server_of(task.id()).is_none() && task.sched_policy().is_fair();
+        let rt = |task: &Task| matches!(task.sched_policy(), SchedPolicy::Fifo | SchedPolicy::RoundRobin);
 
-        let balance_start = Timestamp::now();
-        self.global_stats.load_balance_calls.fetch_add(1, Ordering::Relaxed);
-        
-        kernel_debug!("Starting load balance operation");
-        
-        // Get load balancing configuration
-        let config = self.config.read().load_balance.clone();
-        
-        // Check if enough time has passed since last balance
-        let current_time = balance_start.as_nanos();
-        let last_balance = self.last_balance_time.load(Ordering::Acquire);
-        let balance_interval_ns = config.balance_interval * 1_000_000; // Convert to nanoseconds
-        
-        if current_time - last_balance < balance_interval_ns {
-            return Ok(()); // Too soon for another balance
+        let (boosted, yielded) = if target.id() == current.id() || target.current_cpu() != cpu {
+            (false, self.yield_current(&current))
+        } else if fair(&current) && fair(target) {
+            let now_task = self.update_rq_clock(cpu);
+            (self.fair.yield_to(cpu, target.id(), now_task), true)
+        } else if rt(&current) && rt(target) {
+            (self.rt.yield_to(cpu, current.id(), target.id()), true)
+        } else {
+            (false, self.yield_current(&current))
+        };
+        if yielded {
+            self.per_cpu_data.get(cpu).yielded.store(true, Ordering::Release);
+            if sched_feat(SchedFeature::YieldReschedule) {
+                self.preempt.request_reschedule()?;
+            }
         }
-        
-        // Perform the load balancing
-        let migrations = self.migration.balance_load_intelligent(&config)?;
-        
-        // Update statistics
-        self.global_stats.migrations.fetch_add(migrations as u64, Ordering::Relaxed);
-        self.last_balance_time.store(current_time, Ordering::Release);
-        
-        let balance_time = Timestamp::now().as_nanos() - balance_start.as_nanos();
-        kernel_debug!("Load balance completed: {} migrations in {} μs", 
-                     migrations, balance_time / 1000);
-        
-        Ok(())
+        Ok(boosted)
     }
 
-    /// Migrate task with comprehensive validation and state management
-    pub fn migrate_task(&self, task: &Task, target_cpu: CpuId) -> KernelResult<()> {
-        // Validate migration is possible
-        if !task.can_migrate_to(target_cpu)? {
-            return Err(SchedulerError::MigrationNotAllowed.into());
+    /// Move the current task behind its runnable peers
+    ///
+    /// Returns whether the CPU should reschedule: false for a fair or RT
+    /// task that has no peer to yield to.
+    fn yield_current(&self, current: &Task) -> bool {
+        let cpu = current.current_cpu();
+        if self.deadline.server_of(current.id()).is_some() {
+            return true;
         }
-        
-        // Check CPU affinity
-        if !task.cpu_affinity().contains(target_cpu) {
-            return Err(SchedulerError::AffinityViolation.into());
+        match current.sched_policy() {
+            SchedPolicy::Normal | SchedPolicy::Interactive | SchedPolicy::Batch
+            | SchedPolicy::Background | SchedPolicy::Idle => {
+                let now_task = self.update_rq_clock(cpu);
+                self.fair.yield_task(cpu, now_task)
+            }
+            SchedPolicy::Fifo | SchedPolicy::RoundRobin => {
+                let now_task = self.update_rq_clock(cpu);
+                self.rt.yield_task(cpu, current.id(), now_task)
+            }
+            SchedPolicy::Deadline => true,
         }
-        
-        kernel_debug!("Migrating task {} from CPU {} to CPU {}", 
-                     task.id().as_u64(), task.current_cpu().as_u32(), target_cpu.as_u32());
-        
-        // Perform migration
-        let result = self.migration.migrate_task_safe(task, target_cpu);
-        
-        if result.is_ok() {
-            self.global_stats.migrations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Choose the CPU a waking task is enqueued on
+    ///
+    /// Tasks of a CBS server stay on its CPU. Under critical pressure,
+    /// fair tasks are packed onto busy CPUs so that the others can idle.
+    /// A task that does not fit its previous CPU goes where its estimated
+    /// utilization fits, and a NUMA-aware scheduler prefers the node its
+    /// memory lives on, or else its waker's node while its cache is still
+    /// hot. Otherwise the configured `WakeupStrategy` decides. A CPU the
+    /// task's affinity or cpuset excludes is never chosen while an allowed
+    /// one is online.
+    pub fn select_task_rq(&self, task: &Task) -> CpuId {
+        let prev_cpu = task.current_cpu();
+        let util_est = self.pelt.task_util_est(task);
+        let fair_policy = task.sched_policy().is_fair();
+        let sched_idle = task.sched_policy() == SchedPolicy::Idle;
+        let (numa_aware, strategy) = {
+            let config = self.config.load();
+            (config.load_balance.numa_aware, config.wakeup_strategy)
+        };
+
+        let mut target_cpu = None;
+        if let Some(server) = self.deadline.server_of(task.id()) {
+            target_cpu = Some(self.deadline.server_cpu(server).unwrap_or(prev_cpu));
+        } else if self.psi_hint() == SchedulingHint::ReduceLoad && fair_policy {
+            target_cpu = self.find_busy_fitting_cpu(task, util_est);
+        } else if !self.pelt.task_fits_cpu(util_est, prev_cpu) {
+            target_cpu = self.find_fitting_cpu(task, util_est);
+        } else if numa_aware {
+            let node = match self.fair.preferred_node(task.id()) {
+                Some(node) => Some(node),
+                None if self.is_task_cache_hot(task) => Some(self.topology.node_of_cpu(current_cpu_id())),
+                None => None,
+            };
+            target_cpu = node.and_then(|node| self.select_node_local_cpu(task, node, util_est));
         }
-        
-        result
+        if target_cpu.is_none() {
+            target_cpu = self.select_cache_group_cpu(task, util_est);
+        }
+
+        let affinity = task.cpu_affinity();
+        let mut target_cpu = target_cpu.unwrap_or_else(|| {
+            let mut allowed = CpuMask::empty();
+            for cpu in affinity.iter().filter(|&cpu| self.isolation.task_allowed_on(&affinity, cpu)) {
+                allowed.set(cpu);
+            }
+            let thermal = strategy == WakeupStrategy::SpreadThermal;
+            // A CPU running only SCHED_IDLE tasks is as good as idle for
+            // any other task
+            self.topology.select_wakeup_cpu(strategy, &allowed, prev_cpu, current_cpu_id(), |cpu| CpuLoad {
+                nr_running: if !sched_idle && self.fair.sched_idle_cpu(cpu) {
+                    0
+                } else {
+                    self.class_nr_running(cpu)
+                },
+                fits: self.pelt.task_fits_cpu(util_est, cpu),
+                idle_time: if thermal { get_idle_statistics_for(cpu).total_idle_time } else { 0 },
+                temperature: if thermal { get_cpu_temperature(cpu) } else { None },
+            })
+        });
+        if !self.isolation.task_allowed_on(&affinity, target_cpu) {
+            if let Some(cpu) = self.find_housekeeping_cpu(task) {
+                target_cpu = cpu;
+            }
+        }
+        // The affinity may have changed since the placement was decided
+        if !task.cpu_affinity().contains(target_cpu) || !self.isolation.cpuset_allows_cpu(task.id(), target_cpu) {
+            if let Some(cpu) = self.find_allowed_cpu(task) {
+                target_cpu = cpu;
+            }
+        }
+        target_cpu
     }
 
-    /// Enhanced scheduler debugging with detailed information
-    pub fn debug_info(&self) -> KernelResult<()> {
-        if !self.config.read().debug_enabled {
+    /// Place a task that just exec'd before its new image runs
+    ///
+    /// The new image has no cache footprint worth keeping, which makes exec
+    /// the cheapest moment to move a task: it goes to the least loaded
+    /// allowed CPU of the least loaded node and package, ignoring cache
+    /// affinity, regardless of when it last migrated. Tasks of a CBS server
+    /// stay on its CPU.
+    pub fn balance_on_exec(&self, task: &Task) -> KernelResult<()> {
+        if self.deadline.server_of(task.id()).is_some() {
+            return Ok(());
+        }
+        let util_est = self.pelt.task_util_est(task);
+        let affinity = task.cpu_affinity();
+        let online = CpuMask::online();
+        let mut allowed = CpuMask::empty();
+        for cpu in affinity.iter()
+            .filter(|&cpu| online.contains(cpu))
+            .filter(|&cpu| self.isolation.cpuset_allows_cpu(task.id(), cpu))
+            .filter(|&cpu| self.isolation.task_allowed_on(&affinity, cpu)) {
+            allowed.set(cpu);
+        }
+
+        let prev_cpu = task.current_cpu();
+        let target_cpu = self.topology.select_exec_cpu(&allowed, prev_cpu, |cpu| CpuLoad {
+            nr_running: self.class_nr_running(cpu),
+            fits: self.pelt.task_fits_cpu(util_est, cpu),
+            ..Default::default()
+        });
+        if target_cpu == prev_cpu {
+            return Ok(());
+        }
+        kernel_debug!("Exec balance: task {} CPU {} -> {}",
+                     task.id().as_u64(), prev_cpu.as_u32(), target_cpu.as_u32());
+        self.move_task_to(task, target_cpu)
+    }
+
+    /// Current PSI scheduling hint, `Normal` when PSI steering is disabled
+    fn psi_hint(&self) -> SchedulingHint {
+        if !self.config.load().psi_aware {
+            return SchedulingHint::Normal;
+        }
+        self.psi.read().get_scheduling_hint()
+    }
+
+    /// Refresh PSI metrics, enforce the resulting hint and release held
+    /// tasks once pressure eases
+    ///
+    /// Metrics, and the hint with them, only change once per PSI
+    /// `update_interval`; ticks in between return after a check under the
+    /// read lock.
+    fn update_psi(&self) -> KernelResult<()> {
+        if self.config.load().psi_aware {
+            if !self.psi.read().update_due() {
+                return Ok(());
+            }
+            self.psi.write().update_metrics();
+        }
+        let hint = self.psi_hint();
+        self.apply_pressure_hint(hint)?;
+        if matches!(hint, SchedulingHint::LimitNewTasks | SchedulingHint::ReduceLoad) {
             return Ok(());
         }
         
-        kernel_info!("=== Scheduler Debug Information ===");
-        kernel_info!("State: {:?}", self.get_state());
-        kernel_info!("Uptime: {} ticks", self.uptime_ticks());
-        
-        // Global statistics
-        let stats = &self.global_stats;
-        kernel_info!("Context switches: {}", stats.context_switches.load(Ordering::Relaxed));
-        kernel_info!("Preemptions: {}", stats.preemptions.load(Ordering::Relaxed));
-        kernel_info!("Migrations: {}", stats.migrations.load(Ordering::Relaxed));
-        kernel_info!("Load balance calls: {}", stats.load_balance_calls.load(Ordering::Relaxed));
-        kernel_info!("Schedule failures: {}", stats.schedule_failures.load(Ordering::Relaxed));
-        kernel_info!("RT throttled: {}", stats.rt_throttled.load(Ordering::Relaxed));
-        kernel_info!("Deadline misses: {}", stats.deadline_misses.load(Ordering::Relaxed));
+        let held: Vec<TaskId> = self.psi_held.lock().drain(..).collect();
+        for id in held {
+            if let Some(task) = Task::get_by_id(id) {
+                self.wake_up_task(&task)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Enforce a PSI scheduling hint with the enabled pressure actions
+    ///
+    /// Under `ReduceLoad`, waking `Background` and `Idle` tasks are held
+    /// back and cpufreq runs the powersave governor; from
+    /// `PreferLightTasks` on, fair picks favor low-utilization tasks.
+    /// Actions the hint no longer calls for are reverted: held tasks are
+    /// woken and the previous governor is restored.
+    pub fn apply_pressure_hint(&self, hint: SchedulingHint) -> KernelResult<()> {
+        let enabled = self.config.load().pressure_actions;
+        let reduce = hint == SchedulingHint::ReduceLoad;
+        let wanted = [
+            reduce && enabled.hold_background,
+            reduce && enabled.powersave,
+            hint != SchedulingHint::Normal && enabled.prefer_light,
+        ];
+
+        let now = Timestamp::now().as_nanos();
+        let mut release_held = false;
+        {
+            let mut pressure = self.pressure.lock();
+            for action in PressureAction::ALL {
+                let i = action as usize;
+                if wanted[i] == (pressure.since[i] != 0) {
+                    continue;
+                }
+                if wanted[i] {
+                    pressure.since[i] = now;
+                    if action == PressureAction::Powersave {
+                        pressure.saved_governor = get_current_governor().ok();
+                        if let Err(e) = set_governor(Governor::Powersave) {
+                            kernel_warn!("Pressure: failed to switch to powersave: {:?}", e);
+                        }
+                    }
+                } else {
+                    pressure.total[i] += now.saturating_sub(pressure.since[i]);
+                    pressure.since[i] = 0;
+                    match action {
+                        PressureAction::HoldBackground => release_held = true,
+                        PressureAction::Powersave => {
+                            if let Some(governor) = pressure.saved_governor.take() {
+                                if let Err(e) = set_governor(governor) {
+                                    kernel_warn!("Pressure: failed to restore {}: {:?}", governor.as_str(), e);
+                                }
+                            }
+                        }
+                        PressureAction::PreferLight => {}
+                    }
+                }
+                kernel_info!("Pressure action {:?} {}", action, if wanted[i] { "engaged" } else { "released" });
+            }
+        }
+
+        if release_held {
+            let held: Vec<TaskId> = self.pressure_held.lock().drain(..).collect();
+            for id in held {
+                if let Some(task) = Task::get_by_id(id) {
+                    self.wake_up_task(&task)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check whether a pressure action is being enforced
+    pub fn pressure_action_active(&self, action: PressureAction) -> bool {
+        self.pressure.lock().since[action as usize] != 0
+    }
+
+    /// Total time a pressure action was enforced, the current activation
+    /// included
+    pub fn pressure_action_time(&self, action: PressureAction) -> Duration {
+        let pressure = self.pressure.lock();
+        let i = action as usize;
+        let current = match pressure.since[i] {
+            0 => 0,
+            since => Timestamp::now().as_nanos().saturating_sub(since),
+        };
+        Duration::from_nanos(pressure.total[i] + current)
+    }
+
+    /// Choose which pressure actions enforce PSI scheduling hints
+    ///
+    /// Disabled actions are reverted on the next PSI update.
+    pub fn set_pressure_actions(&self, actions: PressureActions) {
+        self.update_config(|config| config.pressure_actions = actions);
+    }
+
+    /// Throttle the running task once it exceeds its CPU quota and restore
+    /// throttled tasks whose quota window ended
+    fn enforce_task_quotas(&self, cpu: CpuId) -> KernelResult<()> {
+        let now = self.clock.sched_clock(cpu);
+        for id in self.stats.expire_quota_windows(now) {
+            if let Some(task) = Task::get_by_id(id) {
+                if matches!(task.state(), TaskState::Runnable | TaskState::Running) {
+                    if self.enqueue_woken(&task)? {
+                        if task.current_cpu() == cpu {
+                            self.preempt.request_reschedule()?;
+                        } else {
+                            send_reschedule_ipi(task.current_cpu());
+                        }
+                    }
+                    self.account_wakeup(&task);
+                }
+            }
+        }
+        
+        if let Some(current) = self.get_current_task(cpu) {
+            if self.stats.quota_tick(current.id(), now) {
+                kernel_debug!("Task {} throttled by its CPU quota", current.id().as_u64());
+                // Charged up to now as on a switch, then off the runqueue
+                // until its quota window ends
+                let now_task = self.update_rq_clock(cpu);
+                self.fair.put_prev_task(cpu, current.id(), now_task);
+                self.fair.dequeue_task(&current)?;
+                self.pelt.util_est_dequeue(current.id(), cpu);
+                self.pelt.dequeue_load(current.id(), cpu, now_task, false);
+                self.preempt.request_reschedule()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Enqueue a fair task in its group, as a batch or `SCHED_IDLE` task if
+    /// its policy says so
+    fn enqueue_fair(&self, task: &Task) -> KernelResult<()> {
+        match task.sched_policy() {
+            SchedPolicy::Batch | SchedPolicy::Background => {
+                self.fair.enqueue_task_batch(task, self.fair_group(task.id()))
+            }
+            SchedPolicy::Idle => self.fair.enqueue_task_idle(task, self.fair_group(task.id())),
+            _ => self.fair.enqueue_task(task, self.fair_group(task.id())),
+        }
+    }
+
+    /// Advance a CPU's runqueue clock, excluding hypervisor steal time
+    fn update_rq_clock(&self, cpu: CpuId) -> u64 {
+        self.clock.update_rq_clock(cpu, steal_clock(cpu))
+    }
+
+    /// Charge the running task's runtime and update PELT by `rq_clock_task`
+    fn update_task_clock_accounting(&self, cpu: CpuId) {
+        let now_task = self.update_rq_clock(cpu);
+        self.fair.task_tick(cpu, now_task);
+        let misses = self.deadline.task_tick(cpu, now_task);
+        self.global_stats.deadline_misses.fetch_add(misses as u64, Ordering::Relaxed);
+        let rt_tick = self.rt.task_tick(cpu, now_task);
+        if rt_tick.stalled {
+            self.global_stats.rt_stalls.fetch_add(1, Ordering::Relaxed);
+        }
+        if rt_tick.resched {
+            let _ = self.preempt.request_reschedule();
+        }
+
+        let current = self.get_current_task(cpu);
+        let running = current.as_ref().map_or(false, |t| t.state() == TaskState::Running);
+        let fair_running = running && current.as_ref().map_or(false, |t| t.sched_policy().is_fair());
+        let fair_runnable = fair_running || self.fair.nr_running(cpu) > 0;
+        if self.deadline.fair_server_tick(cpu, now_task, fair_running, fair_runnable) {
+            let _ = self.preempt.request_reschedule();
+        }
+        if let Some(current) = &current {
+            let weight = match current.sched_policy() {
+                SchedPolicy::Idle => WEIGHT_IDLEPRIO as u64,
+                _ => nice_to_weight(current.nice()) as u64,
+            };
+            self.pelt.update_task_load(current.id(), cpu, now_task, weight, true, running);
+        }
+        self.pelt.update_cpu_load(cpu, now_task, self.fair.load_weight(cpu), running);
+    }
+
+    /// End-of-tick processing: tick programming and load accounting
+    ///
+    /// A `nohz_full` CPU running a single task stops its tick. Housekeeping
+    /// CPUs fold the active task counts of stopped CPUs remotely so that
+    /// the load average does not miss them, sample the load average, run
+    /// the CFS bandwidth period timers and let schedutil rescale the
+    /// frequency domains. Every CPU records its runqueue length and
+    /// utilization in its tick history and hands its current frequency
+    /// scale to PELT.
+    fn update_tick(&self, cpu: CpuId, now: u64) {
+        let cpu_data = self.per_cpu_data.get(cpu);
+        self.sync_freq_scale(cpu);
+        let nr_running = self.class_nr_running(cpu);
+        let capacity = self.topology.cpu_capacity(cpu).max(1) as u64;
+        let util = (self.pelt.cpu_util(cpu) as u64 * 1000 / capacity).min(1000) as u32;
+        cpu_data.cpu_utilization.store(util, Ordering::Relaxed);
+        cpu_data.history.record(nr_running, util);
+        self.loadavg.calc_load_fold(cpu, nr_running, cpu_data.nr_uninterruptible.load(Ordering::Relaxed));
+
+        let nohz_full = self.isolation.is_nohz_full(cpu);
+        if !nohz_full {
+            for remote in self.isolation.nohz_full_mask().iter() {
+                if self.clock.is_tick_stopped(remote) {
+                    let remote_data = self.per_cpu_data.get(remote);
+                    self.loadavg.calc_load_fold(remote, self.class_nr_running(remote),
+                                                remote_data.nr_uninterruptible.load(Ordering::Relaxed));
+                }
+            }
+            self.loadavg.calc_global_load(now);
+            for unthrottled in self.fair.do_bandwidth_timers(now) {
+                send_reschedule_ipi(unthrottled);
+            }
+            match schedutil_update(|cpu| self.pelt.cpu_util_est(cpu)) {
+                Ok(changes) if !changes.is_empty() => {
+                    for cpu in CpuMask::online().iter() {
+                        self.sync_freq_scale(cpu);
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => kernel_debug!("Schedutil update failed: {:?}", e),
+            }
+        }
+
+        self.clock.program_next_tick(cpu, now, nohz_full && nr_running == 1);
+    }
+
+    /// Make PELT scale a CPU's utilization by its current frequency
+    fn sync_freq_scale(&self, cpu: CpuId) {
+        if let Err(e) = self.pelt.set_freq_scale(cpu, frequency_scale(cpu)) {
+            kernel_debug!("CPU {} frequency scale not updated: {:?}", cpu.as_u32(), e);
+        }
+    }
+
+    /// Check whether a task ran recently enough to still have a warm cache
+    fn is_task_cache_hot(&self, task: &Task) -> bool {
+        let now = Timestamp::now().as_nanos();
+        now.saturating_sub(task.last_run().as_nanos()) < self.migration.migration_cost()
+    }
+
+    /// CPUs running or queueing the other tasks of a task's cache affinity
+    /// group (empty if the task has no group)
+    fn cache_group_cpus(&self, task: TaskId) -> Vec<CpuId> {
+        let groups = self.cache_groups.read();
+        let group = match groups.get(&task) {
+            Some(&group) => group,
+            None => return Vec::new(),
+        };
+        groups.iter()
+            .filter(|&(&other, &g)| g == group && other != task)
+            .filter_map(|(&other, _)| Task::get_by_id(other))
+            .map(|other| other.current_cpu())
+            .collect()
+    }
+
+    /// Cache affinity group of the task running on a CPU (0 if none)
+    fn running_cache_group(&self, cpu: CpuId) -> u32 {
+        let running = *self.per_cpu_data.get(cpu).current_task.lock();
+        running.map_or(0, |task| self.cache_affinity_group(task))
+    }
+
+    /// Pick a CPU sharing a last-level cache with the task's cache affinity
+    /// group, off SMT siblings running other groups where possible
+    fn select_cache_group_cpu(&self, task: &Task, util: u32) -> Option<CpuId> {
+        let members = self.cache_group_cpus(task.id());
+        if members.is_empty() {
+            return None;
+        }
+        let group = self.cache_affinity_group(task.id());
+        let affinity = task.cpu_affinity();
+        let mut allowed = CpuMask::empty();
+        for cpu in affinity.iter().filter(|&cpu| self.isolation.task_allowed_on(&affinity, cpu)) {
+            allowed.set(cpu);
+        }
+        self.topology.select_cache_group_cpu(&allowed, task.current_cpu(), &members, |cpu| CpuLoad {
+            nr_running: self.class_nr_running(cpu),
+            fits: self.pelt.task_fits_cpu(util, cpu),
+            ..Default::default()
+        }, |cpu| {
+            let other = self.running_cache_group(cpu);
+            other != 0 && other != group
+        })
+    }
+
+    /// Pick the least utilized allowed CPU on a NUMA node
+    ///
+    /// Returns `None` if the task already sits on that node or no CPU of
+    /// the node is allowed and fits the task.
+    fn select_node_local_cpu(&self, task: &Task, node: NodeId, util: u32) -> Option<CpuId> {
+        if self.topology.node_of_cpu(task.current_cpu()) == node {
+            return None;
+        }
+        if self.isolation.task_nodes(task.id()).map_or(false, |nodes| !nodes.contains(node)) {
+            return None;
+        }
+        let affinity = task.cpu_affinity();
+        self.topology.cpus_on_node(node).iter()
+            .filter(|&cpu| affinity.contains(cpu))
+            .filter(|&cpu| self.isolation.task_allowed_on(&affinity, cpu))
+            .filter(|&cpu| self.pelt.task_fits_cpu(util, cpu))
+            .min_by_key(|&cpu| self.pelt.cpu_util_est(cpu))
+    }
+
+    /// Find an allowed CPU whose capacity fits the given utilization
+    fn find_fitting_cpu(&self, task: &Task, util: u32) -> Option<CpuId> {
+        let affinity = task.cpu_affinity();
+        affinity.iter()
+            .filter(|&cpu| self.isolation.task_allowed_on(&affinity, cpu))
+            .filter(|&cpu| self.pelt.task_fits_cpu(util, cpu))
+            .min_by_key(|&cpu| self.pelt.cpu_util_est(cpu))
+    }
+
+    /// Find the most utilized busy allowed CPU that still fits the task
+    fn find_busy_fitting_cpu(&self, task: &Task, util: u32) -> Option<CpuId> {
+        let affinity = task.cpu_affinity();
+        affinity.iter()
+            .filter(|&cpu| self.class_nr_running(cpu) > 0)
+            .filter(|&cpu| self.isolation.task_allowed_on(&affinity, cpu))
+            .filter(|&cpu| self.pelt.task_fits_cpu(util, cpu))
+            .max_by_key(|&cpu| self.pelt.cpu_util_est(cpu))
+    }
+
+    /// Find the least utilized online CPU a task's affinity, cpuset and
+    /// CPU isolation allow
+    fn find_allowed_cpu(&self, task: &Task) -> Option<CpuId> {
+        let affinity = task.cpu_affinity();
+        let online = CpuMask::online();
+        affinity.iter()
+            .filter(|&cpu| online.contains(cpu))
+            .filter(|&cpu| self.isolation.cpuset_allows_cpu(task.id(), cpu))
+            .filter(|&cpu| self.isolation.task_allowed_on(&affinity, cpu))
+            .min_by_key(|&cpu| self.pelt.cpu_util_est(cpu))
+    }
+
+    /// Find the least utilized allowed housekeeping CPU
+    fn find_housekeeping_cpu(&self, task: &Task) -> Option<CpuId> {
+        let affinity = task.cpu_affinity();
+        self.isolation.housekeeping_mask().iter()
+            .filter(|&cpu| affinity.contains(cpu))
+            .min_by_key(|&cpu| self.pelt.cpu_util_est(cpu))
+    }
+
+    /// Balance the domains of the current CPU whose interval elapsed
+    ///
+    /// Each domain is balanced at its own interval and only by its
+    /// designated balancer CPU.
+    fn maybe_load_balance(&self) -> KernelResult<()> {
+        let cpu = current_cpu_id();
+        let due = self.domains.due_for_balance(cpu, Timestamp::now().as_nanos());
+        if due.is_empty() {
+            return Ok(());
+        }
+        self.balance_domains(cpu, &due)
+    }
+
+    /// Balance every domain of the current CPU now, regardless of their
+    /// intervals
+    pub fn load_balance(&self) -> KernelResult<()> {
+        if !self.is_running() {
+            return Ok(());
+        }
+        let cpu = current_cpu_id();
+        self.balance_domains(cpu, &self.domains.domains_of(cpu))
+    }
+
+    /// Balance domains of a CPU bottom-up, cheap levels first
+    fn balance_domains(&self, cpu: CpuId, domains: &[SchedDomain]) -> KernelResult<()> {
+        let balance_start = Timestamp::now();
+        self.global_stats.load_balance_calls.fetch_add(1, Ordering::Relaxed);
+        
+        kernel_debug!("Starting load balance operation");
+        
+        // Get load balancing configuration
+        let config = self.config.load().load_balance.clone();
+        
+        let mut migrations = 0;
+        for domain in domains {
+            if domain.has_flag(SD_NUMA) && !config.numa_aware {
+                continue;
+            }
+            migrations += self.migration.balance_domain(cpu, domain, &config, self)?;
+        }
+        
+        // Update statistics
+        self.global_stats.migrations.fetch_add(migrations as u64, Ordering::Relaxed);
+        self.last_balance_time.store(balance_start.as_nanos(), Ordering::Release);
+        
+        let balance_time = Timestamp::now().as_nanos().saturating_sub(balance_start.as_nanos());
+        kernel_debug!("Load balance completed: {} migrations in {} μs", 
+                     migrations, balance_time / 1000);
+        
+        Ok(())
+    }
+
+    /// Migrate task with comprehensive validation and state management
+    pub fn migrate_task(&self, task: &Task, target_cpu: CpuId) -> KernelResult<()> {
+        if self.migration_throttled(task) {
+            return Err(SchedulerError::MigrationNotAllowed.into());
+        }
+        self.move_task_to(task, target_cpu)
+    }
+
+    /// Check whether a task moved too recently to migrate again, unless its
+    /// CPU is going away
+    fn migration_throttled(&self, task: &Task) -> bool {
+        CpuMask::online().contains(task.current_cpu()) && self.migration.migration_throttled(task.id())
+    }
+
+    /// Move a task to another CPU regardless of when it last migrated
+    fn move_task_to(&self, task: &Task, target_cpu: CpuId) -> KernelResult<()> {
+        // Validate migration is possible; per-CPU kthreads never move
+        if !self.kthread.may_run_on(task.id(), target_cpu) || !task.can_migrate_to(target_cpu)? {
+            return Err(SchedulerError::MigrationNotAllowed.into());
+        }
+        
+        // Check CPU affinity and cpuset
+        if !task.cpu_affinity().contains(target_cpu) || !self.isolation.cpuset_allows_cpu(task.id(), target_cpu) {
+            return Err(SchedulerError::AffinityViolation.into());
+        }
+        
+        kernel_debug!("Migrating task {} from CPU {} to CPU {}", 
+                     task.id().as_u64(), task.current_cpu().as_u32(), target_cpu.as_u32());
+        
+        // Perform migration. A running task is moved with both CPUs
+        // stopped so that neither side can touch it mid-move; a queued task
+        // has no CPU state and moves directly.
+        let source_cpu = task.current_cpu();
+        let stoppers_up = self.stop_task.has_stopper(source_cpu) && self.stop_task.has_stopper(target_cpu);
+        let result = if task.state() == TaskState::Running && stoppers_up {
+            self.stop_task.stop_two_cpus(source_cpu, target_cpu, || {
+                self.move_task_between(task, source_cpu, target_cpu)
+            })
+        } else {
+            self.move_task_between(task, source_cpu, target_cpu)
+        };
+        
+        if result.is_ok() {
+            self.stats.on_migrate(task.id());
+            self.global_stats.migrations.fetch_add(1, Ordering::Relaxed);
+        }
+        
+        result
+    }
+
+    /// Set the CPUs a task may run on and enforce the new mask right away
+    ///
+    /// A task whose CPU is no longer allowed moves to the least utilized
+    /// allowed one: the running task is pushed away by its CPU's stopper as
+    /// in active balancing, any other task moves directly, so a pending
+    /// wakeup or quota window end enqueues it on an allowed CPU. The
+    /// migration interval does not apply. The mask is narrowed to the
+    /// task's cpuset and must then contain an online CPU. A per-CPU kthread
+    /// keeps its affinity (`SchedulerError::InvalidParameter`).
+    pub fn set_affinity(&self, task: &Task, mask: CpuMask) -> KernelResult<()> {
+        if self.kthread.is_per_cpu(task.id()) {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        let mask = self.isolation.constrain_affinity(task.id(), &mask);
+        let online = CpuMask::online();
+        if !mask.iter().any(|cpu| online.contains(cpu)) {
+            return Err(SchedulerError::AffinityViolation.into());
+        }
+        task.set_cpu_affinity(mask)?;
+
+        let source_cpu = task.current_cpu();
+        if task.cpu_affinity().contains(source_cpu) {
+            return Ok(());
+        }
+        let target_cpu = self.find_allowed_cpu(task).ok_or(SchedulerError::AffinityViolation)?;
+        kernel_debug!("Affinity change moves task {} from CPU {} to CPU {}",
+                     task.id().as_u64(), source_cpu.as_u32(), target_cpu.as_u32());
+
+        if task.state() == TaskState::Running {
+            return BalanceSource::push_running_task(self, task.id(), source_cpu, target_cpu);
+        }
+        self.move_task_between(task, source_cpu, target_cpu)?;
+        self.stats.on_migrate(task.id());
+        self.global_stats.migrations.fetch_add(1, Ordering::Relaxed);
+        if task.state() == TaskState::Runnable {
+            send_reschedule_ipi(target_cpu);
+        }
+        Ok(())
+    }
+
+    /// Move a task's CPU assignment and runqueue state from one CPU to another
+    fn move_task_between(&self, task: &Task, source_cpu: CpuId, target_cpu: CpuId) -> KernelResult<()> {
+        self.migration.migrate_task_safe(task, target_cpu)?;
+        self.fair.migrate_task(task.id(), source_cpu, target_cpu);
+        self.rt.migrate_task(task.id(), source_cpu, target_cpu);
+        self.deadline.migrate_task(task.id(), source_cpu, target_cpu);
+        self.pelt.migrate_load(task.id(), source_cpu, target_cpu);
+        self.trace.emit(|| SchedEvent::Migrate { task: task.id(), src: source_cpu, dst: target_cpu });
+        Ok(())
+    }
+
+    /// Push of active balancing: move a task that was running on
+    /// `source_cpu` to `target_cpu`
+    ///
+    /// Runs on the source CPU's stopper, which preempted the task, so the
+    /// task can move like a queued one. Does nothing if it has already
+    /// left the CPU or may no longer run on the target.
+    fn active_push(&self, task: TaskId, source_cpu: CpuId, target_cpu: CpuId) -> KernelResult<()> {
+        let task = match Task::get_by_id(task) {
+            Some(task) if task.current_cpu() == source_cpu => task,
+            _ => return Ok(()),
+        };
+        if !self.can_run_on(task.id(), target_cpu) {
+            return Ok(());
+        }
+        self.move_task_between(&task, source_cpu, target_cpu)?;
+        self.stats.on_migrate(task.id());
+        self.global_stats.migrations.fetch_add(1, Ordering::Relaxed);
+        send_reschedule_ipi(target_cpu);
+        Ok(())
+    }
+
+    /// Set the CFS minimum granularity: how long a task runs before it
+    /// can be preempted
+    pub fn set_min_granularity(&self, granularity_ns: u64) -> KernelResult<()> {
+        self.fair.set_min_granularity(granularity_ns)
+    }
+
+    /// Set the CFS wakeup granularity: the vruntime lead a waking task
+    /// needs to preempt the running task
+    pub fn set_wakeup_granularity(&self, granularity_ns: u64) -> KernelResult<()> {
+        self.fair.set_wakeup_granularity(granularity_ns)
+    }
+
+    /// Set the CFS target latency: the period over which every runnable
+    /// fair task should run once
+    pub fn set_target_latency(&self, latency: Duration) -> KernelResult<()> {
+        self.fair.set_target_latency(latency)
+    }
+
+    /// Current CFS scheduling period of a CPU
+    pub fn sched_period(&self, cpu: CpuId) -> Duration {
+        Duration::from_nanos(self.fair.sched_period(cpu))
+    }
+
+    /// Set the time since a task last ran during which load balancing
+    /// treats its cache as warm and leaves it in place
+    pub fn set_migration_cost(&self, cost_ns: u64) -> KernelResult<()> {
+        self.migration.set_migration_cost(cost_ns)
+    }
+
+    /// Migrations waiting to happen, as (task, target CPU)
+    ///
+    /// A task lands here when it should have moved but had migrated too
+    /// recently; the target pulls it when it next goes idle.
+    pub fn pending_migrations(&self) -> Vec<(TaskId, CpuId)> {
+        self.migration.pending_migrations()
+    }
+
+    /// Set the latency nice value of a fair task (-20..=19)
+    ///
+    /// Higher values make the task preempt more eagerly when it wakes,
+    /// lower values make it more tolerant of waiting.
+    pub fn set_latency_nice(&self, task: &Task, latency_nice: i8) -> KernelResult<()> {
+        self.fair.set_latency_nice(task, latency_nice)
+    }
+
+    /// Set how a deadline task's runtime exhaustion and deadline misses
+    /// are handled
+    pub fn set_overrun_policy(&self, task: &Task, policy: OverrunPolicy) -> KernelResult<()> {
+        self.deadline.set_overrun_policy(task.id(), policy)
+    }
+
+    /// Call `callback` with every deadline miss, or stop with `None`
+    ///
+    /// While debugging is enabled each miss carries the task's last
+    /// scheduling events: when it was enqueued and picked, what preempted
+    /// it and when it was throttled.
+    pub fn set_deadline_miss_callback(&self, callback: Option<DeadlineMissCallback>) {
+        self.deadline.set_miss_callback(callback);
+    }
+
+    /// Last scheduling events of a deadline task, oldest first (empty
+    /// unless debugging is enabled)
+    pub fn deadline_history(&self, task: &Task) -> Vec<DlHistoryEntry> {
+        self.deadline.history(task.id())
+    }
+
+    /// Give a `RoundRobin` task its own time slice (0.1ms..=1s)
+    pub fn set_rr_timeslice(&self, task: &Task, slice: Duration) -> KernelResult<()> {
+        self.rt.set_timeslice(task, slice)
+    }
+
+    /// Set the RT priority of a `Fifo` or `RoundRobin` task (1..=99)
+    ///
+    /// # Returns
+    /// - `Ok(())` if the priority was set
+    /// - `Err(SchedulerError::InvalidParameter)` if the task is not an RT
+    ///   task or the priority is out of range
+    pub fn set_rt_priority(&self, task: &Task, prio: u8) -> KernelResult<()> {
+        if !matches!(task.sched_policy(), SchedPolicy::Fifo | SchedPolicy::RoundRobin) {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        if self.rt.set_priority(task, prio)? {
+            self.preempt.request_reschedule()?;
+        }
+        Ok(())
+    }
+
+    /// Raise a `Fifo` or `RoundRobin` task to the priority ceiling of a
+    /// lock it acquires (immediate priority ceiling protocol)
+    ///
+    /// The task runs at least at `ceiling` (1..=99) until the matching
+    /// `release_ceiling`, so no task that could contend for the lock gets
+    /// to preempt it inside the critical section. Ceilings of nested locks
+    /// stack.
+    ///
+    /// # Returns
+    /// - `Ok(())` if the task was raised
+    /// - `Err(SchedulerError::InvalidParameter)` if the task is not an RT
+    ///   task, or the ceiling is out of range or below the task's RT
+    ///   priority
+    pub fn acquire_with_ceiling(&self, task: &Task, ceiling: u8) -> KernelResult<()> {
+        if !matches!(task.sched_policy(), SchedPolicy::Fifo | SchedPolicy::RoundRobin) {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        if self.rt.acquire_ceiling(task, ceiling)? {
+            self.preempt.request_reschedule()?;
+        }
+        Ok(())
+    }
+
+    /// Drop the priority ceiling a task acquired last, when it releases
+    /// the lock
+    ///
+    /// The task falls back to its next ceiling or its own RT priority and
+    /// gives way if a queued task now outranks it.
+    ///
+    /// # Returns
+    /// - `Ok(())` if the ceiling was dropped
+    /// - `Err(SchedulerError::InvalidParameter)` if the task holds no ceiling
+    pub fn release_ceiling(&self, task: &Task) -> KernelResult<()> {
+        if self.rt.release_ceiling(task)? {
+            self.preempt.request_reschedule()?;
+        }
+        Ok(())
+    }
+
+    /// Get the RT priority of a `Fifo` or `RoundRobin` task
+    ///
+    /// # Returns
+    /// - `Ok(prio)` with the task's priority (1..=99)
+    /// - `Err(SchedulerError::InvalidParameter)` if the task is not an RT task
+    pub fn get_rt_priority(&self, task: &Task) -> KernelResult<u8> {
+        match task.sched_policy() {
+            SchedPolicy::Fifo | SchedPolicy::RoundRobin => Ok(task.rt_priority()),
+            _ => Err(SchedulerError::InvalidParameter.into()),
+        }
+    }
+
+    /// Deadline and RT bandwidth use of every online CPU and the system
+    ///
+    /// Deadline bandwidth is what admitted deadline tasks and servers have
+    /// reserved, RT bandwidth what RT tasks actually ran in the last
+    /// second. Both count against the RT bandwidth cap; a CPU whose use
+    /// reaches `SchedulerConfig::bandwidth_margin_percent` of the cap sets
+    /// the warning flag, a hint to refuse new RT or deadline work before
+    /// deadlines get missed.
+    pub fn bandwidth_report(&self) -> BandwidthReport {
+        let cap = self.rt.bandwidth_percent() * 10;
+        let margin = cap * self.config.load().bandwidth_margin_percent.min(100) / 100;
+        let deadline = self.deadline.cpu_bandwidth();
+        let cpus: Vec<CpuBandwidth> = CpuMask::online().iter().map(|cpu| {
+            let dl = deadline.get(&cpu).copied().unwrap_or(0).min(1000) as u32;
+            let rt = self.rt.rt_utilization(cpu);
+            CpuBandwidth {
+                cpu,
+                deadline: dl,
+                rt,
+                headroom: cap.saturating_sub(dl + rt),
+                over_margin: dl + rt >= margin,
+            }
+        }).collect();
+        BandwidthReport {
+            cap,
+            margin,
+            deadline: cpus.iter().map(|c| c.deadline).sum(),
+            rt: cpus.iter().map(|c| c.rt).sum(),
+            headroom: cpus.iter().map(|c| c.headroom).sum(),
+            warning: cpus.iter().any(|c| c.over_margin),
+            cpus,
+        }
+    }
+
+    /// Set the fair class's guaranteed runtime per period on every CPU
+    ///
+    /// A zero runtime disables the fair server. Fails with
+    /// `SchedulerError::InvalidParameter` if the runtime exceeds the period.
+    pub fn set_fair_server(&self, runtime: Duration, period: Duration) -> KernelResult<()> {
+        if runtime.as_nanos() == 0 {
+            return self.deadline.set_fair_server(None);
+        }
+        self.deadline.set_fair_server(Some(DlParams {
+            runtime_ns: runtime.as_nanos(),
+            deadline_ns: period.as_nanos(),
+            period_ns: period.as_nanos(),
+        }))
+    }
+
+    /// Check whether the fair server currently runs fair tasks on a CPU
+    /// ahead of runnable RT tasks
+    pub fn fair_server_throttling_rt(&self, cpu: CpuId) -> bool {
+        self.deadline.fair_server_active(cpu) && self.rt.nr_running(cpu) > 0
+    }
+
+    /// Flag RT tasks that run for longer than `threshold` without yielding
+    ///
+    /// `callback` is told about every flagged task; with `resched` the task
+    /// is also sent behind its peers. A zero threshold turns the watchdog off.
+    pub fn set_rt_stall_watchdog(&self, threshold: Duration, callback: Option<StallCallback>, resched: bool) {
+        self.rt.set_stall_callback(callback);
+        self.rt.set_stall_resched(resched);
+        self.rt.set_stall_watchdog(threshold);
+    }
+
+    /// Find online CPUs that have not scheduled for longer than `threshold`
+    ///
+    /// Meant to be called periodically from a healthy CPU. Idle CPUs,
+    /// CPUs that never scheduled and CPUs whose tick is stopped (nohz_full
+    /// CPUs running a single task) are not reported. The stall callback is
+    /// called once per stall, on the first check that finds it.
+    pub fn check_stalls(&self, threshold: Duration) -> Vec<CpuId> {
+        let now = Timestamp::now().as_nanos();
+        let callback = *self.stall_callback.lock();
+        let mut stalled = Vec::new();
+        for cpu in CpuMask::online().iter() {
+            let data = self.per_cpu_data.get(cpu);
+            let (stalled_ns, first) = match data.check_stall(now, threshold.as_nanos(), self.clock.is_tick_stopped(cpu)) {
+                Some(stall) => stall,
+                None => continue,
+            };
+            stalled.push(cpu);
+            if first {
+                kernel_warn!("CPU {} has not scheduled for {}ns", cpu.as_u32(), stalled_ns);
+                if let Some(callback) = callback {
+                    callback(cpu, stalled_ns);
+                }
+            }
+        }
+        stalled
+    }
+
+    /// Set the function called when `check_stalls` finds a stuck CPU
+    pub fn set_stall_callback(&self, callback: Option<CpuStallCallback>) {
+        *self.stall_callback.lock() = callback;
+    }
+
+    /// Create a constant bandwidth server granting `runtime` every `period`
+    pub fn create_cbs_server(&self, runtime: Duration, period: Duration) -> KernelResult<ServerId> {
+        self.deadline.create_cbs_server(runtime, period)
+    }
+
+    /// Run a fair task under a CBS server's reservation
+    ///
+    /// The task moves to the server's CPU and from the fair class to the
+    /// deadline class.
+    pub fn attach_task_to_cbs_server(&self, server: ServerId, task: &Task) -> KernelResult<()> {
+        if !task.sched_policy().is_fair() {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        self.deadline.attach_task(server, task)?;
+        let queued = matches!(task.state(), TaskState::Runnable | TaskState::Running);
+        if queued {
+            self.fair.dequeue_task(task)?;
+        }
+        if let Some(cpu) = self.deadline.server_cpu(server) {
+            if cpu != task.current_cpu() {
+                self.move_task_to(task, cpu)?;
+            }
+        }
+        if queued {
+            self.deadline.enqueue_task(task)?;
+        }
+        self.preempt.request_reschedule()
+    }
+
+    /// Return a task attached to a CBS server to the fair class
+    pub fn detach_task_from_cbs_server(&self, task: &Task) -> KernelResult<()> {
+        if self.deadline.server_of(task.id()).is_none() {
+            return Ok(());
+        }
+        self.deadline.detach_task(task);
+        if matches!(task.state(), TaskState::Runnable | TaskState::Running) {
+            self.enqueue_fair(task)?;
+        }
+        self.preempt.request_reschedule()
+    }
+
+    /// Cap a fair task at `percent` of one CPU averaged over `window`
+    ///
+    /// Once the task's runtime in the current window exceeds its share, it
+    /// is taken off its runqueue until the window ends.
+    ///
+    /// # Returns
+    /// - `Ok(())` on success
+    /// - `Err(SchedulerError::InvalidParameter)` if `percent` is outside
+    ///   1..=100, `window` is zero or the task is not a fair task
+    pub fn set_task_cpu_quota(&self, task: &Task, percent: u8, window: Duration) -> KernelResult<()> {
+        if !(1..=100).contains(&percent) || window.as_nanos() == 0 {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        if !task.sched_policy().is_fair() {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        let now = self.clock.sched_clock(task.current_cpu());
+        let running = task.state() == TaskState::Running;
+        self.stats.set_task_quota(task.id(), percent, window.as_nanos(), running, now);
+        kernel_debug!("Task {} capped at {}% of a CPU per {} ns", 
+                     task.id().as_u64(), percent, window.as_nanos());
+        Ok(())
+    }
+
+    /// Remove a task's CPU quota, making it runnable again if throttled
+    pub fn clear_task_cpu_quota(&self, task: &Task) -> KernelResult<()> {
+        if self.stats.clear_task_quota(task.id())
+            && matches!(task.state(), TaskState::Runnable | TaskState::Running) {
+            self.enqueue_fair(task)?;
+            send_reschedule_ipi(task.current_cpu());
+        }
+        Ok(())
+    }
+
+    /// Time a task spent throttled by its CPU quota
+    pub fn task_quota_throttled_time(&self, task: &Task) -> Duration {
+        let now = self.clock.sched_clock(current_cpu_id());
+        Duration::from_nanos(self.stats.quota_throttled_time(task.id(), now))
+    }
+
+    /// Budget a CBS server has left in its current period
+    pub fn cbs_server_budget(&self, server: ServerId) -> Option<Duration> {
+        self.deadline.server_budget(server)
+    }
+
+    /// Latency nice value of a task
+    pub fn latency_nice(&self, task: &Task) -> i8 {
+        self.fair.latency_nice(task.id())
+    }
+
+    /// Set the bound on the `sched_clock` difference between CPUs
+    pub fn set_max_clock_skew(&self, skew_ns: u64) -> KernelResult<()> {
+        self.clock.set_max_clock_skew(skew_ns)
+    }
+
+    /// Deliver every scheduler event (switches, wakeups, migrations and
+    /// preemptions) to `cb`, which may run in interrupt context and must
+    /// not sleep
+    pub fn subscribe_events(&self, cb: SchedEventCallback) -> SubscriptionHandle {
+        self.trace.subscribe(cb)
+    }
+
+    /// Stop delivering scheduler events to a subscriber
+    pub fn unsubscribe_events(&self, handle: SubscriptionHandle) -> KernelResult<()> {
+        self.trace.unsubscribe(handle)
+    }
+
+    /// Register the stopper task of a CPU, enabling stop work on it
+    ///
+    /// The task's body must call `run_stopper` for its CPU whenever it is
+    /// picked, then block.
+    pub fn register_stopper_task(&self, cpu: CpuId, task: Arc<Task>) {
+        self.stop_task.register_stopper(cpu, task);
+    }
+
+    /// Run a CPU's stop work and push away the running tasks active
+    /// balancing queued on it; called by that CPU's stopper task
+    ///
+    /// Returns the number of works and pushes carried out.
+    pub fn run_stopper(&self, cpu: CpuId) -> usize {
+        let mut count = 0;
+        loop {
+            let works = self.stop_task.run_stop_works(cpu);
+            let pushes = self.stop_task.take_pushes(cpu);
+            if works == 0 && pushes.is_empty() {
+                return count;
+            }
+            count += works + pushes.len();
+            for (task, dst) in pushes {
+                if let Err(e) = self.active_push(task, cpu, dst) {
+                    kernel_warn!("Failed to push task {} to CPU {}: {:?}", task.as_u64(), dst.as_u32(), e);
+                }
+            }
+        }
+    }
+
+    /// Isolate a CPU from general scheduling at runtime
+    ///
+    /// Only tasks pinned to isolated CPUs may run there afterwards. Queued
+    /// and running tasks that are not pinned are moved to housekeeping CPUs.
+    pub fn isolate_cpu(&self, cpu: CpuId) -> KernelResult<()> {
+        self.isolation.isolate_cpu(cpu)?;
+
+        let mut tasks = self.fair.queued_tasks(cpu);
+        if let Some(current) = *self.per_cpu_data.get(cpu).current_task.lock() {
+            tasks.push(current);
+        }
+        for task in tasks.into_iter().filter_map(Task::get_by_id) {
+            if self.isolation.task_allowed_on(&task.cpu_affinity(), cpu) {
+                continue;
+            }
+            match self.find_housekeeping_cpu(&task) {
+                Some(target) => self.move_task_to(&task, target)?,
+                None => kernel_warn!("Task {} has no housekeeping CPU to move to",
+                                     task.id().as_u64()),
+            }
+        }
+        self.preempt.request_reschedule()
+    }
+
+    /// Return an isolated CPU to general scheduling
+    pub fn unisolate_cpu(&self, cpu: CpuId) -> KernelResult<()> {
+        self.isolation.unisolate_cpu(cpu)
+    }
+
+    /// Bind a kernel thread to `cpu` for good
+    ///
+    /// The thread moves to the CPU and is pinned to it; from then on load
+    /// balancing, migration and affinity changes leave it there, and the
+    /// CPU can only go offline while it is parked.
+    pub fn register_per_cpu_kthread(&self, task: &Task, cpu: CpuId) -> KernelResult<()> {
+        if !CpuMask::online().contains(cpu) {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        let mut mask = CpuMask::empty();
+        mask.set(cpu);
+        task.set_cpu_affinity(mask)?;
+        if task.current_cpu() != cpu {
+            self.move_task_to(task, cpu)?;
+        }
+        self.kthread.register(task.id(), cpu)
+    }
+
+    /// Forget a per-CPU kernel thread that exited
+    pub fn unregister_per_cpu_kthread(&self, task: &Task) {
+        self.kthread.unregister(task.id());
+    }
+
+    /// Ask a per-CPU kernel thread to park, e.g. before its CPU goes
+    /// offline
+    ///
+    /// The thread is woken so that it notices the request; it parks at its
+    /// next `kthread_parkme`. Returns whether it is already parked.
+    pub fn park_kthread(&self, task: &Task) -> KernelResult<bool> {
+        if self.kthread.request_park(task.id())? {
+            return Ok(true);
+        }
+        if !matches!(task.state(), TaskState::Runnable | TaskState::Running) {
+            self.wake_up_task(task)?;
+        }
+        Ok(false)
+    }
+
+    /// Let a parked per-CPU kernel thread run again
+    pub fn unpark_kthread(&self, task: &Task) -> KernelResult<()> {
+        if self.kthread.unpark(task.id())? {
+            self.wake_up_task(task)?;
+        }
+        Ok(())
+    }
+
+    /// Check whether the current per-CPU kernel thread was asked to park
+    pub fn kthread_should_park(&self) -> bool {
+        Task::current().map_or(false, |current| self.kthread.should_park(current.id()))
+    }
+
+    /// Park the current per-CPU kernel thread if it was asked to, until
+    /// it is unparked
+    ///
+    /// Called by the thread where it holds no per-CPU state. A parked
+    /// thread sleeps interruptibly, so it does not count toward the load
+    /// average.
+    pub fn kthread_parkme(&self) -> KernelResult<()> {
+        let current = Task::current().ok_or(SchedulerError::TaskNotFound)?;
+        if !self.kthread.park_self(current.id()) {
+            return Ok(());
+        }
+        kernel_debug!("Kthread {} parked", current.id().as_u64());
+        loop {
+            current.set_state(TaskState::InterruptibleSleep);
+            if !self.kthread.is_parked(current.id()) {
+                break;
+            }
+            Task::block_current(None);
+        }
+        current.set_state(TaskState::Running);
+        Ok(())
+    }
+
+    /// Move every task off a CPU that is about to go offline
+    ///
+    /// Queued and running tasks move to the least utilized other CPU they
+    /// are allowed on, RT tasks with their priority and slice, deadline
+    /// tasks with their deadline and remaining runtime. A CBS server whose tasks run here moves, with all of its
+    /// tasks, to a CPU every one of them is allowed on. Nothing moves
+    /// unless all of them can.
+    ///
+    /// # Returns
+    /// - `Ok(())` once only the CPU's idle, stopper and parked kthreads
+    ///   are left on it
+    /// - `Err(SchedulerError::PinnedTaskBlocking)` if one of the CPU's
+    ///   per-CPU kthreads is not parked, or a task is allowed on no other
+    ///   CPU
+    pub fn cpu_offline(&self, cpu: CpuId) -> KernelResult<()> {
+        self.kthread.check_offline(cpu)?;
+
+        let idle = self.idle.get_idle_task(cpu).ok().map(|task| task.id());
+        let mut tasks = self.fair.queued_tasks(cpu);
+        tasks.extend(self.rt.queued_tasks(cpu));
+        tasks.extend(self.deadline.queued_tasks(cpu));
+        if let Some(current) = *self.per_cpu_data.get(cpu).current_task.lock() {
+            let pinned = Some(current) == idle || self.stop_task.is_stopper(cpu, current);
+            if !pinned && !tasks.contains(&current) {
+                tasks.push(current);
+            }
+        }
+
+        let mut servers = Vec::new();
+        for server in self.deadline.servers_on(cpu) {
+            let members: Vec<Arc<Task>> = self.deadline.server_tasks(server).into_iter()
+                .filter_map(Task::get_by_id)
+                .collect();
+            match self.offline_target(cpu, &members) {
+                Some(target) => servers.push((server, target)),
+                None => {
+                    kernel_warn!("CBS server {} has no CPU to move to from CPU {}", server, cpu.as_u32());
+                    return Err(SchedulerError::PinnedTaskBlocking.into());
+                }
+            }
+        }
+
+        let mut moves = Vec::new();
+        for task in tasks.into_iter().filter_map(Task::get_by_id) {
+            if self.kthread.is_per_cpu(task.id()) {
+                continue;
+            }
+            let target = match self.deadline.server_of(task.id()) {
+                Some(server) => servers.iter().find(|&&(s, _)| s == server).map(|&(_, target)| target),
+                None => self.offline_target(cpu, core::slice::from_ref(&task)),
+            };
+            match target {
+                Some(target) => moves.push((task, target)),
+                None => {
+                    kernel_warn!("Task {} is pinned to CPU {} going offline", task.id().as_u64(), cpu.as_u32());
+                    return Err(SchedulerError::PinnedTaskBlocking.into());
+                }
+            }
+        }
+
+        for (server, target) in servers {
+            self.deadline.migrate_server(server, target)?;
+        }
+        let moved = moves.len();
+        for (task, target) in moves {
+            self.move_task_to(&task, target)?;
+        }
+        kernel_info!("CPU {} ready to go offline, {} tasks moved away", cpu.as_u32(), moved);
+        Ok(())
+    }
+
+    /// Least utilized online CPU other than `cpu` that all of `tasks` are
+    /// allowed on
+    fn offline_target(&self, cpu: CpuId, tasks: &[Arc<Task>]) -> Option<CpuId> {
+        CpuMask::online().iter()
+            .filter(|&target| target != cpu)
+            .filter(|&target| tasks.iter().all(|task| {
+                let affinity = task.cpu_affinity();
+                affinity.contains(target)
+                    && self.isolation.cpuset_allows_cpu(task.id(), target)
+                    && self.isolation.task_allowed_on(&affinity, target)
+            }))
+            .min_by_key(|&target| self.pelt.cpu_util_est(target))
+    }
+
+    /// Set the number of ticks of runqueue and utilization history every
+    /// CPU computes trends over (2-`MAX_TICK_HISTORY`)
+    pub fn set_tick_history_len(&self, ticks: usize) -> KernelResult<()> {
+        if !(2..=MAX_TICK_HISTORY).contains(&ticks) {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        for cpu in CpuMask::online().iter() {
+            self.per_cpu_data.get(cpu).history.set_window(ticks)?;
+        }
+        self.update_config(|config| config.tick_history_len = ticks);
+        Ok(())
+    }
+
+    /// Set the longest interval between two balance attempts of a
+    /// scheduling domain (milliseconds)
+    ///
+    /// Every domain interval is derived again from the new maximum.
+    pub fn set_balance_interval(&self, ms: u64) -> KernelResult<()> {
+        self.domains.set_max_balance_interval(ms)?;
+        self.update_config(|config| config.load_balance.balance_interval = ms);
+        Ok(())
+    }
+
+    /// When the scheduler last balanced any domain (ns, 0 if never)
+    pub fn last_balance_time(&self) -> u64 {
+        self.last_balance_time.load(Ordering::Acquire)
+    }
+
+    /// Record the physical location of a CPU
+    ///
+    /// CPUs registered before `init` shape the scheduling domains it
+    /// builds; without any, all online CPUs form one flat domain. Changes
+    /// after `init` rebuild the domains.
+    pub fn set_cpu_topology(&self, cpu: CpuId, topo: CpuTopology) -> KernelResult<()> {
+        self.topology.set_cpu_topology(cpu, topo);
+        if self.get_state() == SchedulerState::Uninitialized {
+            return Ok(());
+        }
+        self.domains.build_from_topology(&self.topology)
+    }
+
+    /// Set the compute capacity of a CPU (1024 = biggest core)
+    ///
+    /// Updates topology and load tracking and rebuilds the scheduling
+    /// domains so that `SD_ASYM_CPUCAPACITY` reflects the new capacities.
+    pub fn set_cpu_capacity(&self, cpu: CpuId, capacity: u32) -> KernelResult<()> {
+        self.topology.set_cpu_capacity(cpu, capacity)?;
+        self.pelt.set_capacity_scale(cpu, capacity)?;
+        self.domains.build_from_topology(&self.topology)
+    }
+
+    /// Snapshot of the scheduler configuration
+    ///
+    /// The snapshot never changes; later updates publish a new one.
+    pub fn config(&self) -> Arc<SchedulerConfig> {
+        self.config.load()
+    }
+
+    /// Change the scheduler configuration by read-copy-update
+    ///
+    /// `f` edits a copy of the current configuration, which then replaces
+    /// it; readers never block and see either the old or the new copy in
+    /// full. Concurrent updates are serialized. Settings the subsystems
+    /// took over when the scheduler was created, such as the tick
+    /// frequency, keep their value; use the dedicated setters for those.
+    pub fn update_config<F: FnOnce(&mut SchedulerConfig)>(&self, f: F) {
+        self.config.update(f);
+    }
+
+    /// Per-task scheduling statistics (all zero unless schedstats are enabled)
+    pub fn task_schedstats(&self, task: &Task) -> TaskSchedStats {
+        self.stats.task_schedstats(task.id())
+    }
+
+    /// Enable or disable per-task scheduling statistics
+    pub fn set_schedstats_enabled(&self, enabled: bool) {
+        self.update_config(|config| config.schedstats_enabled = enabled);
+        self.stats.set_enabled(enabled);
+    }
+
+    /// Enable or disable scheduler debugging: runqueue dumps and deadline
+    /// task event histories
+    pub fn set_debug_enabled(&self, enabled: bool) {
+        self.update_config(|config| config.debug_enabled = enabled);
+        self.deadline.set_history_enabled(enabled);
+    }
+
+    /// Per-CPU busy share, idle state residency and average frequency
+    /// since the previous report
+    pub fn cpu_report(&self) -> Vec<CpuReport> {
+        self.report.snapshot_report(|cpu| self.per_cpu_data.get(cpu).cpu_utilization.load(Ordering::Relaxed))
+    }
+
+    /// Enable or disable a scheduler feature by name (e.g. `"START_DEBIT"`)
+    pub fn set_sched_feature(&self, name: &str, enabled: bool) -> KernelResult<()> {
+        self.features.set_feature(name, enabled)
+    }
+
+    /// All scheduler features with their current state
+    pub fn sched_features(&self) -> Vec<(&'static str, bool)> {
+        self.features.list_features()
+    }
+
+    /// Execute a membarrier command for the task running on this CPU
+    pub fn membarrier(&self, cmd: MembarrierCmd) -> KernelResult<u32> {
+        let current = self.get_current_task(current_cpu_id()).ok_or(SchedulerError::TaskNotFound)?;
+        self.membarrier.membarrier(cmd, &current)
+    }
+
+    /// Enable or disable automatic per-session task groups
+    pub fn set_autogroup_enabled(&self, enabled: bool) {
+        self.autogroup.set_enabled(enabled);
+    }
+
+    /// Move a task to the autogroup of a session
+    ///
+    /// A queued fair task is requeued in its new group right away; a
+    /// running task switches groups on its next wakeup.
+    pub fn attach_task_autogroup(&self, task: &Task, session_id: u64) -> KernelResult<()> {
+        self.autogroup.attach_task(task.id(), session_id);
+        if task.state() != TaskState::Runnable {
+            return Ok(());
+        }
+        if !task.sched_policy().is_fair() {
+            return Ok(());
+        }
+        self.fair.dequeue_task(task)?;
+        self.enqueue_fair(task)
+    }
+
+    /// Set the nice value of a session's autogroup
+    pub fn set_autogroup_nice(&self, session_id: u64, nice: i8) -> KernelResult<()> {
+        self.autogroup.set_autogroup_nice(session_id, nice)?;
+        if let Some(group) = self.autogroup.session_group(session_id) {
+            self.fair.reweight_group(group);
+        }
+        Ok(())
+    }
+
+    /// Create a nested task group with a `cpu.weight` (1..=10000)
+    pub fn create_task_group(&self, parent: GroupId, weight: u32) -> KernelResult<GroupId> {
+        self.fair.create_group(parent, weight)
+    }
+
+    /// Remove an empty task group
+    pub fn remove_task_group(&self, group: GroupId) -> KernelResult<()> {
+        self.fair.remove_group(group)
+    }
+
+    /// Change the `cpu.weight` of a task group
+    pub fn set_task_group_weight(&self, group: GroupId, weight: u32) -> KernelResult<()> {
+        self.fair.set_group_weight(group, weight)
+    }
+
+    /// Move a task into a task group
+    ///
+    /// Tasks in a task group are exempt from autogrouping; a task moved
+    /// back to the root group rejoins its autogroup on its next wakeup.
+    pub fn move_task_to_group(&self, task: &Task, group: GroupId) -> KernelResult<()> {
+        self.fair.move_task(task, group)?;
+        self.preempt.request_reschedule()
+    }
+
+    /// Create a top-level cpuset confining tasks to CPUs and memory nodes
+    pub fn create_cpuset(&self, cpus: CpuMask, nodes: NodeMask) -> CpusetId {
+        self.isolation.create_cpuset(cpus, nodes)
+    }
+
+    /// Create a cpuset within `parent`, narrowing its CPUs and nodes
+    pub fn create_child_cpuset(&self, parent: CpusetId, cpus: CpuMask, nodes: NodeMask) -> KernelResult<CpusetId> {
+        self.isolation.create_child_cpuset(parent, cpus, nodes)
+    }
+
+    /// Remove a cpuset without tasks or children
+    pub fn remove_cpuset(&self, cpuset: CpusetId) -> KernelResult<()> {
+        self.isolation.remove_cpuset(cpuset)
+    }
+
+    /// Confine a task to a cpuset
+    ///
+    /// The task's affinity is narrowed to the cpuset's CPUs and the task
+    /// moves off a CPU outside it. Fails with `AffinityViolation` if the
+    /// affinity has no online CPU in common with the cpuset.
+    pub fn attach_task_to_cpuset(&self, cpuset: CpusetId, task: &Task) -> KernelResult<()> {
+        let affinity = self.isolation.attach_task(cpuset, task.id(), &task.cpu_affinity())?;
+        self.set_affinity(task, affinity)
+    }
+
+    /// Group a fair task is scheduled in: the task group it was moved into,
+    /// or else its autogroup
+    fn fair_group(&self, task: TaskId) -> TaskGroup {
+        self.fair.task_group(task).unwrap_or_else(|| self.autogroup.task_group(task))
+    }
+
+    /// Limit the CPU time of a task group, or remove its limit with `None`
+    pub fn set_group_bandwidth(&self, group: GroupId, limit: Option<CfsBandwidth>) -> KernelResult<()> {
+        self.fair.set_group_bandwidth(group, limit)?;
+        self.preempt.request_reschedule()
+    }
+
+    /// CPU bandwidth limit of a task group
+    pub fn group_bandwidth(&self, group: GroupId) -> Option<CfsBandwidth> {
+        self.fair.group_bandwidth(group)
+    }
+
+    /// Throttling statistics of a bandwidth-limited task group
+    pub fn bandwidth_stats(&self, group: GroupId) -> Option<CfsBandwidthStats> {
+        self.fair.bandwidth_stats(group)
+    }
+
+    /// Context switch and preemption counters of every policy, in
+    /// `SchedPolicy::ALL` order
+    pub fn policy_stats(&self) -> [PolicyStatsSnapshot; NR_SCHED_POLICIES] {
+        self.global_stats.policy_snapshot()
+    }
+
+    /// Context switches of every reason, in `SwitchReason::ALL` order
+    pub fn switch_reason_stats(&self) -> [u64; NR_SWITCH_REASONS] {
+        self.global_stats.switch_reason_snapshot()
+    }
+
+    /// The 1, 5 and 15 minute load averages
+    pub fn get_loadavg(&self) -> (f64, f64, f64) {
+        self.loadavg.get_loadavg()
+    }
+
+    /// Snapshot of every CPU's runqueues for post-mortem debugging
+    ///
+    /// Returns `None` unless `SchedulerConfig::debug_enabled` is set, since
+    /// collecting the dump walks every runqueue.
+    pub fn dump_runqueues(&self) -> Option<RunqueueDump> {
+        if !self.config.load().debug_enabled {
+            return None;
+        }
+        Some(self.debug.dump_runqueues(self))
+    }
+
+    /// Disable preemption on the current CPU (nests)
+    pub fn preempt_disable(&self) {
+        self.preempt.preempt_disable();
+    }
+
+    /// Re-enable preemption, performing a reschedule deferred meanwhile
+    pub fn preempt_enable(&self) -> KernelResult<()> {
+        if self.preempt.preempt_enable() {
+            self.schedule()?;
+        }
+        Ok(())
+    }
+
+    /// Average time a task waited between being woken and running
+    ///
+    /// A moving average over the task's recent wakeups; zero if the task
+    /// never ran after a wakeup.
+    pub fn task_wakeup_latency(&self, task: &Task) -> Duration {
+        Duration::from_nanos(self.stats.task_wakeup_latency(task.id()))
+    }
+
+    /// Distribution of wakeup latencies over all tasks
+    pub fn wakeup_latency_histogram(&self) -> LatencyHistogram {
+        self.stats.wakeup_latency_histogram()
+    }
+
+    /// Voluntary preemption point for long kernel loops
+    ///
+    /// Reschedules if a reschedule is pending and preemption is not
+    /// disabled on this CPU; otherwise costs two atomic loads, so that a
+    /// loop zeroing a huge page or copying a large buffer can call it on
+    /// every iteration. With preemption enabled the pending reschedule is
+    /// normally taken on return from the interrupt that raised it and this
+    /// rarely reschedules; with `SchedulerConfig::preemption_enabled` off,
+    /// it is how long kernel loops give up the CPU.
+    ///
+    /// # Returns
+    /// `true` if the current task was switched out, `false` if it kept
+    /// the CPU
+    pub fn cond_resched(&self) -> bool {
+        if !self.preempt.should_resched() {
+            return false;
+        }
+        self.preempt.account_voluntary_reschedule();
+        // Switching away from this task counts a switch on this CPU before
+        // the task runs again
+        let switches = &self.per_cpu_data.get(current_cpu_id()).local_stats.context_switches;
+        let before = switches.load(Ordering::Relaxed);
+        self.schedule().is_ok() && switches.load(Ordering::Relaxed) != before
+    }
+
+    /// Set the core scheduling cookie of a task
+    ///
+    /// Tasks with different cookies never run on SMT siblings of the same
+    /// core at the same time. Cookie 0 removes the task from its group.
+    pub fn set_core_cookie(&self, task: &Task, cookie: u64) -> KernelResult<()> {
+        let mut cookies = self.core_cookies.write();
+        if cookie == 0 {
+            cookies.remove(&task.id());
+        } else {
+            cookies.insert(task.id(), cookie);
+        }
+        kernel_debug!("Task {} core cookie set to {:#x}", task.id().as_u64(), cookie);
+        Ok(())
+    }
+
+    /// Put a task in a cache affinity group
+    ///
+    /// A soft placement hint for performance: tasks of a group are placed
+    /// on CPUs sharing a last-level cache, and kept off SMT siblings
+    /// running tasks of other groups when possible. Unlike core scheduling
+    /// nothing is enforced. Group 0 removes the task from its group.
+    pub fn set_cache_affinity_group(&self, task: &Task, group: u32) {
+        let mut groups = self.cache_groups.write();
+        if group == 0 {
+            groups.remove(&task.id());
+        } else {
+            groups.insert(task.id(), group);
+        }
+        kernel_debug!("Task {} cache affinity group set to {}", task.id().as_u64(), group);
+    }
+
+    /// Cache affinity group of a task (0 if none)
+    pub fn cache_affinity_group(&self, task: TaskId) -> u32 {
+        self.cache_groups.read().get(&task).copied().unwrap_or(0)
+    }
+
+    /// Last-level cache domain a CPU belongs to (`None` if its topology is
+    /// unknown)
+    pub fn llc_of_cpu(&self, cpu: CpuId) -> Option<u32> {
+        self.topology.llc_id(cpu)
+    }
+
+    /// Core scheduling cookie of a task (0 if untagged)
+    pub fn core_cookie(&self, task: TaskId) -> u64 {
+        self.core_cookies.read().get(&task).copied().unwrap_or(0)
+    }
+
+    /// Check whether every busy SMT sibling of `cpu` runs a task with the same cookie
+    fn siblings_allow(&self, cpu: CpuId, task: TaskId) -> bool {
+        let cookie = self.core_cookie(task);
+        self.topology.smt_siblings(cpu).iter()
+            .filter(|&sibling| sibling != cpu)
+            .map(|sibling| self.per_cpu_data.get(sibling))
+            .all(|data| !data.core_busy.load(Ordering::Acquire)
+                 || data.core_cookie.load(Ordering::Acquire) == cookie)
+    }
+
+    /// Record that `cpu` runs `task`, ending any forced idle period
+    fn mark_core_busy(&self, cpu: CpuId, task: TaskId) {
+        let data = self.per_cpu_data.get(cpu);
+        data.core_cookie.store(self.core_cookie(task), Ordering::Release);
+        data.core_busy.store(true, Ordering::Release);
+
+        let start = data.force_idle_start.swap(0, Ordering::AcqRel);
+        if start != 0 {
+            let idle = Timestamp::now().as_nanos().saturating_sub(start);
+            data.local_stats.force_idle_time.fetch_add(idle, Ordering::Relaxed);
+            self.global_stats.force_idle_time.fetch_add(idle, Ordering::Relaxed);
+        }
+    }
+
+    /// Enhanced scheduler debugging with detailed information
+    pub fn debug_info(&self) -> KernelResult<()> {
+        if !self.config.load().debug_enabled {
+            return Ok(());
+        }
+        
+        kernel_info!("=== Scheduler Debug Information ===");
+        kernel_info!("State: {:?}", self.get_state());
+        kernel_info!("Uptime: {} ticks", self.uptime_ticks());
+        
+        // Global statistics
+        let stats = &self.global_stats;
+        kernel_info!("Context switches: {}", stats.context_switches.load(Ordering::Relaxed));
+        kernel_info!("Preemptions: {}", stats.preemptions.load(Ordering::Relaxed));
+        kernel_info!("Migrations: {}", stats.migrations.load(Ordering::Relaxed));
+        kernel_info!("Load balance calls: {}", stats.load_balance_calls.load(Ordering::Relaxed));
+        kernel_info!("Last load balance: {} ns", self.last_balance_time());
+        kernel_info!("Schedule failures: {}", stats.schedule_failures.load(Ordering::Relaxed));
+        kernel_info!("RT throttled: {}", stats.rt_throttled.load(Ordering::Relaxed));
+        kernel_info!("Deadline misses: {}", stats.deadline_misses.load(Ordering::Relaxed));
+        kernel_info!("RT stalls: {}", stats.rt_stalls.load(Ordering::Relaxed));
         kernel_info!("Avg schedule latency: {} ns", stats.avg_schedule_latency.load(Ordering::Relaxed));
         kernel_info!("Peak schedule latency: {} ns", stats.peak_schedule_latency.load(Ordering::Relaxed));
+        kernel_info!("Avg switch latency: {} ns", stats.avg_switch_latency.load(Ordering::Relaxed));
+        kernel_info!("Peak switch latency: {} ns", stats.peak_switch_latency.load(Ordering::Relaxed));
+        kernel_info!("Discarded latency samples: {}", stats.discarded_latency_samples.load(Ordering::Relaxed));
+        kernel_info!("Core sched force idle: {} ns", stats.force_idle_time.load(Ordering::Relaxed));
+        kernel_info!("CPU idle time: {} us", stats.cpu_idle_time.load(Ordering::Relaxed));
         kernel_info!("System load: {:.1}%", stats.system_load_percent());
+        for (policy, counts) in SchedPolicy::ALL.iter().zip(stats.policy_snapshot()) {
+            if counts != PolicyStatsSnapshot::default() {
+                kernel_info!("{:?}: {} switches in, {} out, {} preemptions, {} preempted",
+                            policy, counts.switches_in, counts.switches_out, counts.preemptions, counts.preempted);
+            }
+        }
+        for (reason, count) in SwitchReason::ALL.iter().zip(stats.switch_reason_snapshot()) {
+            kernel_info!("Switches, {:?}: {}", reason, count);
+        }
         
         // Per-CPU information
         self.debug_per_cpu_info()?;
         
         // Scheduler-specific debug info
         self.debug.print_scheduler_info()?;
+        self.topology.print_topology_info();
+        self.isolation.print_isolation_info();
+        self.loadavg.print_loadavg_info();
+        self.autogroup.print_autogroup_info();
+        self.stop_task.print_stop_task_info();
+        self.kthread.print_kthread_info();
+        self.clock.print_clock_info();
+        self.membarrier.print_membarrier_info();
+        self.domains.print_domains();
         self.fair.print_fair_info()?;
+        self.fair.print_numa_info();
+        self.preempt.print_preempt_info();
+        self.features.print_features_info();
         self.rt.print_rt_info()?;
         self.deadline.print_deadline_info()?;
         self.idle.print_idle_info()?;
+        self.trace.print_trace_info();
+        self.wait.print_wait_info();
+        self.swait.print_swait_info();
+        self.completion.print_completion_info();
+        for action in PressureAction::ALL {
+            kernel_info!("Pressure action {:?}: active {}, enforced {}ms", action,
+                        self.pressure_action_active(action), self.pressure_action_time(action).as_nanos() / 1_000_000);
+        }
         kernel_info!("=== End of Scheduler Debug Information ===");
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+impl BalanceSource for CoreScheduler {
+    fn cpu_load(&self, cpu: CpuId) -> u64 {
+        self.pelt.runnable_avg(cpu)
+    }
+
+    fn cpu_capacity(&self, cpu: CpuId) -> u32 {
+        self.topology.cpu_capacity(cpu)
+    }
+
+    fn nr_running(&self, cpu: CpuId) -> u32 {
+        self.class_nr_running(cpu)
+    }
+
+    fn util_trend(&self, cpu: CpuId) -> i32 {
+        self.per_cpu_data.get(cpu).util_trend()
+    }
+
+    fn candidates(&self, cpu: CpuId) -> Vec<MigrationCandidate> {
+        self.fair.queued_tasks(cpu).into_iter()
+            .filter(|&task| !self.kthread.is_per_cpu(task))
+            .map(|task| MigrationCandidate {
+                task,
+                load: self.pelt.task_load(task),
+                util: self.pelt.task_util(task),
+            })
+            .collect()
+    }
+
+    fn can_run_on(&self, task: TaskId, cpu: CpuId) -> bool {
+        if !self.kthread.may_run_on(task, cpu) {
+            return false;
+        }
+        Task::get_by_id(task)
+            .map(|t| {
+                let affinity = t.cpu_affinity();
+                affinity.contains(cpu) && self.isolation.task_allowed_on(&affinity, cpu)
+            })
+            .unwrap_or(false)
+    }
+
+    fn move_task(&self, task: TaskId, dst: CpuId) -> KernelResult<()> {
+        let task = Task::get_by_id(task).ok_or(SchedulerError::TaskNotFound)?;
+        self.migrate_task(&task, dst)
+    }
+
+    fn cpu_distance(&self, from: CpuId, to: CpuId) -> u8 {
+        self.topology.cpu_distance(from, to)
+    }
+
+    fn prefers_cpu(&self, task: TaskId, cpu: CpuId) -> Option<bool> {
+        self.fair.preferred_node(task).map(|node| self.topology.node_of_cpu(cpu) == node)
+    }
+
+    fn shares_cache_group(&self, task: TaskId, cpu: CpuId) -> Option<bool> {
+        let members = self.cache_group_cpus(task);
+        if members.is_empty() {
+            return None;
+        }
+        let llc = self.topology.llc_id(cpu);
+        Some(members.iter().any(|&member| self.topology.llc_id(member) == llc))
+    }
+
+    fn running_task(&self, cpu: CpuId) -> Option<TaskId> {
+        self.fair.curr_task(cpu).filter(|&task| !self.kthread.is_per_cpu(task))
+    }
+
+    fn push_running_task(&self, task: TaskId, cpu: CpuId, dst: CpuId) -> KernelResult<()> {
+        self.stop_task.queue_push(cpu, task, dst)
+    }
+}
+
+impl RunqueueSource for CoreScheduler {
+    fn current_task(&self, cpu: CpuId) -> Option<TaskId> {
+        *self.per_cpu_data.get(cpu).current_task.lock()
+    }
+
+    fn nr_running(&self, cpu: CpuId) -> u32 {
+        self.class_nr_running(cpu)
+    }
+
+    fn min_vruntime(&self, cpu: CpuId) -> u64 {
+        self.fair.min_vruntime(cpu)
+    }
+
+    fn cfs_tasks(&self, cpu: CpuId) -> Vec<(TaskId, u64)> {
+        self.fair.timeline(cpu)
+    }
+
+    fn pelt_load(&self, cpu: CpuId) -> (u64, u64) {
+        (self.pelt.runnable_avg(cpu), self.pelt.blocked_avg(cpu))
+    }
+
+    fn rt_tasks(&self, cpu: CpuId) -> Vec<(u8, Vec<TaskId>)> {
+        self.rt.run_lists(cpu)
+    }
+
+    fn dl_tasks(&self, cpu: CpuId) -> Vec<(TaskId, u64)> {
+        self.deadline.deadline_tree(cpu)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wakeups_during_suspend_are_held_once_in_order() {
+        let mut suspend = SuspendState::default();
+        let (a, b) = (TaskId::new(1), TaskId::new(2));
+        assert!(!suspend.hold(a));
+
+        suspend.begin(1_000);
+        assert!(suspend.hold(b));
+        assert!(suspend.hold(a));
+        assert!(suspend.hold(b));
+
+        // Resume takes every held wakeup and leaves nothing behind
+        let saved = suspend.end();
+        assert_eq!(saved.since, 1_000);
+        assert_eq!(saved.held, [b, a]);
+        assert!(suspend.held.is_empty());
+        assert_eq!(suspend.since, 0);
+        assert!(!suspend.hold(a));
+    }
+
+    #[test]
+    fn test_wakeups_held_across_suspend_become_runnable_on_resume() {
+        let suspend = SpinLock::new(SuspendState::default());
+        let mut rq = CfsRq::new();
+        // Same path as `wake_up_task`: held while suspended, else enqueued
+        let wake = |suspend: &SpinLock<SuspendState>, rq: &mut CfsRq, id: TaskId| -> KernelResult<()> {
+            if !suspend.lock().hold(id) {
+                rq.enqueue(id, NICE_0_LOAD, false, 0, TaskGroup::ROOT);
+            }
+            Ok(())
+        };
+        let (a, b, c) = (TaskId::new(1), TaskId::new(2), TaskId::new(3));
+
+        wake(&suspend, &mut rq, a).unwrap();
+        assert_eq!(rq.nr_running(), 1);
+
+        suspend.lock().begin(1_000);
+        wake(&suspend, &mut rq, b).unwrap();
+        wake(&suspend, &mut rq, c).unwrap();
+        wake(&suspend, &mut rq, b).unwrap();
+        // Nothing queued while suspended
+        assert_eq!(rq.nr_running(), 1);
+        assert_eq!(suspend.lock().held, [b, c]);
+
+        let mut saved = suspend.lock().end();
+        saved.release(|id| wake(&suspend, &mut rq, id)).unwrap();
+
+        assert_eq!(rq.nr_running(), 3);
+        let mut queued = rq.queued();
+        queued.sort();
+        assert_eq!(queued, [a, b, c]);
+        assert!(saved.held.is_empty());
+        assert!(suspend.lock().held.is_empty());
+    }
+
+    #[test]
+    fn test_stalls_are_reported_once_and_skip_tickless_cpus() {
+        const MS: u64 = 1_000_000;
+        let data = PerCpuSchedulerData::default();
+        // Never scheduled
+        assert_eq!(data.check_stall(100 * MS, 10 * MS, false), None);
+
+        data.last_schedule_time.store(5 * MS, Ordering::Release);
+        assert_eq!(data.check_stall(15 * MS, 10 * MS, false), None);
+        // Found by every check, reported by the first one only
+        assert_eq!(data.check_stall(20 * MS, 10 * MS, false), Some((15 * MS, true)));
+        assert_eq!(data.check_stall(30 * MS, 10 * MS, false), Some((25 * MS, false)));
+        // A nohz_full CPU running its only task, or an idle CPU, is not stalled
+        assert_eq!(data.check_stall(30 * MS, 10 * MS, true), None);
+        data.idle_start.store(30 * MS, Ordering::Release);
+        assert_eq!(data.check_stall(40 * MS, 10 * MS, false), None);
+        data.idle_start.store(0, Ordering::Release);
+
+        // Once it scheduled again, the next stall is reported anew
+        data.last_schedule_time.store(50 * MS, Ordering::Release);
+        assert_eq!(data.check_stall(55 * MS, 10 * MS, false), None);
+        assert_eq!(data.check_stall(70 * MS, 10 * MS, false), Some((20 * MS, true)));
+    }
+
+    #[test]
+    fn test_switches_are_counted_per_policy() {
+        let stats = SchedulerStats::default();
+        stats.account_switch(None, SchedPolicy::Normal);
+        stats.account_switch(Some((SchedPolicy::Normal, true)), SchedPolicy::Deadline);
+        stats.account_switch(Some((SchedPolicy::Deadline, false)), SchedPolicy::Normal);
+
+        let counts = stats.policy_snapshot();
+        let normal = counts[SchedPolicy::Normal as usize];
+        let deadline = counts[SchedPolicy::Deadline as usize];
+        assert_eq!((normal.switches_in, normal.switches_out, normal.preempted), (2, 1, 1));
+        assert_eq!((deadline.switches_in, deadline.switches_out, deadline.preemptions), (1, 1, 1));
+        assert_eq!(counts[SchedPolicy::Fifo as usize], PolicyStatsSnapshot::default());
+
+        stats.reset();
+        assert!(stats.policy_snapshot().iter().all(|c| *c == PolicyStatsSnapshot::default()));
+    }
+
+    #[test]
+    fn test_config_readers_keep_their_snapshot_across_updates() {
+        let flip = |config: &mut SchedulerConfig| {
+            config.load_balance.aggressive_balance = !config.load_balance.aggressive_balance;
+            config.tick_history_len += 1;
+        };
+        let config = RcuCell::new(SchedulerConfig::default());
+        let held = config.load();
+        config.update(flip);
+        config.update(flip);
+
+        // The held snapshot stays whole and unchanged across both updates
+        assert_eq!(held.tick_history_len, DEFAULT_TICK_HISTORY);
+        assert!(!held.load_balance.aggressive_balance);
+        let current = config.load();
+        assert_eq!(current.tick_history_len, DEFAULT_TICK_HISTORY + 2);
+        assert!(!current.load_balance.aggressive_balance);
+
+        // Only the reader kept the replaced snapshot alive
+        assert_eq!(Arc::strong_count(&held), 1);
+        let replaced = Arc::downgrade(&held);
+        drop(held);
+        assert!(replaced.upgrade().is_none());
+        // The intermediate snapshot was released by the second update
+        assert_eq!(Arc::strong_count(&current), 2);
+    }
+
+    #[test]
+    fn test_scheduler_idle_time_matches_idle_state_usage() {
+        let cpu = CpuId::new(61);
+        let stats = SchedulerStats::default();
+        for &(state, start, len) in &[(1, 1_000, 250), (3, 2_000, 1_200), (1, 5_000, 75)] {
+            idle_enter_at(cpu, state, start);
+            stats.account_idle(idle_exit_at(cpu, start + len));
+        }
+        // Leaving idle twice charges the period once
+        stats.account_idle(idle_exit_at(cpu, 9_000));
+
+        let per_state: u64 = get_idle_statistics_for(cpu).state_usage_time.iter().map(|&(_, time)| time).sum();
+        assert_eq!(per_state, 1_525);
+        assert_eq!(stats.cpu_idle_time.load(Ordering::Relaxed), per_state);
+    }
+
+    #[test]
+    fn test_bogus_latency_samples_are_discarded() {
+        let stats = SchedulerStats::default();
+        assert!(stats.record_schedule_latency(1_000, 9_000));
+        assert!(stats.record_schedule_latency(2_000, 18_000));
+        // End read on a CPU whose clock lags, and a sample spanning seconds
+        assert!(!stats.record_schedule_latency(5_000, 4_000));
+        assert!(!stats.record_switch_latency(0, 2 * MAX_LATENCY_SAMPLE_NS));
+        assert!(stats.record_switch_latency(0, MAX_LATENCY_SAMPLE_NS));
+
+        assert_eq!(stats.peak_schedule_latency.load(Ordering::Relaxed), 16_000);
+        assert_eq!(stats.avg_schedule_latency.load(Ordering::Relaxed), 9_000);
+        assert_eq!(stats.peak_switch_latency.load(Ordering::Relaxed), MAX_LATENCY_SAMPLE_NS);
+        assert_eq!(stats.discarded_latency_samples.load(Ordering::Relaxed), 2);
+
+        stats.reset();
+        assert_eq!(stats.discarded_latency_samples.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_switches_are_counted_per_reason() {
+        let stats = SchedulerStats::default();
+        stats.account_switch_reason(SwitchReason::Voluntary);
+        stats.account_switch_reason(SwitchReason::Voluntary);
+        stats.account_switch_reason(SwitchReason::SliceExpired);
+        stats.account_switch_reason(SwitchReason::IdleEntry);
+
+        assert_eq!(stats.switch_reason_snapshot(), [2, 0, 1, 0, 1]);
+        let involuntary: u64 = SwitchReason::ALL.iter().zip(stats.switch_reason_snapshot())
+            .filter(|(reason, _)| reason.is_involuntary())
+            .map(|(_, count)| count)
+            .sum();
+        assert_eq!(involuntary, 1);
+
+        stats.reset();
+        assert_eq!(stats.switch_reason_snapshot(), [0; NR_SWITCH_REASONS]);
+    }
+}
@@ -7,17 +7,29 @@
 //! ## Features
 //! - Dynamic frequency scaling with validation
 //! - Governor-based frequency management policies
-//! - Thermal throttling protection
+//! - Thermal zones with per-zone trip points and a throttle map
 //! - Performance monitoring and statistics
 //! - Safe frequency transitions with hardware limits
 //! - Multi-core frequency coordination
+//! - Tunable thresholds and sampling rate for load-based governors
+//! - Transition notifiers for frequency-dependent subsystems
+//! - Package power budget enforced through a frequency ceiling
+//! - Per-frequency-domain scaling for asymmetric (big.LITTLE) CPUs
+//! - Transition latency accounting and a non-sleeping fast switch path
+//! - Userspace setpoint kept across governor switches and thermal clamps
+//! - Downshifts of a critically hot CPU bypass the frequency change rate
+//!   limit
+//! - I/O wait boost: CPUs waking tasks from I/O wait ramp their frequency
+//!   up, and back down once the I/O wakeups stop
 //!
 //! ## Supported Governors
 //! - **Performance**: Maximum frequency for high performance
 //! - **Powersave**: Minimum frequency for power efficiency
 //! - **Ondemand**: Dynamic scaling based on CPU load
 //! - **Conservative**: Gradual frequency adjustments
-//! - **Userspace**: Manual frequency control
+//! - **Userspace**: Manual frequency control, re-applied once thermal and
+//!   power caps allow it
+//! - **Schedutil**: Per-domain frequency from scheduler utilization
 //!
 //! ## Usage
 //! ```rust
@@ -42,10 +54,15 @@ use crate::kernel::scheduler::cpufreq::cpufreq_impl::{
     CpuFreqImplResult, CpuFreqImplConfig
 };
 use crate::kernel::log::{kernel_info, kernel_warn, kernel_error, kernel_debug};
-use crate::kernel::time::get_current_time_us;
+use crate::kernel::sync::{SpinLock, RwLock};
+use crate::kernel::cpu::{CpuId, CpuMask};
+use crate::kernel::time::{get_current_time_us, Timestamp};
+use crate::arch::cpu::current_cpu_id;
 use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::cmp::Reverse;
 use alloc::vec::Vec;
 use alloc::string::String;
+use alloc::collections::BTreeMap;
 
 pub mod cpufreq_impl;
 
@@ -60,9 +77,101 @@ const MIN_SAFE_FREQUENCY: u64 = 400_000_000;  // 400 MHz
 const MAX_SAFE_FREQUENCY: u64 = 5_000_000_000; // 5 GHz
 const FREQ_CHANGE_MIN_INTERVAL_US: u64 = 10_000; // 10ms minimum between changes
 
-/// Thermal throttling thresholds
-const THERMAL_THROTTLE_TEMP: u64 = 85; // 85°C
-const THERMAL_CRITICAL_TEMP: u64 = 95; // 95°C
+/// Transitions slower than this are expensive, e.g. a PLL relock rather
+/// than a step on the same voltage rail
+const EXPENSIVE_TRANSITION_NS: u64 = 100_000; // 100us
+
+/// Smallest step, in percent of the maximum frequency, that is worth an
+/// expensive transition at any time
+const MARGINAL_STEP_PERCENT: u64 = 10;
+
+/// Time the frequency must have been stable before a governor makes a
+/// marginal expensive transition
+const MARGINAL_TRANSITION_HOLD_US: u64 = 100_000; // 100ms
+
+/// Default trip points of a thermal zone (in Celsius)
+pub const DEFAULT_PASSIVE_TEMP: u64 = 85; // 85°C
+pub const DEFAULT_CRITICAL_TEMP: u64 = 95; // 95°C
+
+/// Thermal throttling hysteresis limits (in Celsius)
+pub const DEFAULT_THERMAL_HYSTERESIS_CELSIUS: u64 = 5;
+const MAX_THERMAL_HYSTERESIS_CELSIUS: u64 = 20;
+
+/// Thermal zone of the CPU package, backed by the platform driver
+pub const CPU_THERMAL_ZONE: u32 = 0;
+
+/// Governor sampling rate limits (in microseconds)
+const MIN_SAMPLING_RATE_US: u64 = FREQ_CHANGE_MIN_INTERVAL_US;
+const MAX_SAMPLING_RATE_US: u64 = 1_000_000; // 1s
+
+/// Frequency schedutil requests relative to the utilization, in percent
+const SCHEDUTIL_HEADROOM_PERCENT: u64 = 125;
+
+/// I/O wait boost of the first I/O wakeup, in utilization (0-1024)
+const IO_BOOST_MIN: u32 = 1024 / 8;
+
+/// Largest I/O wait boost, in utilization (0-1024)
+const IO_BOOST_MAX: u32 = 1024;
+
+/// Time without an I/O wakeup after which the boost halves; one frequency
+/// change interval, so a boost is seen by at least one governor update
+const IO_BOOST_DECAY_US: u64 = FREQ_CHANGE_MIN_INTERVAL_US;
+
+/// Tunables of the Ondemand governor
+static ONDEMAND_TUNABLES: SpinLock<GovernorTunables> = SpinLock::new(GovernorTunables::ondemand_default());
+
+/// Tunables of the Conservative governor
+static CONSERVATIVE_TUNABLES: SpinLock<GovernorTunables> = SpinLock::new(GovernorTunables::conservative_default());
+
+/// Timestamp of the last governor sample
+static LAST_GOVERNOR_SAMPLE: AtomicU64 = AtomicU64::new(0);
+
+/// Registered frequency transition notifiers
+static TRANSITION_NOTIFIERS: RwLock<Vec<(NotifierHandle, TransitionNotifier)>> = RwLock::new(Vec::new());
+
+/// Next transition notifier handle to hand out
+static NEXT_NOTIFIER_HANDLE: AtomicU64 = AtomicU64::new(1);
+
+/// Sum of observed transition latencies (in nanoseconds)
+static TRANSITION_LATENCY_SUM_NS: AtomicU64 = AtomicU64::new(0);
+
+/// Number of observed transitions
+static TRANSITION_LATENCY_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Frequency cap of the most restrictive thermal zone at its last sample
+/// (0 when uncapped)
+static THERMAL_CAP: AtomicU64 = AtomicU64::new(0);
+
+/// Whether a thermal zone was above its critical trip point at its last sample
+static THERMAL_CRITICAL: AtomicBool = AtomicBool::new(false);
+
+/// Last frequency requested under the Userspace governor (0 when none)
+static USERSPACE_SETPOINT: AtomicU64 = AtomicU64::new(0);
+
+/// I/O wait boost of every CPU that woke a task from I/O wait
+static IO_BOOSTS: SpinLock<BTreeMap<CpuId, IoBoost>> = SpinLock::new(BTreeMap::new());
+
+/// Time CPUs spent I/O boosted in boosts that have ended (us)
+static IO_BOOST_TIME_US: AtomicU64 = AtomicU64::new(0);
+
+/// Registered frequency domains
+static FREQ_DOMAINS: RwLock<Vec<FrequencyDomain>> = RwLock::new(Vec::new());
+
+/// Frequency of each CPU relative to its maximum (0-1024) as of its last transition
+static FREQ_SCALES: SpinLock<BTreeMap<CpuId, u32>> = SpinLock::new(BTreeMap::new());
+
+/// Registered thermal zones
+static THERMAL_ZONES: RwLock<Vec<ThermalZone>> = RwLock::new(Vec::new());
+
+/// Package power budget in mW (0 when no budget is set)
+static POWER_BUDGET_MW: AtomicU64 = AtomicU64::new(0);
+
+/// Frequency ceiling enforcing the power budget (0 when no budget is set)
+static POWER_CEILING: AtomicU64 = AtomicU64::new(0);
+
+/// Degrees below the passive trip point a zone must cool to before
+/// throttling disengages
+static THERMAL_HYSTERESIS_CELSIUS: AtomicU64 = AtomicU64::new(DEFAULT_THERMAL_HYSTERESIS_CELSIUS);
 
 /// CPU frequency governors
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -77,6 +186,8 @@ pub enum Governor {
     Conservative,
     /// Manual frequency control
     Userspace,
+    /// Per-domain scaling driven by scheduler utilization
+    Schedutil,
 }
 
 impl Governor {
@@ -88,7 +199,320 @@ impl Governor {
             Governor::Ondemand => "ondemand",
             Governor::Conservative => "conservative",
             Governor::Userspace => "userspace",
+            Governor::Schedutil => "schedutil",
+        }
+    }
+}
+
+/// CPUs that share a clock and always run at the same frequency
+///
+/// On asymmetric systems every cluster is its own domain: its
+/// utilization is measured against its own capacity, so a little cluster
+/// can run flat out while the big cluster idles at its lowest frequency.
+#[derive(Debug, Clone)]
+pub struct FrequencyDomain {
+    /// Domain identifier known to the platform driver
+    pub id: u32,
+    /// CPUs of the domain
+    pub cpus: CpuMask,
+    /// Available frequencies in Hz, ascending
+    pub frequencies: Vec<u64>,
+    /// Capacity of the domain's CPUs at their highest frequency (1024 = biggest core)
+    pub max_capacity: u32,
+    /// Frequency the domain runs at (Hz)
+    pub current_frequency: u64,
+    /// Time of the last schedutil update (us)
+    last_update: u64,
+    /// Time of the last frequency change (us)
+    last_change: u64,
+}
+
+impl FrequencyDomain {
+    /// Creates a domain running at its lowest frequency
+    pub fn new(id: u32, cpus: CpuMask, mut frequencies: Vec<u64>, max_capacity: u32) -> Self {
+        frequencies.sort_unstable();
+        frequencies.dedup();
+        let current_frequency = frequencies.first().copied().unwrap_or(0);
+        Self {
+            id,
+            cpus,
+            frequencies,
+            max_capacity,
+            current_frequency,
+            last_update: 0,
+            last_change: 0,
+        }
+    }
+
+    /// Returns the frequency schedutil selects for the domain
+    ///
+    /// The busiest CPU decides: its utilization, relative to the domain's
+    /// capacity and with 25% headroom, scales the highest frequency. The
+    /// result is the lowest available frequency at or above that target.
+    ///
+    /// # Arguments
+    /// * `util` - Frequency- and capacity-invariant utilization of a CPU (0-1024)
+    pub fn schedutil_frequency(&self, util: impl Fn(CpuId) -> u32) -> u64 {
+        let max_freq = match self.frequencies.last() {
+            Some(&max) => max,
+            None => return 0,
+        };
+        let capacity = self.max_capacity.max(1) as u64;
+        let max_util = self.cpus.iter().map(util).max().unwrap_or(0) as u64;
+        let target = max_freq * max_util.min(capacity) * SCHEDUTIL_HEADROOM_PERCENT / (capacity * 100);
+        self.frequencies.iter()
+            .find(|&&f| f >= target)
+            .copied()
+            .unwrap_or(max_freq)
+    }
+}
+
+/// I/O wait boost of a CPU
+///
+/// Each I/O wakeup doubles the boost, starting from `IO_BOOST_MIN`, up to
+/// `IO_BOOST_MAX`; every `IO_BOOST_DECAY_US` without one halves it until
+/// it drops below `IO_BOOST_MIN` and ends.
+#[derive(Debug, Clone, Copy, Default)]
+struct IoBoost {
+    /// Current boost in utilization (0 when not boosted)
+    util: u32,
+    /// Time the boost last doubled or decayed (us)
+    updated: u64,
+    /// Start of the current boost (us)
+    since: u64,
+}
+
+impl IoBoost {
+    /// Decay the boost to `now`
+    ///
+    /// Returns the length of the boost if it ended (us).
+    fn decay(&mut self, now: u64) -> Option<u64> {
+        if self.util == 0 {
+            return None;
+        }
+        let halvings = now.saturating_sub(self.updated) / IO_BOOST_DECAY_US;
+        if halvings == 0 {
+            return None;
+        }
+        self.util = self.util.checked_shr(halvings.min(u32::MAX as u64) as u32).unwrap_or(0);
+        self.updated += halvings * IO_BOOST_DECAY_US;
+        if self.util >= IO_BOOST_MIN {
+            return None;
+        }
+        self.util = 0;
+        Some(self.updated.saturating_sub(self.since))
+    }
+
+    /// Account an I/O wakeup at `now`
+    ///
+    /// Returns the length of a boost that ended before it (us).
+    fn wakeup(&mut self, now: u64) -> Option<u64> {
+        let ended = self.decay(now);
+        if self.util == 0 {
+            self.util = IO_BOOST_MIN;
+            self.since = now;
+        } else {
+            self.util = (self.util * 2).min(IO_BOOST_MAX);
+        }
+        self.updated = now;
+        ended
+    }
+}
+
+/// Load-based governor tunables
+///
+/// Shared layout for the Ondemand and Conservative governors. `freq_step`
+/// is only meaningful for Conservative.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GovernorTunables {
+    /// Load percentage above which the governor scales up
+    pub up_threshold: u64,
+    /// Load percentage below which the governor scales down
+    pub down_threshold: u64,
+    /// Minimum interval between two governor samples (in microseconds)
+    pub sampling_rate_us: u64,
+    /// Frequency step as a percentage of the maximum frequency (Conservative only)
+    pub freq_step: u64,
+}
+
+impl GovernorTunables {
+    /// Default tunables for the Ondemand governor
+    pub const fn ondemand_default() -> Self {
+        Self {
+            up_threshold: 80,
+            down_threshold: 20,
+            sampling_rate_us: 20_000,
+            freq_step: 0,
+        }
+    }
+
+    /// Default tunables for the Conservative governor
+    pub const fn conservative_default() -> Self {
+        Self {
+            up_threshold: 80,
+            down_threshold: 20,
+            sampling_rate_us: 40_000,
+            freq_step: 5,
+        }
+    }
+
+    /// Validates the tunables as a whole
+    fn validate(&self) -> CpuFreqImplResult<()> {
+        if self.up_threshold == 0 || self.up_threshold > 100 {
+            return Err(CpuFreqImplError::InvalidParameter);
         }
+        if self.down_threshold >= self.up_threshold {
+            return Err(CpuFreqImplError::InvalidParameter);
+        }
+        if self.sampling_rate_us < MIN_SAMPLING_RATE_US || self.sampling_rate_us > MAX_SAMPLING_RATE_US {
+            return Err(CpuFreqImplError::InvalidParameter);
+        }
+        if self.freq_step > 100 {
+            return Err(CpuFreqImplError::InvalidParameter);
+        }
+        Ok(())
+    }
+}
+
+/// Phase of a frequency transition reported to notifiers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransitionPhase {
+    /// The frequency is about to change
+    PreChange,
+    /// The frequency change has completed (or was rolled back)
+    PostChange,
+}
+
+/// Frequency transition callback
+///
+/// Receives the phase, the old and new frequency in Hz and the CPU
+/// performing the change. Callbacks run with the notifier list read-locked
+/// and must not register or unregister notifiers themselves. They may run
+/// from the scheduler tick through the fast switch path and must not sleep.
+pub type TransitionNotifier = fn(phase: TransitionPhase, old: u64, new: u64, cpu: CpuId);
+
+/// Handle identifying a registered transition notifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NotifierHandle(u64);
+
+/// Thermal zone temperature sensor
+///
+/// Returns the current temperature of the zone in Celsius. Sensors run with
+/// the thermal zone list locked and must not register or unregister zones.
+pub type ThermalSensor = fn() -> CpuFreqImplResult<u64>;
+
+/// Trip points of a thermal zone (in Celsius)
+///
+/// Above `passive` the zone caps the frequency. Once engaged, throttling
+/// only disengages when the zone cools below `passive` minus the thermal
+/// hysteresis; while engaged the cap falls linearly from the maximum
+/// frequency at that release temperature down to the minimum frequency at
+/// `critical`. Above `critical` no frequency increase is allowed at all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TripPoints {
+    /// Temperature at which passive throttling starts
+    pub passive: u64,
+    /// Temperature at which frequency increases are rejected
+    pub critical: u64,
+}
+
+impl TripPoints {
+    /// Creates trip points
+    pub const fn new(passive: u64, critical: u64) -> Self {
+        Self { passive, critical }
+    }
+
+    /// Validates the trip points as a whole
+    fn validate(&self) -> CpuFreqImplResult<()> {
+        if self.passive == 0 || self.passive >= self.critical {
+            return Err(CpuFreqImplError::InvalidParameter);
+        }
+        Ok(())
+    }
+
+    /// Frequency cap of an engaged zone releasing at `release` degrees
+    fn throttle_frequency(&self, temperature: u64, release: u64, min_freq: u64, max_freq: u64) -> u64 {
+        if temperature >= self.critical {
+            return min_freq;
+        }
+        let over = temperature.saturating_sub(release);
+        let span = self.critical - release;
+        max_freq - (max_freq - min_freq) * over / span
+    }
+}
+
+impl Default for TripPoints {
+    fn default() -> Self {
+        Self::new(DEFAULT_PASSIVE_TEMP, DEFAULT_CRITICAL_TEMP)
+    }
+}
+
+/// A registered thermal zone
+struct ThermalZone {
+    id: u32,
+    sensor: ThermalSensor,
+    trips: TripPoints,
+    /// Whether throttling is engaged
+    throttled: bool,
+    /// Number of throttle engage and disengage transitions
+    transitions: u64,
+    /// When the current throttled period started (in microseconds)
+    throttle_start: u64,
+    /// Time spent throttled in completed periods (in microseconds)
+    throttle_time: u64,
+    /// CPUs whose temperature the zone measures (empty if unattributed)
+    cpus: CpuMask,
+}
+
+impl ThermalZone {
+    /// Reads the sensor and updates the throttle state of the zone
+    fn sample(&mut self, now: u64, min_freq: u64, max_freq: u64) -> CpuFreqImplResult<ThermalInfo> {
+        let temperature = (self.sensor)()?;
+        let release = self.trips.passive.saturating_sub(get_thermal_hysteresis());
+        let throttled = if self.throttled {
+            temperature >= release
+        } else {
+            temperature > self.trips.passive
+        };
+        if throttled != self.throttled {
+            if throttled {
+                self.throttle_start = now;
+                kernel_debug!("Thermal zone {} throttling engaged at {} °C", self.id, temperature);
+            } else {
+                self.throttle_time += now - self.throttle_start;
+                kernel_debug!("Thermal zone {} throttling disengaged at {} °C", self.id, temperature);
+            }
+            self.throttled = throttled;
+            self.transitions += 1;
+        }
+        
+        let throttle_time = if throttled {
+            self.throttle_time + (now - self.throttle_start)
+        } else {
+            self.throttle_time
+        };
+        Ok(ThermalInfo {
+            temperature,
+            throttled,
+            throttle_frequency: throttled
+                .then(|| self.trips.throttle_frequency(temperature, release, min_freq, max_freq)),
+            throttle_time,
+            throttle_transitions: self.transitions,
+        })
+    }
+}
+
+/// Sampled state of one thermal zone
+struct ZoneSample {
+    id: u32,
+    info: ThermalInfo,
+    critical: bool,
+}
+
+impl ZoneSample {
+    /// Ordering key: critical first, then lowest frequency cap, then hottest
+    fn restriction(&self) -> (bool, Reverse<u64>, u64) {
+        (self.critical, Reverse(self.info.throttle_frequency.unwrap_or(u64::MAX)), self.info.temperature)
     }
 }
 
@@ -115,6 +539,15 @@ pub struct CpuFreqStats {
     pub thermal_throttled: bool,
     /// Power consumption estimate (in mW, if available)
     pub power_consumption: Option<u64>,
+    /// Whether a package power budget is set
+    pub power_budget_active: bool,
+    /// Frequency ceiling currently enforced by the power budget
+    pub power_ceiling: Option<u64>,
+    /// Average observed transition latency in nanoseconds (0 before the
+    /// first transition)
+    pub average_transition_latency: u64,
+    /// Time CPUs spent I/O boosted (us)
+    pub io_boost_time_us: u64,
 }
 
 /// Thermal throttling information
@@ -128,6 +561,8 @@ pub struct ThermalInfo {
     pub throttle_frequency: Option<u64>,
     /// Time spent throttled (in microseconds)
     pub throttle_time: u64,
+    /// Number of throttle engage and disengage transitions
+    pub throttle_transitions: u64,
 }
 
 /// Initializes the CPU frequency management module with enhanced configuration
@@ -177,6 +612,10 @@ pub fn init_with_config(config: CpuFreqImplConfig) -> CpuFreqImplResult<()> {
     CpuFreq::set_impl(cpufreq_impl);
     INITIALIZED.store(true, Ordering::Release);
     
+    if let Err(e) = register_thermal_zone(CPU_THERMAL_ZONE, cpu_package_temperature) {
+        kernel_warn!("Failed to register CPU thermal zone: {:?}", e);
+    }
+    
     // Log initialization details
     if let Ok(freqs) = get_available_frequencies() {
         kernel_info!("Available frequencies: {:?} MHz", 
@@ -225,10 +664,14 @@ pub fn get_current_frequency() -> CpuFreqImplResult<u64> {
 ///
 /// # Safety
 /// - Validates frequency is within safe operating limits
-/// - Checks thermal conditions before frequency changes
+/// - Checks every thermal zone before frequency increases
 /// - Enforces minimum time between frequency changes
 /// - Verifies frequency is available on the hardware
 ///
+/// Under the Userspace governor a valid request also becomes the setpoint,
+/// even if it is rate limited or capped for now: the governor re-applies it
+/// once the caps lift.
+///
 /// # Examples
 /// ```rust
 /// // Set to 2.4 GHz
@@ -239,12 +682,120 @@ pub fn get_current_frequency() -> CpuFreqImplResult<u64> {
 /// cpufreq::set_frequency(max_freq)?;
 /// ```
 pub fn set_frequency(frequency: u64) -> CpuFreqImplResult<()> {
+    let result = apply_frequency(frequency);
+    let valid = matches!(result, Ok(())
+        | Err(CpuFreqImplError::RateLimited)
+        | Err(CpuFreqImplError::PowerLimited)
+        | Err(CpuFreqImplError::ThermalThrottled));
+    if valid && matches!(get_current_governor(), Ok(Governor::Userspace)) {
+        USERSPACE_SETPOINT.store(frequency, Ordering::Release);
+    }
+    result
+}
+
+/// Gets the frequency last requested under the Userspace governor
+///
+/// The setpoint survives switches to other governors, which ignore it, and
+/// is re-applied when switching back to Userspace.
+pub fn get_userspace_setpoint() -> Option<u64> {
+    match USERSPACE_SETPOINT.load(Ordering::Acquire) {
+        0 => None,
+        setpoint => Some(setpoint),
+    }
+}
+
+/// Frequency the Userspace setpoint asks for under `governor`, after `clamp`
+/// applies the thermal and power caps; `None` if the setpoint does not apply
+fn userspace_target(governor: Governor, setpoint: u64, clamp: impl Fn(u64) -> u64) -> Option<u64> {
+    if governor != Governor::Userspace || setpoint == 0 {
+        return None;
+    }
+    Some(clamp(setpoint))
+}
+
+/// Moves the frequency towards the Userspace setpoint as far as the caps allow
+///
+/// # Returns
+/// - `Ok(frequency)` with the frequency in effect afterwards
+/// - `Err(CpuFreqImplError)` if the change could not be applied
+fn apply_userspace_setpoint(governor: Governor, current_freq: u64) -> CpuFreqImplResult<u64> {
+    let available_freqs = get_available_frequencies()?;
+    let setpoint = USERSPACE_SETPOINT.load(Ordering::Acquire);
+    let target_freq = match userspace_target(governor, setpoint, |f| {
+        clamp_to_power_ceiling(clamp_to_thermal_limit(f, &available_freqs))
+    }) {
+        Some(target) if target != current_freq => target,
+        _ => return Ok(current_freq),
+    };
+    
+    kernel_debug!("Userspace setpoint {} MHz, applying {} MHz", 
+                 setpoint / 1_000_000, target_freq / 1_000_000);
+    if !fast_switch_frequency(target_freq)? {
+        apply_governor_frequency(current_freq, target_freq)?;
+    }
+    Ok(target_freq)
+}
+
+/// Sets the CPU frequency immediately if it is a thermal-critical downshift
+///
+/// While a thermal zone is above its critical trip point, a change to a
+/// lower frequency bypasses the rate limit: a hot CPU must not wait up to
+/// `FREQ_CHANGE_MIN_INTERVAL_US` for it. The change still counts as the
+/// last one, so ordinary changes right after it remain rate limited. Any
+/// other change, including every increase, goes through `set_frequency`
+/// and its rate limit.
+///
+/// # Returns
+/// - `Ok(())` if frequency was set successfully
+/// - `Err(CpuFreqImplError)` as for `set_frequency`
+pub fn set_frequency_urgent(frequency: u64) -> CpuFreqImplResult<()> {
+    ensure_initialized()?;
+    
+    let available_freqs = get_available_frequencies()?;
+    let min_freq = *available_freqs.iter().min().ok_or(CpuFreqImplError::NoFrequenciesAvailable)?;
+    let max_freq = *available_freqs.iter().max().ok_or(CpuFreqImplError::NoFrequenciesAvailable)?;
+    let critical = most_restrictive_zone(min_freq, max_freq).map_or(false, |zone| zone.critical);
+    if !bypasses_rate_limit(get_current_frequency()?, frequency, critical) {
+        return set_frequency(frequency);
+    }
+    
+    kernel_warn!("Thermal-critical downshift to {} MHz, bypassing rate limit", frequency / 1_000_000);
+    change_frequency(frequency, true)
+}
+
+/// Whether a change from `from` to `to` may bypass the rate limit: only
+/// downshifts while a thermal zone is critical may
+fn bypasses_rate_limit(from: u64, to: u64, thermal_critical: bool) -> bool {
+    thermal_critical && to < from
+}
+
+/// Whether a change at `now` comes too soon after the one at `last_change`
+fn rate_limited(now: u64, last_change: u64) -> bool {
+    now.saturating_sub(last_change) < FREQ_CHANGE_MIN_INTERVAL_US
+}
+
+/// Applies a governor's target, bypassing the rate limit for a downshift
+/// of a critically hot CPU
+///
+/// Relies on the thermal zones having just been sampled by
+/// `clamp_to_thermal_limit`.
+fn apply_governor_frequency(current_freq: u64, target_freq: u64) -> CpuFreqImplResult<()> {
+    let critical = THERMAL_CRITICAL.load(Ordering::Acquire);
+    change_frequency(target_freq, bypasses_rate_limit(current_freq, target_freq, critical))
+}
+
+/// Sets the CPU frequency without touching the Userspace setpoint
+fn apply_frequency(frequency: u64) -> CpuFreqImplResult<()> {
+    change_frequency(frequency, false)
+}
+
+/// Sets the CPU frequency, subject to the rate limit unless `urgent`
+fn change_frequency(frequency: u64, urgent: bool) -> CpuFreqImplResult<()> {
     ensure_initialized()?;
     
     // Rate limiting check
     let current_time = get_current_time_us();
-    let last_change = LAST_FREQ_CHANGE.load(Ordering::Acquire);
-    if current_time - last_change < FREQ_CHANGE_MIN_INTERVAL_US {
+    if !urgent && rate_limited(current_time, LAST_FREQ_CHANGE.load(Ordering::Acquire)) {
         kernel_debug!("Frequency change rate limited");
         return Err(CpuFreqImplError::RateLimited);
     }
@@ -263,22 +814,29 @@ pub fn set_frequency(frequency: u64) -> CpuFreqImplResult<()> {
         return Err(CpuFreqImplError::UnsupportedFrequency);
     }
     
-    // Thermal protection check
-    if let Ok(thermal_info) = get_thermal_info() {
-        if thermal_info.temperature > THERMAL_CRITICAL_TEMP {
-            kernel_error!("CPU temperature too high ({} °C), rejecting frequency increase", 
-                         thermal_info.temperature);
-            return Err(CpuFreqImplError::ThermalThrottled);
-        }
-        
-        if thermal_info.temperature > THERMAL_THROTTLE_TEMP {
-            let current_freq = get_current_frequency()?;
-            if frequency > current_freq {
-                kernel_warn!("CPU temperature high ({} °C), limiting frequency increase", 
-                           thermal_info.temperature);
-                // Allow only conservative increases
-                let max_allowed = current_freq + (current_freq / 10); // 10% increase max
-                if frequency > max_allowed {
+    // Power budget check
+    let ceiling = POWER_CEILING.load(Ordering::Acquire);
+    if ceiling != 0 && frequency > ceiling {
+        kernel_warn!("Frequency {} MHz exceeds power budget ceiling of {} MHz", 
+                    frequency / 1_000_000, ceiling / 1_000_000);
+        return Err(CpuFreqImplError::PowerLimited);
+    }
+    
+    // Thermal protection check against every zone
+    let current_freq = get_current_frequency()?;
+    if frequency > current_freq {
+        let min_freq = *available_freqs.iter().min().ok_or(CpuFreqImplError::NoFrequenciesAvailable)?;
+        let max_freq = *available_freqs.iter().max().ok_or(CpuFreqImplError::NoFrequenciesAvailable)?;
+        if let Some(zone) = most_restrictive_zone(min_freq, max_freq) {
+            if zone.critical {
+                kernel_error!("Thermal zone {} too hot ({} °C), rejecting frequency increase", 
+                             zone.id, zone.info.temperature);
+                return Err(CpuFreqImplError::ThermalThrottled);
+            }
+            if let Some(limit) = zone.info.throttle_frequency {
+                if frequency > limit {
+                    kernel_warn!("Thermal zone {} hot ({} °C), limiting frequency to {} MHz", 
+                               zone.id, zone.info.temperature, limit / 1_000_000);
                     return Err(CpuFreqImplError::ThermalThrottled);
                 }
             }
@@ -286,17 +844,293 @@ pub fn set_frequency(frequency: u64) -> CpuFreqImplResult<()> {
     }
     
     // Perform the frequency change
-    CpuFreq::get_impl().set_frequency(frequency)
-        .map_err(|e| {
-            kernel_error!("Failed to set frequency to {} Hz: {:?}", frequency, e);
-            e
-        })?;
+    let old_frequency = current_freq;
+    notify_transition(TransitionPhase::PreChange, old_frequency, frequency);
+    
+    let transition_start = Timestamp::now().as_nanos();
+    if let Err(e) = CpuFreq::get_impl().set_frequency(frequency) {
+        kernel_error!("Failed to set frequency to {} Hz: {:?}", frequency, e);
+        notify_transition(TransitionPhase::PostChange, old_frequency, old_frequency);
+        return Err(e);
+    }
+    record_transition_latency(transition_start);
     
+    notify_transition(TransitionPhase::PostChange, old_frequency, frequency);
     LAST_FREQ_CHANGE.store(current_time, Ordering::Release);
     kernel_info!("CPU frequency set to {} MHz", frequency / 1_000_000);
     Ok(())
 }
 
+/// Switches the CPU frequency without sleeping, if the hardware can
+///
+/// The path for the scheduler tick and other atomic contexts. It skips
+/// the rate limit and does not read thermal sensors: the thermal cap of
+/// the last sample and the power ceiling are enforced instead. Transition
+/// notifiers run as for `set_frequency`.
+///
+/// # Returns
+/// - `Ok(true)` if the frequency was switched (or already set)
+/// - `Ok(false)` if the transition needs the slow path; call
+///   `set_frequency` from a context that may sleep
+/// - `Err(CpuFreqImplError)` if the frequency is invalid or not allowed
+pub fn fast_switch_frequency(frequency: u64) -> CpuFreqImplResult<bool> {
+    ensure_initialized()?;
+    
+    if !get_available_frequencies()?.contains(&frequency) {
+        return Err(CpuFreqImplError::UnsupportedFrequency);
+    }
+    if clamp_to_power_ceiling(frequency) < frequency {
+        return Err(CpuFreqImplError::PowerLimited);
+    }
+    let current_freq = get_current_frequency()?;
+    if frequency == current_freq {
+        return Ok(true);
+    }
+    let thermal_cap = THERMAL_CAP.load(Ordering::Acquire);
+    if frequency > current_freq && thermal_cap != 0 && frequency > thermal_cap {
+        return Err(CpuFreqImplError::ThermalThrottled);
+    }
+    if !CpuFreq::get_impl().can_fast_switch(current_freq, frequency) {
+        return Ok(false);
+    }
+    
+    notify_transition(TransitionPhase::PreChange, current_freq, frequency);
+    let transition_start = Timestamp::now().as_nanos();
+    if let Err(e) = CpuFreq::get_impl().fast_switch(frequency) {
+        notify_transition(TransitionPhase::PostChange, current_freq, current_freq);
+        return Err(e);
+    }
+    record_transition_latency(transition_start);
+    notify_transition(TransitionPhase::PostChange, current_freq, frequency);
+    LAST_FREQ_CHANGE.store(get_current_time_us(), Ordering::Release);
+    Ok(true)
+}
+
+/// Returns the latency of a transition between two frequencies in
+/// nanoseconds, as reported by the platform
+///
+/// Transitions on the same voltage rail are typically cheap; those that
+/// need a PLL relock or a voltage change are slow.
+pub fn transition_latency_ns(from: u64, to: u64) -> u64 {
+    if from == to {
+        return 0;
+    }
+    CpuFreq::get_impl().transition_latency_ns(from, to)
+}
+
+/// Returns the average observed transition latency in nanoseconds
+pub fn average_transition_latency() -> u64 {
+    let count = TRANSITION_LATENCY_COUNT.load(Ordering::Relaxed);
+    if count == 0 {
+        return 0;
+    }
+    TRANSITION_LATENCY_SUM_NS.load(Ordering::Relaxed) / count
+}
+
+/// Accounts a transition that started at `start` (in nanoseconds) and just completed
+fn record_transition_latency(start: u64) {
+    let latency = Timestamp::now().as_nanos().saturating_sub(start);
+    TRANSITION_LATENCY_SUM_NS.fetch_add(latency, Ordering::Relaxed);
+    TRANSITION_LATENCY_COUNT.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Checks whether a governor should make a transition now
+///
+/// Cheap transitions and large steps always go ahead. A marginal step
+/// that needs an expensive transition waits until the frequency has been
+/// stable for `MARGINAL_TRANSITION_HOLD_US`, so that a load hovering
+/// around a threshold does not pay for a slow transition every sample.
+///
+/// # Arguments
+/// * `latency_ns` - Latency of the transition
+/// * `stable_us` - Time since the last frequency change
+fn transition_worthwhile(from: u64, to: u64, latency_ns: u64, max_freq: u64, stable_us: u64) -> bool {
+    if from == to {
+        return false;
+    }
+    from.abs_diff(to) * 100 >= max_freq * MARGINAL_STEP_PERCENT
+        || latency_ns < EXPENSIVE_TRANSITION_NS
+        || stable_us >= MARGINAL_TRANSITION_HOLD_US
+}
+
+/// Sets the package power budget
+///
+/// Computes the highest available frequency at which every online core
+/// together stays within the budget, using the per-frequency power
+/// estimates of the platform, and enforces it as a ceiling: higher
+/// frequencies are rejected by `set_frequency` and governors are clamped to
+/// it. If the current frequency is above the ceiling it is lowered right
+/// away. The ceiling is re-evaluated on every governor tick.
+///
+/// # Arguments
+/// * `budget` - Package power budget in mW, 0 to remove the budget
+///
+/// # Returns
+/// - `Ok(())` if the budget was applied
+/// - `Err(CpuFreqImplError)` if the power estimates are unavailable or the
+///   frequency could not be lowered
+///
+/// # Examples
+/// ```rust
+/// // Keep the package under 15 W
+/// cpufreq::set_power_budget_mw(15_000)?;
+/// ```
+pub fn set_power_budget_mw(budget: u64) -> CpuFreqImplResult<()> {
+    ensure_initialized()?;
+    
+    POWER_BUDGET_MW.store(budget, Ordering::Release);
+    let ceiling = match update_power_ceiling()? {
+        Some(ceiling) => ceiling,
+        None => {
+            kernel_info!("Power budget removed");
+            return Ok(());
+        }
+    };
+    kernel_info!("Power budget set to {} mW, frequency ceiling {} MHz", budget, ceiling / 1_000_000);
+    
+    if get_current_frequency()? > ceiling {
+        apply_frequency(ceiling)?;
+    }
+    Ok(())
+}
+
+/// Gets the package power budget in mW, if one is set
+pub fn get_power_budget_mw() -> Option<u64> {
+    match POWER_BUDGET_MW.load(Ordering::Acquire) {
+        0 => None,
+        budget => Some(budget),
+    }
+}
+
+/// Recomputes the frequency ceiling enforcing the power budget
+///
+/// The ceiling is the highest available frequency whose estimated power
+/// times the number of online cores fits the budget, or the minimum
+/// frequency if none does.
+fn update_power_ceiling() -> CpuFreqImplResult<Option<u64>> {
+    let budget = POWER_BUDGET_MW.load(Ordering::Acquire);
+    if budget == 0 {
+        POWER_CEILING.store(0, Ordering::Release);
+        return Ok(None);
+    }
+    
+    let mut available_freqs = get_available_frequencies()?;
+    available_freqs.sort_unstable();
+    let min_freq = *available_freqs.first().ok_or(CpuFreqImplError::NoFrequenciesAvailable)?;
+    let nr_cores = (CpuMask::online().weight() as u64).max(1);
+    
+    let mut ceiling = None;
+    for &freq in available_freqs.iter().rev() {
+        let per_core = CpuFreq::get_impl().estimate_power(freq)?;
+        if per_core * nr_cores <= budget {
+            ceiling = Some(freq);
+            break;
+        }
+    }
+    let ceiling = ceiling.unwrap_or_else(|| {
+        kernel_warn!("Power budget of {} mW below minimum frequency draw", budget);
+        min_freq
+    });
+    
+    if POWER_CEILING.swap(ceiling, Ordering::AcqRel) != ceiling {
+        kernel_debug!("Power budget ceiling now {} MHz", ceiling / 1_000_000);
+    }
+    Ok(Some(ceiling))
+}
+
+/// Clamps a governor target to the power budget ceiling
+fn clamp_to_power_ceiling(target: u64) -> u64 {
+    match POWER_CEILING.load(Ordering::Acquire) {
+        0 => target,
+        ceiling => target.min(ceiling),
+    }
+}
+
+/// Registers a frequency transition notifier
+///
+/// The callback is invoked with `PreChange` before and `PostChange` after
+/// every frequency change, including changes driven by a governor. If the
+/// hardware rejects the change, `PostChange` reports the old frequency as
+/// the new one.
+///
+/// # Arguments
+/// * `cb` - Callback receiving the phase, old and new frequency and CPU
+///
+/// # Returns
+/// - A handle to pass to `unregister_transition_notifier`
+///
+/// # Examples
+/// ```rust
+/// fn on_transition(phase: TransitionPhase, old: u64, new: u64, cpu: CpuId) {
+///     if phase == TransitionPhase::PostChange {
+///         timer::rescale(cpu, old, new);
+///     }
+/// }
+///
+/// let handle = cpufreq::register_transition_notifier(on_transition);
+/// ```
+pub fn register_transition_notifier(cb: TransitionNotifier) -> NotifierHandle {
+    let handle = NotifierHandle(NEXT_NOTIFIER_HANDLE.fetch_add(1, Ordering::Relaxed));
+    TRANSITION_NOTIFIERS.write().push((handle, cb));
+    kernel_debug!("Registered frequency transition notifier {:?}", handle);
+    handle
+}
+
+/// Unregisters a frequency transition notifier
+///
+/// # Arguments
+/// * `handle` - Handle returned by `register_transition_notifier`
+///
+/// # Returns
+/// - `Ok(())` if the notifier was removed
+/// - `Err(CpuFreqImplError::InvalidParameter)` if the handle is unknown
+pub fn unregister_transition_notifier(handle: NotifierHandle) -> CpuFreqImplResult<()> {
+    let mut notifiers = TRANSITION_NOTIFIERS.write();
+    let index = notifiers.iter()
+        .position(|(h, _)| *h == handle)
+        .ok_or(CpuFreqImplError::InvalidParameter)?;
+    notifiers.remove(index);
+    kernel_debug!("Unregistered frequency transition notifier {:?}", handle);
+    Ok(())
+}
+
+/// Invokes all registered transition notifiers for one phase
+///
+/// After a change the frequency scale of every online CPU follows the
+/// new frequency.
+fn notify_transition(phase: TransitionPhase, old: u64, new: u64) {
+    if phase == TransitionPhase::PostChange {
+        if let Ok(max) = get_max_frequency() {
+            record_frequency_scale(&CpuMask::online(), new, max);
+        }
+    }
+    let cpu = current_cpu_id();
+    for (_, cb) in TRANSITION_NOTIFIERS.read().iter() {
+        cb(phase, old, new, cpu);
+    }
+}
+
+/// Records the frequency of CPUs relative to `max_freq`
+fn record_frequency_scale(cpus: &CpuMask, freq: u64, max_freq: u64) {
+    if max_freq == 0 {
+        return;
+    }
+    let scale = (freq.saturating_mul(1024) / max_freq).min(1024) as u32;
+    let mut scales = FREQ_SCALES.lock();
+    for cpu in cpus.iter() {
+        scales.insert(cpu, scale);
+    }
+}
+
+/// Returns the current frequency of a CPU relative to its maximum (0-1024)
+///
+/// Follows every frequency transition, whether requested directly or by
+/// Schedutil; the scheduler feeds it to PELT to keep utilization
+/// frequency-invariant. A CPU whose frequency never changed is at 1024.
+pub fn frequency_scale(cpu: CpuId) -> u32 {
+    FREQ_SCALES.lock().get(&cpu).copied().unwrap_or(1024)
+}
+
 /// Returns the list of available CPU frequencies
 ///
 /// # Returns
@@ -369,6 +1203,8 @@ pub fn restore_default_frequency() -> CpuFreqImplResult<()> {
 
 /// Sets the CPU frequency governor
 ///
+/// Switching to Userspace re-applies the Userspace setpoint, if any.
+///
 /// # Arguments
 /// * `governor` - The governor to set
 ///
@@ -394,6 +1230,12 @@ pub fn set_governor(governor: Governor) -> CpuFreqImplResult<()> {
         })?;
     
     kernel_info!("CPU frequency governor set to: {}", governor.as_str());
+    
+    if governor == Governor::Userspace {
+        if let Err(e) = apply_userspace_setpoint(governor, get_current_frequency()?) {
+            kernel_debug!("Userspace setpoint deferred to the next sample: {:?}", e);
+        }
+    }
     Ok(())
 }
 
@@ -412,6 +1254,347 @@ pub fn get_current_governor() -> CpuFreqImplResult<Governor> {
         })
 }
 
+/// Sets a tunable of the current governor
+///
+/// Supported tunables are `up_threshold`, `down_threshold`, `sampling_rate_us`
+/// and, for the Conservative governor only, `freq_step`.
+///
+/// # Arguments
+/// * `name` - Name of the tunable
+/// * `value` - New value of the tunable
+///
+/// # Returns
+/// - `Ok(())` if the tunable was updated
+/// - `Err(CpuFreqImplError::InvalidParameter)` if the governor has no such tunable
+///   or the value is out of range (e.g. `down_threshold >= up_threshold`)
+///
+/// # Examples
+/// ```rust
+/// cpufreq::set_governor(Governor::Ondemand)?;
+/// cpufreq::set_governor_tunable("up_threshold", 90)?;
+/// ```
+pub fn set_governor_tunable(name: &str, value: u64) -> CpuFreqImplResult<()> {
+    let governor = get_current_governor()?;
+    let tunables = governor_tunables(governor)?;
+    
+    let mut guard = tunables.lock();
+    let mut updated = *guard;
+    match name {
+        "up_threshold" => updated.up_threshold = value,
+        "down_threshold" => updated.down_threshold = value,
+        "sampling_rate_us" => updated.sampling_rate_us = value,
+        "freq_step" if governor == Governor::Conservative => {
+            if value == 0 {
+                return Err(CpuFreqImplError::InvalidParameter);
+            }
+            updated.freq_step = value;
+        }
+        _ => {
+            kernel_warn!("Unknown tunable '{}' for governor {}", name, governor.as_str());
+            return Err(CpuFreqImplError::InvalidParameter);
+        }
+    }
+    
+    updated.validate().map_err(|e| {
+        kernel_warn!("Rejected {} = {} for governor {}", name, value, governor.as_str());
+        e
+    })?;
+    *guard = updated;
+    
+    kernel_info!("Governor {} tunable {} set to {}", governor.as_str(), name, value);
+    Ok(())
+}
+
+/// Gets a tunable of the current governor
+///
+/// # Arguments
+/// * `name` - Name of the tunable
+///
+/// # Returns
+/// - `Ok(value)` with the current value of the tunable
+/// - `Err(CpuFreqImplError::InvalidParameter)` if the governor has no such tunable
+pub fn get_governor_tunable(name: &str) -> CpuFreqImplResult<u64> {
+    let governor = get_current_governor()?;
+    let tunables = *governor_tunables(governor)?.lock();
+    
+    match name {
+        "up_threshold" => Ok(tunables.up_threshold),
+        "down_threshold" => Ok(tunables.down_threshold),
+        "sampling_rate_us" => Ok(tunables.sampling_rate_us),
+        "freq_step" if governor == Governor::Conservative => Ok(tunables.freq_step),
+        _ => Err(CpuFreqImplError::InvalidParameter),
+    }
+}
+
+/// Runs one sample of the current load-based governor
+///
+/// Ondemand jumps to the maximum frequency when the load exceeds
+/// `up_threshold` and steps down one level when it falls below
+/// `down_threshold`. Conservative moves by `freq_step` percent of the
+/// maximum frequency in either direction. Samples closer together than
+/// `sampling_rate_us` are ignored. Every tick also re-evaluates the power
+/// budget ceiling and lowers the frequency if it is above it. Under the
+/// Userspace governor the sample moves towards the setpoint instead, so a
+/// manual frequency returns once a thermal clamp lifts.
+///
+/// # Arguments
+/// * `cpu_load` - Current CPU load percentage (0-100)
+///
+/// # Returns
+/// - `Ok(frequency)` with the frequency in effect after the sample
+/// - `Err(CpuFreqImplError)` if the sample could not be applied
+pub fn governor_sample(cpu_load: u32) -> CpuFreqImplResult<u64> {
+    ensure_initialized()?;
+    
+    if cpu_load > 100 {
+        return Err(CpuFreqImplError::InvalidParameter);
+    }
+    
+    let governor = get_current_governor()?;
+    let current_freq = get_current_frequency()?;
+    if let Some(ceiling) = update_power_ceiling()? {
+        if current_freq > ceiling {
+            apply_frequency(ceiling)?;
+            return Ok(ceiling);
+        }
+    }
+    
+    let tunables = match governor {
+        Governor::Ondemand | Governor::Conservative => *governor_tunables(governor)?.lock(),
+        Governor::Userspace => return apply_userspace_setpoint(governor, current_freq),
+        _ => return Ok(current_freq),
+    };
+    
+    let now = get_current_time_us();
+    let last_sample = LAST_GOVERNOR_SAMPLE.load(Ordering::Acquire);
+    if now - last_sample < tunables.sampling_rate_us {
+        return Ok(current_freq);
+    }
+    LAST_GOVERNOR_SAMPLE.store(now, Ordering::Release);
+    
+    let mut available_freqs = get_available_frequencies()?;
+    available_freqs.sort_unstable();
+    let min_freq = *available_freqs.first().ok_or(CpuFreqImplError::NoFrequenciesAvailable)?;
+    let max_freq = *available_freqs.last().ok_or(CpuFreqImplError::NoFrequenciesAvailable)?;
+    let load = (cpu_load as u64).max(io_boost_load());
+    
+    let target_freq = match governor {
+        Governor::Ondemand => {
+            if load > tunables.up_threshold {
+                max_freq
+            } else if load < tunables.down_threshold {
+                available_freqs.iter().rev()
+                    .find(|&&f| f < current_freq)
+                    .copied()
+                    .unwrap_or(min_freq)
+            } else {
+                current_freq
+            }
+        }
+        _ => {
+            let step = max_freq * tunables.freq_step / 100;
+            if load > tunables.up_threshold {
+                let target = (current_freq + step).min(max_freq);
+                available_freqs.iter()
+                    .find(|&&f| f >= target)
+                    .copied()
+                    .unwrap_or(max_freq)
+            } else if load < tunables.down_threshold {
+                let target = current_freq.saturating_sub(step).max(min_freq);
+                available_freqs.iter().rev()
+                    .find(|&&f| f <= target)
+                    .copied()
+                    .unwrap_or(min_freq)
+            } else {
+                current_freq
+            }
+        }
+    };
+    let stable_us = now.saturating_sub(LAST_FREQ_CHANGE.load(Ordering::Acquire));
+    let latency = transition_latency_ns(current_freq, target_freq);
+    let target_freq = if transition_worthwhile(current_freq, target_freq, latency, max_freq, stable_us) {
+        target_freq
+    } else {
+        current_freq
+    };
+    let target_freq = clamp_to_power_ceiling(clamp_to_thermal_limit(target_freq, &available_freqs));
+    
+    if target_freq != current_freq {
+        kernel_debug!("Governor {} sample: load {}%, {} -> {} MHz", governor.as_str(), 
+                     cpu_load, current_freq / 1_000_000, target_freq / 1_000_000);
+        if !fast_switch_frequency(target_freq)? {
+            apply_governor_frequency(current_freq, target_freq)?;
+        }
+    }
+    
+    Ok(target_freq)
+}
+
+/// Returns the tunables storage of a load-based governor
+fn governor_tunables(governor: Governor) -> CpuFreqImplResult<&'static SpinLock<GovernorTunables>> {
+    match governor {
+        Governor::Ondemand => Ok(&ONDEMAND_TUNABLES),
+        Governor::Conservative => Ok(&CONSERVATIVE_TUNABLES),
+        _ => {
+            kernel_warn!("Governor {} has no tunables", governor.as_str());
+            Err(CpuFreqImplError::InvalidParameter)
+        }
+    }
+}
+
+/// Registers a frequency domain for the Schedutil governor
+///
+/// # Returns
+/// - `Ok(())` if the domain was registered
+/// - `Err(CpuFreqImplError::InvalidParameter)` if the domain has no CPUs or
+///   frequencies, its capacity is outside 1-1024, its id is already in use
+///   or it shares a CPU with another domain
+///
+/// # Examples
+/// ```rust
+/// let little = FrequencyDomain::new(0, little_cpus, little_freqs, 512);
+/// let big = FrequencyDomain::new(1, big_cpus, big_freqs, 1024);
+/// cpufreq::register_frequency_domain(little)?;
+/// cpufreq::register_frequency_domain(big)?;
+/// cpufreq::set_governor(Governor::Schedutil)?;
+/// ```
+pub fn register_frequency_domain(domain: FrequencyDomain) -> CpuFreqImplResult<()> {
+    if domain.cpus.is_empty() || domain.frequencies.is_empty()
+        || domain.max_capacity == 0 || domain.max_capacity > 1024 {
+        return Err(CpuFreqImplError::InvalidParameter);
+    }
+    let mut domains = FREQ_DOMAINS.write();
+    if domains.iter().any(|d| d.id == domain.id || d.cpus.iter().any(|cpu| domain.cpus.contains(cpu))) {
+        return Err(CpuFreqImplError::InvalidParameter);
+    }
+    kernel_debug!("Registered frequency domain {} ({} CPUs, capacity {})",
+                 domain.id, domain.cpus.weight(), domain.max_capacity);
+    domains.push(domain);
+    Ok(())
+}
+
+/// Unregisters a frequency domain
+///
+/// # Returns
+/// - `Ok(())` if the domain was removed
+/// - `Err(CpuFreqImplError::InvalidParameter)` if the domain is unknown
+pub fn unregister_frequency_domain(id: u32) -> CpuFreqImplResult<()> {
+    let mut domains = FREQ_DOMAINS.write();
+    let index = domains.iter()
+        .position(|d| d.id == id)
+        .ok_or(CpuFreqImplError::InvalidParameter)?;
+    domains.remove(index);
+    Ok(())
+}
+
+/// Returns the registered frequency domains
+pub fn get_frequency_domains() -> Vec<FrequencyDomain> {
+    FREQ_DOMAINS.read().clone()
+}
+
+/// Runs the Schedutil governor over every frequency domain
+///
+/// Each domain is updated at most once per `FREQ_CHANGE_MIN_INTERVAL_US`
+/// and stays within the thermal limit and power ceiling. A CPU's
+/// utilization is raised to its I/O wait boost. Does nothing unless
+/// Schedutil is the current governor.
+///
+/// # Arguments
+/// * `util` - Frequency- and capacity-invariant utilization of a CPU (0-1024)
+///
+/// # Returns
+/// - `Ok(changes)` with the (domain id, frequency) of every domain that changed
+/// - `Err(CpuFreqImplError)` if a domain frequency could not be set
+pub fn schedutil_update(util: impl Fn(CpuId) -> u32) -> CpuFreqImplResult<Vec<(u32, u64)>> {
+    ensure_initialized()?;
+    if get_current_governor()? != Governor::Schedutil {
+        return Ok(Vec::new());
+    }
+    update_power_ceiling()?;
+
+    let now = get_current_time_us();
+    let mut changes = Vec::new();
+    for domain in FREQ_DOMAINS.write().iter_mut() {
+        if now.saturating_sub(domain.last_update) < FREQ_CHANGE_MIN_INTERVAL_US {
+            continue;
+        }
+        domain.last_update = now;
+        let current = domain.current_frequency;
+        let mut target = domain.schedutil_frequency(|cpu| util(cpu).max(io_boost_util(cpu)));
+        let max_freq = domain.frequencies.last().copied().unwrap_or(target);
+        if !transition_worthwhile(current, target, transition_latency_ns(current, target), max_freq,
+                                  now.saturating_sub(domain.last_change)) {
+            target = current;
+        }
+        let target = clamp_to_power_ceiling(clamp_to_thermal_limit(target, &domain.frequencies));
+        if target == current {
+            continue;
+        }
+        let transition_start = Timestamp::now().as_nanos();
+        CpuFreq::get_impl().set_domain_frequency(domain.id, target)?;
+        record_transition_latency(transition_start);
+        kernel_debug!("Schedutil: domain {} {} -> {} MHz", domain.id,
+                     current / 1_000_000, target / 1_000_000);
+        domain.current_frequency = target;
+        domain.last_change = now;
+        if let Some(&max) = domain.frequencies.iter().max() {
+            record_frequency_scale(&domain.cpus, target, max);
+        }
+        changes.push((domain.id, target));
+    }
+    Ok(changes)
+}
+
+/// Boosts the frequency of a CPU that woke a task from I/O wait
+///
+/// Called from the wakeup path. Like schedutil's iowait boost, the CPU's
+/// utilization is raised to at least the boost, which doubles with every
+/// consecutive I/O wakeup up to the maximum and decays once the wakeups
+/// stop. Ondemand and Conservative see the boost as load. It lets
+/// I/O-bound tasks, which never build up utilization between waits, run
+/// at a frequency that keeps the device busy.
+///
+/// # Arguments
+/// * `cpu` - CPU the woken task is placed on
+pub fn io_boost(cpu: CpuId) {
+    let now = get_current_time_us();
+    let ended = IO_BOOSTS.lock().entry(cpu).or_default().wakeup(now);
+    if let Some(boosted_us) = ended {
+        IO_BOOST_TIME_US.fetch_add(boosted_us, Ordering::Relaxed);
+    }
+}
+
+/// Returns the current I/O wait boost of a CPU in utilization (0-1024)
+pub fn io_boost_util(cpu: CpuId) -> u32 {
+    let now = get_current_time_us();
+    let mut boosts = IO_BOOSTS.lock();
+    let boost = match boosts.get_mut(&cpu) {
+        Some(boost) => boost,
+        None => return 0,
+    };
+    if let Some(boosted_us) = boost.decay(now) {
+        IO_BOOST_TIME_US.fetch_add(boosted_us, Ordering::Relaxed);
+    }
+    boost.util
+}
+
+/// Returns the time CPUs spent I/O boosted, including running boosts (us)
+pub fn io_boost_time_us() -> u64 {
+    let now = get_current_time_us();
+    let running: u64 = IO_BOOSTS.lock().values()
+        .filter(|boost| boost.util != 0)
+        .map(|boost| now.saturating_sub(boost.since))
+        .sum();
+    IO_BOOST_TIME_US.load(Ordering::Relaxed) + running
+}
+
+/// Returns the largest I/O wait boost of any CPU as a load percentage
+fn io_boost_load() -> u64 {
+    let cpus: Vec<CpuId> = IO_BOOSTS.lock().keys().copied().collect();
+    let boost = cpus.into_iter().map(io_boost_util).max().unwrap_or(0);
+    boost as u64 * 100 / IO_BOOST_MAX as u64
+}
+
 /// Gets comprehensive CPU frequency statistics
 ///
 /// # Returns
@@ -428,14 +1611,24 @@ pub fn get_current_governor() -> CpuFreqImplResult<Governor> {
 pub fn get_frequency_stats() -> CpuFreqImplResult<CpuFreqStats> {
     ensure_initialized()?;
     
-    CpuFreq::get_impl().get_frequency_stats()
+    let mut stats = CpuFreq::get_impl().get_frequency_stats()
         .map_err(|e| {
             kernel_warn!("Failed to get frequency statistics: {:?}", e);
             e
-        })
+        })?;
+    
+    let ceiling = POWER_CEILING.load(Ordering::Acquire);
+    stats.power_budget_active = ceiling != 0;
+    stats.power_ceiling = (ceiling != 0).then_some(ceiling);
+    stats.average_transition_latency = average_transition_latency();
+    stats.io_boost_time_us = io_boost_time_us();
+    Ok(stats)
 }
 
-/// Gets thermal information and throttling status
+/// Gets thermal information of the most restrictive thermal zone
+///
+/// A zone above its critical trip point is the most restrictive; otherwise
+/// the throttled zone with the lowest frequency cap, otherwise the hottest.
 ///
 /// # Returns
 /// - `Ok(ThermalInfo)` with thermal data
@@ -443,11 +1636,240 @@ pub fn get_frequency_stats() -> CpuFreqImplResult<CpuFreqStats> {
 pub fn get_thermal_info() -> CpuFreqImplResult<ThermalInfo> {
     ensure_initialized()?;
     
-    CpuFreq::get_impl().get_thermal_info()
-        .map_err(|e| {
-            kernel_debug!("Failed to get thermal info: {:?}", e);
-            e
-        })
+    let min_freq = get_min_frequency()?;
+    let max_freq = get_max_frequency()?;
+    match most_restrictive_zone(min_freq, max_freq) {
+        Some(zone) => Ok(zone.info),
+        None => CpuFreq::get_impl().get_thermal_info()
+            .map_err(|e| {
+                kernel_debug!("Failed to get thermal info: {:?}", e);
+                e
+            }),
+    }
+}
+
+/// Registers a thermal zone with default trip points
+///
+/// # Arguments
+/// * `id` - Zone identifier, unique among registered zones
+/// * `sensor` - Function reading the zone temperature in Celsius
+///
+/// # Returns
+/// - `Ok(())` if the zone was registered
+/// - `Err(CpuFreqImplError::InvalidParameter)` if the id is already in use
+///
+/// # Examples
+/// ```rust
+/// const GPU_ZONE: u32 = 1;
+///
+/// fn gpu_temperature() -> CpuFreqImplResult<u64> {
+///     Ok(gpu::read_temperature())
+/// }
+///
+/// cpufreq::register_thermal_zone(GPU_ZONE, gpu_temperature)?;
+/// cpufreq::set_thermal_trip_points(GPU_ZONE, TripPoints::new(75, 90))?;
+/// ```
+pub fn register_thermal_zone(id: u32, sensor: ThermalSensor) -> CpuFreqImplResult<()> {
+    let mut zones = THERMAL_ZONES.write();
+    if zones.iter().any(|z| z.id == id) {
+        return Err(CpuFreqImplError::InvalidParameter);
+    }
+    zones.push(ThermalZone {
+        id,
+        sensor,
+        trips: TripPoints::default(),
+        throttled: false,
+        transitions: 0,
+        throttle_start: 0,
+        throttle_time: 0,
+        cpus: CpuMask::empty(),
+    });
+    kernel_debug!("Registered thermal zone {}", id);
+    Ok(())
+}
+
+/// Unregisters a thermal zone
+///
+/// # Returns
+/// - `Ok(())` if the zone was removed
+/// - `Err(CpuFreqImplError::InvalidParameter)` if the zone is unknown
+pub fn unregister_thermal_zone(id: u32) -> CpuFreqImplResult<()> {
+    let mut zones = THERMAL_ZONES.write();
+    let index = zones.iter()
+        .position(|z| z.id == id)
+        .ok_or(CpuFreqImplError::InvalidParameter)?;
+    zones.remove(index);
+    kernel_debug!("Unregistered thermal zone {}", id);
+    Ok(())
+}
+
+/// Sets the trip points of a thermal zone
+///
+/// # Returns
+/// - `Ok(())` if the trip points were updated
+/// - `Err(CpuFreqImplError::InvalidParameter)` if the zone is unknown or
+///   `passive` is not below `critical`
+pub fn set_thermal_trip_points(id: u32, trips: TripPoints) -> CpuFreqImplResult<()> {
+    trips.validate()?;
+    
+    let mut zones = THERMAL_ZONES.write();
+    let zone = zones.iter_mut()
+        .find(|z| z.id == id)
+        .ok_or(CpuFreqImplError::InvalidParameter)?;
+    zone.trips = trips;
+    kernel_info!("Thermal zone {} trip points: passive {} °C, critical {} °C", 
+                id, trips.passive, trips.critical);
+    Ok(())
+}
+
+/// Gets the trip points of a thermal zone
+pub fn get_thermal_trip_points(id: u32) -> CpuFreqImplResult<TripPoints> {
+    THERMAL_ZONES.read().iter()
+        .find(|z| z.id == id)
+        .map(|z| z.trips)
+        .ok_or(CpuFreqImplError::InvalidParameter)
+}
+
+/// Attributes a thermal zone to the CPUs it measures
+///
+/// Per-CPU temperatures (see `get_cpu_temperature`) are only known for
+/// CPUs covered by a zone. Zones start out covering no CPU.
+///
+/// # Returns
+/// - `Ok(())` if the CPUs were set
+/// - `Err(CpuFreqImplError::InvalidParameter)` if the zone is unknown
+pub fn set_thermal_zone_cpus(id: u32, cpus: CpuMask) -> CpuFreqImplResult<()> {
+    let mut zones = THERMAL_ZONES.write();
+    let zone = zones.iter_mut()
+        .find(|z| z.id == id)
+        .ok_or(CpuFreqImplError::InvalidParameter)?;
+    zone.cpus = cpus;
+    Ok(())
+}
+
+/// Returns the temperature of a CPU in Celsius
+///
+/// Reads the smallest thermal zone covering the CPU, which is the most
+/// local measurement available. Returns `None` if no zone covers the CPU
+/// or its sensor cannot be read. Does not update the throttle state.
+pub fn get_cpu_temperature(cpu: CpuId) -> Option<u64> {
+    THERMAL_ZONES.read().iter()
+        .filter(|z| z.cpus.contains(cpu))
+        .min_by_key(|z| z.cpus.weight())
+        .and_then(|z| (z.sensor)().ok())
+}
+
+/// Sets the thermal throttling hysteresis
+///
+/// Once a zone engages throttling above its passive trip point, it only
+/// disengages after cooling below the passive trip point minus this many
+/// degrees. The deadband keeps the frequency from oscillating when the
+/// temperature hovers around the trip point.
+///
+/// # Arguments
+/// * `celsius` - Hysteresis in degrees Celsius, 0 to disable
+///
+/// # Returns
+/// - `Ok(())` if the hysteresis was updated
+/// - `Err(CpuFreqImplError::InvalidParameter)` if `celsius` is too large
+pub fn set_thermal_hysteresis(celsius: u64) -> CpuFreqImplResult<()> {
+    if celsius > MAX_THERMAL_HYSTERESIS_CELSIUS {
+        return Err(CpuFreqImplError::InvalidParameter);
+    }
+    THERMAL_HYSTERESIS_CELSIUS.store(celsius, Ordering::Relaxed);
+    kernel_info!("Thermal hysteresis set to {} °C", celsius);
+    Ok(())
+}
+
+/// Gets the thermal throttling hysteresis in degrees Celsius
+pub fn get_thermal_hysteresis() -> u64 {
+    THERMAL_HYSTERESIS_CELSIUS.load(Ordering::Relaxed)
+}
+
+/// Gets thermal information of one thermal zone
+///
+/// # Returns
+/// - `Ok(ThermalInfo)` with the zone's temperature and throttle state
+/// - `Err(CpuFreqImplError::InvalidParameter)` if the zone is unknown
+/// - `Err(CpuFreqImplError)` if the sensor could not be read
+pub fn get_thermal_zone(id: u32) -> CpuFreqImplResult<ThermalInfo> {
+    ensure_initialized()?;
+    
+    let min_freq = get_min_frequency()?;
+    let max_freq = get_max_frequency()?;
+    let now = get_current_time_us();
+    let mut zones = THERMAL_ZONES.write();
+    let zone = zones.iter_mut()
+        .find(|z| z.id == id)
+        .ok_or(CpuFreqImplError::InvalidParameter)?;
+    zone.sample(now, min_freq, max_freq)
+}
+
+/// Samples every thermal zone and returns the most restrictive one
+///
+/// Zones whose sensor cannot be read are skipped.
+fn most_restrictive_zone(min_freq: u64, max_freq: u64) -> Option<ZoneSample> {
+    let now = get_current_time_us();
+    let mut worst: Option<ZoneSample> = None;
+    
+    for zone in THERMAL_ZONES.write().iter_mut() {
+        let info = match zone.sample(now, min_freq, max_freq) {
+            Ok(info) => info,
+            Err(e) => {
+                kernel_debug!("Failed to read thermal zone {}: {:?}", zone.id, e);
+                continue;
+            }
+        };
+        let sample = ZoneSample {
+            id: zone.id,
+            critical: info.temperature > zone.trips.critical,
+            info,
+        };
+        
+        if worst.as_ref().map_or(true, |w| sample.restriction() > w.restriction()) {
+            worst = Some(sample);
+        }
+    }
+    let cap = match &worst {
+        Some(zone) if zone.critical => min_freq,
+        Some(zone) => zone.info.throttle_frequency.unwrap_or(0),
+        None => 0,
+    };
+    THERMAL_CAP.store(cap, Ordering::Release);
+    THERMAL_CRITICAL.store(worst.as_ref().map_or(false, |zone| zone.critical), Ordering::Release);
+    worst
+}
+
+/// Clamps a governor target to the cap of the most restrictive thermal zone
+///
+/// The result is the highest available frequency not above the cap, or the
+/// minimum available frequency if none is.
+fn clamp_to_thermal_limit(target: u64, available_freqs: &[u64]) -> u64 {
+    let (min_freq, max_freq) = match (available_freqs.iter().min(), available_freqs.iter().max()) {
+        (Some(&min), Some(&max)) => (min, max),
+        _ => return target,
+    };
+    let limit = match most_restrictive_zone(min_freq, max_freq) {
+        Some(zone) if zone.critical => min_freq,
+        Some(zone) => match zone.info.throttle_frequency {
+            Some(limit) => limit,
+            None => return target,
+        },
+        None => return target,
+    };
+    if target <= limit {
+        return target;
+    }
+    available_freqs.iter()
+        .filter(|&&f| f <= limit)
+        .max()
+        .copied()
+        .unwrap_or(min_freq)
+}
+
+/// Sensor of the CPU package thermal zone
+fn cpu_package_temperature() -> CpuFreqImplResult<u64> {
+    CpuFreq::get_impl().get_thermal_info().map(|info| info.temperature)
 }
 
 /// Resets frequency statistics counters
@@ -463,6 +1885,13 @@ pub fn reset_frequency_stats() -> CpuFreqImplResult<()> {
             kernel_error!("Failed to reset frequency statistics: {:?}", e);
             e
         })?;
+    TRANSITION_LATENCY_SUM_NS.store(0, Ordering::Relaxed);
+    TRANSITION_LATENCY_COUNT.store(0, Ordering::Relaxed);
+    IO_BOOST_TIME_US.store(0, Ordering::Relaxed);
+    let now = get_current_time_us();
+    for boost in IO_BOOSTS.lock().values_mut().filter(|boost| boost.util != 0) {
+        boost.since = now;
+    }
     
     kernel_info!("CPU frequency statistics reset");
     Ok(())
@@ -517,9 +1946,11 @@ pub fn scale_frequency_intelligent(cpu_load: u32, target_latency: u64) -> CpuFre
     } else {
         target_freq
     };
+    let latency_adjusted_freq = clamp_to_power_ceiling(
+        clamp_to_thermal_limit(latency_adjusted_freq, &available_freqs));
     
     if latency_adjusted_freq != current_freq {
-        set_frequency(latency_adjusted_freq)?;
+        apply_governor_frequency(current_freq, latency_adjusted_freq)?;
     }
     
     Ok(latency_adjusted_freq)
@@ -562,6 +1993,10 @@ pub fn shutdown() -> CpuFreqImplResult<()> {
     
     kernel_info!("Shutting down CPU frequency management...");
     
+    // Lift the power budget so the default frequency is reachable
+    POWER_BUDGET_MW.store(0, Ordering::Release);
+    POWER_CEILING.store(0, Ordering::Release);
+    
     // Restore safe default frequency
     if let Err(e) = restore_default_frequency() {
         kernel_warn!("Failed to restore default frequency during shutdown: {:?}", e);
@@ -578,6 +2013,7 @@ pub fn shutdown() -> CpuFreqImplResult<()> {
             e
         })?;
     
+    let _ = unregister_thermal_zone(CPU_THERMAL_ZONE);
     INITIALIZED.store(false, Ordering::Release);
     kernel_info!("CPU frequency management shutdown complete");
     Ok(())
@@ -635,4 +2071,122 @@ pub fn set_balanced_mode() -> CpuFreqImplResult<()> {
     restore_default_frequency()?;
     kernel_info!("Balanced mode enabled");
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schedutil_scales_each_cluster_by_its_own_capacity() {
+        let mhz = |list: &[u64]| list.iter().map(|f| f * 1_000_000).collect::<Vec<_>>();
+        let mut little_cpus = CpuMask::empty();
+        let mut big_cpus = CpuMask::empty();
+        for cpu in 0..4 {
+            little_cpus.set(CpuId::new(cpu));
+            big_cpus.set(CpuId::new(cpu + 4));
+        }
+        let little = FrequencyDomain::new(0, little_cpus, mhz(&[2000, 600, 1200, 1600]), 512);
+        let big = FrequencyDomain::new(1, big_cpus, mhz(&[800, 1600, 2400, 3000]), 1024);
+
+        // A misfit task saturates little CPU 1; the big cluster is nearly idle
+        let util = |cpu: CpuId| match cpu.as_u32() {
+            1 => 480,
+            4..=7 => 100,
+            _ => 50,
+        };
+        assert_eq!(little.schedutil_frequency(util), 2_000_000_000);
+        assert_eq!(big.schedutil_frequency(util), 800_000_000);
+
+        // 200 of 512 with headroom: 2000 MHz * 250 / 512 = 976 MHz -> 1200 MHz
+        assert_eq!(little.schedutil_frequency(|_| 200), 1_200_000_000);
+        // The same absolute utilization needs far less of the big cluster
+        assert_eq!(big.schedutil_frequency(|_| 200), 800_000_000);
+        assert_eq!(big.schedutil_frequency(|_| 1024), 3_000_000_000);
+    }
+
+    #[test]
+    fn test_frequency_scale_follows_transitions() {
+        let mut cpus = CpuMask::empty();
+        cpus.set(CpuId::new(40));
+        cpus.set(CpuId::new(41));
+        assert_eq!(frequency_scale(CpuId::new(40)), 1024);
+
+        record_frequency_scale(&cpus, 1_200_000_000, 2_400_000_000);
+        assert_eq!(frequency_scale(CpuId::new(40)), 512);
+        assert_eq!(frequency_scale(CpuId::new(41)), 512);
+        assert_eq!(frequency_scale(CpuId::new(42)), 1024);
+
+        record_frequency_scale(&cpus, 2_400_000_000, 2_400_000_000);
+        assert_eq!(frequency_scale(CpuId::new(41)), 1024);
+    }
+
+    #[test]
+    fn test_marginal_expensive_transitions_wait_for_stable_frequency() {
+        const MAX: u64 = 3_000_000_000;
+        let (slow, fast) = (500_000, 20_000);
+        // A 200 MHz step is marginal: only cheap transitions go right away
+        assert!(transition_worthwhile(2_000_000_000, 2_200_000_000, fast, MAX, 0));
+        assert!(!transition_worthwhile(2_000_000_000, 2_200_000_000, slow, MAX, 20_000));
+        assert!(transition_worthwhile(2_000_000_000, 2_200_000_000, slow, MAX, MARGINAL_TRANSITION_HOLD_US));
+        // A large step is always worth it
+        assert!(transition_worthwhile(1_000_000_000, 3_000_000_000, slow, MAX, 0));
+        assert!(!transition_worthwhile(MAX, MAX, 0, MAX, u64::MAX));
+    }
+
+    #[test]
+    fn test_userspace_setpoint_returns_when_thermal_clamp_lifts() {
+        const SETPOINT: u64 = 2_400_000_000;
+        let throttled = |f: u64| f.min(1_600_000_000);
+        let unthrottled = |f: u64| f;
+
+        assert_eq!(userspace_target(Governor::Userspace, SETPOINT, throttled), Some(1_600_000_000));
+        assert_eq!(userspace_target(Governor::Userspace, SETPOINT, unthrottled), Some(SETPOINT));
+        // Other governors ignore the setpoint, which is kept for later
+        assert_eq!(userspace_target(Governor::Ondemand, SETPOINT, unthrottled), None);
+        assert_eq!(userspace_target(Governor::Performance, SETPOINT, unthrottled), None);
+        assert_eq!(userspace_target(Governor::Userspace, 0, unthrottled), None);
+    }
+
+    #[test]
+    fn test_only_thermal_critical_downshifts_bypass_rate_limit() {
+        const HIGH: u64 = 3_000_000_000;
+        const LOW: u64 = 800_000_000;
+        assert!(bypasses_rate_limit(HIGH, LOW, true));
+        assert!(!bypasses_rate_limit(LOW, HIGH, true));
+        assert!(!bypasses_rate_limit(HIGH, HIGH, true));
+        assert!(!bypasses_rate_limit(HIGH, LOW, false));
+
+        // An urgent change at 1000us still holds off ordinary ones
+        assert!(rate_limited(1_000 + FREQ_CHANGE_MIN_INTERVAL_US - 1, 1_000));
+        assert!(!rate_limited(1_000 + FREQ_CHANGE_MIN_INTERVAL_US, 1_000));
+    }
+
+    #[test]
+    fn test_io_boost_doubles_per_wakeup_and_decays_when_they_stop() {
+        let mut boost = IoBoost::default();
+        let mut now = 1_000;
+        let mut utils = Vec::new();
+        for _ in 0..5 {
+            assert_eq!(boost.wakeup(now), None);
+            utils.push(boost.util);
+            now += 1_000;
+        }
+        assert_eq!(utils, [128, 256, 512, 1024, 1024]);
+
+        // Without I/O wakeups the boost halves per interval, then ends
+        let last = now - 1_000;
+        assert_eq!(boost.decay(last + IO_BOOST_DECAY_US - 1), None);
+        assert_eq!(boost.util, 1024);
+        assert_eq!(boost.decay(last + IO_BOOST_DECAY_US), None);
+        assert_eq!(boost.util, 512);
+        assert_eq!(boost.decay(last + 3 * IO_BOOST_DECAY_US), None);
+        assert_eq!(boost.util, 128);
+        assert_eq!(boost.decay(last + 4 * IO_BOOST_DECAY_US), Some(last + 4 * IO_BOOST_DECAY_US - 1_000));
+        assert_eq!(boost.util, 0);
+
+        // The next I/O wakeup starts over from the minimum
+        assert_eq!(boost.wakeup(last + 10 * IO_BOOST_DECAY_US), None);
+        assert_eq!(boost.util, IO_BOOST_MIN);
+    }
+}
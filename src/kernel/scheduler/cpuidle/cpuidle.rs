@@ -10,6 +10,11 @@
 //! - Set specific idle states with validation
 //! - Restore default configurations
 //! - Runtime support detection
+//! - Idle state selection with interrupt-rate demotion
+//! - Statistical idle duration prediction from recent idle periods
+//! - Per-CPU idle statistics
+//! - Idle injection (powerclamp): CPUs forced idle for a duty cycle to cap
+//!   power without lowering the frequency, accounted apart from natural idle
 //!
 //! ## Usage
 //! ```rust
@@ -27,13 +32,41 @@
 //!     cpuidle::set_idle_state(states[0])?;
 //! }
 //! ```
+//!
+//! ## Idle State Drivers
+//! The platform side is a `CpuIdleImplTrait` driver. `init` installs the
+//! hardware driver; `init_with_driver` installs any other, such as a
+//! platform driver supplied by a third party or `MockCpuIdle` in tests.
+//! A driver provides:
+//! - `get_available_idle_states`: IDs of the states the CPU supports,
+//!   deeper states having higher IDs
+//! - `get_current_idle_state` / `set_idle_state`: the state in effect
+//! - `get_default_idle_state`: the state restored at shutdown
+//! - `get_target_residency`: time (in microseconds) a CPU must stay in a
+//!   state for entering it to save power; drives state selection
+//! - `get_idle_state_name`: human readable name of a state
+//! - `is_supported`: whether the platform can manage idle states at all
+//! - `get_statistics` / `reset_statistics`: the driver's own usage counters,
+//!   used when the idle loop does not report entries and exits
+//! - `shutdown`: release the hardware
+//!
+//! Every method fails with a `CpuIdleImplError`; a driver must be `Send`
+//! and `Sync`, since all CPUs share it.
 
 use crate::kernel::scheduler::cpuidle::cpuidle_impl::{
     CpuIdle, CpuIdleImpl, CpuIdleImplTrait, CpuIdleImplError, 
     CpuIdleImplResult, CpuIdleImplConfig
 };
-use crate::kernel::log::{kernel_info, kernel_warn, kernel_error};
-use core::sync::atomic::{AtomicBool, Ordering};
+use crate::kernel::log::{kernel_info, kernel_warn, kernel_error, kernel_debug};
+use crate::kernel::sync::SpinLock;
+use crate::kernel::cpu::CpuId;
+use crate::kernel::time::{get_current_time_us, Duration};
+use crate::arch::cpu::current_cpu_id;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
 
 pub mod cpuidle_impl;
 
@@ -44,6 +77,149 @@ static INITIALIZED: AtomicBool = AtomicBool::new(false);
 const MIN_IDLE_STATE: u64 = 0;
 const MAX_IDLE_STATE: u64 = 7; // Typical maximum for most architectures
 
+/// Interrupt rate measurement window (in microseconds)
+const IRQ_RATE_WINDOW_US: u64 = 100_000; // 100ms
+
+/// Default interrupt rate above which deep idle states are demoted
+pub const DEFAULT_DEMOTION_THRESHOLD: u64 = 1_000; // irqs per second
+
+/// Interrupt rate above which idle state selection demotes (0 disables)
+static DEMOTION_THRESHOLD: AtomicU64 = AtomicU64::new(DEFAULT_DEMOTION_THRESHOLD);
+
+/// Number of idle state selections demoted because of the interrupt rate
+static DEMOTION_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Per-CPU interrupt rate trackers
+static IRQ_RATE: SpinLock<BTreeMap<CpuId, IrqRateTracker>> = SpinLock::new(BTreeMap::new());
+
+/// Per-CPU idle accounting
+static IDLE_ACCOUNTING: SpinLock<BTreeMap<CpuId, IdleAccounting>> = SpinLock::new(BTreeMap::new());
+
+/// Per-CPU idle injection duty cycles
+static IDLE_INJECTION: SpinLock<BTreeMap<CpuId, IdleInjection>> = SpinLock::new(BTreeMap::new());
+
+/// Shortest idle injection period (in microseconds)
+const MIN_INJECTION_PERIOD_US: u64 = 1_000; // 1ms
+
+/// Largest idle injection duty cycle (in percent)
+const MAX_INJECTION_DUTY_PERCENT: u8 = 99;
+
+/// Weight of a new idle period in the predicted idle duration (1/8)
+const IDLE_PREDICTION_SHIFT: u32 = 3;
+
+/// Idle state usage of one CPU
+#[derive(Debug, Clone, Default)]
+struct IdleAccounting {
+    /// State and entry time (in microseconds) of the idle period in progress
+    entered: Option<(u64, u64)>,
+    /// Whether the idle period in progress was forced by idle injection
+    entered_injected: bool,
+    /// Last entered idle state
+    last_state: u64,
+    /// Total time (in microseconds) and entries per state
+    usage: BTreeMap<u64, (u64, u64)>,
+    /// Total time (in microseconds) forced idle by idle injection
+    injected_time: u64,
+    /// Moving average of natural idle periods (in microseconds)
+    avg_period: u64,
+}
+
+/// Forced idle duty cycle of one CPU
+#[derive(Debug, Clone, Copy)]
+struct IdleInjection {
+    /// Share of every period the CPU is forced idle
+    duty_percent: u8,
+    /// Length of a period (in microseconds)
+    period_us: u64,
+    /// Start of the first period (in microseconds)
+    start_us: u64,
+}
+
+impl IdleInjection {
+    /// Whether `now` falls in the forced idle part of its period, which
+    /// is the start of every period
+    fn active(&self, now: u64) -> bool {
+        let phase = now.saturating_sub(self.start_us) % self.period_us;
+        phase < self.period_us * self.duty_percent as u64 / 100
+    }
+}
+
+impl IdleAccounting {
+    /// Folds the accounting into a statistics snapshot
+    fn add_to(&self, stats: &mut CpuIdleStats) {
+        for (&state, &(time, count)) in self.usage.iter() {
+            add_usage(&mut stats.state_usage_time, state, time);
+            add_usage(&mut stats.state_entry_count, state, count);
+            stats.total_idle_time += time;
+        }
+        stats.injected_idle_time += self.injected_time;
+    }
+
+    /// Statistics snapshot of this CPU alone
+    fn stats(&self) -> CpuIdleStats {
+        let mut stats = CpuIdleStats::default();
+        self.add_to(&mut stats);
+        finish_stats(&mut stats);
+        stats.current_state = self.last_state;
+        stats
+    }
+}
+
+/// Adds `value` to the entry of `state` in a `(state_id, value)` list
+fn add_usage(list: &mut Vec<(u64, u64)>, state: u64, value: u64) {
+    match list.iter_mut().find(|(s, _)| *s == state) {
+        Some((_, v)) => *v += value,
+        None => list.push((state, value)),
+    }
+}
+
+/// Computes the average residency of every state from usage time and entries
+fn finish_stats(stats: &mut CpuIdleStats) {
+    stats.state_usage_time.sort_unstable();
+    stats.state_entry_count.sort_unstable();
+    stats.average_residency = stats.state_usage_time.iter()
+        .zip(stats.state_entry_count.iter())
+        .map(|(&(state, time), &(_, count))| (state, if count > 0 { time / count } else { 0 }))
+        .collect();
+}
+
+/// Interrupt rate of one CPU, measured over fixed windows
+#[derive(Debug, Clone, Copy, Default)]
+struct IrqRateTracker {
+    /// Start of the current window (in microseconds)
+    window_start: u64,
+    /// Interrupts in the current window
+    count: u64,
+    /// Rate over the last completed window (interrupts per second)
+    rate: u64,
+}
+
+impl IrqRateTracker {
+    /// Accounts one interrupt at `now`
+    fn record(&mut self, now: u64) {
+        let elapsed = now - self.window_start;
+        if elapsed >= IRQ_RATE_WINDOW_US {
+            self.rate = self.count * 1_000_000 / elapsed;
+            self.window_start = now;
+            self.count = 0;
+        }
+        self.count += 1;
+    }
+
+    /// Recent interrupt rate at `now` in interrupts per second
+    ///
+    /// Once the current window has run its length without being closed by
+    /// an interrupt, it is the more recent measurement and wins.
+    fn rate(&self, now: u64) -> u64 {
+        let elapsed = now - self.window_start;
+        if elapsed >= IRQ_RATE_WINDOW_US {
+            self.count * 1_000_000 / elapsed
+        } else {
+            self.rate
+        }
+    }
+}
+
 /// Initializes the CPU idle states management module with enhanced error handling
 /// 
 /// This function sets up the CPU idle state management system with default
@@ -75,13 +251,36 @@ pub fn init() -> CpuIdleImplResult<()> {
             e
         })?;
     
-    CpuIdle::set_impl(cpuidle_impl);
+    CpuIdle::set_impl(Box::new(cpuidle_impl));
     INITIALIZED.store(true, Ordering::Release);
     
     kernel_info!("CPU idle states management initialized successfully");
     Ok(())
 }
 
+/// Initializes the CPU idle states management module with a given driver
+///
+/// Like `init`, but the platform side is `driver` instead of the hardware
+/// driver, e.g. a third-party platform driver or a mock in tests. Replaces
+/// the driver if the module is already initialized.
+///
+/// # Examples
+/// ```rust
+/// cpuidle::init_with_driver(Box::new(MockCpuIdle::new()
+///     .with_state(0, "POLL", 0, 0)
+///     .with_state(1, "C1", 2, 10)))?;
+/// ```
+pub fn init_with_driver(driver: Box<dyn CpuIdleImplTrait + Send + Sync>) -> CpuIdleImplResult<()> {
+    if !driver.is_supported()? {
+        kernel_warn!("CPU idle driver does not support idle state management");
+    }
+    CpuIdle::set_impl(driver);
+    INITIALIZED.store(true, Ordering::Release);
+    
+    kernel_info!("CPU idle states management initialized with custom driver");
+    Ok(())
+}
+
 /// Returns the current CPU idle state
 ///
 /// # Returns
@@ -209,6 +408,165 @@ pub fn restore_default_idle_state() -> CpuIdleImplResult<()> {
     Ok(())
 }
 
+/// Records an interrupt on a CPU
+///
+/// Feeds the interrupt rate tracker used by `select_idle_state`. Called
+/// from the interrupt entry path.
+///
+/// # Arguments
+/// * `cpu` - CPU that took the interrupt
+pub fn record_interrupt(cpu: CpuId) {
+    let now = get_current_time_us();
+    IRQ_RATE.lock().entry(cpu).or_default().record(now);
+}
+
+/// Returns the recent interrupt rate of a CPU in interrupts per second
+pub fn get_interrupt_rate(cpu: CpuId) -> u64 {
+    let now = get_current_time_us();
+    IRQ_RATE.lock().get(&cpu).map_or(0, |t| t.rate(now))
+}
+
+/// Forces a CPU idle for a share of every period (idle injection)
+///
+/// During the first `duty_percent` of each period the scheduler runs the
+/// idle task on `cpu` even while fair tasks are runnable, which caps power
+/// without lowering the frequency. Stop, RT and deadline tasks are never
+/// held back. Idle periods starting in a forced part are accounted as
+/// injected idle, apart from natural idle. Replaces a previous injection
+/// on the CPU.
+///
+/// # Arguments
+/// * `cpu` - The CPU to force idle
+/// * `duty_percent` - Share of each period to force idle (1-99)
+/// * `period` - Length of one duty cycle (at least 1ms)
+///
+/// # Returns
+/// - `Ok(())` if the injection is active
+/// - `Err(CpuIdleImplError::InvalidParameter)` for an out of range duty
+///   cycle or period
+///
+/// # Examples
+/// ```rust
+/// // Keep CPU 2 idle for 25% of every 24ms
+/// cpuidle::inject_idle(CpuId::new(2), 25, Duration::from_millis(24))?;
+/// ```
+pub fn inject_idle(cpu: CpuId, duty_percent: u8, period: Duration) -> CpuIdleImplResult<()> {
+    let period_us = period.as_nanos() / 1_000;
+    if !(1..=MAX_INJECTION_DUTY_PERCENT).contains(&duty_percent) || period_us < MIN_INJECTION_PERIOD_US {
+        return Err(CpuIdleImplError::InvalidParameter);
+    }
+    let injection = IdleInjection {
+        duty_percent,
+        period_us,
+        start_us: get_current_time_us(),
+    };
+    IDLE_INJECTION.lock().insert(cpu, injection);
+    kernel_info!("Idle injection on CPU {}: {}% of every {} us", cpu.as_u32(), duty_percent, period_us);
+    Ok(())
+}
+
+/// Stops idle injection on a CPU
+///
+/// # Returns
+/// - `true` if an injection was active on the CPU
+pub fn cancel_idle_injection(cpu: CpuId) -> bool {
+    let cancelled = IDLE_INJECTION.lock().remove(&cpu).is_some();
+    if cancelled {
+        kernel_info!("Idle injection on CPU {} cancelled", cpu.as_u32());
+    }
+    cancelled
+}
+
+/// Checks whether a CPU is in the forced idle part of its injection period
+pub fn idle_injection_active(cpu: CpuId) -> bool {
+    let now = get_current_time_us();
+    IDLE_INJECTION.lock().get(&cpu).map_or(false, |injection| injection.active(now))
+}
+
+/// Sets the interrupt rate above which deep idle states are demoted
+///
+/// # Arguments
+/// * `irqs_per_sec` - Interrupt rate threshold, 0 to disable demotion
+pub fn set_demotion_threshold(irqs_per_sec: u64) {
+    DEMOTION_THRESHOLD.store(irqs_per_sec, Ordering::Relaxed);
+    kernel_info!("Idle state demotion threshold set to {} irqs/s", irqs_per_sec);
+}
+
+/// Gets the interrupt rate threshold for idle state demotion
+pub fn get_demotion_threshold() -> u64 {
+    DEMOTION_THRESHOLD.load(Ordering::Relaxed)
+}
+
+/// Returns how many idle state selections were demoted
+pub fn get_demotion_count() -> u64 {
+    DEMOTION_COUNT.load(Ordering::Relaxed)
+}
+
+/// Predicts how long a CPU about to go idle will stay idle
+///
+/// The statistical guess used when nothing better is known: a moving
+/// average of the CPU's recent natural idle periods, in microseconds.
+/// Returns 0 before the CPU has reported an idle period, which selects
+/// the shallowest state.
+pub fn predict_idle_duration(cpu: CpuId) -> u64 {
+    IDLE_ACCOUNTING.lock().get(&cpu).map_or(0, |accounting| accounting.avg_period)
+}
+
+/// Selects the idle state for a CPU about to go idle
+///
+/// Picks the deepest available state whose target residency fits the
+/// predicted idle time. When the CPU's recent interrupt rate exceeds the
+/// demotion threshold, the prediction is capped at the average interval
+/// between interrupts, since the next interrupt will most likely end the
+/// idle period early and a deep state would only pay its entry and exit
+/// cost.
+///
+/// # Arguments
+/// * `cpu` - CPU going idle
+/// * `predicted_idle_us` - Predicted idle duration in microseconds
+///
+/// # Returns
+/// - `Ok(state)` with the selected idle state ID
+/// - `Err(CpuIdleImplError)` if the operation fails
+///
+/// # Examples
+/// ```rust
+/// let state = cpuidle::select_idle_state(cpu, next_timer_us)?;
+/// cpuidle::set_idle_state(state)?;
+/// ```
+pub fn select_idle_state(cpu: CpuId, predicted_idle_us: u64) -> CpuIdleImplResult<u64> {
+    ensure_initialized()?;
+    
+    let mut states = get_available_idle_states()?;
+    states.sort_unstable();
+    let shallowest = *states.first().ok_or(CpuIdleImplError::UnsupportedState)?;
+    
+    let deepest_fitting = |idle_us: u64| -> CpuIdleImplResult<u64> {
+        for &state in states.iter().rev() {
+            if CpuIdle::get_impl().get_target_residency(state)? <= idle_us {
+                return Ok(state);
+            }
+        }
+        Ok(shallowest)
+    };
+    let selected = deepest_fitting(predicted_idle_us)?;
+    
+    let threshold = DEMOTION_THRESHOLD.load(Ordering::Relaxed);
+    let irq_rate = get_interrupt_rate(cpu);
+    if threshold == 0 || irq_rate <= threshold {
+        return Ok(selected);
+    }
+    
+    let irq_interval_us = 1_000_000 / irq_rate;
+    let demoted = deepest_fitting(predicted_idle_us.min(irq_interval_us))?;
+    if demoted < selected {
+        DEMOTION_COUNT.fetch_add(1, Ordering::Relaxed);
+        kernel_debug!("CPU {:?}: {} irqs/s, demoting idle state {} -> {}", 
+                     cpu, irq_rate, selected, demoted);
+    }
+    Ok(demoted)
+}
+
 /// Checks if CPU idle state management is supported on this system
 ///
 /// # Returns
@@ -252,19 +610,131 @@ pub fn get_idle_state_name(state: u64) -> CpuIdleImplResult<String> {
         })
 }
 
+/// Records that the current CPU enters an idle state
+///
+/// Called by the idle loop right before entering `state`; pairs with
+/// `idle_exit`. Accounting is keyed by `current_cpu_id()`.
+///
+/// # Arguments
+/// * `state` - The idle state ID being entered
+pub fn idle_enter(state: u64) {
+    idle_enter_at(current_cpu_id(), state, get_current_time_us());
+}
+
+/// Records that the current CPU left its idle state
+///
+/// Charges the time since `idle_enter` to the entered state, or to
+/// injected idle if the period was forced by idle injection. Does nothing
+/// if the CPU is not in an accounted idle period.
+pub fn idle_exit() {
+    idle_exit_at(current_cpu_id(), get_current_time_us());
+}
+
+/// Records that `cpu` entered an idle state at `now` (in microseconds)
+///
+/// Like `idle_enter`, for a caller that accounts on behalf of a CPU, such
+/// as the scheduler when it switches a CPU to its idle task. Entering
+/// again before the exit restarts the period.
+pub fn idle_enter_at(cpu: CpuId, state: u64, now: u64) {
+    let injected = idle_injection_active(cpu);
+    let mut accounting = IDLE_ACCOUNTING.lock();
+    let cpu = accounting.entry(cpu).or_default();
+    cpu.entered = Some((state, now));
+    cpu.entered_injected = injected;
+    cpu.last_state = state;
+}
+
+/// Records that `cpu` left its idle state at `now` (in microseconds)
+///
+/// Charges the period as `idle_exit` does and returns its length in
+/// microseconds, 0 if the CPU was not in an accounted idle period. The
+/// returned time is exactly what was added to the state usage (or to
+/// injected idle), so callers keeping their own idle total stay in step
+/// with the per-state statistics.
+pub fn idle_exit_at(cpu: CpuId, now: u64) -> u64 {
+    let mut accounting = IDLE_ACCOUNTING.lock();
+    let cpu = match accounting.get_mut(&cpu) {
+        Some(cpu) => cpu,
+        None => return 0,
+    };
+    let (state, start) = match cpu.entered.take() {
+        Some(entered) => entered,
+        None => return 0,
+    };
+    let period = now.saturating_sub(start);
+    if core::mem::take(&mut cpu.entered_injected) {
+        cpu.injected_time += period;
+        return period;
+    }
+    let usage = cpu.usage.entry(state).or_default();
+    usage.0 += period;
+    usage.1 += 1;
+    cpu.avg_period = if cpu.avg_period == 0 {
+        period
+    } else {
+        cpu.avg_period - (cpu.avg_period >> IDLE_PREDICTION_SHIFT) + (period >> IDLE_PREDICTION_SHIFT)
+    };
+    period
+}
+
 /// Gets detailed statistics about CPU idle state usage
 ///
+/// Sums the per-CPU statistics of all CPUs. Falls back to the statistics
+/// of the platform driver if the idle loop does not report entries and
+/// exits.
+///
 /// # Returns
 /// - `Ok(CpuIdleStats)` with usage statistics
 /// - `Err(CpuIdleImplError)` if the operation fails
 pub fn get_idle_statistics() -> CpuIdleImplResult<CpuIdleStats> {
     ensure_initialized()?;
     
-    CpuIdle::get_impl().get_statistics()
-        .map_err(|e| {
-            kernel_warn!("Failed to get idle statistics: {:?}", e);
-            e
-        })
+    let accounting = IDLE_ACCOUNTING.lock();
+    if accounting.is_empty() {
+        drop(accounting);
+        return CpuIdle::get_impl().get_statistics()
+            .map_err(|e| {
+                kernel_warn!("Failed to get idle statistics: {:?}", e);
+                e
+            });
+    }
+    
+    let mut stats = CpuIdleStats::default();
+    for cpu in accounting.values() {
+        cpu.add_to(&mut stats);
+    }
+    drop(accounting);
+    finish_stats(&mut stats);
+    stats.current_state = get_current_idle_state()?;
+    Ok(stats)
+}
+
+/// Gets idle state usage statistics of one CPU
+///
+/// # Arguments
+/// * `cpu` - The CPU to report
+///
+/// # Returns
+/// - `CpuIdleStats` of the CPU; empty if the CPU never reported idle
+///
+/// # Examples
+/// ```rust
+/// let stats = cpuidle::get_idle_statistics_for(CpuId::new(3));
+/// println!("CPU 3 idle for {} us", stats.total_idle_time);
+/// ```
+pub fn get_idle_statistics_for(cpu: CpuId) -> CpuIdleStats {
+    IDLE_ACCOUNTING.lock().get(&cpu)
+        .map_or_else(CpuIdleStats::default, IdleAccounting::stats)
+}
+
+/// Gets idle state usage statistics of every CPU that reported idle
+///
+/// # Returns
+/// - `(CpuId, CpuIdleStats)` pairs ordered by CPU
+pub fn get_all_idle_statistics() -> Vec<(CpuId, CpuIdleStats)> {
+    IDLE_ACCOUNTING.lock().iter()
+        .map(|(&cpu, accounting)| (cpu, accounting.stats()))
+        .collect()
 }
 
 /// Resets CPU idle state statistics counters
@@ -280,6 +750,10 @@ pub fn reset_idle_statistics() -> CpuIdleImplResult<()> {
             kernel_error!("Failed to reset idle statistics: {:?}", e);
             e
         })?;
+    for cpu in IDLE_ACCOUNTING.lock().values_mut() {
+        cpu.usage.clear();
+        cpu.injected_time = 0;
+    }
     
     kernel_info!("CPU idle statistics reset");
     Ok(())
@@ -303,6 +777,7 @@ pub fn shutdown() -> CpuIdleImplResult<()> {
     if let Err(e) = restore_default_idle_state() {
         kernel_warn!("Failed to restore default state during shutdown: {:?}", e);
     }
+    IDLE_INJECTION.lock().clear();
     
     CpuIdle::get_impl().shutdown()
         .map_err(|e| {
@@ -330,7 +805,7 @@ fn ensure_initialized() -> CpuIdleImplResult<()> {
 }
 
 /// CPU idle state usage statistics
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct CpuIdleStats {
     /// Total time spent in each idle state (in microseconds)
     pub state_usage_time: Vec<(u64, u64)>, // (state_id, time_us)
@@ -342,4 +817,163 @@ pub struct CpuIdleStats {
     pub current_state: u64,
     /// Total idle time across all states
     pub total_idle_time: u64,
-}
\ No newline at end of file
+    /// Time forced idle by idle injection, not part of the state usage
+    pub injected_idle_time: u64,
+}
+
+/// One idle state of a `MockCpuIdle`
+#[cfg(test)]
+#[derive(Debug, Clone)]
+struct MockIdleState {
+    id: u64,
+    name: String,
+    exit_latency_us: u64,
+    target_residency_us: u64,
+}
+
+/// Idle state driver with programmable states for tests
+///
+/// Keeps the state set by `set_idle_state` and counts entries into each
+/// state; nothing touches the hardware.
+#[cfg(test)]
+pub struct MockCpuIdle {
+    states: Vec<MockIdleState>,
+    default_state: u64,
+    current: AtomicU64,
+    entries: SpinLock<BTreeMap<u64, u64>>,
+}
+
+#[cfg(test)]
+impl MockCpuIdle {
+    /// Create a driver without idle states
+    pub fn new() -> Self {
+        Self {
+            states: Vec::new(),
+            default_state: 0,
+            current: AtomicU64::new(0),
+            entries: SpinLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// Add an idle state with its exit latency and target residency
+    /// (in microseconds)
+    pub fn with_state(mut self, id: u64, name: &str, exit_latency_us: u64, target_residency_us: u64) -> Self {
+        self.states.push(MockIdleState {
+            id,
+            name: String::from(name),
+            exit_latency_us,
+            target_residency_us,
+        });
+        self
+    }
+
+    /// Set the state restored by `restore_default_idle_state`
+    pub fn with_default_state(mut self, state: u64) -> Self {
+        self.default_state = state;
+        self
+    }
+
+    /// Exit latency of a state (in microseconds)
+    pub fn exit_latency(&self, state: u64) -> CpuIdleImplResult<u64> {
+        self.state(state).map(|s| s.exit_latency_us)
+    }
+
+    fn state(&self, state: u64) -> CpuIdleImplResult<&MockIdleState> {
+        self.states.iter().find(|s| s.id == state).ok_or(CpuIdleImplError::UnsupportedState)
+    }
+}
+
+#[cfg(test)]
+impl Default for MockCpuIdle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+impl CpuIdleImplTrait for MockCpuIdle {
+    fn get_current_idle_state(&self) -> CpuIdleImplResult<u64> {
+        Ok(self.current.load(Ordering::Acquire))
+    }
+
+    fn set_idle_state(&self, state: u64) -> CpuIdleImplResult<()> {
+        self.state(state)?;
+        self.current.store(state, Ordering::Release);
+        *self.entries.lock().entry(state).or_default() += 1;
+        Ok(())
+    }
+
+    fn get_available_idle_states(&self) -> CpuIdleImplResult<Vec<u64>> {
+        Ok(self.states.iter().map(|s| s.id).collect())
+    }
+
+    fn get_default_idle_state(&self) -> CpuIdleImplResult<u64> {
+        Ok(self.default_state)
+    }
+
+    fn get_target_residency(&self, state: u64) -> CpuIdleImplResult<u64> {
+        self.state(state).map(|s| s.target_residency_us)
+    }
+
+    fn get_idle_state_name(&self, state: u64) -> CpuIdleImplResult<String> {
+        self.state(state).map(|s| s.name.clone())
+    }
+
+    fn is_supported(&self) -> CpuIdleImplResult<bool> {
+        Ok(!self.states.is_empty())
+    }
+
+    fn get_statistics(&self) -> CpuIdleImplResult<CpuIdleStats> {
+        let mut stats = CpuIdleStats::default();
+        stats.state_entry_count = self.entries.lock().iter().map(|(&s, &n)| (s, n)).collect();
+        stats.current_state = self.current.load(Ordering::Acquire);
+        Ok(stats)
+    }
+
+    fn reset_statistics(&self) -> CpuIdleImplResult<()> {
+        self.entries.lock().clear();
+        Ok(())
+    }
+
+    fn shutdown(&self) -> CpuIdleImplResult<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_menu_selection_and_demotion_with_mock_driver() {
+        let driver = MockCpuIdle::new()
+            .with_state(0, "POLL", 0, 0)
+            .with_state(1, "C1", 2, 10)
+            .with_state(2, "C3", 50, 200)
+            .with_state(3, "C6", 150, 800)
+            .with_default_state(1);
+        assert_eq!(driver.exit_latency(3).unwrap(), 150);
+        init_with_driver(Box::new(driver)).unwrap();
+        assert!(is_supported());
+
+        // The deepest state whose target residency fits the prediction
+        let cpu = CpuId::new(0);
+        assert_eq!(select_idle_state(cpu, 5).unwrap(), 0);
+        assert_eq!(select_idle_state(cpu, 300).unwrap(), 2);
+        assert_eq!(select_idle_state(cpu, 1_000).unwrap(), 3);
+
+        // 5000 irqs/s: the next interrupt comes within 200us, so C6 is demoted
+        set_demotion_threshold(DEFAULT_DEMOTION_THRESHOLD);
+        let demotions = get_demotion_count();
+        let tracker = IrqRateTracker { window_start: get_current_time_us(), count: 0, rate: 5_000 };
+        IRQ_RATE.lock().insert(cpu, tracker);
+        assert_eq!(select_idle_state(cpu, 1_000).unwrap(), 2);
+        assert_eq!(get_demotion_count(), demotions + 1);
+
+        set_idle_state(3).unwrap();
+        assert_eq!(get_current_idle_state().unwrap(), 3);
+        assert!(matches!(set_idle_state(5), Err(CpuIdleImplError::UnsupportedState)));
+        restore_default_idle_state().unwrap();
+        assert_eq!(get_current_idle_state().unwrap(), 1);
+    }
+}
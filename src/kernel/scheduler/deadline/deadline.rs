@@ -0,0 +1,1276 @@
+//! # Deadline Scheduler
+//!
+//! This module implements the `Deadline` scheduling class (EDF). Every
+//! deadline task declares a runtime it needs within each period and a
+//! relative deadline by which that runtime must be delivered. Runnable
+//! tasks are ordered by absolute deadline and the earliest one runs first.
+//!
+//! ## Features
+//! - Per-task runtime / deadline / period parameters
+//! - Per-CPU runqueues ordered by absolute deadline
+//! - Deadline and runtime replenishment on wakeup
+//! - Wakeup preemption by earlier deadlines
+//! - Runtime accounting with per-task overrun policies, applied when a
+//!   task exhausts its runtime before its deadline or is still runnable
+//!   when its deadline passes
+//! - Constant bandwidth servers: non-deadline tasks run under a shared
+//!   runtime/period reservation, round-robin within the server
+//! - Per-CPU fair server guaranteeing the fair class a minimum share of
+//!   CPU time under RT load
+//!
+//! - Per-task history of the last scheduling events (enqueued, picked,
+//!   preempted, throttled), reported with every deadline miss while
+//!   debugging is enabled
+//!
+//! The fair server is deferred: it stays out of the way while fair tasks
+//! get their reserved runtime on their own, and only once they can no
+//! longer get it by the end of the period does it run them ahead of RT
+//! tasks until the reservation is used up.
+//!
+//! ## Usage
+//! ```rust
+//! use crate::kernel::scheduler::deadline::{DeadlineScheduler, DlParams};
+//!
+//! let deadline = DeadlineScheduler::with_config(95);
+//! deadline.set_params(task.id(), DlParams {
+//!     runtime_ns: 2_000_000,
+//!     deadline_ns: 10_000_000,
+//!     period_ns: 10_000_000,
+//! })?;
+//! deadline.set_overrun_policy(task.id(), OverrunPolicy::Throttle)?;
+//! deadline.enqueue_task(&task)?;
+//!
+//! let server = deadline.create_cbs_server(Duration::from_millis(20), Duration::from_millis(30))?;
+//! deadline.attach_task(server, &decoder)?;
+//!
+//! // 50ms of every second for the fair class, whatever RT does
+//! deadline.set_fair_server(Some(DlParams {
+//!     runtime_ns: 50_000_000,
+//!     deadline_ns: 1_000_000_000,
+//!     period_ns: 1_000_000_000,
+//! }))?;
+//!
+//! // Find out why deadlines are missed
+//! fn on_miss(miss: &DeadlineMiss) {
+//!     for entry in &miss.history {
+//!         log_event(miss.task, entry.time_ns, entry.event);
+//!     }
+//! }
+//! deadline.set_history_enabled(true);
+//! deadline.set_miss_callback(Some(on_miss));
+//! ```
+
+use crate::kernel::scheduler::trace::SchedEvent;
+use crate::kernel::task::{Task, TaskId, TaskState};
+use crate::kernel::cpu::{CpuId, CpuMask};
+use crate::kernel::time::{Timestamp, Duration};
+use crate::kernel::error::{KernelResult, SchedulerError};
+use crate::kernel::sync::{SpinLock, RwLock};
+use crate::kernel::log::{kernel_info, kernel_debug};
+use crate::kernel::memory::percpu::PerCpu;
+
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+
+/// Signal sent by `OverrunPolicy::Kill`
+const SIGKILL: u32 = 9;
+
+/// Scheduling events kept per deadline task
+pub const DL_HISTORY_LEN: usize = 8;
+
+/// Scheduling parameters of a deadline task
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DlParams {
+    /// CPU time needed per period
+    pub runtime_ns: u64,
+    /// Deadline relative to the start of each period
+    pub deadline_ns: u64,
+    /// Activation period
+    pub period_ns: u64,
+}
+
+impl DlParams {
+    /// Share of a CPU the reservation takes (per mille)
+    pub fn bandwidth(&self) -> u64 {
+        self.runtime_ns * 1000 / self.period_ns
+    }
+
+    /// Check `0 < runtime <= deadline <= period`
+    pub fn validate(&self) -> KernelResult<()> {
+        if self.runtime_ns == 0 || self.runtime_ns > self.deadline_ns || self.deadline_ns > self.period_ns {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        Ok(())
+    }
+}
+
+/// Identifier of a constant bandwidth server
+pub type ServerId = u64;
+
+/// A scheduling event in a deadline task's history
+#[derive(Debug, Clone, Copy)]
+pub struct DlHistoryEntry {
+    /// When the event happened (ns)
+    pub time_ns: u64,
+    pub event: SchedEvent,
+}
+
+/// A missed deadline and the events leading up to it
+#[derive(Debug, Clone)]
+pub struct DeadlineMiss {
+    pub task: TaskId,
+    pub cpu: CpuId,
+    /// How far past its deadline the task was when the miss was noticed
+    pub overrun_ns: u64,
+    /// The task's last scheduling events, oldest first; empty unless the
+    /// history is enabled
+    pub history: Vec<DlHistoryEntry>,
+}
+
+/// Receives deadline misses; called without runqueue locks held
+pub type DeadlineMissCallback = fn(miss: &DeadlineMiss);
+
+/// Reaction to a deadline task overrunning its reservation
+#[derive(Debug, Clone, Copy)]
+pub enum OverrunPolicy {
+    /// Kill the task
+    Kill,
+    /// Send the task a signal
+    Signal(u32),
+    /// Stop running the task until its next period
+    Throttle,
+    /// Call a function with the task and the overrun in nanoseconds
+    Notify(fn(task: TaskId, overrun_ns: u64)),
+}
+
+/// Runtime state of a deadline task
+#[derive(Debug, Clone, Copy, Default)]
+struct DlEntity {
+    /// Absolute deadline of the current instance
+    abs_deadline: u64,
+    /// Runtime left in the current instance
+    remaining_ns: u64,
+    /// The current instance ran out of runtime before its deadline
+    exhausted: bool,
+    /// The current instance's deadline passed while it was runnable
+    missed: bool,
+}
+
+impl DlEntity {
+    /// Start a new instance with a fresh deadline and full runtime
+    fn replenish(&mut self, params: &DlParams, now: u64) {
+        self.abs_deadline = now + params.deadline_ns;
+        self.remaining_ns = params.runtime_ns;
+        self.exhausted = false;
+        self.missed = false;
+    }
+}
+
+/// A constant bandwidth server
+#[derive(Debug, Clone, Copy)]
+struct CbsServer {
+    /// Budget per period; the deadline equals the period
+    params: DlParams,
+    /// CPU the server's tasks run on, while it has any
+    cpu: Option<CpuId>,
+    /// Number of attached tasks
+    nr_tasks: usize,
+}
+
+/// Runtime state of a constant bandwidth server on its CPU
+#[derive(Debug)]
+struct ServerRq {
+    params: DlParams,
+    /// Deadline and remaining budget of the server
+    se: DlEntity,
+    /// Runnable attached tasks waiting to run, in round-robin order
+    queue: VecDeque<TaskId>,
+    /// The running task used up a budget and should let the next one run
+    rotate: bool,
+}
+
+/// Per-CPU state of the fair server
+#[derive(Debug, Clone, Copy)]
+struct FairServer {
+    /// Start of the current period (task clock)
+    period_start: u64,
+    /// Fair runtime still owed in the current period
+    remaining_ns: u64,
+    /// Task clock of the last update
+    last_update: u64,
+    /// Fair tasks run ahead of RT tasks
+    active: bool,
+}
+
+impl FairServer {
+    /// Start a period at `now` with the full reservation
+    fn new(params: &DlParams, now: u64) -> Self {
+        Self {
+            period_start: now,
+            remaining_ns: params.runtime_ns,
+            last_update: now,
+            active: false,
+        }
+    }
+
+    /// Charge the fair time since the last update and decide whether the
+    /// server must run fair tasks ahead of RT
+    ///
+    /// `fair_ran` tells whether a fair task ran since the last update. The
+    /// server activates at zero laxity, when the runtime still owed only
+    /// just fits before the deadline, and stays active until it is paid.
+    fn update(&mut self, params: &DlParams, now: u64, fair_ran: bool, fair_runnable: bool) -> bool {
+        if fair_ran {
+            self.remaining_ns = self.remaining_ns.saturating_sub(now.saturating_sub(self.last_update));
+        }
+        self.last_update = now;
+        let elapsed = now.saturating_sub(self.period_start);
+        if elapsed >= params.period_ns {
+            self.period_start += elapsed / params.period_ns * params.period_ns;
+            self.remaining_ns = params.runtime_ns;
+        }
+        let zero_laxity = self.period_start + params.deadline_ns - self.remaining_ns;
+        self.active = fair_runnable && self.remaining_ns > 0 && now >= zero_laxity;
+        self.active
+    }
+}
+
+/// Something scheduled by deadline: a deadline task or a server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum DlNode {
+    Task(TaskId),
+    Server(ServerId),
+}
+
+/// Per-CPU deadline runqueue
+#[derive(Debug, Default)]
+struct DlRq {
+    /// Queued tasks, and servers with queued tasks, by absolute deadline
+    tree: BTreeSet<(u64, DlNode)>,
+    /// State of queued and running tasks
+    entities: BTreeMap<TaskId, DlEntity>,
+    /// Servers that have had tasks on this CPU
+    servers: BTreeMap<ServerId, ServerRq>,
+    /// Currently running deadline or served task
+    curr: Option<TaskId>,
+    /// Server of `curr`, if it is a served task
+    curr_server: Option<ServerId>,
+    /// Task clock when `curr` was last charged
+    exec_start: u64,
+    /// Throttled tasks and when they are replenished
+    throttled: BTreeMap<TaskId, u64>,
+    /// Fair server state, from its first update
+    fair_server: Option<FairServer>,
+}
+
+impl DlRq {
+    /// Deadline a task is scheduled by: its own, or its server's
+    fn deadline_of(&self, task: TaskId, server: Option<ServerId>) -> Option<u64> {
+        match server {
+            Some(server) => self.servers.get(&server).map(|s| s.se.abs_deadline),
+            None => self.entities.get(&task).map(|se| se.abs_deadline),
+        }
+    }
+
+    /// Deadline the running task is scheduled by
+    fn curr_deadline(&self) -> Option<u64> {
+        self.deadline_of(self.curr?, self.curr_server)
+    }
+
+    /// Whether the running task must give way to any queued one: it is
+    /// throttled, or its server's budget rotated to the next task
+    fn curr_must_yield(&self) -> bool {
+        let throttled = self.curr.map_or(false, |task| self.throttled.contains_key(&task));
+        let rotated = self.curr_server.and_then(|server| self.servers.get(&server))
+            .map_or(false, |s| s.rotate && !s.queue.is_empty());
+        throttled || rotated
+    }
+
+    /// Take a server out of the tree before changing its deadline or queue
+    fn unlink_server(&mut self, server: ServerId) {
+        if let Some(s) = self.servers.get(&server) {
+            self.tree.remove(&(s.se.abs_deadline, DlNode::Server(server)));
+        }
+    }
+
+    /// Put a server back into the tree if it has queued tasks
+    fn link_server(&mut self, server: ServerId) {
+        if let Some(s) = self.servers.get(&server) {
+            if !s.queue.is_empty() {
+                self.tree.insert((s.se.abs_deadline, DlNode::Server(server)));
+            }
+        }
+    }
+
+    /// Charge a server's budget
+    ///
+    /// CBS rule: each time the budget runs out, it is refilled and the
+    /// deadline postponed by one period.
+    fn charge_server(&mut self, server: ServerId, mut delta: u64) {
+        self.unlink_server(server);
+        if let Some(s) = self.servers.get_mut(&server) {
+            while delta >= s.se.remaining_ns {
+                delta -= s.se.remaining_ns;
+                s.se.abs_deadline += s.params.period_ns;
+                s.se.remaining_ns = s.params.runtime_ns;
+                s.rotate = true;
+            }
+            s.se.remaining_ns -= delta;
+        }
+        self.link_server(server);
+    }
+}
+
+/// Deadline scheduler component
+pub struct DeadlineScheduler {
+    rqs: PerCpu<SpinLock<DlRq>>,
+    params: RwLock<BTreeMap<TaskId, DlParams>>,
+    policies: RwLock<BTreeMap<TaskId, OverrunPolicy>>,
+    servers: RwLock<BTreeMap<ServerId, CbsServer>>,
+    /// Server of every attached task
+    served: RwLock<BTreeMap<TaskId, ServerId>>,
+    next_server_id: AtomicU64,
+    bandwidth_percent: AtomicU32,
+    /// Reservation of the fair server on every CPU
+    fair_server: RwLock<Option<DlParams>>,
+    /// Last scheduling events of every deadline task, while enabled
+    history: SpinLock<BTreeMap<TaskId, VecDeque<DlHistoryEntry>>>,
+    history_enabled: AtomicBool,
+    miss_callback: RwLock<Option<DeadlineMissCallback>>,
+}
+
+impl DeadlineScheduler {
+    /// Create a deadline scheduler limited to a percentage of CPU time
+    pub fn with_config(bandwidth_percent: u32) -> Self {
+        Self::with_fair_server(bandwidth_percent, None)
+    }
+
+    /// Create a deadline scheduler limited to a percentage of CPU time,
+    /// with a fair server reservation on every CPU
+    ///
+    /// Invalid fair server parameters leave the fair server disabled.
+    pub fn with_fair_server(bandwidth_percent: u32, fair_server: Option<DlParams>) -> Self {
+        let fair_server = fair_server.filter(|params| params.validate().is_ok());
+        Self {
+            rqs: PerCpu::new(SpinLock::new(DlRq::default())),
+            params: RwLock::new(BTreeMap::new()),
+            policies: RwLock::new(BTreeMap::new()),
+            servers: RwLock::new(BTreeMap::new()),
+            served: RwLock::new(BTreeMap::new()),
+            next_server_id: AtomicU64::new(1),
+            bandwidth_percent: AtomicU32::new(bandwidth_percent.min(100)),
+            fair_server: RwLock::new(fair_server),
+            history: SpinLock::new(BTreeMap::new()),
+            history_enabled: AtomicBool::new(false),
+            miss_callback: RwLock::new(None),
+        }
+    }
+
+    /// Set the deadline parameters of a task
+    ///
+    /// Fails with `SchedulerError::InvalidParameter` for invalid parameters
+    /// or a task attached to a server.
+    pub fn set_params(&self, task: TaskId, params: DlParams) -> KernelResult<()> {
+        params.validate()?;
+        if self.server_of(task).is_some() {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        self.params.write().insert(task, params);
+        Ok(())
+    }
+
+    /// Deadline parameters of a task
+    pub fn params(&self, task: TaskId) -> Option<DlParams> {
+        self.params.read().get(&task).copied()
+    }
+
+    /// Set how a deadline task's overruns are handled
+    ///
+    /// Without a policy overruns are only counted.
+    ///
+    /// # Returns
+    /// - `Ok(())` on success
+    /// - `Err(SchedulerError::InvalidParameter)` if the task has no
+    ///   deadline parameters
+    pub fn set_overrun_policy(&self, task: TaskId, policy: OverrunPolicy) -> KernelResult<()> {
+        if self.params(task).is_none() {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        self.policies.write().insert(task, policy);
+        Ok(())
+    }
+
+    /// Overrun policy of a task
+    pub fn overrun_policy(&self, task: TaskId) -> Option<OverrunPolicy> {
+        self.policies.read().get(&task).copied()
+    }
+
+    /// Create a constant bandwidth server granting `runtime` every `period`
+    ///
+    /// Tasks attached to the server are scheduled by its deadline and
+    /// share its budget.
+    ///
+    /// # Returns
+    /// - The new server's id
+    /// - `Err(SchedulerError::InvalidParameter)` unless
+    ///   `0 < runtime <= period`
+    pub fn create_cbs_server(&self, runtime: Duration, period: Duration) -> KernelResult<ServerId> {
+        let params = DlParams {
+            runtime_ns: runtime.as_nanos(),
+            deadline_ns: period.as_nanos(),
+            period_ns: period.as_nanos(),
+        };
+        params.validate()?;
+        let id = self.next_server_id.fetch_add(1, Ordering::Relaxed);
+        self.servers.write().insert(id, CbsServer { params, cpu: None, nr_tasks: 0 });
+        kernel_debug!("CBS server {} created: {}ns every {}ns", id, params.runtime_ns, params.period_ns);
+        Ok(id)
+    }
+
+    /// Destroy a constant bandwidth server
+    ///
+    /// # Returns
+    /// - `Ok(())` on success
+    /// - `Err(SchedulerError::InvalidParameter)` if the server does not exist
+    /// - `Err(SchedulerError::Busy)` if tasks are still attached
+    pub fn destroy_cbs_server(&self, server: ServerId) -> KernelResult<()> {
+        let mut servers = self.servers.write();
+        match servers.get(&server) {
+            None => return Err(SchedulerError::InvalidParameter.into()),
+            Some(s) if s.nr_tasks > 0 => return Err(SchedulerError::Busy.into()),
+            Some(_) => {}
+        }
+        servers.remove(&server);
+        drop(servers);
+        for cpu in CpuMask::online().iter() {
+            let mut rq = self.rqs.get(cpu).lock();
+            rq.unlink_server(server);
+            rq.servers.remove(&server);
+        }
+        Ok(())
+    }
+
+    /// Attach a non-deadline task to a server
+    ///
+    /// All tasks of a server run on one CPU: the CPU of the first attached
+    /// task. The caller moves the task to `server_cpu` and requeues it
+    /// from its class to this one.
+    ///
+    /// # Returns
+    /// - `Ok(())` on success
+    /// - `Err(SchedulerError::InvalidParameter)` if the server does not
+    ///   exist, the task is a deadline task or attached to another server
+    pub fn attach_task(&self, server: ServerId, task: &Task) -> KernelResult<()> {
+        if self.params(task.id()).is_some() {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        let mut servers = self.servers.write();
+        let s = servers.get_mut(&server).ok_or(SchedulerError::InvalidParameter)?;
+        let mut served = self.served.write();
+        match served.get(&task.id()) {
+            Some(&current) if current == server => return Ok(()),
+            Some(_) => return Err(SchedulerError::InvalidParameter.into()),
+            None => {}
+        }
+        served.insert(task.id(), server);
+        s.cpu.get_or_insert(task.current_cpu());
+        s.nr_tasks += 1;
+        Ok(())
+    }
+
+    /// Detach a task from its server
+    ///
+    /// The task leaves this class; the caller requeues it in its own.
+    pub fn detach_task(&self, task: &Task) {
+        let server = match self.served.write().remove(&task.id()) {
+            Some(server) => server,
+            None => return,
+        };
+        {
+            let mut rq = self.rqs.get(task.current_cpu()).lock();
+            Self::unqueue_served(&mut rq, task.id(), server);
+        }
+        if let Some(s) = self.servers.write().get_mut(&server) {
+            s.nr_tasks -= 1;
+            if s.nr_tasks == 0 {
+                s.cpu = None;
+            }
+        }
+    }
+
+    /// Server a task is attached to
+    pub fn server_of(&self, task: TaskId) -> Option<ServerId> {
+        self.served.read().get(&task).copied()
+    }
+
+    /// CPU the tasks of a server run on
+    pub fn server_cpu(&self, server: ServerId) -> Option<CpuId> {
+        self.servers.read().get(&server).and_then(|s| s.cpu)
+    }
+
+    /// Budget a server has left in its current period
+    pub fn server_budget(&self, server: ServerId) -> Option<Duration> {
+        let s = self.servers.read().get(&server).copied()?;
+        let remaining = s.cpu
+            .and_then(|cpu| self.rqs.get(cpu).lock().servers.get(&server).map(|rq| rq.se.remaining_ns))
+            .unwrap_or(s.params.runtime_ns);
+        Some(Duration::from_nanos(remaining))
+    }
+
+    /// Make a deadline or served task runnable
+    ///
+    /// If the previous deadline has passed, a new instance starts with a
+    /// fresh deadline and full runtime. A throttled task is queued when it
+    /// is replenished.
+    pub fn enqueue_task(&self, task: &Task) -> KernelResult<()> {
+        if let Some(server) = self.server_of(task.id()) {
+            return self.enqueue_served(task, server);
+        }
+        let params = self.params(task.id()).ok_or(SchedulerError::InvalidParameter)?;
+        let now = Timestamp::now().as_nanos();
+        let mut rq = self.rqs.get(task.current_cpu()).lock();
+        if rq.curr == Some(task.id()) || rq.throttled.contains_key(&task.id())
+            || rq.tree.iter().any(|&(_, node)| node == DlNode::Task(task.id())) {
+            return Ok(());
+        }
+
+        let mut se = rq.entities.get(&task.id()).copied().unwrap_or_default();
+        if se.abs_deadline <= now {
+            se.replenish(&params, now);
+        }
+        rq.entities.insert(task.id(), se);
+        rq.tree.insert((se.abs_deadline, DlNode::Task(task.id())));
+        self.record_event(task.id(), SchedEvent::Wakeup { task: task.id(), target_cpu: task.current_cpu() });
+        kernel_debug!("DL task {} enqueued, deadline {}", task.id().as_u64(), se.abs_deadline);
+        Ok(())
+    }
+
+    /// Remove a deadline or served task from its CPU
+    pub fn dequeue_task(&self, task: &Task) -> KernelResult<()> {
+        let server = self.server_of(task.id());
+        let mut rq = self.rqs.get(task.current_cpu()).lock();
+        match server {
+            Some(server) => Self::unqueue_served(&mut rq, task.id(), server),
+            None => {
+                if let Some(se) = rq.entities.get(&task.id()).copied() {
+                    rq.tree.remove(&(se.abs_deadline, DlNode::Task(task.id())));
+                }
+                if rq.curr == Some(task.id()) {
+                    rq.curr = None;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Peek at the queued task with the earliest deadline
+    ///
+    /// For a server that is its next task in round-robin order.
+    pub fn pick_next_task(&self, cpu: CpuId) -> KernelResult<Option<Arc<Task>>> {
+        let rq = self.rqs.get(cpu).lock();
+        let next = rq.tree.iter().next().and_then(|&(_, node)| match node {
+            DlNode::Task(task) => Some(task),
+            DlNode::Server(server) => rq.servers.get(&server).and_then(|s| s.queue.front().copied()),
+        });
+        drop(rq);
+        Ok(next.and_then(Task::get_by_id))
+    }
+
+    /// Check whether a woken task's deadline is earlier than the running
+    /// one's, or the running one has to give way anyway
+    pub fn should_preempt_current(&self, task: &Task) -> KernelResult<bool> {
+        let server = self.server_of(task.id());
+        let rq = self.rqs.get(task.current_cpu()).lock();
+        if rq.curr_must_yield() {
+            return Ok(true);
+        }
+        Ok(match (rq.curr_deadline(), rq.deadline_of(task.id(), server)) {
+            (Some(curr), Some(new)) => new < curr,
+            (None, _) => true,
+            (Some(_), None) => false,
+        })
+    }
+
+    /// Start running a queued deadline or served task on a CPU at task
+    /// clock `now`
+    pub fn set_curr_task(&self, cpu: CpuId, task: TaskId, now: u64) {
+        let server = self.server_of(task);
+        let mut rq = self.rqs.get(cpu).lock();
+        match server {
+            Some(server) => {
+                let pos = rq.servers.get(&server).and_then(|s| s.queue.iter().position(|&t| t == task));
+                let pos = match pos {
+                    Some(pos) => pos,
+                    None => return,
+                };
+                rq.unlink_server(server);
+                if let Some(s) = rq.servers.get_mut(&server) {
+                    s.queue.remove(pos);
+                    s.rotate = false;
+                }
+                rq.link_server(server);
+                rq.curr = Some(task);
+                rq.curr_server = Some(server);
+                rq.exec_start = now;
+            }
+            None => {
+                if let Some(se) = rq.entities.get(&task).copied() {
+                    if rq.tree.remove(&(se.abs_deadline, DlNode::Task(task))) {
+                        rq.curr = Some(task);
+                        rq.exec_start = now;
+                        self.record_event(task, SchedEvent::SwitchIn { next: task });
+                    }
+                }
+            }
+        }
+    }
+
+    /// Stop running a deadline or served task, charging its runtime and
+    /// requeueing it by deadline unless it is throttled
+    ///
+    /// A served task goes to the back of its server's queue.
+    pub fn put_prev_task(&self, cpu: CpuId, task: TaskId, now: u64) {
+        let mut overruns = Vec::new();
+        {
+            let mut rq = self.rqs.get(cpu).lock();
+            if rq.curr != Some(task) {
+                return;
+            }
+            if let Some(overrun_ns) = Self::update_curr(&mut rq, now, Timestamp::now().as_nanos()) {
+                self.overrun(&mut rq, task, overrun_ns, &mut overruns);
+            }
+            rq.curr = None;
+            if let Some(server) = rq.curr_server.take() {
+                rq.unlink_server(server);
+                if let Some(s) = rq.servers.get_mut(&server) {
+                    s.queue.push_back(task);
+                    s.rotate = false;
+                }
+                rq.link_server(server);
+            } else if let Some(se) = rq.entities.get(&task).copied() {
+                if !rq.throttled.contains_key(&task) {
+                    rq.tree.insert((se.abs_deadline, DlNode::Task(task)));
+                }
+            }
+        }
+        Self::apply_overrun_policies(overruns);
+    }
+
+    /// Charge the running deadline task and check for overruns
+    ///
+    /// `now` is the task clock. Replenishes throttled tasks whose period
+    /// has come, applies the overrun policy of a task that exhausted its
+    /// runtime or whose deadline passed while it was runnable, reports
+    /// every miss to the miss callback and returns the number of deadlines
+    /// missed.
+    pub fn task_tick(&self, cpu: CpuId, now: u64) -> usize {
+        let wall_now = Timestamp::now().as_nanos();
+        let mut overruns = Vec::new();
+        let mut misses = Vec::new();
+        {
+            let mut rq = self.rqs.get(cpu).lock();
+            self.replenish_throttled(&mut rq, wall_now);
+            if let Some(task) = rq.curr {
+                if let Some(overrun_ns) = Self::update_curr(&mut rq, now, wall_now) {
+                    self.overrun(&mut rq, task, overrun_ns, &mut overruns);
+                }
+            }
+
+            // Servers are soft reservations: only deadline tasks miss
+            let queued = rq.tree.iter()
+                .take_while(|&&(deadline, _)| deadline < wall_now)
+                .filter_map(|&(_, node)| match node {
+                    DlNode::Task(task) => Some(task),
+                    DlNode::Server(_) => None,
+                });
+            let runnable = rq.curr.filter(|_| rq.curr_server.is_none()).into_iter().chain(queued);
+            let missed: Vec<(TaskId, u64)> = runnable
+                .filter(|task| !rq.throttled.contains_key(task))
+                .filter_map(|task| rq.entities.get(&task).map(|se| (task, *se)))
+                .filter(|(_, se)| se.abs_deadline < wall_now && !se.missed)
+                .map(|(task, se)| (task, wall_now - se.abs_deadline))
+                .collect();
+            for (task, overrun_ns) in missed {
+                if let Some(se) = rq.entities.get_mut(&task) {
+                    se.missed = true;
+                }
+                misses.push((task, overrun_ns));
+                kernel_debug!("DL task {} missed its deadline by {}ns", task.as_u64(), overrun_ns);
+                self.overrun(&mut rq, task, overrun_ns, &mut overruns);
+            }
+        }
+        Self::apply_overrun_policies(overruns);
+        let nr_misses = misses.len();
+        self.report_misses(cpu, misses);
+        nr_misses
+    }
+
+    /// Earliest time (in nanoseconds) a throttled task of a CPU starts
+    /// its next period, if any task is throttled
+    pub fn next_replenish(&self, cpu: CpuId) -> Option<u64> {
+        self.rqs.get(cpu).lock().throttled.values().min().copied()
+    }
+
+    /// Check whether the running deadline task of a CPU is throttled
+    pub fn curr_throttled(&self, cpu: CpuId) -> bool {
+        let rq = self.rqs.get(cpu).lock();
+        rq.curr.map_or(false, |task| rq.throttled.contains_key(&task))
+    }
+
+    /// Queued deadline and served tasks of a CPU ordered by the absolute
+    /// deadline they are scheduled by
+    pub fn deadline_tree(&self, cpu: CpuId) -> Vec<(TaskId, u64)> {
+        let rq = self.rqs.get(cpu).lock();
+        let mut tree = Vec::new();
+        for &(deadline, node) in rq.tree.iter() {
+            match node {
+                DlNode::Task(task) => tree.push((task, deadline)),
+                DlNode::Server(server) => {
+                    if let Some(s) = rq.servers.get(&server) {
+                        tree.extend(s.queue.iter().map(|&task| (task, deadline)));
+                    }
+                }
+            }
+        }
+        tree
+    }
+
+    /// Queued deadline and served tasks of a CPU, throttled tasks included
+    pub fn queued_tasks(&self, cpu: CpuId) -> Vec<TaskId> {
+        let mut tasks: Vec<TaskId> = self.deadline_tree(cpu).into_iter().map(|(task, _)| task).collect();
+        tasks.extend(self.rqs.get(cpu).lock().throttled.keys());
+        tasks
+    }
+
+    /// Move a deadline task's runqueue state between CPUs
+    ///
+    /// Its deadline, remaining runtime and throttling go with it; a
+    /// running task arrives queued. Tasks of a server move with the server
+    /// (`migrate_server`).
+    pub fn migrate_task(&self, task: TaskId, from: CpuId, to: CpuId) {
+        if from == to || self.server_of(task).is_some() {
+            return;
+        }
+        let (se, queued, replenish) = {
+            let mut rq = self.rqs.get(from).lock();
+            let se = match rq.entities.remove(&task) {
+                Some(se) => se,
+                None => return,
+            };
+            let queued = rq.tree.remove(&(se.abs_deadline, DlNode::Task(task))) || rq.curr == Some(task);
+            if rq.curr == Some(task) {
+                rq.curr = None;
+            }
+            (se, queued, rq.throttled.remove(&task))
+        };
+        let mut rq = self.rqs.get(to).lock();
+        rq.entities.insert(task, se);
+        match replenish {
+            Some(at) => {
+                rq.throttled.insert(task, at);
+            }
+            None if queued => {
+                rq.tree.insert((se.abs_deadline, DlNode::Task(task)));
+            }
+            None => {}
+        }
+    }
+
+    /// Servers whose tasks run on a CPU
+    pub fn servers_on(&self, cpu: CpuId) -> Vec<ServerId> {
+        self.servers.read().iter().filter(|(_, s)| s.cpu == Some(cpu)).map(|(&id, _)| id).collect()
+    }
+
+    /// Tasks attached to a server
+    pub fn server_tasks(&self, server: ServerId) -> Vec<TaskId> {
+        self.served.read().iter().filter(|&(_, &s)| s == server).map(|(&task, _)| task).collect()
+    }
+
+    /// Move a server, with its deadline, budget and queued tasks, to
+    /// another CPU
+    ///
+    /// Its tasks run on `to` afterwards, and a running one arrives queued;
+    /// the caller moves the queued and running tasks there. A server
+    /// without tasks is not bound to a CPU and stays as it is.
+    ///
+    /// # Returns
+    /// - `Ok(())` on success
+    /// - `Err(SchedulerError::InvalidParameter)` if the server does not exist
+    pub fn migrate_server(&self, server: ServerId, to: CpuId) -> KernelResult<()> {
+        let from = {
+            let mut servers = self.servers.write();
+            let s = servers.get_mut(&server).ok_or(SchedulerError::InvalidParameter)?;
+            match s.cpu {
+                Some(from) if from != to => {
+                    s.cpu = Some(to);
+                    from
+                }
+                _ => return Ok(()),
+            }
+        };
+        let moved = {
+            let mut rq = self.rqs.get(from).lock();
+            rq.unlink_server(server);
+            let mut moved = rq.servers.remove(&server);
+            if rq.curr_server == Some(server) {
+                if let (Some(s), Some(task)) = (moved.as_mut(), rq.curr) {
+                    s.queue.push_front(task);
+                }
+                rq.curr = None;
+                rq.curr_server = None;
+            }
+            moved
+        };
+        if let Some(s) = moved {
+            let mut rq = self.rqs.get(to).lock();
+            rq.servers.insert(server, s);
+            rq.link_server(server);
+        }
+        Ok(())
+    }
+
+    /// Forget an exiting task
+    pub fn remove_task(&self, task: &Task) {
+        self.detach_task(task);
+        let mut rq = self.rqs.get(task.current_cpu()).lock();
+        if let Some(se) = rq.entities.remove(&task.id()) {
+            rq.tree.remove(&(se.abs_deadline, DlNode::Task(task.id())));
+        }
+        rq.throttled.remove(&task.id());
+        if rq.curr == Some(task.id()) {
+            rq.curr = None;
+        }
+        drop(rq);
+        self.params.write().remove(&task.id());
+        self.policies.write().remove(&task.id());
+        self.history.lock().remove(&task.id());
+    }
+
+    /// Enable or disable the per-task scheduling event history
+    ///
+    /// Disabling drops the histories collected so far.
+    pub fn set_history_enabled(&self, enabled: bool) {
+        self.history_enabled.store(enabled, Ordering::Relaxed);
+        if !enabled {
+            self.history.lock().clear();
+        }
+    }
+
+    /// Check whether the per-task scheduling event history is collected
+    pub fn history_enabled(&self) -> bool {
+        self.history_enabled.load(Ordering::Relaxed)
+    }
+
+    /// Add an event to a deadline task's history, dropping the oldest one
+    /// beyond `DL_HISTORY_LEN`
+    ///
+    /// Does nothing while the history is disabled or for a task without
+    /// deadline parameters.
+    pub fn record_event(&self, task: TaskId, event: SchedEvent) {
+        if !self.history_enabled() || self.params(task).is_none() {
+            return;
+        }
+        let entry = DlHistoryEntry { time_ns: Timestamp::now().as_nanos(), event };
+        let mut history = self.history.lock();
+        let events = history.entry(task).or_insert_with(|| VecDeque::with_capacity(DL_HISTORY_LEN));
+        if events.len() == DL_HISTORY_LEN {
+            events.pop_front();
+        }
+        events.push_back(entry);
+    }
+
+    /// Last scheduling events of a deadline task, oldest first
+    pub fn history(&self, task: TaskId) -> Vec<DlHistoryEntry> {
+        self.history.lock().get(&task).map_or_else(Vec::new, |events| events.iter().copied().collect())
+    }
+
+    /// Call `callback` with every deadline miss, or stop with `None`
+    pub fn set_miss_callback(&self, callback: Option<DeadlineMissCallback>) {
+        *self.miss_callback.write() = callback;
+    }
+
+    /// Deadline bandwidth limit in percent of each CPU
+    pub fn bandwidth_percent(&self) -> u32 {
+        self.bandwidth_percent.load(Ordering::Relaxed)
+    }
+
+    /// Bandwidth reserved by deadline tasks, CBS servers and the fair
+    /// servers of the online CPUs (0-1000 per CPU)
+    pub fn total_bandwidth(&self) -> u64 {
+        let fair_servers = self.fair_server.read().as_ref().map_or(0, DlParams::bandwidth)
+            * CpuMask::online().weight() as u64;
+        self.params.read().values().map(DlParams::bandwidth).sum::<u64>()
+            + self.servers.read().values().map(|s| s.params.bandwidth()).sum::<u64>()
+            + fair_servers
+    }
+
+    /// Bandwidth reserved on each CPU (0-1000)
+    ///
+    /// Deadline tasks count on the CPU they are on, CBS servers on the CPU
+    /// of their tasks and the fair server on every online CPU. A server
+    /// without tasks is not on any CPU yet.
+    pub fn cpu_bandwidth(&self) -> BTreeMap<CpuId, u64> {
+        let mut cpus: BTreeMap<CpuId, u64> = BTreeMap::new();
+        if let Some(params) = self.fair_server() {
+            for cpu in CpuMask::online().iter() {
+                *cpus.entry(cpu).or_default() += params.bandwidth();
+            }
+        }
+        for (&task, params) in self.params.read().iter() {
+            if let Some(task) = Task::get_by_id(task) {
+                *cpus.entry(task.current_cpu()).or_default() += params.bandwidth();
+            }
+        }
+        for server in self.servers.read().values() {
+            if let Some(cpu) = server.cpu {
+                *cpus.entry(cpu).or_default() += server.params.bandwidth();
+            }
+        }
+        cpus
+    }
+
+    /// Set the fair server reservation of every CPU, or disable the fair
+    /// server with `None`
+    ///
+    /// Every CPU starts a new period at its next update. Fails with
+    /// `SchedulerError::InvalidParameter` for invalid parameters.
+    pub fn set_fair_server(&self, params: Option<DlParams>) -> KernelResult<()> {
+        if let Some(params) = &params {
+            params.validate()?;
+        }
+        *self.fair_server.write() = params;
+        for cpu in CpuMask::online().iter() {
+            self.rqs.get(cpu).lock().fair_server = None;
+        }
+        match params {
+            Some(p) => kernel_info!("Fair server: {}ns every {}ns", p.runtime_ns, p.period_ns),
+            None => kernel_info!("Fair server disabled"),
+        }
+        Ok(())
+    }
+
+    /// Fair server reservation, if the fair server is enabled
+    pub fn fair_server(&self) -> Option<DlParams> {
+        *self.fair_server.read()
+    }
+
+    /// Charge a CPU's fair server and decide whether it runs fair tasks
+    /// ahead of RT
+    ///
+    /// `now` is the task clock; `fair_ran` tells whether a fair task ran
+    /// since the last update. Returns whether the decision changed, in
+    /// which case the CPU must reschedule.
+    pub fn fair_server_tick(&self, cpu: CpuId, now: u64, fair_ran: bool, fair_runnable: bool) -> bool {
+        let params = match self.fair_server() {
+            Some(params) => params,
+            None => return false,
+        };
+        let mut rq = self.rqs.get(cpu).lock();
+        let server = rq.fair_server.get_or_insert_with(|| FairServer::new(&params, now));
+        let was_active = server.active;
+        server.update(&params, now, fair_ran, fair_runnable) != was_active
+    }
+
+    /// Check whether a CPU's fair server currently runs fair tasks ahead
+    /// of RT tasks
+    pub fn fair_server_active(&self, cpu: CpuId) -> bool {
+        self.rqs.get(cpu).lock().fair_server.map_or(false, |server| server.active)
+    }
+
+    /// Log deadline scheduler state
+    pub fn print_deadline_info(&self) -> KernelResult<()> {
+        kernel_info!("Deadline bandwidth: {}%", self.bandwidth_percent());
+        kernel_info!("Deadline tasks: {}", self.params.read().len());
+        kernel_info!("Deadline tasks with overrun policy: {}", self.policies.read().len());
+        for (id, server) in self.servers.read().iter() {
+            kernel_info!("CBS server {}: {}ns every {}ns, {} tasks", id,
+                        server.params.runtime_ns, server.params.period_ns, server.nr_tasks);
+        }
+        if let Some(params) = self.fair_server() {
+            kernel_info!("Fair server: {}ns every {}ns", params.runtime_ns, params.period_ns);
+        }
+        Ok(())
+    }
+
+    /// Queue a served task in its server
+    ///
+    /// CBS wakeup rule: if the server was idle and its remaining budget
+    /// cannot be used up by its current deadline at its reserved rate, it
+    /// gets a fresh deadline and full budget.
+    fn enqueue_served(&self, task: &Task, server: ServerId) -> KernelResult<()> {
+        let params = self.servers.read().get(&server).map(|s| s.params)
+            .ok_or(SchedulerError::InvalidParameter)?;
+        let now = Timestamp::now().as_nanos();
+        let mut guard = self.rqs.get(task.current_cpu()).lock();
+        let rq = &mut *guard;
+        if rq.curr == Some(task.id()) {
+            return Ok(());
+        }
+        let was_idle = rq.curr_server != Some(server)
+            && rq.servers.get(&server).map_or(true, |s| s.queue.is_empty());
+
+        rq.unlink_server(server);
+        let s = rq.servers.entry(server).or_insert_with(|| ServerRq {
+            params,
+            se: DlEntity::default(),
+            queue: VecDeque::new(),
+            rotate: false,
+        });
+        if !s.queue.contains(&task.id()) {
+            if was_idle {
+                let se = &mut s.se;
+                let stale = se.abs_deadline <= now
+                    || se.remaining_ns as u128 * params.period_ns as u128
+                        > (se.abs_deadline - now) as u128 * params.runtime_ns as u128;
+                if stale {
+                    se.replenish(&params, now);
+                }
+            }
+            s.queue.push_back(task.id());
+        }
+        rq.link_server(server);
+        kernel_debug!("Task {} queued on CBS server {}", task.id().as_u64(), server);
+        Ok(())
+    }
+
+    /// Take a served task off its server's queue or the CPU
+    fn unqueue_served(rq: &mut DlRq, task: TaskId, server: ServerId) {
+        rq.unlink_server(server);
+        if let Some(s) = rq.servers.get_mut(&server) {
+            s.queue.retain(|&t| t != task);
+        }
+        rq.link_server(server);
+        if rq.curr == Some(task) {
+            rq.curr = None;
+            rq.curr_server = None;
+        }
+    }
+
+    /// Charge the running task's runtime since it was last charged
+    ///
+    /// Returns the overrun if this exhausted its runtime before its
+    /// deadline for the first time in the current instance.
+    fn update_curr(rq: &mut DlRq, now: u64, wall_now: u64) -> Option<u64> {
+        let task = rq.curr?;
+        let delta = now.saturating_sub(rq.exec_start);
+        rq.exec_start = now;
+        if let Some(server) = rq.curr_server {
+            rq.charge_server(server, delta);
+            return None;
+        }
+        let se = rq.entities.get_mut(&task)?;
+        if se.remaining_ns > delta {
+            se.remaining_ns -= delta;
+            return None;
+        }
+        let overrun_ns = delta - se.remaining_ns;
+        se.remaining_ns = 0;
+        if se.exhausted || wall_now >= se.abs_deadline {
+            return None;
+        }
+        se.exhausted = true;
+        kernel_debug!("DL task {} exhausted its runtime, overrun {}ns", task.as_u64(), overrun_ns);
+        Some(overrun_ns)
+    }
+
+    /// React to an overrun: throttle right away, or record the policy to
+    /// apply once the runqueue lock is dropped
+    fn overrun(&self, rq: &mut DlRq, task: TaskId, overrun_ns: u64,
+               overruns: &mut Vec<(TaskId, u64, OverrunPolicy)>) {
+        match self.overrun_policy(task) {
+            Some(OverrunPolicy::Throttle) => self.throttle(rq, task),
+            Some(policy) => overruns.push((task, overrun_ns, policy)),
+            None => {}
+        }
+    }
+
+    /// Stop a task from running until the start of its next period
+    fn throttle(&self, rq: &mut DlRq, task: TaskId) {
+        let (se, params) = match (rq.entities.get(&task).copied(), self.params(task)) {
+            (Some(se), Some(params)) => (se, params),
+            _ => return,
+        };
+        rq.tree.remove(&(se.abs_deadline, DlNode::Task(task)));
+        let next_period = se.abs_deadline - params.deadline_ns + params.period_ns;
+        rq.throttled.insert(task, next_period);
+        self.record_event(task, SchedEvent::Throttle { task, until: next_period });
+        kernel_debug!("DL task {} throttled until {}", task.as_u64(), next_period);
+    }
+
+    /// Start a new instance of every throttled task whose period has come,
+    /// queueing those that are still runnable
+    fn replenish_throttled(&self, rq: &mut DlRq, now: u64) {
+        let due: Vec<TaskId> = rq.throttled.iter()
+            .filter(|&(_, &at)| at <= now)
+            .map(|(&task, _)| task)
+            .collect();
+        for task in due {
+            rq.throttled.remove(&task);
+            let (se, params) = match (rq.entities.get_mut(&task), self.params(task)) {
+                (Some(se), Some(params)) => (se, params),
+                _ => continue,
+            };
+            se.replenish(&params, now);
+            let abs_deadline = se.abs_deadline;
+            let runnable = Task::get_by_id(task)
+                .map_or(false, |t| matches!(t.state(), TaskState::Runnable | TaskState::Running));
+            if rq.curr != Some(task) && runnable {
+                rq.tree.insert((abs_deadline, DlNode::Task(task)));
+            }
+        }
+    }
+
+    /// Hand the misses of a tick to the miss callback, with each task's
+    /// history
+    fn report_misses(&self, cpu: CpuId, misses: Vec<(TaskId, u64)>) {
+        let callback = match *self.miss_callback.read() {
+            Some(callback) => callback,
+            None => return,
+        };
+        for (task, overrun_ns) in misses {
+            callback(&DeadlineMiss { task, cpu, overrun_ns, history: self.history(task) });
+        }
+    }
+
+    /// Apply the non-throttling overrun policies collected under a
+    /// runqueue lock
+    fn apply_overrun_policies(overruns: Vec<(TaskId, u64, OverrunPolicy)>) {
+        for (task, overrun_ns, policy) in overruns {
+            match policy {
+                OverrunPolicy::Kill => {
+                    if let Some(task) = Task::get_by_id(task) {
+                        task.send_signal(SIGKILL);
+                    }
+                }
+                OverrunPolicy::Signal(sig) => {
+                    if let Some(task) = Task::get_by_id(task) {
+                        task.send_signal(sig);
+                    }
+                }
+                OverrunPolicy::Notify(callback) => callback(task, overrun_ns),
+                OverrunPolicy::Throttle => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fair_server_lets_cfs_progress_under_fifo_hog() {
+        let params = DlParams {
+            runtime_ns: 50_000_000,
+            deadline_ns: 1_000_000_000,
+            period_ns: 1_000_000_000,
+        };
+        let mut server = FairServer::new(&params, 0);
+
+        // A CPU-bound FIFO task and a fair task share the CPU for 2s,
+        // scheduled in 1ms ticks: the FIFO task runs unless the server is
+        // active
+        let (mut fair_ms, mut fifo_ms) = (0, 0);
+        let mut fair_ran = false;
+        for tick in 1..=2_000 {
+            fair_ran = server.update(&params, tick * 1_000_000, fair_ran, true);
+            if fair_ran {
+                fair_ms += 1;
+            } else {
+                fifo_ms += 1;
+            }
+        }
+        assert_eq!(fair_ms, 100);
+        assert_eq!(fifo_ms, 1_900);
+
+        // Fair time earned on its own counts toward the reservation
+        let mut server = FairServer::new(&params, 0);
+        for tick in 1..=60 {
+            server.update(&params, tick * 1_000_000, true, true);
+        }
+        assert!(!server.update(&params, 999_000_000, false, true));
+        // Nothing to run, nothing to throttle RT for
+        let mut server = FairServer::new(&params, 0);
+        assert!(!server.update(&params, 990_000_000, false, false));
+    }
+
+    #[test]
+    fn test_history_keeps_last_events_of_deadline_tasks_while_enabled() {
+        let deadline = DeadlineScheduler::with_config(95);
+        let (task, other) = (TaskId::new(1), TaskId::new(2));
+        deadline.set_params(task, DlParams {
+            runtime_ns: 1_000_000,
+            deadline_ns: 10_000_000,
+            period_ns: 10_000_000,
+        }).unwrap();
+        let picked = SchedEvent::SwitchIn { next: task };
+
+        // Off by default
+        deadline.record_event(task, picked);
+        assert!(deadline.history(task).is_empty());
+
+        deadline.set_history_enabled(true);
+        deadline.record_event(other, SchedEvent::SwitchIn { next: other });
+        assert!(deadline.history(other).is_empty());
+
+        deadline.record_event(task, SchedEvent::Preempt { prev: task, next: other });
+        for _ in 0..DL_HISTORY_LEN {
+            deadline.record_event(task, picked);
+        }
+        let history = deadline.history(task);
+        assert_eq!(history.len(), DL_HISTORY_LEN);
+        assert!(history.iter().all(|entry| matches!(entry.event, SchedEvent::SwitchIn { .. })));
+
+        deadline.set_history_enabled(false);
+        assert!(deadline.history(task).is_empty());
+    }
+
+    #[test]
+    fn test_deadline_tasks_and_servers_move_off_an_offlining_cpu() {
+        let deadline = DeadlineScheduler::with_config(95);
+        let (cpu, target) = (CpuId::new(1), CpuId::new(2));
+        let (queued, throttled, running, served) = (TaskId::new(1), TaskId::new(2), TaskId::new(3), TaskId::new(4));
+        let server = deadline.create_cbs_server(Duration::from_nanos(1_000_000), Duration::from_nanos(10_000_000))
+            .unwrap();
+        let params = deadline.servers.read()[&server].params;
+        {
+            let mut rq = deadline.rqs.get(cpu).lock();
+            let se = DlEntity { abs_deadline: 5_000_000, remaining_ns: 300_000, ..Default::default() };
+            rq.entities.insert(queued, se);
+            rq.tree.insert((se.abs_deadline, DlNode::Task(queued)));
+            rq.entities.insert(throttled, DlEntity::default());
+            rq.throttled.insert(throttled, 8_000_000);
+            let server_se = DlEntity { abs_deadline: 7_000_000, remaining_ns: 400_000, ..Default::default() };
+            rq.servers.insert(server, ServerRq { params, se: server_se, queue: VecDeque::new(), rotate: false });
+            rq.curr = Some(running);
+            rq.curr_server = Some(server);
+        }
+        deadline.served.write().insert(running, server);
+        deadline.served.write().insert(served, server);
+        if let Some(s) = deadline.servers.write().get_mut(&server) {
+            s.cpu = Some(cpu);
+            s.nr_tasks = 2;
+        }
+        let mut on_cpu = deadline.queued_tasks(cpu);
+        on_cpu.sort();
+        assert_eq!(on_cpu, [queued, throttled]);
+        assert_eq!(deadline.servers_on(cpu), [server]);
+        assert_eq!(deadline.server_tasks(server), [running, served]);
+
+        deadline.migrate_server(server, target).unwrap();
+        for task in [queued, throttled, running] {
+            deadline.migrate_task(task, cpu, target);
+        }
+
+        // Nothing is left behind, and deadlines, budgets and throttling move along
+        assert!(deadline.queued_tasks(cpu).is_empty());
+        assert!(deadline.servers_on(cpu).is_empty());
+        assert!(!deadline.curr_throttled(cpu));
+        assert_eq!(deadline.server_cpu(server), Some(target));
+        assert_eq!(deadline.deadline_tree(target), [(queued, 5_000_000), (running, 7_000_000)]);
+        assert_eq!(deadline.server_budget(server), Some(Duration::from_nanos(400_000)));
+        assert_eq!(deadline.next_replenish(target), Some(8_000_000));
+    }
+}
@@ -0,0 +1,154 @@
+//! # Scheduler Debugging
+//!
+//! This module collects snapshots of the scheduler state for debugging and
+//! post-mortem analysis, in the spirit of `/proc/sched_debug`. Snapshots are
+//! returned as plain data so they can be logged, stored, or compared.
+//!
+//! ## Features
+//! - Per-CPU runqueue dump: current task, CFS timeline with vruntimes,
+//!   RT run lists per priority and the deadline queue
+//! - Structured `RunqueueDump` values
+//! - Logging of collected dumps
+//!
+//! ## Usage
+//! ```rust
+//! use crate::kernel::scheduler::debug::DebugScheduler;
+//!
+//! let dump = debug.dump_runqueues(&scheduler);
+//! for cpu in &dump.cpus {
+//!     kernel_info!("CPU {}: {} CFS tasks", cpu.cpu.as_u32(), cpu.cfs.len());
+//! }
+//! ```
+
+use crate::kernel::task::TaskId;
+use crate::kernel::cpu::{CpuId, CpuMask};
+use crate::kernel::time::Timestamp;
+use crate::kernel::error::KernelResult;
+use crate::kernel::log::kernel_info;
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Runqueue contents of one CPU
+#[derive(Debug, Clone)]
+pub struct CpuRunqueueDump {
+    /// CPU the runqueue belongs to
+    pub cpu: CpuId,
+    /// Task running on the CPU
+    pub current: Option<TaskId>,
+    /// Runnable tasks of all classes
+    pub nr_running: u32,
+    /// CFS `min_vruntime`
+    pub min_vruntime: u64,
+    /// Queued CFS tasks with their vruntime, in timeline order
+    pub cfs: Vec<(TaskId, u64)>,
+    /// Queued RT tasks per priority, highest priority first
+    pub rt: Vec<(u8, Vec<TaskId>)>,
+    /// Queued deadline tasks with their absolute deadline, earliest first
+    pub dl: Vec<(TaskId, u64)>,
+    /// PELT load of the runnable tasks
+    pub runnable_load: u64,
+    /// PELT load left by tasks that slept on the CPU
+    pub blocked_load: u64,
+}
+
+/// Snapshot of all runqueues
+#[derive(Debug, Clone, Default)]
+pub struct RunqueueDump {
+    /// Time the snapshot was taken
+    pub timestamp: u64,
+    /// One entry per online CPU
+    pub cpus: Vec<CpuRunqueueDump>,
+}
+
+/// Read access to the runqueues of every scheduling class
+///
+/// Implemented by the core scheduler, which owns the class schedulers.
+pub trait RunqueueSource {
+    /// Task running on a CPU
+    fn current_task(&self, cpu: CpuId) -> Option<TaskId>;
+    /// Runnable tasks of all classes on a CPU
+    fn nr_running(&self, cpu: CpuId) -> u32;
+    /// CFS `min_vruntime` of a CPU
+    fn min_vruntime(&self, cpu: CpuId) -> u64;
+    /// Queued CFS tasks with their vruntime
+    fn cfs_tasks(&self, cpu: CpuId) -> Vec<(TaskId, u64)>;
+    /// Queued RT tasks per priority
+    fn rt_tasks(&self, cpu: CpuId) -> Vec<(u8, Vec<TaskId>)>;
+    /// Queued deadline tasks with their absolute deadline
+    fn dl_tasks(&self, cpu: CpuId) -> Vec<(TaskId, u64)>;
+    /// PELT runnable and blocked load of a CPU
+    fn pelt_load(&self, _cpu: CpuId) -> (u64, u64) {
+        (0, 0)
+    }
+}
+
+/// Debug scheduler component
+pub struct DebugScheduler {
+    dumps_taken: AtomicU64,
+}
+
+impl DebugScheduler {
+    /// Create a debug scheduler
+    pub fn new() -> Self {
+        Self {
+            dumps_taken: AtomicU64::new(0),
+        }
+    }
+
+    /// Collect the runqueues of every online CPU
+    pub fn dump_runqueues(&self, src: &dyn RunqueueSource) -> RunqueueDump {
+        self.dumps_taken.fetch_add(1, Ordering::Relaxed);
+        let cpus = CpuMask::online().iter()
+            .map(|cpu| {
+                let (runnable_load, blocked_load) = src.pelt_load(cpu);
+                CpuRunqueueDump {
+                    cpu,
+                    current: src.current_task(cpu),
+                    nr_running: src.nr_running(cpu),
+                    min_vruntime: src.min_vruntime(cpu),
+                    cfs: src.cfs_tasks(cpu),
+                    rt: src.rt_tasks(cpu),
+                    dl: src.dl_tasks(cpu),
+                    runnable_load,
+                    blocked_load,
+                }
+            })
+            .collect();
+        RunqueueDump {
+            timestamp: Timestamp::now().as_nanos(),
+            cpus,
+        }
+    }
+
+    /// Log a collected runqueue dump
+    pub fn print_runqueue_dump(&self, dump: &RunqueueDump) {
+        kernel_info!("=== Runqueues at {} ns ===", dump.timestamp);
+        for rq in &dump.cpus {
+            kernel_info!("CPU {}: current={:?} nr_running={} min_vruntime={} load runnable={} blocked={}",
+                        rq.cpu.as_u32(), rq.current.map(|t| t.as_u64()), rq.nr_running, rq.min_vruntime,
+                        rq.runnable_load, rq.blocked_load);
+            for (task, vruntime) in &rq.cfs {
+                kernel_info!("  CFS task {} vruntime={}", task.as_u64(), vruntime);
+            }
+            for (prio, tasks) in &rq.rt {
+                kernel_info!("  RT prio {}: {:?}", prio, tasks.iter().map(|t| t.as_u64()).collect::<Vec<_>>());
+            }
+            for (task, deadline) in &rq.dl {
+                kernel_info!("  DL task {} deadline={}", task.as_u64(), deadline);
+            }
+        }
+    }
+
+    /// Log debug scheduler state
+    pub fn print_scheduler_info(&self) -> KernelResult<()> {
+        kernel_info!("Runqueue dumps taken: {}", self.dumps_taken.load(Ordering::Relaxed));
+        Ok(())
+    }
+}
+
+impl Default for DebugScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
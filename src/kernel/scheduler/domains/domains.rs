@@ -0,0 +1,409 @@
+//! # Scheduling Domains
+//!
+//! This module builds the scheduling domain hierarchy used by load
+//! balancing. Each CPU gets a stack of nested domains, from the smallest
+//! set of CPUs sharing hardware (SMT siblings) up to the whole machine
+//! (NUMA). Balancing walks the stack bottom-up so that tasks move between
+//! cheap neighbours often and across expensive boundaries rarely.
+//!
+//! Each domain has its own balance interval, growing with its span and
+//! stretched further across NUMA nodes up to the configured maximum
+//! balance interval, and a designated balancer: only
+//! the first CPU of each group balances the domain, so the CPUs of a
+//! group do not all pull from the same busiest CPU at once.
+//!
+//! ## Domain Levels
+//! - **SMT**: hardware threads of one core, sharing all core resources
+//! - **MC**: cores of one package, sharing the last-level cache
+//! - **NUMA**: packages on different memory nodes
+//!
+//! ## Usage
+//! ```rust
+//! use crate::kernel::scheduler::domains::DomainsScheduler;
+//!
+//! let domains = DomainsScheduler::new();
+//! domains.build_from_topology(&topology)?;
+//!
+//! for domain in domains.domains_of(CpuId::new(0)) {
+//!     println!("{:?}: {} CPUs", domain.level, domain.span.weight());
+//! }
+//!
+//! // On the tick: balance only the domains whose interval elapsed
+//! for domain in domains.due_for_balance(cpu, Timestamp::now().as_nanos()) {
+//!     migration.balance_domain(cpu, &domain, &config, src)?;
+//! }
+//! ```
+
+use crate::kernel::scheduler::topology::TopologyScheduler;
+use crate::kernel::cpu::{CpuId, CpuMask};
+use crate::kernel::error::{KernelResult, SchedulerError};
+use crate::kernel::sync::RwLock;
+use crate::kernel::log::{kernel_info, kernel_debug};
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Balance when a CPU is about to go idle
+pub const SD_BALANCE_NEWIDLE: u32 = 1 << 0;
+/// Balance on exec
+pub const SD_BALANCE_EXEC: u32 = 1 << 1;
+/// Balance on fork
+pub const SD_BALANCE_FORK: u32 = 1 << 2;
+/// Balance on wakeup
+pub const SD_BALANCE_WAKE: u32 = 1 << 3;
+/// Wake tasks next to their waker when possible
+pub const SD_WAKE_AFFINE: u32 = 1 << 4;
+/// CPUs share core compute resources (SMT)
+pub const SD_SHARE_CPUCAPACITY: u32 = 1 << 5;
+/// CPUs share package resources such as the last-level cache
+pub const SD_SHARE_PKG_RESOURCES: u32 = 1 << 6;
+/// Only one CPU may balance this domain at a time
+pub const SD_SERIALIZE: u32 = 1 << 7;
+/// CPUs in the domain have different compute capacities
+pub const SD_ASYM_CPUCAPACITY: u32 = 1 << 8;
+/// Domain crosses NUMA nodes
+pub const SD_NUMA: u32 = 1 << 9;
+
+/// How much longer than its span in milliseconds a NUMA domain waits
+/// between two balance attempts
+const NUMA_BALANCE_FACTOR: u64 = 4;
+
+/// Default longest interval between two balance attempts of a domain
+/// (milliseconds)
+pub const DEFAULT_MAX_BALANCE_INTERVAL_MS: u64 = 100;
+
+/// Level of a scheduling domain in the hierarchy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DomainLevel {
+    /// Hardware threads of one core
+    Smt,
+    /// Cores of one package
+    Mc,
+    /// Whole machine across NUMA nodes
+    Numa,
+}
+
+impl DomainLevel {
+    /// Returns the level name as a string
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DomainLevel::Smt => "SMT",
+            DomainLevel::Mc => "MC",
+            DomainLevel::Numa => "NUMA",
+        }
+    }
+}
+
+/// One level of the scheduling domain hierarchy of a CPU
+#[derive(Debug, Clone)]
+pub struct SchedDomain {
+    /// Level of this domain
+    pub level: DomainLevel,
+    /// CPUs covered by this domain
+    pub span: CpuMask,
+    /// Groups balanced against each other (the spans of the child domain)
+    pub groups: Vec<CpuMask>,
+    /// Interval between two balance attempts (milliseconds)
+    pub balance_interval_ms: u64,
+    /// CPU that balances this domain for the group containing the owner
+    pub balancer: CpuId,
+    /// When this domain was last balanced (ns, 0 if never)
+    pub last_balance: u64,
+    /// `SD_*` flags
+    pub flags: u32,
+}
+
+impl SchedDomain {
+    /// Check whether the domain has a flag
+    pub fn has_flag(&self, flag: u32) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// Check whether the balance interval elapsed at `now` (ns)
+    pub fn balance_due(&self, now: u64) -> bool {
+        self.last_balance == 0
+            || now.saturating_sub(self.last_balance) >= self.balance_interval_ms * 1_000_000
+    }
+}
+
+/// Scheduling domains scheduler component
+pub struct DomainsScheduler {
+    /// Domain stack of each CPU, ordered bottom-up
+    hierarchy: RwLock<BTreeMap<CpuId, Vec<SchedDomain>>>,
+    /// Longest interval between two balance attempts of a domain (ms)
+    max_balance_interval_ms: AtomicU64,
+}
+
+impl DomainsScheduler {
+    /// Create an empty domain hierarchy
+    pub fn new() -> Self {
+        Self {
+            hierarchy: RwLock::new(BTreeMap::new()),
+            max_balance_interval_ms: AtomicU64::new(DEFAULT_MAX_BALANCE_INTERVAL_MS),
+        }
+    }
+
+    /// Set the longest interval between two balance attempts of a domain
+    /// (milliseconds, at least 1)
+    ///
+    /// Recomputes the interval of every domain already built.
+    pub fn set_max_balance_interval(&self, ms: u64) -> KernelResult<()> {
+        if ms == 0 {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        self.max_balance_interval_ms.store(ms, Ordering::Relaxed);
+        for levels in self.hierarchy.write().values_mut() {
+            for domain in levels.iter_mut() {
+                domain.balance_interval_ms = Self::balance_interval_ms(domain.level, &domain.span, ms);
+            }
+        }
+        Ok(())
+    }
+
+    /// Build the SMT -> MC -> NUMA hierarchy from the detected topology
+    ///
+    /// Levels whose span is identical to the level below are skipped, so a
+    /// machine without SMT starts at MC and a single-node machine has no
+    /// NUMA level. Rebuilding replaces the previous hierarchy.
+    pub fn build_from_topology(&self, topo: &TopologyScheduler) -> KernelResult<()> {
+        let cpus = topo.known_cpus();
+        if cpus.is_empty() {
+            return Err(SchedulerError::InvalidTopology.into());
+        }
+        let multi_node = topo.nr_nodes() > 1;
+        let max_interval = self.max_balance_interval_ms.load(Ordering::Relaxed);
+
+        let mut hierarchy = BTreeMap::new();
+        for cpu in cpus.iter() {
+            let mut levels = Vec::new();
+            let candidates = [
+                (DomainLevel::Smt, topo.smt_siblings(cpu)),
+                (DomainLevel::Mc, topo.package_mask(cpu)),
+                (DomainLevel::Numa, if multi_node { cpus.clone() } else { topo.package_mask(cpu) }),
+            ];
+
+            let mut child_span: Option<CpuMask> = None;
+            for (level, span) in candidates {
+                // Skip degenerate levels
+                if span.weight() <= 1 || child_span.as_ref() == Some(&span) {
+                    continue;
+                }
+                let groups = Self::build_groups(topo, level, &span, child_span.as_ref());
+                let mut flags = Self::level_flags(level);
+                if topo.is_asymmetric(&span) {
+                    flags |= SD_ASYM_CPUCAPACITY;
+                }
+                let balancer = groups.iter()
+                    .find(|group| group.contains(cpu))
+                    .and_then(|group| group.iter().next())
+                    .unwrap_or(cpu);
+                levels.push(SchedDomain {
+                    level,
+                    flags,
+                    balance_interval_ms: Self::balance_interval_ms(level, &span, max_interval),
+                    balancer,
+                    last_balance: 0,
+                    groups,
+                    span: span.clone(),
+                });
+                child_span = Some(span);
+            }
+            hierarchy.insert(cpu, levels);
+        }
+
+        *self.hierarchy.write() = hierarchy;
+        kernel_info!("Scheduling domains built for {} CPUs", cpus.weight());
+        Ok(())
+    }
+
+    /// Domains of a CPU, ordered from the smallest to the largest span
+    pub fn domains_of(&self, cpu: CpuId) -> Vec<SchedDomain> {
+        self.hierarchy.read().get(&cpu).cloned().unwrap_or_default()
+    }
+
+    /// Domains a CPU should balance at `now` (ns), ordered bottom-up
+    ///
+    /// Returns the domains whose interval elapsed and for which the CPU
+    /// is the designated balancer, and marks them balanced at `now`.
+    pub fn due_for_balance(&self, cpu: CpuId, now: u64) -> Vec<SchedDomain> {
+        let mut hierarchy = self.hierarchy.write();
+        let levels = match hierarchy.get_mut(&cpu) {
+            Some(levels) => levels,
+            None => return Vec::new(),
+        };
+        levels.iter_mut()
+            .filter(|domain| domain.balancer == cpu && domain.balance_due(now))
+            .map(|domain| {
+                domain.last_balance = now;
+                domain.clone()
+            })
+            .collect()
+    }
+
+    /// Snapshot of the whole hierarchy for debugging
+    pub fn hierarchy(&self) -> BTreeMap<CpuId, Vec<SchedDomain>> {
+        self.hierarchy.read().clone()
+    }
+
+    /// Log the domain hierarchy of every CPU
+    pub fn print_domains(&self) {
+        kernel_info!("=== Scheduling Domains ===");
+        for (cpu, levels) in self.hierarchy.read().iter() {
+            for (depth, domain) in levels.iter().enumerate() {
+                kernel_info!("CPU {} domain {}: {} span={:?} groups={} interval={}ms balancer={} flags={:#x}",
+                            cpu.as_u32(), depth, domain.level.as_str(), domain.span,
+                            domain.groups.len(), domain.balance_interval_ms,
+                            domain.balancer.as_u32(), domain.flags);
+            }
+        }
+    }
+
+    /// Default flags of a domain level
+    fn level_flags(level: DomainLevel) -> u32 {
+        let common = SD_BALANCE_NEWIDLE | SD_BALANCE_EXEC | SD_BALANCE_FORK;
+        match level {
+            DomainLevel::Smt => common | SD_WAKE_AFFINE | SD_SHARE_CPUCAPACITY | SD_SHARE_PKG_RESOURCES,
+            DomainLevel::Mc => common | SD_WAKE_AFFINE | SD_SHARE_PKG_RESOURCES,
+            DomainLevel::Numa => common | SD_NUMA | SD_SERIALIZE,
+        }
+    }
+
+    /// Interval between two balance attempts of a domain (milliseconds)
+    ///
+    /// One millisecond per CPU spanned, so larger domains balance less
+    /// often; NUMA domains wait `NUMA_BALANCE_FACTOR` times longer. No
+    /// domain waits longer than `max_interval`.
+    fn balance_interval_ms(level: DomainLevel, span: &CpuMask, max_interval: u64) -> u64 {
+        let interval = span.weight() as u64;
+        let interval = match level {
+            DomainLevel::Numa => interval * NUMA_BALANCE_FACTOR,
+            DomainLevel::Smt | DomainLevel::Mc => interval,
+        };
+        interval.min(max_interval)
+    }
+
+    /// Split a domain span into balancing groups
+    ///
+    /// The groups of a domain are the spans of the next level down; the
+    /// lowest level balances individual CPUs.
+    fn build_groups(topo: &TopologyScheduler, level: DomainLevel, span: &CpuMask,
+                    child_span: Option<&CpuMask>) -> Vec<CpuMask> {
+        let mut groups: Vec<CpuMask> = Vec::new();
+        for cpu in span.iter() {
+            if groups.iter().any(|g| g.contains(cpu)) {
+                continue;
+            }
+            let group = match (level, child_span) {
+                (_, None) => {
+                    let mut single = CpuMask::empty();
+                    single.set(cpu);
+                    single
+                }
+                (DomainLevel::Smt, Some(_)) | (DomainLevel::Mc, Some(_)) => topo.smt_siblings(cpu),
+                (DomainLevel::Numa, Some(_)) => topo.package_mask(cpu),
+            };
+            kernel_debug!("Domain {} group {:?}", level.as_str(), group);
+            groups.push(group);
+        }
+        groups
+    }
+}
+
+impl Default for DomainsScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kernel::scheduler::topology::{CpuTopology, NodeId};
+
+    /// Two packages on two nodes of four single-threaded cores each
+    fn two_node_domains() -> DomainsScheduler {
+        let topology = TopologyScheduler::new();
+        for cpu in 0..8 {
+            topology.set_cpu_topology(CpuId::new(cpu), CpuTopology {
+                core_id: cpu,
+                package_id: cpu / 4,
+                node: NodeId(cpu / 4),
+            });
+        }
+        let domains = DomainsScheduler::new();
+        domains.build_from_topology(&topology).unwrap();
+        domains
+    }
+
+    /// Number of balance attempts per level over `ms` one-millisecond ticks
+    fn balances(domains: &DomainsScheduler, cpu: u32, ms: u64) -> BTreeMap<DomainLevel, u64> {
+        let mut counts = BTreeMap::new();
+        for tick in 1..=ms {
+            for domain in domains.due_for_balance(CpuId::new(cpu), tick * 1_000_000) {
+                *counts.entry(domain.level).or_insert(0) += 1;
+            }
+        }
+        counts
+    }
+
+    #[test]
+    fn test_numa_level_balances_less_often_than_mc() {
+        let domains = two_node_domains();
+        let levels: Vec<DomainLevel> = domains.domains_of(CpuId::new(0)).iter().map(|d| d.level).collect();
+        assert_eq!(levels, [DomainLevel::Mc, DomainLevel::Numa]);
+
+        let counts = balances(&domains, 0, 320);
+        // 4ms MC interval, 8 CPUs * 4 = 32ms NUMA interval
+        assert_eq!(counts[&DomainLevel::Mc], 80);
+        assert_eq!(counts[&DomainLevel::Numa], 10);
+    }
+
+    #[test]
+    fn test_one_balancer_per_group() {
+        let domains = two_node_domains();
+        // Every CPU balances MC for itself, but only the first CPU of each
+        // package balances NUMA for its package
+        let counts = balances(&domains, 1, 64);
+        assert_eq!(counts.get(&DomainLevel::Mc), Some(&16));
+        assert_eq!(counts.get(&DomainLevel::Numa), None);
+        assert_eq!(balances(&domains, 4, 64).get(&DomainLevel::Numa), Some(&2));
+    }
+
+    #[test]
+    fn test_flat_topology_builds_one_domain() {
+        let topology = TopologyScheduler::new();
+        let domains = DomainsScheduler::new();
+        assert!(domains.build_from_topology(&topology).is_err());
+
+        let mut cpus = CpuMask::empty();
+        for cpu in 0..4 {
+            cpus.set(CpuId::new(cpu));
+        }
+        topology.add_flat_cpus(&cpus);
+        domains.build_from_topology(&topology).unwrap();
+        for cpu in cpus.iter() {
+            let levels = domains.domains_of(cpu);
+            assert_eq!(levels.len(), 1);
+            assert_eq!(levels[0].level, DomainLevel::Mc);
+            assert_eq!(levels[0].span, cpus);
+        }
+    }
+
+    #[test]
+    fn test_max_balance_interval_caps_every_domain() {
+        let domains = two_node_domains();
+        assert!(domains.set_max_balance_interval(0).is_err());
+
+        domains.set_max_balance_interval(8).unwrap();
+        let intervals: Vec<u64> = domains.domains_of(CpuId::new(0)).iter()
+            .map(|d| d.balance_interval_ms)
+            .collect();
+        // MC stays at 4ms, NUMA drops from 32ms to the cap
+        assert_eq!(intervals, [4, 8]);
+
+        let counts = balances(&domains, 0, 320);
+        assert_eq!(counts[&DomainLevel::Mc], 80);
+        assert_eq!(counts[&DomainLevel::Numa], 40);
+    }
+}
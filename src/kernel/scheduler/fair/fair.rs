@@ -0,0 +1,2858 @@
+//! # Completely Fair Scheduler (CFS)
+//!
+//! This module implements the fair scheduling class used by `Normal`,
+//! `Interactive`, `Batch`, `Background` and `Idle` tasks. Every task accumulates
+//! virtual runtime (vruntime) inversely proportional to its weight, and
+//! the task with the smallest vruntime runs next, so CPU time is shared in
+//! proportion to the weights derived from nice values.
+//!
+//! In EEVDF mode every task additionally requests a slice of CPU time and
+//! gets a virtual deadline one request ahead of its vruntime; the task run
+//! next is the eligible one (not ahead of the average vruntime) with the
+//! earliest virtual deadline, so short requests are served sooner without
+//! getting more than their share.
+//!
+//! ## Features
+//! - Per-CPU runqueues ordered by vruntime
+//! - Nice-to-weight mapping compatible with Linux
+//! - Monotonic per-runqueue `min_vruntime` for placing waking tasks;
+//!   vruntimes compare by wrapping difference, so they may wrap around
+//!   64 bits, and migrating tasks are re-based on the destination's
+//!   `min_vruntime`
+//! - Hierarchical group scheduling: nested task groups with a
+//!   `cpu.weight` each; CPU time is shared between sibling groups by
+//!   weight at every level before it is shared within a group
+//! - Slice-based tick preemption, with slices dividing a configurable
+//!   target latency among the runnable tasks, and granularity-limited wakeup
+//!   preemption (`WAKEUP_PREEMPTION`), start debit for new tasks
+//!   (`START_DEBIT`) and bounded sleeper credit for waking tasks
+//!   (`GENTLE_FAIR_SLEEPERS`)
+//! - Per-task latency nice hints for wakeup preemption
+//! - `SCHED_IDLE` tasks with a near-zero weight, picked only while no
+//!   other fair task is queued and preempted by any other fair task
+//! - Cache-warm buddy picks for just-woken (`NEXT_BUDDY`) and
+//!   just-preempted (`LAST_BUDDY`) tasks
+//! - Yielding behind the queued peers, or to a chosen task that receives
+//!   the rest of the slice
+//! - CPU bandwidth control: groups limited to a quota per period are
+//!   throttled once it is used up and unthrottled by the period timer
+//! - Automatic NUMA balancing: tasks drift toward the node their memory
+//!   faults land on
+//! - EEVDF mode (`FairMode::Eevdf`) with request sizes derived from
+//!   latency nice, next to the classic vruntime mode
+//!
+//! ## Usage
+//! ```rust
+//! use crate::kernel::scheduler::fair::{FairMode, FairScheduler, TaskGroup};
+//!
+//! let fair = FairScheduler::with_mode(10_000, FairMode::Eevdf);
+//! fair.enqueue_task(&task, TaskGroup::ROOT)?;
+//!
+//! if let Some(next) = fair.pick_next_task(cpu)? {
+//!     // switch to `next`
+//! }
+//! ```
+
+use crate::kernel::scheduler::topology::NodeId;
+use crate::kernel::scheduler::features::{sched_feat, SchedFeature};
+use crate::kernel::task::{Task, TaskId};
+use crate::kernel::cpu::{CpuId, CpuMask};
+use crate::kernel::time::{Timestamp, Duration};
+use crate::kernel::error::{KernelResult, SchedulerError};
+use crate::kernel::sync::{SpinLock, RwLock};
+use crate::kernel::log::{kernel_info, kernel_debug};
+use crate::kernel::memory::percpu::PerCpu;
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Weight of a nice-0 task
+pub const NICE_0_LOAD: u32 = 1024;
+
+/// Weight of a `SCHED_IDLE` task, far below nice 19
+pub const WEIGHT_IDLEPRIO: u32 = 3;
+
+/// Nice value to weight mapping; each nice level is worth ~10% CPU
+const SCHED_PRIO_TO_WEIGHT: [u32; 40] = [
+    /* -20 */ 88761, 71755, 56483, 46273, 36291,
+    /* -15 */ 29154, 23254, 18705, 14949, 11916,
+    /* -10 */  9548,  7620,  6100,  4904,  3906,
+    /*  -5 */  3121,  2501,  1991,  1586,  1277,
+    /*   0 */  1024,   820,   655,   526,   423,
+    /*   5 */   335,   272,   215,   172,   137,
+    /*  10 */   110,    87,    70,    56,    45,
+    /*  15 */    36,    29,    23,    18,    15,
+];
+
+/// Default vruntime lead a waking task needs before it preempts the running one
+pub const DEFAULT_WAKEUP_GRANULARITY_NS: u64 = 1_000_000; // 1ms
+
+/// Default EEVDF request size at latency nice 0
+pub const DEFAULT_BASE_SLICE_NS: u64 = 3_000_000; // 3ms
+
+/// Default minimum runtime of a task before it can be preempted
+pub const DEFAULT_MIN_GRANULARITY_NS: u64 = 750_000; // 0.75ms
+
+/// Default period over which every runnable task should run once; bounds
+/// the vruntime credit a waking task keeps from its sleep
+pub const SCHED_LATENCY_NS: u64 = 6_000_000; // 6ms
+
+/// Bounds of `min_granularity`
+const MIN_GRANULARITY_RANGE_NS: (u64, u64) = (100_000, 1_000_000_000); // 0.1ms..=1s
+
+/// Bounds of the target latency
+const TARGET_LATENCY_RANGE_NS: (u64, u64) = (100_000, 1_000_000_000); // 0.1ms..=1s
+
+/// Upper bound of `wakeup_granularity`
+const MAX_WAKEUP_GRANULARITY_NS: u64 = 1_000_000_000; // 1s
+
+/// vruntime lead granted per latency nice level at wakeup preemption
+const LATENCY_NICE_STEP_NS: i64 = 100_000; // 0.1ms
+
+/// Interval between two NUMA placement evaluations of a task
+const NUMA_SCAN_PERIOD_NS: u64 = 1_000_000_000; // 1s
+
+/// Consecutive windows a node must win before it becomes preferred
+const NUMA_PREFERRED_HYSTERESIS: u32 = 3;
+
+/// Minimum share of a window's faults (percent) for a node to win it
+const NUMA_MIN_FAULT_SHARE: u64 = 50;
+
+/// Convert a nice value (-20..=19) to a load weight
+pub fn nice_to_weight(nice: i8) -> u32 {
+    let index = (nice.clamp(-20, 19) + 20) as usize;
+    SCHED_PRIO_TO_WEIGHT[index]
+}
+
+/// Wakeup preemption advantage of a latency nice value
+#[inline]
+fn latency_offset(latency_nice: i8) -> i64 {
+    latency_nice as i64 * LATENCY_NICE_STEP_NS
+}
+
+/// Shortest request size a latency nice value maps to in EEVDF mode
+const MIN_REQUEST_SIZE_NS: u64 = 100_000; // 0.1ms
+
+/// EEVDF request size of a latency nice value
+///
+/// Latency nice 0 requests `base_slice`, -20 twice that and 19 a
+/// twentieth of it, so latency-sensitive tasks get earlier deadlines.
+#[inline]
+fn request_size(latency_nice: i8, base_slice: u64) -> u64 {
+    let scaled = base_slice * (20 - latency_nice.clamp(-20, 19) as i64) as u64 / 20;
+    scaled.max(MIN_REQUEST_SIZE_NS)
+}
+
+/// Virtual deadline of a request starting at an entity's vruntime
+#[inline]
+fn virtual_deadline(se: &SchedEntity, base_slice: u64) -> u64 {
+    se.vruntime.wrapping_add(calc_delta_fair(request_size(se.latency_nice, base_slice), se.weight))
+}
+
+/// Scheduling period of `nr_running` tasks
+///
+/// The target latency, stretched once it would give a task less than
+/// `min_gran`.
+#[inline]
+fn calc_sched_period(target_latency: u64, min_gran: u64, nr_running: usize) -> u64 {
+    target_latency.max(min_gran.saturating_mul(nr_running as u64))
+}
+
+/// Time slice of each of `nr_running` tasks: an equal share of the target
+/// latency, but never less than `min_gran`
+#[inline]
+fn calc_sched_slice(target_latency: u64, min_gran: u64, nr_running: usize) -> u64 {
+    (target_latency / nr_running.max(1) as u64).max(min_gran)
+}
+
+/// Check whether vruntime `a` is before `b`
+///
+/// vruntimes only grow and eventually wrap around 64 bits. Comparing the
+/// sign of their difference orders them correctly across the wrap as long
+/// as they are less than 2^63 apart, which holds for all vruntimes near a
+/// runqueue's `min_vruntime`.
+#[inline]
+pub fn vruntime_before(a: u64, b: u64) -> bool {
+    (a.wrapping_sub(b) as i64) < 0
+}
+
+/// Later of two vruntimes
+#[inline]
+fn max_vruntime(a: u64, b: u64) -> u64 {
+    if vruntime_before(a, b) { b } else { a }
+}
+
+/// Earlier of two vruntimes
+#[inline]
+fn min_vruntime(a: u64, b: u64) -> u64 {
+    if vruntime_before(a, b) { a } else { b }
+}
+
+/// A vruntime (or virtual deadline) ordered by `vruntime_before`, as the
+/// key of a group's timeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct VKey(u64);
+
+impl Ord for VKey {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        (self.0.wrapping_sub(other.0) as i64).cmp(&0)
+    }
+}
+
+impl PartialOrd for VKey {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Scale a runtime delta into vruntime for a given weight
+#[inline]
+fn calc_delta_fair(delta: u64, weight: u32) -> u64 {
+    if weight == NICE_0_LOAD {
+        return delta;
+    }
+    ((delta as u128 * NICE_0_LOAD as u128) / weight.max(1) as u128) as u64
+}
+
+/// How the fair class picks the next task
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FairMode {
+    /// Classic CFS: the task with the smallest vruntime runs next
+    #[default]
+    Cfs,
+    /// EEVDF: the eligible task with the earliest virtual deadline runs next
+    Eevdf,
+}
+
+/// Identifier of a task group
+pub type GroupId = u64;
+
+/// Group that every task belongs to when group scheduling is off
+pub const ROOT_TASK_GROUP: GroupId = 0;
+
+/// First id handed out by `FairScheduler::create_group`; lower ids belong
+/// to autogroups, which use their session id
+pub const FIRST_TASK_GROUP_ID: GroupId = 1 << 32;
+
+/// Smallest `cpu.weight` of a task group
+pub const CPU_WEIGHT_MIN: u32 = 1;
+
+/// Default `cpu.weight` of a task group, equivalent to nice 0
+pub const CPU_WEIGHT_DEFAULT: u32 = 100;
+
+/// Largest `cpu.weight` of a task group
+pub const CPU_WEIGHT_MAX: u32 = 10_000;
+
+/// Convert a `cpu.weight` (1..=10000) to a load weight
+pub fn cpu_weight_to_load(weight: u32) -> u32 {
+    let weight = weight.clamp(CPU_WEIGHT_MIN, CPU_WEIGHT_MAX) as u64;
+    (weight * NICE_0_LOAD as u64 / CPU_WEIGHT_DEFAULT as u64).max(1) as u32
+}
+
+/// A set of tasks that competes for CPU time as a single entity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskGroup {
+    /// Group identifier
+    pub id: GroupId,
+    /// Group this group competes in (the root group is its own parent)
+    pub parent: GroupId,
+    /// Load weight of the group as a whole
+    pub weight: u32,
+}
+
+impl TaskGroup {
+    /// The root group
+    pub const ROOT: TaskGroup = TaskGroup { id: ROOT_TASK_GROUP, parent: ROOT_TASK_GROUP, weight: NICE_0_LOAD };
+}
+
+impl Default for TaskGroup {
+    fn default() -> Self {
+        Self::ROOT
+    }
+}
+
+/// Fair scheduling state of one task
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SchedEntity {
+    /// Virtual runtime in nanoseconds, relative to the task's group
+    pub vruntime: u64,
+    /// Load weight derived from nice
+    pub weight: u32,
+    /// Start of the current execution slice
+    pub exec_start: u64,
+    /// Total CPU time consumed
+    pub sum_exec_runtime: u64,
+    /// `sum_exec_runtime` when the task last started running
+    pub prev_sum_exec_runtime: u64,
+    /// Whether the task is queued on (or running from) a runqueue
+    pub on_rq: bool,
+    /// Batch tasks never wakeup-preempt
+    pub batch: bool,
+    /// `SCHED_IDLE` task: runs only while no other fair task is queued
+    pub idle: bool,
+    /// Group the task is scheduled in, carried along on migration
+    pub group: TaskGroup,
+    /// Latency nice value; higher preempts more eagerly on wakeup
+    pub latency_nice: i8,
+    /// Virtual deadline of the current request, used in EEVDF mode
+    pub deadline: u64,
+}
+
+/// Runtime a CPU fetches from a group's quota at a time
+const BANDWIDTH_SLICE_NS: u64 = 5_000_000; // 5ms
+
+/// Shortest allowed bandwidth period
+const MIN_BANDWIDTH_PERIOD_US: u64 = 1_000; // 1ms
+
+/// Longest allowed bandwidth period
+const MAX_BANDWIDTH_PERIOD_US: u64 = 1_000_000; // 1s
+
+/// Smallest allowed bandwidth quota
+const MIN_BANDWIDTH_QUOTA_US: u64 = 1_000; // 1ms
+
+/// CPU bandwidth limit of a task group (`cpu.max`)
+///
+/// The group's tasks may run for `quota_us` in total, summed over all
+/// CPUs, in every `period_us`. A quota larger than the period lets the
+/// group use more than one CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CfsBandwidth {
+    /// Runtime per period in microseconds
+    pub quota_us: u64,
+    /// Period length in microseconds
+    pub period_us: u64,
+}
+
+impl CfsBandwidth {
+    /// Check the period (1ms..=1s) and quota (at least 1ms)
+    pub fn validate(&self) -> KernelResult<()> {
+        if !(MIN_BANDWIDTH_PERIOD_US..=MAX_BANDWIDTH_PERIOD_US).contains(&self.period_us)
+            || self.quota_us < MIN_BANDWIDTH_QUOTA_US
+        {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        Ok(())
+    }
+}
+
+/// Throttling statistics of a bandwidth-limited group (`cpu.stat`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CfsBandwidthStats {
+    /// Periods elapsed since the limit was set
+    pub nr_periods: u64,
+    /// Periods in which the group was throttled
+    pub nr_throttled: u64,
+    /// Total time the group's runqueues spent throttled
+    pub throttled_time_ns: u64,
+}
+
+/// Bandwidth state of a limited group
+#[derive(Debug)]
+struct GroupBandwidth {
+    config: CfsBandwidth,
+    /// Runtime left in the current period, not yet handed to a CPU
+    runtime_ns: u64,
+    /// End of the current period
+    period_end: u64,
+    /// Whether the group was throttled in the current period
+    throttled_this_period: bool,
+    stats: CfsBandwidthStats,
+}
+
+impl GroupBandwidth {
+    /// Hand out up to `wanted` runtime from the pool
+    fn take_runtime(&mut self, wanted: u64) -> u64 {
+        let granted = wanted.min(self.runtime_ns);
+        self.runtime_ns -= granted;
+        granted
+    }
+}
+
+/// A schedulable entity in a group's timeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum SchedNode {
+    Task(TaskId),
+    Group(GroupId),
+}
+
+/// Per-group part of a CPU's fair runqueue
+#[derive(Debug, Clone, Default)]
+struct GroupRq {
+    /// Group this group competes in
+    parent: GroupId,
+    /// Queued tasks and runnable child groups ordered by vruntime
+    timeline: BTreeSet<(VKey, SchedNode)>,
+    /// Virtual runtime of the group in its parent
+    vruntime: u64,
+    /// Load weight of the group
+    weight: u32,
+    /// Monotonic lower bound of the vruntimes in the group
+    min_vruntime: u64,
+    /// Runnable tasks of the group and its descendants, including a running one
+    h_nr_running: usize,
+    /// Queued tasks of the group and its descendants, except those below
+    /// a throttled descendant
+    h_nr_queued: usize,
+    /// Number of child groups
+    nr_children: usize,
+    /// Whether the group's CPU time is limited by a bandwidth quota
+    bandwidth: bool,
+    /// Runtime this CPU may still use from the group's quota
+    runtime_remaining: i64,
+    /// Whether the group has exhausted its quota on this CPU
+    throttled: bool,
+    /// Time the group was throttled
+    throttled_at: u64,
+}
+
+/// Per-CPU fair runqueue
+///
+/// Groups form a tree below the root group. Every group orders its queued
+/// tasks and runnable child groups by vruntime, and picking descends from
+/// the root through the leftmost entity of each level until it reaches a
+/// task, so CPU time is shared by weight between siblings at every level.
+/// With only the root group this is plain CFS.
+#[derive(Debug, Clone)]
+pub struct CfsRq {
+    /// Groups on this runqueue, including the root group
+    groups: BTreeMap<GroupId, GroupRq>,
+    /// State of every task that has been on this runqueue
+    entities: BTreeMap<TaskId, SchedEntity>,
+    /// Currently running fair task
+    curr: Option<TaskId>,
+    /// Sum of the weights of runnable tasks
+    load_weight: u64,
+    /// Task that was just woken, preferred as the next pick
+    next_buddy: Option<TaskId>,
+    /// Task that was just preempted, preferred as the next pick
+    last_buddy: Option<TaskId>,
+    /// How the next task is picked
+    mode: FairMode,
+    /// EEVDF request size at latency nice 0
+    base_slice: u64,
+    /// Virtual deadline the running task was picked with
+    curr_deadline: u64,
+    /// How far behind its group's `min_vruntime` a waking task may be placed
+    sleeper_credit: u64,
+    /// Runnable `SCHED_IDLE` tasks, including a running one
+    nr_idle_running: usize,
+}
+
+impl CfsRq {
+    /// Create an empty runqueue in CFS mode
+    pub fn new() -> Self {
+        Self::with_mode(FairMode::Cfs, DEFAULT_BASE_SLICE_NS)
+    }
+
+    /// Create an empty runqueue picking in `mode`, with EEVDF requests of
+    /// `base_slice` at latency nice 0
+    pub fn with_mode(mode: FairMode, base_slice: u64) -> Self {
+        let mut groups = BTreeMap::new();
+        groups.insert(ROOT_TASK_GROUP, GroupRq { weight: NICE_0_LOAD, ..GroupRq::default() });
+        Self {
+            groups,
+            entities: BTreeMap::new(),
+            curr: None,
+            load_weight: 0,
+            next_buddy: None,
+            last_buddy: None,
+            mode,
+            base_slice,
+            curr_deadline: 0,
+            sleeper_credit: 0,
+            nr_idle_running: 0,
+        }
+    }
+
+    /// How the next task is picked
+    pub fn mode(&self) -> FairMode {
+        self.mode
+    }
+
+    /// Change how the next task is picked
+    ///
+    /// Every task starts a new request at its current vruntime.
+    pub fn set_mode(&mut self, mode: FairMode, base_slice: u64) {
+        self.mode = mode;
+        self.base_slice = base_slice;
+        for se in self.entities.values_mut() {
+            se.deadline = virtual_deadline(se, base_slice);
+        }
+    }
+
+    /// Let waking tasks be placed up to `credit` behind their group's
+    /// `min_vruntime` (0, the default, gives them no credit)
+    pub fn set_sleeper_credit(&mut self, credit: u64) {
+        self.sleeper_credit = credit;
+    }
+
+    /// Number of runnable tasks, including the running one
+    ///
+    /// Tasks of throttled groups can't run and are not counted.
+    pub fn nr_running(&self) -> usize {
+        self.root().h_nr_queued + self.curr.is_some() as usize
+    }
+
+    /// Current `min_vruntime` of the root group
+    pub fn min_vruntime(&self) -> u64 {
+        self.root().min_vruntime
+    }
+
+    /// Sum of the weights of runnable tasks
+    pub fn load_weight(&self) -> u64 {
+        self.load_weight
+    }
+
+    /// Check whether every runnable task is a `SCHED_IDLE` task, so that
+    /// the CPU counts as idle for other tasks
+    pub fn only_idle_tasks(&self) -> bool {
+        self.nr_idle_running > 0 && self.nr_idle_running == self.nr_running()
+    }
+
+    /// State of a task on this runqueue
+    pub fn entity(&self, task: TaskId) -> Option<&SchedEntity> {
+        self.entities.get(&task)
+    }
+
+    /// Virtual runtime of a group in its parent on this runqueue
+    pub fn group_vruntime(&self, group: GroupId) -> Option<u64> {
+        self.groups.get(&group).map(|g| g.vruntime)
+    }
+
+    /// Weight of a group on this runqueue
+    pub fn group_weight(&self, group: GroupId) -> Option<u32> {
+        self.groups.get(&group).map(|g| g.weight)
+    }
+
+    /// Queued tasks in pick order
+    pub fn queued(&self) -> Vec<TaskId> {
+        self.timeline().into_iter().map(|(id, _)| id).collect()
+    }
+
+    /// Queued tasks with their vruntime, in pick order
+    pub fn timeline(&self) -> Vec<(TaskId, u64)> {
+        let mut tasks = Vec::new();
+        self.collect_timeline(ROOT_TASK_GROUP, &mut tasks);
+        tasks
+    }
+
+    /// Task to run next: descend through the leftmost entity of each level
+    pub fn leftmost(&self) -> Option<TaskId> {
+        let mut gid = ROOT_TASK_GROUP;
+        loop {
+            match self.groups.get(&gid)?.timeline.iter().next()?.1 {
+                SchedNode::Task(task) => return Some(task),
+                SchedNode::Group(child) => gid = child,
+            }
+        }
+    }
+
+    /// Currently running task
+    pub fn curr(&self) -> Option<TaskId> {
+        self.curr
+    }
+
+    /// Task to run next, preferring the buddies over the leftmost task
+    ///
+    /// The next buddy, then the last buddy, is picked if its vruntime
+    /// trails the leftmost task's by no more than `gran` (scaled by the
+    /// leftmost's weight). This keeps communicating tasks cache-warm
+    /// while bounding the unfairness to one granularity.
+    ///
+    /// In EEVDF mode buddies are ignored and `pick_eevdf` decides.
+    /// Either way a `SCHED_IDLE` task is only picked if no other task is
+    /// queued.
+    pub fn pick_next(&self, gran: u64) -> Option<TaskId> {
+        let pick = if self.mode == FairMode::Eevdf {
+            self.pick_eevdf()?
+        } else {
+            let left = self.leftmost()?;
+            [self.next_buddy, self.last_buddy].into_iter().flatten()
+                .find(|&buddy| self.buddy_eligible(buddy, left, gran))
+                .unwrap_or(left)
+        };
+        Some(self.skip_idle(pick))
+    }
+
+    /// Task to run next, biased toward tasks with low utilization
+    ///
+    /// Of the tasks trailing the regular pick by no more than `gran`, as a
+    /// buddy may, the one with the lowest `util` runs; ties keep the
+    /// regular pick. The unfairness stays bounded by one granularity.
+    pub fn pick_light(&self, gran: u64, util: impl Fn(TaskId) -> u32) -> Option<TaskId> {
+        let pick = self.pick_next(gran)?;
+        let pick_idle = self.is_idle(pick);
+        self.queued().into_iter()
+            .filter(|&task| self.buddy_eligible(task, pick, gran) && (pick_idle || !self.is_idle(task)))
+            .min_by_key(|&task| (util(task), task != pick))
+    }
+
+    /// Task to run next by EEVDF: descend through the eligible entity
+    /// with the earliest virtual deadline of each level
+    ///
+    /// An entity is eligible if its vruntime does not exceed the weighted
+    /// average vruntime of its level, running entity included, i.e. it has
+    /// not received more than its share. Should rounding leave no entity
+    /// eligible, the leftmost one is picked.
+    pub fn pick_eevdf(&self) -> Option<TaskId> {
+        let mut gid = ROOT_TASK_GROUP;
+        loop {
+            let g = self.groups.get(&gid)?;
+            let avg = self.avg_vruntime(gid);
+            let node = g.timeline.iter()
+                .take_while(|&&(VKey(vruntime), _)| Self::eligible(vruntime, avg))
+                .min_by_key(|&&(VKey(vruntime), node)| VKey(self.node_deadline(vruntime, node)))
+                .or_else(|| g.timeline.iter().next())?
+                .1;
+            match node {
+                SchedNode::Task(task) => return Some(task),
+                SchedNode::Group(child) => gid = child,
+            }
+        }
+    }
+
+    /// Prefer a woken task as the next pick
+    pub fn set_next_buddy(&mut self, task: TaskId) {
+        if self.entity(task).map_or(false, |se| se.on_rq) {
+            self.next_buddy = Some(task);
+        }
+    }
+
+    /// Prefer a preempted task as the next pick
+    pub fn set_last_buddy(&mut self, task: TaskId) {
+        if self.entity(task).map_or(false, |se| se.on_rq) {
+            self.last_buddy = Some(task);
+        }
+    }
+
+    /// Current next and last buddies
+    pub fn buddies(&self) -> (Option<TaskId>, Option<TaskId>) {
+        (self.next_buddy, self.last_buddy)
+    }
+
+    /// Add a group below its parent on this runqueue
+    ///
+    /// Does nothing if the group exists. A group whose parent is unknown
+    /// on this runqueue is added below the root group.
+    pub fn add_group(&mut self, group: TaskGroup) {
+        self.group_entry(group);
+    }
+
+    /// Remove a group without runnable tasks or child groups
+    ///
+    /// Returns whether the group was removed.
+    pub fn remove_group(&mut self, gid: GroupId) -> bool {
+        let parent = match self.groups.get(&gid) {
+            Some(g) if gid != ROOT_TASK_GROUP && g.h_nr_running == 0 && g.nr_children == 0 => g.parent,
+            _ => return false,
+        };
+        self.groups.remove(&gid);
+        if let Some(p) = self.groups.get_mut(&parent) {
+            p.nr_children -= 1;
+        }
+        true
+    }
+
+    /// Make a task runnable on this runqueue
+    ///
+    /// A waking task is placed no earlier than the sleeper credit before
+    /// its group's `min_vruntime`: it gets a bounded boost for having
+    /// slept, but cannot claim all the CPU time it did not use while
+    /// sleeping. A group becoming runnable starts no earlier than its
+    /// parent's `min_vruntime`. A task new to this runqueue starts
+    /// `start_debit` behind its group's `min_vruntime`.
+    ///
+    /// Every enqueue starts a new EEVDF request.
+    pub fn enqueue(&mut self, task: TaskId, weight: u32, batch: bool, start_debit: u64, group: TaskGroup) {
+        let base_slice = self.base_slice;
+        let group_min = self.group_entry(group).min_vruntime;
+        let se = self.entities.entry(task).or_insert_with(|| SchedEntity {
+            vruntime: group_min.wrapping_add(start_debit),
+            group,
+            ..SchedEntity::default()
+        });
+        if se.on_rq {
+            return;
+        }
+        if se.group.id != group.id {
+            se.vruntime = group_min;
+        }
+        se.vruntime = max_vruntime(se.vruntime, group_min.wrapping_sub(self.sleeper_credit));
+        se.weight = weight;
+        se.batch = batch;
+        se.group = group;
+        se.on_rq = true;
+        se.deadline = virtual_deadline(se, base_slice);
+        let vruntime = se.vruntime;
+        self.nr_idle_running += se.idle as usize;
+        self.load_weight += weight as u64;
+
+        self.unlink_path(group.id);
+        for gid in self.path(group.id) {
+            let parent_min = self.parent_min_vruntime(gid);
+            if let Some(g) = self.groups.get_mut(&gid) {
+                if g.h_nr_running == 0 && gid != ROOT_TASK_GROUP {
+                    g.vruntime = max_vruntime(g.vruntime, parent_min);
+                }
+                g.h_nr_running += 1;
+            }
+        }
+        self.queue_task(group.id, vruntime, task);
+        self.link_path(group.id);
+        self.update_min_vruntime(group.id);
+    }
+
+    /// Remove a task from this runqueue (sleep or migration)
+    pub fn dequeue(&mut self, task: TaskId) {
+        let (gid, vruntime) = match self.entities.get_mut(&task) {
+            Some(se) if se.on_rq => {
+                se.on_rq = false;
+                self.nr_idle_running -= se.idle as usize;
+                (se.group.id, se.vruntime)
+            }
+            Some(_) => return,
+            None => {
+                if self.curr == Some(task) {
+                    self.curr = None;
+                }
+                return;
+            }
+        };
+        self.load_weight -= self.entities[&task].weight as u64;
+
+        self.unlink_path(gid);
+        self.unqueue_task(gid, vruntime, task);
+        for level in self.path(gid) {
+            if let Some(g) = self.groups.get_mut(&level) {
+                g.h_nr_running -= 1;
+            }
+        }
+        self.link_path(gid);
+        if self.curr == Some(task) {
+            self.curr = None;
+        }
+        self.clear_buddies(task);
+        self.update_min_vruntime(gid);
+    }
+
+    /// Forget a task entirely (exit)
+    pub fn remove(&mut self, task: TaskId) {
+        self.dequeue(task);
+        self.entities.remove(&task);
+    }
+
+    /// Move a task to another group on this runqueue
+    ///
+    /// A runnable task is requeued at the new group's `min_vruntime` right
+    /// away and keeps running if it was; a sleeping task switches groups
+    /// when it is next enqueued.
+    pub fn move_task(&mut self, task: TaskId, group: TaskGroup) {
+        let se = match self.entities.get(&task) {
+            Some(se) if se.on_rq && se.group.id != group.id => *se,
+            _ => return,
+        };
+        let running = self.curr == Some(task);
+        self.dequeue(task);
+        self.enqueue(task, se.weight, se.batch, 0, group);
+        if running {
+            self.set_curr(task, se.exec_start);
+            if let Some(moved) = self.entities.get_mut(&task) {
+                moved.prev_sum_exec_runtime = se.prev_sum_exec_runtime;
+            }
+        }
+    }
+
+    /// Set the latency nice value of a task on this runqueue
+    ///
+    /// The task starts a new EEVDF request of the matching size.
+    pub fn set_latency_nice(&mut self, task: TaskId, latency_nice: i8) {
+        let base_slice = self.base_slice;
+        if let Some(se) = self.entities.get_mut(&task) {
+            se.latency_nice = latency_nice;
+            se.deadline = virtual_deadline(se, base_slice);
+        }
+    }
+
+    /// Mark a task on this runqueue as a `SCHED_IDLE` task or not
+    pub fn set_idle(&mut self, task: TaskId, idle: bool) {
+        if let Some(se) = self.entities.get_mut(&task) {
+            if se.on_rq && se.idle != idle {
+                if idle {
+                    self.nr_idle_running += 1;
+                } else {
+                    self.nr_idle_running -= 1;
+                }
+            }
+            se.idle = idle;
+        }
+    }
+
+    /// Runtime of the running task since it was last picked
+    pub fn curr_runtime(&self) -> Option<u64> {
+        let se = self.curr.and_then(|id| self.entities.get(&id))?;
+        Some(se.sum_exec_runtime - se.prev_sum_exec_runtime)
+    }
+
+    /// Check whether the running task should be preempted on the tick
+    ///
+    /// It must have run for its `slice`, and for at least `min_gran`, while
+    /// other tasks are queued. In EEVDF mode it must instead have been
+    /// served the request it was picked with. A running `SCHED_IDLE` task
+    /// is preempted as soon as another task is queued.
+    pub fn check_preempt_tick(&self, slice: u64, min_gran: u64) -> bool {
+        if self.leftmost().is_none() {
+            return false;
+        }
+        if self.curr.map_or(false, |curr| self.is_idle(curr))
+            && self.queued().into_iter().any(|task| !self.is_idle(task)) {
+            return true;
+        }
+        if self.mode == FairMode::Eevdf {
+            return self.curr.and_then(|id| self.entities.get(&id))
+                .map_or(true, |se| se.deadline != self.curr_deadline);
+        }
+        self.curr_runtime().map_or(true, |ran| ran >= slice.max(min_gran))
+    }
+
+    /// Check whether a woken task should preempt the running task
+    ///
+    /// As `wakeup_preempt` with `wakeup_gran`, but a running task that
+    /// has not yet run for `min_gran` is never preempted. In EEVDF mode
+    /// the virtual deadlines alone decide.
+    pub fn check_preempt_wakeup(&self, task: TaskId, min_gran: u64, wakeup_gran: u64) -> bool {
+        if self.preempts_idle_curr(task) {
+            return true;
+        }
+        if self.mode == FairMode::Cfs && self.curr_runtime().map_or(false, |ran| ran < min_gran) {
+            return false;
+        }
+        self.wakeup_preempt(task, wakeup_gran)
+    }
+
+    /// Check whether a queued task should preempt the running task
+    ///
+    /// Within a group the task must lead by more than `gran` (scaled by its
+    /// weight), shifted by the difference of both tasks' latency offsets.
+    /// Across groups the entities of both tasks below their closest common
+    /// ancestor are compared. Batch and `SCHED_IDLE` tasks never preempt,
+    /// except that any other task preempts a running `SCHED_IDLE` task;
+    /// anything preempts an idle runqueue.
+    ///
+    /// In EEVDF mode `gran` is not used: the task's entity at the common
+    /// ancestor must be eligible and have an earlier virtual deadline.
+    pub fn wakeup_preempt(&self, task: TaskId, gran: u64) -> bool {
+        if self.preempts_idle_curr(task) {
+            return true;
+        }
+        let se = match self.entity(task) {
+            Some(se) if !se.batch && !se.idle => *se,
+            _ => return false,
+        };
+        let curr = match self.curr.filter(|&curr| curr != task).and_then(|curr| self.entity(curr)) {
+            Some(curr) => *curr,
+            None => return self.curr.is_none(),
+        };
+        if self.mode == FairMode::Eevdf {
+            let common = self.common_ancestor(&curr, &se);
+            return match (self.level_deadline(&se, common), self.level_deadline(&curr, common)) {
+                (Some((vruntime, deadline)), Some((_, curr_deadline))) => {
+                    vruntime_before(deadline, curr_deadline) && Self::eligible(vruntime, self.avg_vruntime(common))
+                }
+                _ => false,
+            };
+        }
+        if curr.group.id == se.group.id {
+            let vdiff = curr.vruntime.wrapping_sub(se.vruntime) as i64
+                + latency_offset(se.latency_nice) - latency_offset(curr.latency_nice);
+            return vdiff > calc_delta_fair(gran, se.weight) as i64;
+        }
+        // Different groups compete at their closest common ancestor
+        match self.matching_entities(&curr, &se) {
+            Some(((curr_vruntime, _), (vruntime, weight))) => {
+                vruntime_before(vruntime.wrapping_add(calc_delta_fair(gran, weight)), curr_vruntime)
+            }
+            None => false,
+        }
+    }
+
+    /// Limit or unlimit a group's CPU time on this runqueue
+    ///
+    /// A newly limited group starts without runtime and has to fetch it
+    /// from the group's quota. A group must be unthrottled before its
+    /// limit is removed.
+    pub fn set_group_bandwidth(&mut self, gid: GroupId, enabled: bool) {
+        if let Some(g) = self.groups.get_mut(&gid) {
+            if g.bandwidth != enabled {
+                g.bandwidth = enabled;
+                g.runtime_remaining = 0;
+            }
+        }
+    }
+
+    /// Runtime a group may still use on this runqueue, if it is limited
+    pub fn group_runtime_remaining(&self, gid: GroupId) -> Option<i64> {
+        self.groups.get(&gid).filter(|g| g.bandwidth).map(|g| g.runtime_remaining)
+    }
+
+    /// Grant a limited group more runtime on this runqueue
+    pub fn add_group_runtime(&mut self, gid: GroupId, runtime_ns: u64) {
+        if let Some(g) = self.groups.get_mut(&gid) {
+            g.runtime_remaining += runtime_ns as i64;
+        }
+    }
+
+    /// Check whether a group is throttled on this runqueue
+    pub fn is_group_throttled(&self, gid: GroupId) -> bool {
+        self.groups.get(&gid).map_or(false, |g| g.throttled)
+    }
+
+    /// Check whether the running task's group or one of its ancestors is
+    /// throttled
+    pub fn curr_throttled(&self) -> bool {
+        self.curr
+            .and_then(|id| self.entities.get(&id))
+            .map_or(false, |se| self.path(se.group.id).into_iter().any(|gid| self.is_group_throttled(gid)))
+    }
+
+    /// Innermost limited group of the running task that is out of runtime
+    pub fn curr_exhausted_group(&self) -> Option<GroupId> {
+        let se = self.curr.and_then(|id| self.entities.get(&id))?;
+        self.path(se.group.id).into_iter()
+            .find(|&gid| self.group_runtime_remaining(gid).map_or(false, |remaining| remaining <= 0))
+    }
+
+    /// Stop a group from being picked until it is unthrottled
+    ///
+    /// Its queued tasks stay queued but no longer count as runnable.
+    /// Returns whether the group was newly throttled.
+    pub fn throttle_group(&mut self, gid: GroupId, now: u64) -> bool {
+        if gid == ROOT_TASK_GROUP || self.groups.get(&gid).map_or(true, |g| g.throttled) {
+            return false;
+        }
+        self.unlink_path(gid);
+        let (parent, queued) = match self.groups.get_mut(&gid) {
+            Some(g) => {
+                g.throttled = true;
+                g.throttled_at = now;
+                (g.parent, g.h_nr_queued)
+            }
+            None => return false,
+        };
+        self.account_queued(parent, queued, false);
+        self.link_path(gid);
+        true
+    }
+
+    /// Make a throttled group pickable again
+    ///
+    /// Only the tasks still queued are counted back; tasks that blocked
+    /// while the group was throttled were already removed by `dequeue`.
+    /// Returns how long the group was throttled.
+    pub fn unthrottle_group(&mut self, gid: GroupId, now: u64) -> u64 {
+        if !self.is_group_throttled(gid) {
+            return 0;
+        }
+        self.unlink_path(gid);
+        let (parent, queued, throttled_for) = match self.groups.get_mut(&gid) {
+            Some(g) => {
+                g.throttled = false;
+                (g.parent, g.h_nr_queued, now.saturating_sub(g.throttled_at))
+            }
+            None => return 0,
+        };
+        self.account_queued(parent, queued, true);
+        self.link_path(gid);
+        throttled_for
+    }
+
+    /// Change the weight of a group
+    pub fn reweight_group(&mut self, group: TaskGroup) {
+        if let Some(g) = self.groups.get_mut(&group.id) {
+            g.weight = group.weight;
+        }
+    }
+
+    /// Take a task off this runqueue for migration
+    ///
+    /// The returned entity's vruntime is relative to its group's
+    /// `min_vruntime` so it can be re-based on the destination. The offset
+    /// wraps: a task behind `min_vruntime` keeps its (negative) lag.
+    pub fn detach(&mut self, task: TaskId) -> Option<SchedEntity> {
+        let (was_queued, gid) = self.entities.get(&task).map(|se| (se.on_rq, se.group.id))?;
+        self.dequeue(task);
+        let mut se = self.entities.remove(&task)?;
+        let base = self.groups.get(&gid).map_or(0, |g| g.min_vruntime);
+        se.vruntime = se.vruntime.wrapping_sub(base);
+        se.on_rq = was_queued;
+        Some(se)
+    }
+
+    /// Add a task detached from another runqueue
+    ///
+    /// Its relative vruntime is re-based on its group's `min_vruntime`
+    /// here, so it keeps its lag or lead over its peers rather than its
+    /// absolute vruntime, which means nothing on this runqueue.
+    pub fn attach(&mut self, task: TaskId, mut se: SchedEntity) {
+        let queued = se.on_rq;
+        let group = se.group;
+        let base_slice = self.base_slice;
+        se.vruntime = se.vruntime.wrapping_add(self.group_entry(group).min_vruntime);
+        se.deadline = virtual_deadline(&se, base_slice);
+        se.on_rq = false;
+        let (weight, batch) = (se.weight, se.batch);
+        self.entities.insert(task, se);
+        if queued {
+            self.enqueue(task, weight, batch, 0, group);
+        }
+    }
+
+    /// Charge the running task and its groups for the time since its slice started
+    pub fn update_curr(&mut self, now: u64) {
+        let curr = match self.curr {
+            Some(curr) => curr,
+            None => return,
+        };
+        let base_slice = self.base_slice;
+        let (gid, delta) = match self.entities.get_mut(&curr) {
+            Some(se) => {
+                let delta = now.saturating_sub(se.exec_start);
+                se.exec_start = now;
+                se.sum_exec_runtime += delta;
+                se.vruntime = se.vruntime.wrapping_add(calc_delta_fair(delta, se.weight));
+                if !vruntime_before(se.vruntime, se.deadline) {
+                    se.deadline = virtual_deadline(se, base_slice);
+                }
+                (se.group.id, delta)
+            }
+            None => return,
+        };
+        self.unlink_path(gid);
+        for level in self.path(gid) {
+            if level == ROOT_TASK_GROUP {
+                continue;
+            }
+            if let Some(g) = self.groups.get_mut(&level) {
+                g.vruntime = g.vruntime.wrapping_add(calc_delta_fair(delta, g.weight));
+                if g.bandwidth {
+                    g.runtime_remaining -= delta as i64;
+                }
+            }
+        }
+        self.link_path(gid);
+        self.update_min_vruntime(gid);
+    }
+
+    /// Start running a queued task
+    pub fn set_curr(&mut self, task: TaskId, now: u64) {
+        let (gid, vruntime, deadline) = match self.entities.get_mut(&task) {
+            Some(se) => {
+                se.exec_start = now;
+                se.prev_sum_exec_runtime = se.sum_exec_runtime;
+                (se.group.id, se.vruntime, se.deadline)
+            }
+            None => return,
+        };
+        self.unlink_path(gid);
+        self.unqueue_task(gid, vruntime, task);
+        self.link_path(gid);
+        self.curr = Some(task);
+        self.curr_deadline = deadline;
+        self.clear_buddies(task);
+    }
+
+    /// Stop running the current task, requeueing it if still runnable
+    pub fn put_prev(&mut self, task: TaskId, now: u64) {
+        if self.curr != Some(task) {
+            return;
+        }
+        self.update_curr(now);
+        self.curr = None;
+        let (gid, vruntime) = match self.entities.get(&task) {
+            Some(se) if se.on_rq => (se.group.id, se.vruntime),
+            _ => return,
+        };
+        self.unlink_path(gid);
+        self.queue_task(gid, vruntime, task);
+        self.link_path(gid);
+    }
+
+    /// Move the running task behind the queued entities of its group
+    ///
+    /// Its vruntime advances past the rightmost queued one and it starts a
+    /// new EEVDF request, so its peers run before it. The advance is capped
+    /// at one request, so a yield next to a far-right peer does not cost
+    /// more than a slice. A task alone in its group keeps its vruntime.
+    /// Returns whether the task had a peer to yield to.
+    pub fn yield_curr(&mut self, now: u64) -> bool {
+        self.update_curr(now);
+        let curr = match self.curr {
+            Some(curr) => curr,
+            None => return false,
+        };
+        let gid = match self.entities.get(&curr) {
+            Some(se) => se.group.id,
+            None => return false,
+        };
+        let rightmost = match self.groups.get(&gid).and_then(|g| g.timeline.iter().next_back()) {
+            Some(&(VKey(vruntime), _)) => vruntime,
+            None => return false,
+        };
+        let base_slice = self.base_slice;
+        if let Some(se) = self.entities.get_mut(&curr) {
+            if !vruntime_before(rightmost, se.vruntime) {
+                se.vruntime = min_vruntime(rightmost.wrapping_add(1), virtual_deadline(se, base_slice));
+                se.deadline = virtual_deadline(se, base_slice);
+            }
+        }
+        self.update_min_vruntime(gid);
+        true
+    }
+
+    /// Yield the running task in favour of a queued task
+    ///
+    /// The target gets what is left of the running task's `slice`: its
+    /// vruntime moves earlier by that much, though never ahead of the
+    /// leftmost entity of its group, and it becomes the next buddy. The
+    /// running task then yields. Returns whether the target was boosted.
+    pub fn yield_to(&mut self, target: TaskId, slice: u64, now: u64) -> bool {
+        self.update_curr(now);
+        let boosted = self.curr.map_or(false, |curr| curr != target) && self.donate_slice(target, slice);
+        self.yield_curr(now);
+        if boosted {
+            self.next_buddy = Some(target);
+        }
+        boosted
+    }
+
+    /// The root group, which always exists
+    fn root(&self) -> &GroupRq {
+        &self.groups[&ROOT_TASK_GROUP]
+    }
+
+    /// Group state on this runqueue, created at its parent's `min_vruntime` if new
+    fn group_entry(&mut self, group: TaskGroup) -> &mut GroupRq {
+        if !self.groups.contains_key(&group.id) {
+            let parent = if self.groups.contains_key(&group.parent) { group.parent } else { ROOT_TASK_GROUP };
+            let mut vruntime = 0;
+            if let Some(p) = self.groups.get_mut(&parent) {
+                p.nr_children += 1;
+                vruntime = p.min_vruntime;
+            }
+            self.groups.insert(group.id, GroupRq {
+                parent,
+                vruntime,
+                weight: group.weight,
+                ..GroupRq::default()
+            });
+        }
+        self.groups.entry(group.id).or_default()
+    }
+
+    /// A group and its ancestors, up to and including the root group
+    fn path(&self, mut gid: GroupId) -> Vec<GroupId> {
+        let mut path = Vec::new();
+        path.push(gid);
+        while gid != ROOT_TASK_GROUP {
+            match self.groups.get(&gid) {
+                Some(g) => gid = g.parent,
+                None => break,
+            }
+            path.push(gid);
+        }
+        path
+    }
+
+    /// `min_vruntime` of a group's parent
+    fn parent_min_vruntime(&self, gid: GroupId) -> u64 {
+        self.groups.get(&gid)
+            .and_then(|g| self.groups.get(&g.parent))
+            .map_or(0, |p| p.min_vruntime)
+    }
+
+    /// vruntime and weight of the entity a task competes through in a group:
+    /// the task itself or the child group containing it
+    fn level_entity(&self, se: &SchedEntity, level: GroupId) -> Option<(u64, u32)> {
+        if se.group.id == level {
+            return Some((se.vruntime, se.weight));
+        }
+        let mut gid = se.group.id;
+        loop {
+            let g = self.groups.get(&gid)?;
+            if g.parent == level {
+                return Some((g.vruntime, g.weight));
+            }
+            if gid == ROOT_TASK_GROUP {
+                return None;
+            }
+            gid = g.parent;
+        }
+    }
+
+    /// Closest group containing both of two tasks
+    fn common_ancestor(&self, a: &SchedEntity, b: &SchedEntity) -> GroupId {
+        let a_path = self.path(a.group.id);
+        self.path(b.group.id).into_iter()
+            .find(|gid| a_path.contains(gid))
+            .unwrap_or(ROOT_TASK_GROUP)
+    }
+
+    /// Entities through which two tasks compete at their closest common
+    /// ancestor, as (vruntime, weight)
+    fn matching_entities(&self, a: &SchedEntity, b: &SchedEntity) -> Option<((u64, u32), (u64, u32))> {
+        let common = self.common_ancestor(a, b);
+        Some((self.level_entity(a, common)?, self.level_entity(b, common)?))
+    }
+
+    /// vruntime and virtual deadline of the entity a task competes through
+    /// in a group
+    fn level_deadline(&self, se: &SchedEntity, level: GroupId) -> Option<(u64, u64)> {
+        if se.group.id == level {
+            return Some((se.vruntime, se.deadline));
+        }
+        let (vruntime, weight) = self.level_entity(se, level)?;
+        Some((vruntime, vruntime.wrapping_add(calc_delta_fair(self.base_slice, weight))))
+    }
+
+    /// Virtual deadline of a timeline entry
+    ///
+    /// Groups make no requests of their own; a group always counts as
+    /// requesting the base slice from its vruntime.
+    fn node_deadline(&self, vruntime: u64, node: SchedNode) -> u64 {
+        match node {
+            SchedNode::Task(task) => self.entities.get(&task).map_or(vruntime, |se| se.deadline),
+            SchedNode::Group(gid) => {
+                let weight = self.groups.get(&gid).map_or(NICE_0_LOAD, |g| g.weight);
+                vruntime.wrapping_add(calc_delta_fair(self.base_slice, weight))
+            }
+        }
+    }
+
+    /// Weighted sum of the vruntimes and total weight of the entities
+    /// competing in a group: the queued ones and the running one
+    ///
+    /// vruntimes are summed as keys relative to the group's `min_vruntime`,
+    /// which is returned alongside, so that the sum survives wraparound.
+    fn avg_vruntime(&self, gid: GroupId) -> (u64, i128, i128) {
+        let g = match self.groups.get(&gid) {
+            Some(g) => g,
+            None => return (0, 0, 0),
+        };
+        let base = g.min_vruntime;
+        let (mut sum, mut load) = (0i128, 0i128);
+        let mut add = |vruntime: u64, weight: u32| {
+            sum += vruntime.wrapping_sub(base) as i64 as i128 * weight as i128;
+            load += weight as i128;
+        };
+        for &(VKey(vruntime), node) in &g.timeline {
+            let weight = match node {
+                SchedNode::Task(task) => self.entities.get(&task).map_or(0, |se| se.weight),
+                SchedNode::Group(child) => self.groups.get(&child).map_or(0, |c| c.weight),
+            };
+            add(vruntime, weight);
+        }
+
+        // The running task is off the timeline, and so is its group at a
+        // level unless the group has other queued tasks
+        let curr = self.curr.and_then(|id| self.entities.get(&id)).filter(|se| se.on_rq);
+        if let Some(se) = curr {
+            let path = self.path(se.group.id);
+            match path.iter().position(|&level| level == gid) {
+                Some(0) => add(se.vruntime, se.weight),
+                Some(i) => {
+                    if let Some(child) = self.groups.get(&path[i - 1]) {
+                        if !g.timeline.contains(&(VKey(child.vruntime), SchedNode::Group(path[i - 1]))) {
+                            add(child.vruntime, child.weight);
+                        }
+                    }
+                }
+                None => {}
+            }
+        }
+        (base, sum, load)
+    }
+
+    /// Check whether a vruntime is not ahead of a weighted average
+    fn eligible(vruntime: u64, (base, sum, load): (u64, i128, i128)) -> bool {
+        load == 0 || vruntime.wrapping_sub(base) as i64 as i128 * load <= sum
+    }
+
+    /// Check whether a task is a `SCHED_IDLE` task
+    fn is_idle(&self, task: TaskId) -> bool {
+        self.entities.get(&task).map_or(false, |se| se.idle)
+    }
+
+    /// The first queued task that is not a `SCHED_IDLE` task in place of a
+    /// `SCHED_IDLE` pick, if there is one
+    fn skip_idle(&self, pick: TaskId) -> TaskId {
+        if !self.is_idle(pick) {
+            return pick;
+        }
+        self.queued().into_iter().find(|&task| !self.is_idle(task)).unwrap_or(pick)
+    }
+
+    /// Check whether a task preempts the running task for being a regular
+    /// task while the running one is a `SCHED_IDLE` task
+    fn preempts_idle_curr(&self, task: TaskId) -> bool {
+        let curr_idle = self.curr.filter(|&curr| curr != task).map_or(false, |curr| self.is_idle(curr));
+        curr_idle && self.entity(task).map_or(false, |se| !se.idle)
+    }
+
+    /// Check whether a buddy is queued, pickable and close enough to the
+    /// leftmost task to be picked instead
+    fn buddy_eligible(&self, buddy: TaskId, left: TaskId, gran: u64) -> bool {
+        if buddy == left {
+            return true;
+        }
+        let (se, left_se) = match (self.entity(buddy), self.entity(left)) {
+            (Some(se), Some(left_se)) if se.on_rq && self.curr != Some(buddy) => (*se, *left_se),
+            _ => return false,
+        };
+        if self.path(se.group.id).into_iter().any(|gid| self.is_group_throttled(gid)) {
+            return false;
+        }
+        match self.matching_entities(&se, &left_se) {
+            Some(((vruntime, _), (left_vruntime, left_weight))) => {
+                !vruntime_before(left_vruntime.wrapping_add(calc_delta_fair(gran, left_weight)), vruntime)
+            }
+            None => false,
+        }
+    }
+
+    /// Pull a queued task's vruntime earlier by the running task's unused
+    /// slice, bounded by the leftmost entity of its group
+    fn donate_slice(&mut self, target: TaskId, slice: u64) -> bool {
+        let ran = self.curr.and_then(|curr| self.entities.get(&curr))
+            .map_or(0, |se| se.sum_exec_runtime - se.prev_sum_exec_runtime);
+        let (gid, vruntime, weight) = match self.entities.get(&target) {
+            Some(se) if se.on_rq => (se.group.id, se.vruntime, se.weight),
+            _ => return false,
+        };
+        let leftmost = match self.groups.get(&gid).and_then(|g| g.timeline.iter().next()) {
+            Some(&(VKey(leftmost), _)) => leftmost,
+            None => return false,
+        };
+        let boosted = max_vruntime(vruntime.wrapping_sub(calc_delta_fair(slice.saturating_sub(ran), weight)), leftmost);
+        if vruntime_before(boosted, vruntime) {
+            let base_slice = self.base_slice;
+            self.unlink_path(gid);
+            self.unqueue_task(gid, vruntime, target);
+            if let Some(se) = self.entities.get_mut(&target) {
+                se.vruntime = boosted;
+                se.deadline = virtual_deadline(se, base_slice);
+            }
+            self.queue_task(gid, boosted, target);
+            self.link_path(gid);
+        }
+        true
+    }
+
+    /// Stop preferring a task that was picked or left the runqueue
+    fn clear_buddies(&mut self, task: TaskId) {
+        if self.next_buddy == Some(task) {
+            self.next_buddy = None;
+        }
+        if self.last_buddy == Some(task) {
+            self.last_buddy = None;
+        }
+    }
+
+    /// Append the queued tasks below a group in pick order
+    fn collect_timeline(&self, gid: GroupId, tasks: &mut Vec<(TaskId, u64)>) {
+        if let Some(g) = self.groups.get(&gid) {
+            for &(VKey(vruntime), node) in &g.timeline {
+                match node {
+                    SchedNode::Task(task) => tasks.push((task, vruntime)),
+                    SchedNode::Group(child) => self.collect_timeline(child, tasks),
+                }
+            }
+        }
+    }
+
+    /// Take a group and its ancestors off their parents' timelines before
+    /// changing their vruntimes or what is queued below them
+    fn unlink_path(&mut self, mut gid: GroupId) {
+        while gid != ROOT_TASK_GROUP {
+            let (parent, vruntime) = match self.groups.get(&gid) {
+                Some(g) => (g.parent, g.vruntime),
+                None => return,
+            };
+            if let Some(p) = self.groups.get_mut(&parent) {
+                p.timeline.remove(&(VKey(vruntime), SchedNode::Group(gid)));
+            }
+            gid = parent;
+        }
+    }
+
+    /// Put a group and its ancestors back on their parents' timelines if
+    /// they have queued entities and are not throttled
+    fn link_path(&mut self, mut gid: GroupId) {
+        while gid != ROOT_TASK_GROUP {
+            let (parent, vruntime, runnable) = match self.groups.get(&gid) {
+                Some(g) => (g.parent, g.vruntime, !g.timeline.is_empty() && !g.throttled),
+                None => return,
+            };
+            if runnable {
+                if let Some(p) = self.groups.get_mut(&parent) {
+                    p.timeline.insert((VKey(vruntime), SchedNode::Group(gid)));
+                }
+            }
+            gid = parent;
+        }
+    }
+
+    /// Add a task to its group's timeline
+    fn queue_task(&mut self, gid: GroupId, vruntime: u64, task: TaskId) {
+        let inserted = self.groups.get_mut(&gid)
+            .map_or(false, |g| g.timeline.insert((VKey(vruntime), SchedNode::Task(task))));
+        if inserted {
+            self.account_queued(gid, 1, true);
+        }
+    }
+
+    /// Remove a task from its group's timeline
+    fn unqueue_task(&mut self, gid: GroupId, vruntime: u64, task: TaskId) {
+        let removed = self.groups.get_mut(&gid)
+            .map_or(false, |g| g.timeline.remove(&(VKey(vruntime), SchedNode::Task(task))));
+        if removed {
+            self.account_queued(gid, 1, false);
+        }
+    }
+
+    /// Add or subtract queued tasks from a group and its ancestors, up to
+    /// and including the first throttled one
+    fn account_queued(&mut self, mut gid: GroupId, count: usize, add: bool) {
+        while let Some(g) = self.groups.get_mut(&gid) {
+            if add {
+                g.h_nr_queued += count;
+            } else {
+                g.h_nr_queued -= count;
+            }
+            if g.throttled || gid == ROOT_TASK_GROUP {
+                break;
+            }
+            gid = g.parent;
+        }
+    }
+
+    /// Advance the `min_vruntime` of a group and of its ancestors
+    fn update_min_vruntime(&mut self, gid: GroupId) {
+        let curr = self.curr
+            .and_then(|id| self.entities.get(&id))
+            .filter(|se| se.on_rq)
+            .copied();
+        let curr_path = curr.map(|se| self.path(se.group.id)).unwrap_or_default();
+
+        for level in self.path(gid) {
+            // The running entity at this level: the task or its child group
+            let curr_vruntime = match curr_path.iter().position(|&g| g == level) {
+                Some(0) => curr.map(|se| se.vruntime),
+                Some(i) => self.groups.get(&curr_path[i - 1]).map(|g| g.vruntime),
+                None => None,
+            };
+            if let Some(g) = self.groups.get_mut(&level) {
+                let leftmost = g.timeline.iter().next().map(|&(VKey(v), _)| v);
+                if let Some(candidate) = curr_vruntime.into_iter().chain(leftmost).reduce(min_vruntime) {
+                    g.min_vruntime = max_vruntime(g.min_vruntime, candidate);
+                }
+            }
+        }
+    }
+}
+
+impl Default for CfsRq {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// NUMA fault statistics of a task
+#[derive(Debug, Clone, Default)]
+pub struct NumaFaultStats {
+    /// Faults per node in the current window
+    pub window_faults: BTreeMap<NodeId, u64>,
+    /// Decayed fault history per node
+    pub total_faults: BTreeMap<NodeId, u64>,
+    /// Node the task should run on, once stable
+    pub preferred_node: Option<NodeId>,
+    /// Node that won the last window(s)
+    candidate_node: Option<NodeId>,
+    /// Number of consecutive windows won by `candidate_node`
+    stable_windows: u32,
+    /// Time of the last window evaluation
+    last_scan: u64,
+}
+
+impl NumaFaultStats {
+    /// Close the current window and update the preferred node
+    fn close_window(&mut self) {
+        let window_total: u64 = self.window_faults.values().sum();
+        let winner = self.window_faults.iter()
+            .max_by_key(|(_, &count)| count)
+            .filter(|(_, &count)| window_total > 0 && count * 100 >= window_total * NUMA_MIN_FAULT_SHARE)
+            .map(|(&node, _)| node);
+
+        match winner {
+            Some(node) if self.candidate_node == Some(node) => self.stable_windows += 1,
+            Some(node) => {
+                self.candidate_node = Some(node);
+                self.stable_windows = 1;
+            }
+            None => self.stable_windows = 0,
+        }
+        if self.stable_windows >= NUMA_PREFERRED_HYSTERESIS {
+            self.preferred_node = self.candidate_node;
+        }
+
+        // Halve the history and fold in the window
+        for count in self.total_faults.values_mut() {
+            *count /= 2;
+        }
+        for (node, count) in core::mem::take(&mut self.window_faults) {
+            *self.total_faults.entry(node).or_insert(0) += count;
+        }
+    }
+}
+
+/// A task group created with `FairScheduler::create_group`
+#[derive(Debug, Clone, Copy)]
+struct GroupInfo {
+    group: TaskGroup,
+    /// Tasks moved into the group
+    nr_tasks: usize,
+    /// Groups created below the group
+    nr_children: usize,
+}
+
+/// Fair scheduler component
+pub struct FairScheduler {
+    rqs: PerCpu<SpinLock<CfsRq>>,
+    mode: RwLock<FairMode>,
+    timeslice_us: AtomicU64,
+    min_granularity_ns: AtomicU64,
+    wakeup_granularity_ns: AtomicU64,
+    target_latency_ns: AtomicU64,
+    numa: RwLock<BTreeMap<TaskId, NumaFaultStats>>,
+    latency_nice: RwLock<BTreeMap<TaskId, i8>>,
+    /// Bandwidth limits by group
+    bandwidth: SpinLock<BTreeMap<GroupId, GroupBandwidth>>,
+    /// Created task groups
+    groups: RwLock<BTreeMap<GroupId, GroupInfo>>,
+    /// Group of every task moved out of the root group
+    task_groups: RwLock<BTreeMap<TaskId, GroupId>>,
+    next_group_id: AtomicU64,
+}
+
+impl FairScheduler {
+    /// Create a fair scheduler with a default time slice (microseconds)
+    pub fn with_timeslice(timeslice_us: u64) -> Self {
+        Self::with_mode(timeslice_us, FairMode::Cfs)
+    }
+
+    /// Create a fair scheduler picking in `mode`
+    ///
+    /// In EEVDF mode the time slice is the request size at latency nice 0.
+    pub fn with_mode(timeslice_us: u64, mode: FairMode) -> Self {
+        Self {
+            rqs: PerCpu::new(SpinLock::new(CfsRq::with_mode(mode, timeslice_us * 1_000))),
+            mode: RwLock::new(mode),
+            timeslice_us: AtomicU64::new(timeslice_us),
+            min_granularity_ns: AtomicU64::new(DEFAULT_MIN_GRANULARITY_NS),
+            wakeup_granularity_ns: AtomicU64::new(DEFAULT_WAKEUP_GRANULARITY_NS),
+            target_latency_ns: AtomicU64::new(SCHED_LATENCY_NS),
+            numa: RwLock::new(BTreeMap::new()),
+            latency_nice: RwLock::new(BTreeMap::new()),
+            bandwidth: SpinLock::new(BTreeMap::new()),
+            groups: RwLock::new(BTreeMap::new()),
+            task_groups: RwLock::new(BTreeMap::new()),
+            next_group_id: AtomicU64::new(FIRST_TASK_GROUP_ID),
+        }
+    }
+
+    /// Default time slice in microseconds
+    pub fn timeslice_us(&self) -> u64 {
+        self.timeslice_us.load(Ordering::Relaxed)
+    }
+
+    /// How the next task is picked
+    pub fn mode(&self) -> FairMode {
+        *self.mode.read()
+    }
+
+    /// Switch every CPU between CFS and EEVDF picking
+    pub fn set_mode(&self, mode: FairMode) {
+        *self.mode.write() = mode;
+        for cpu in CpuMask::online().iter() {
+            self.rqs.get(cpu).lock().set_mode(mode, self.timeslice_us() * 1_000);
+        }
+        kernel_info!("Fair scheduler switched to {:?} mode", mode);
+    }
+
+    /// Set the minimum time a task runs before it can be preempted
+    /// (`sched_min_granularity`, 0.1ms..=1s)
+    ///
+    /// Raising it bounds the context switch rate at the cost of latency.
+    pub fn set_min_granularity(&self, granularity_ns: u64) -> KernelResult<()> {
+        let (min, max) = MIN_GRANULARITY_RANGE_NS;
+        if !(min..=max).contains(&granularity_ns) {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        self.min_granularity_ns.store(granularity_ns, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Minimum time a task runs before it can be preempted
+    pub fn min_granularity(&self) -> u64 {
+        self.min_granularity_ns.load(Ordering::Relaxed)
+    }
+
+    /// Set the vruntime lead a waking task needs to preempt the running
+    /// task (`sched_wakeup_granularity`, at most 1s)
+    pub fn set_wakeup_granularity(&self, granularity_ns: u64) -> KernelResult<()> {
+        if granularity_ns > MAX_WAKEUP_GRANULARITY_NS {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        self.wakeup_granularity_ns.store(granularity_ns, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// vruntime lead a waking task needs to preempt the running task
+    pub fn wakeup_granularity(&self) -> u64 {
+        self.wakeup_granularity_ns.load(Ordering::Relaxed)
+    }
+
+    /// Set the period over which every runnable task should run once
+    /// (`sched_latency`, 0.1ms..=1s)
+    ///
+    /// Each task's slice is an equal share of it, but at least the minimum
+    /// granularity; with more tasks than fit, the period grows instead.
+    pub fn set_target_latency(&self, latency: Duration) -> KernelResult<()> {
+        let (min, max) = TARGET_LATENCY_RANGE_NS;
+        if !(min..=max).contains(&latency.as_nanos()) {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        self.target_latency_ns.store(latency.as_nanos(), Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Period over which every runnable task should run once
+    pub fn target_latency(&self) -> u64 {
+        self.target_latency_ns.load(Ordering::Relaxed)
+    }
+
+    /// Current scheduling period of a CPU's fair runqueue
+    pub fn sched_period(&self, cpu: CpuId) -> u64 {
+        let nr_running = self.rqs.get(cpu).lock().nr_running();
+        calc_sched_period(self.target_latency(), self.min_granularity(), nr_running)
+    }
+
+    /// Current time slice of each task on a CPU's fair runqueue
+    pub fn sched_slice(&self, cpu: CpuId) -> u64 {
+        let nr_running = self.rqs.get(cpu).lock().nr_running();
+        calc_sched_slice(self.target_latency(), self.min_granularity(), nr_running)
+    }
+
+    /// Make a normal or interactive task runnable on its CPU within a group
+    pub fn enqueue_task(&self, task: &Task, group: TaskGroup) -> KernelResult<()> {
+        self.enqueue(task, false, group)
+    }
+
+    /// Make a batch or background task runnable on its CPU within a group
+    pub fn enqueue_task_batch(&self, task: &Task, group: TaskGroup) -> KernelResult<()> {
+        self.enqueue(task, true, group)
+    }
+
+    /// Make a `SCHED_IDLE` task runnable on its CPU within a group
+    ///
+    /// It runs with `WEIGHT_IDLEPRIO` whatever its nice value, only while
+    /// no other fair task is queued on the CPU.
+    pub fn enqueue_task_idle(&self, task: &Task, group: TaskGroup) -> KernelResult<()> {
+        self.enqueue_weighted(task, false, Some(WEIGHT_IDLEPRIO), group)
+    }
+
+    /// Make woken tasks of one CPU runnable under a single runqueue lock
+    ///
+    /// Each entry is a task, its group and whether it is a batch task.
+    /// Fails without enqueuing anything if a nice value is invalid.
+    /// Returns whether one of the non-batch tasks should preempt the
+    /// running task, as `check_preempt_wakeup` decides.
+    pub fn enqueue_tasks(&self, cpu: CpuId, tasks: &[(&Task, TaskGroup, bool)], now: u64) -> KernelResult<bool> {
+        if tasks.iter().any(|(task, _, _)| !(-20..=19).contains(&task.nice())) {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        let entries: Vec<(TaskId, u32, TaskGroup, bool)> = tasks.iter()
+            .map(|&(task, group, batch)| (task.id(), nice_to_weight(task.nice()), group, batch))
+            .collect();
+        Ok(self.enqueue_entries(cpu, &entries, now))
+    }
+
+    /// Make woken tasks of one CPU runnable under a single runqueue lock
+    ///
+    /// Each entry is a task, its weight, its group and whether it is a
+    /// batch task. Returns whether one of the non-batch tasks should
+    /// preempt the running task.
+    fn enqueue_entries(&self, cpu: CpuId, tasks: &[(TaskId, u32, TaskGroup, bool)], now: u64) -> bool {
+        let entries: Vec<_> = tasks.iter().map(|&(task, weight, group, batch)| {
+            (task, weight, batch, self.start_debit(weight), group, self.latency_nice(task))
+        }).collect();
+        let limited: BTreeSet<GroupId> = {
+            let bandwidth = self.bandwidth.lock();
+            tasks.iter().map(|(_, _, group, _)| group.id).filter(|gid| bandwidth.contains_key(gid)).collect()
+        };
+        let wakeup_preemption = sched_feat(SchedFeature::WakeupPreemption);
+
+        let sleeper_credit = self.sleeper_credit();
+
+        let mut rq = self.rqs.get(cpu).lock();
+        rq.update_curr(now);
+        rq.set_sleeper_credit(sleeper_credit);
+        let mut preempt = false;
+        for &(task, weight, batch, start_debit, group, latency_nice) in &entries {
+            rq.enqueue(task, weight, batch, start_debit, group);
+            rq.set_idle(task, false);
+            rq.set_group_bandwidth(group.id, limited.contains(&group.id));
+            rq.set_latency_nice(task, latency_nice);
+            if wakeup_preemption && !batch {
+                preempt |= self.check_preempt_woken(&mut rq, task);
+            }
+        }
+        preempt
+    }
+
+    /// Change the weight of a group on every CPU
+    pub fn reweight_group(&self, group: TaskGroup) {
+        for cpu in CpuMask::online().iter() {
+            self.rqs.get(cpu).lock().reweight_group(group);
+        }
+    }
+
+    /// Create a task group below `parent` with a `cpu.weight` (1..=10000,
+    /// 100 being the weight of a nice 0 task)
+    ///
+    /// # Returns
+    /// - The id of the new group
+    /// - `Err(SchedulerError::InvalidParameter)` if the parent does not
+    ///   exist or the weight is out of range
+    pub fn create_group(&self, parent: GroupId, weight: u32) -> KernelResult<GroupId> {
+        if !(CPU_WEIGHT_MIN..=CPU_WEIGHT_MAX).contains(&weight) {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        let group = {
+            let mut groups = self.groups.write();
+            if parent != ROOT_TASK_GROUP {
+                groups.get_mut(&parent).ok_or(SchedulerError::InvalidParameter)?.nr_children += 1;
+            }
+            let id = self.next_group_id.fetch_add(1, Ordering::Relaxed);
+            let group = TaskGroup { id, parent, weight: cpu_weight_to_load(weight) };
+            groups.insert(id, GroupInfo { group, nr_tasks: 0, nr_children: 0 });
+            group
+        };
+        for cpu in CpuMask::online().iter() {
+            self.rqs.get(cpu).lock().add_group(group);
+        }
+        kernel_debug!("Created task group {} below {} with weight {}", group.id, parent, weight);
+        Ok(group.id)
+    }
+
+    /// Remove a task group created with `create_group`
+    ///
+    /// # Returns
+    /// - `Ok(())` on success
+    /// - `Err(SchedulerError::InvalidParameter)` for the root group or an
+    ///   unknown group
+    /// - `Err(SchedulerError::Busy)` if tasks or groups are still in it
+    pub fn remove_group(&self, group: GroupId) -> KernelResult<()> {
+        {
+            let mut groups = self.groups.write();
+            let info = groups.get(&group).ok_or(SchedulerError::InvalidParameter)?;
+            if info.nr_tasks > 0 || info.nr_children > 0 {
+                return Err(SchedulerError::Busy.into());
+            }
+            let parent = info.group.parent;
+            groups.remove(&group);
+            if let Some(parent) = groups.get_mut(&parent) {
+                parent.nr_children -= 1;
+            }
+        }
+        for cpu in CpuMask::online().iter() {
+            self.rqs.get(cpu).lock().remove_group(group);
+        }
+        self.bandwidth.lock().remove(&group);
+        Ok(())
+    }
+
+    /// Change the `cpu.weight` of a task group
+    pub fn set_group_weight(&self, group: GroupId, weight: u32) -> KernelResult<()> {
+        if !(CPU_WEIGHT_MIN..=CPU_WEIGHT_MAX).contains(&weight) {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        let group = {
+            let mut groups = self.groups.write();
+            let info = groups.get_mut(&group).ok_or(SchedulerError::InvalidParameter)?;
+            info.group.weight = cpu_weight_to_load(weight);
+            info.group
+        };
+        self.reweight_group(group);
+        Ok(())
+    }
+
+    /// Move a task into a task group, or back to the root group
+    ///
+    /// A runnable task is requeued in the new group right away.
+    pub fn move_task(&self, task: &Task, group: GroupId) -> KernelResult<()> {
+        let target = {
+            let mut groups = self.groups.write();
+            let target = match group {
+                ROOT_TASK_GROUP => TaskGroup::ROOT,
+                _ => groups.get(&group).ok_or(SchedulerError::InvalidParameter)?.group,
+            };
+            let mut task_groups = self.task_groups.write();
+            let previous = match group {
+                ROOT_TASK_GROUP => task_groups.remove(&task.id()),
+                _ => task_groups.insert(task.id(), group),
+            };
+            if let Some(previous) = previous.and_then(|previous| groups.get_mut(&previous)) {
+                previous.nr_tasks -= 1;
+            }
+            if let Some(info) = groups.get_mut(&group) {
+                info.nr_tasks += 1;
+            }
+            target
+        };
+        let limited = self.bandwidth.lock().contains_key(&group);
+        let mut rq = self.rqs.get(task.current_cpu()).lock();
+        rq.move_task(task.id(), target);
+        rq.set_group_bandwidth(group, limited);
+        Ok(())
+    }
+
+    /// Task group a task was moved into, if it is not in the root group
+    pub fn task_group(&self, task: TaskId) -> Option<TaskGroup> {
+        let group = self.task_groups.read().get(&task).copied()?;
+        self.groups.read().get(&group).map(|info| info.group)
+    }
+
+    /// Remove a task from its CPU's runqueue
+    pub fn dequeue_task(&self, task: &Task) -> KernelResult<()> {
+        self.rqs.get(task.current_cpu()).lock().dequeue(task.id());
+        Ok(())
+    }
+
+    /// Move a task's fair state from one CPU's runqueue to another's
+    pub fn migrate_task(&self, task: TaskId, from: CpuId, to: CpuId) {
+        if from == to {
+            return;
+        }
+        let se = self.rqs.get(from).lock().detach(task);
+        if let Some(se) = se {
+            let limited = self.bandwidth.lock().contains_key(&se.group.id);
+            let mut rq = self.rqs.get(to).lock();
+            let gid = se.group.id;
+            rq.attach(task, se);
+            rq.set_group_bandwidth(gid, limited);
+        }
+    }
+
+    /// Peek at the task that should run next on a CPU
+    pub fn pick_next_task(&self, cpu: CpuId) -> KernelResult<Option<Arc<Task>>> {
+        let next = self.rqs.get(cpu).lock().pick_next(self.wakeup_granularity());
+        Ok(next.and_then(Task::get_by_id))
+    }
+
+    /// Peek at the task that should run next on a CPU, favoring tasks
+    /// with low utilization (see `CfsRq::pick_light`)
+    pub fn pick_light_task(&self, cpu: CpuId, util: impl Fn(TaskId) -> u32) -> KernelResult<Option<Arc<Task>>> {
+        let next = self.rqs.get(cpu).lock().pick_light(self.wakeup_granularity(), util);
+        Ok(next.and_then(Task::get_by_id))
+    }
+
+    /// Start running a task on a CPU
+    ///
+    /// Like every runtime-charging method, `now` is the CPU's task clock
+    /// (`rq_clock_task`), which excludes time stolen by a hypervisor.
+    pub fn set_curr_task(&self, cpu: CpuId, task: TaskId, now: u64) {
+        self.rqs.get(cpu).lock().set_curr(task, now);
+    }
+
+    /// Stop running a task on a CPU
+    pub fn put_prev_task(&self, cpu: CpuId, task: TaskId, now: u64) {
+        self.rqs.get(cpu).lock().put_prev(task, now);
+    }
+
+    /// Move the running task of a CPU behind the other queued tasks of its
+    /// group; returns whether it had a peer to yield to
+    pub fn yield_task(&self, cpu: CpuId, now: u64) -> bool {
+        self.rqs.get(cpu).lock().yield_curr(now)
+    }
+
+    /// Yield the running task of a CPU to a task queued on the same CPU,
+    /// donating the rest of its time slice; returns whether the target
+    /// was boosted
+    pub fn yield_to(&self, cpu: CpuId, target: TaskId, now: u64) -> bool {
+        let slice = self.timeslice_us() * 1_000;
+        self.rqs.get(cpu).lock().yield_to(target, slice, now)
+    }
+
+    /// Charge the running task of a CPU on the scheduler tick
+    ///
+    /// A bandwidth-limited group that used up its runtime on this CPU
+    /// fetches another slice from the group's quota, or is throttled when
+    /// the quota is exhausted.
+    pub fn task_tick(&self, cpu: CpuId, now: u64) {
+        let mut rq = self.rqs.get(cpu).lock();
+        rq.update_curr(now);
+        let gid = match rq.curr_exhausted_group() {
+            Some(gid) => gid,
+            None => return,
+        };
+        let remaining = rq.group_runtime_remaining(gid).unwrap_or(0);
+
+        let mut bandwidth = self.bandwidth.lock();
+        let group = match bandwidth.get_mut(&gid) {
+            Some(group) => group,
+            None => return,
+        };
+        let granted = group.take_runtime(BANDWIDTH_SLICE_NS + remaining.unsigned_abs());
+        rq.add_group_runtime(gid, granted);
+        if rq.group_runtime_remaining(gid).unwrap_or(0) <= 0
+            && rq.throttle_group(gid, Timestamp::now().as_nanos())
+        {
+            if !group.throttled_this_period {
+                group.throttled_this_period = true;
+                group.stats.nr_throttled += 1;
+            }
+            kernel_debug!("Group {} throttled on CPU {}", gid, cpu.as_u32());
+        }
+    }
+
+    /// Limit a group's CPU time, or remove its limit with `None`
+    ///
+    /// The first period starts now with a full quota.
+    pub fn set_group_bandwidth(&self, group: GroupId, limit: Option<CfsBandwidth>) -> KernelResult<()> {
+        if let Some(config) = &limit {
+            config.validate()?;
+        }
+        let now = Timestamp::now().as_nanos();
+        for cpu in CpuMask::online().iter() {
+            let mut rq = self.rqs.get(cpu).lock();
+            if limit.is_none() {
+                let throttled_for = rq.unthrottle_group(group, now);
+                if let Some(bw) = self.bandwidth.lock().get_mut(&group) {
+                    bw.stats.throttled_time_ns += throttled_for;
+                }
+            }
+            rq.set_group_bandwidth(group, limit.is_some());
+        }
+
+        let mut bandwidth = self.bandwidth.lock();
+        match limit {
+            Some(config) => {
+                let stats = bandwidth.get(&group).map(|bw| bw.stats).unwrap_or_default();
+                bandwidth.insert(group, GroupBandwidth {
+                    config,
+                    runtime_ns: config.quota_us * 1_000,
+                    period_end: now + config.period_us * 1_000,
+                    throttled_this_period: false,
+                    stats,
+                });
+                kernel_info!("Group {} bandwidth: {} us per {} us", group, config.quota_us, config.period_us);
+            }
+            None => {
+                bandwidth.remove(&group);
+                kernel_info!("Group {} bandwidth unlimited", group);
+            }
+        }
+        Ok(())
+    }
+
+    /// Bandwidth limit of a group
+    pub fn group_bandwidth(&self, group: GroupId) -> Option<CfsBandwidth> {
+        self.bandwidth.lock().get(&group).map(|bw| bw.config)
+    }
+
+    /// Throttling statistics of a bandwidth-limited group
+    pub fn bandwidth_stats(&self, group: GroupId) -> Option<CfsBandwidthStats> {
+        self.bandwidth.lock().get(&group).map(|bw| bw.stats)
+    }
+
+    /// Check whether a group is throttled on a CPU
+    pub fn is_group_throttled(&self, cpu: CpuId, group: GroupId) -> bool {
+        self.rqs.get(cpu).lock().is_group_throttled(group)
+    }
+
+    /// Check whether the running task of a CPU belongs to a throttled group
+    pub fn curr_throttled(&self, cpu: CpuId) -> bool {
+        self.rqs.get(cpu).lock().curr_throttled()
+    }
+
+    /// Bandwidth period timer
+    ///
+    /// Refills the quota of every group whose period ended and unthrottles
+    /// its runqueues, handing each the runtime it overran by plus a slice.
+    /// Returns the CPUs with unthrottled groups, which should reschedule.
+    pub fn do_bandwidth_timers(&self, now: u64) -> Vec<CpuId> {
+        let mut refilled = Vec::new();
+        for (&gid, bw) in self.bandwidth.lock().iter_mut() {
+            if now < bw.period_end {
+                continue;
+            }
+            let period_ns = bw.config.period_us * 1_000;
+            let periods = (now - bw.period_end) / period_ns + 1;
+            bw.period_end += periods * period_ns;
+            bw.stats.nr_periods += periods;
+            bw.runtime_ns = bw.config.quota_us * 1_000;
+            bw.throttled_this_period = false;
+            refilled.push(gid);
+        }
+        if refilled.is_empty() {
+            return Vec::new();
+        }
+
+        let mut kick = Vec::new();
+        for cpu in CpuMask::online().iter() {
+            let mut rq = self.rqs.get(cpu).lock();
+            let mut unthrottled = false;
+            for &gid in &refilled {
+                if !rq.is_group_throttled(gid) {
+                    continue;
+                }
+                let remaining = rq.group_runtime_remaining(gid).unwrap_or(0);
+                let mut bandwidth = self.bandwidth.lock();
+                let bw = match bandwidth.get_mut(&gid) {
+                    Some(bw) => bw,
+                    None => continue,
+                };
+                let granted = bw.take_runtime(BANDWIDTH_SLICE_NS + remaining.unsigned_abs());
+                rq.add_group_runtime(gid, granted);
+                if rq.group_runtime_remaining(gid).unwrap_or(0) > 0 {
+                    bw.stats.throttled_time_ns += rq.unthrottle_group(gid, now);
+                    unthrottled = true;
+                }
+            }
+            if unthrottled {
+                kick.push(cpu);
+            }
+        }
+        kick
+    }
+
+    /// Check whether the running task of a CPU has used up its time slice
+    /// (and the minimum granularity) while other fair tasks are waiting,
+    /// or its group is throttled
+    pub fn check_preempt_tick(&self, cpu: CpuId, now: u64) -> bool {
+        let mut rq = self.rqs.get(cpu).lock();
+        rq.update_curr(now);
+        self.tick_preempts(&rq)
+    }
+
+    /// As `check_preempt_tick`, but leaving the runqueue untouched: the
+    /// running task is charged up to `now` on a snapshot only
+    pub fn would_preempt_tick(&self, cpu: CpuId, now: u64) -> bool {
+        let mut rq = self.rqs.get(cpu).lock().clone();
+        rq.update_curr(now);
+        self.tick_preempts(&rq)
+    }
+
+    /// Check whether a queued task should preempt the running task of a CPU
+    ///
+    /// The running task must have run for the minimum granularity, and the
+    /// task must lead it by more than the wakeup granularity (scaled by its
+    /// weight), adjusted by the latency nice values of both. In EEVDF mode
+    /// it must instead be eligible with an earlier virtual deadline. Batch
+    /// tasks and a disabled `WAKEUP_PREEMPTION` feature never preempt.
+    ///
+    /// With `NEXT_BUDDY` the woken task becomes the preferred next pick;
+    /// with `LAST_BUDDY` a preempted task is preferred after it.
+    pub fn check_preempt_wakeup(&self, cpu: CpuId, task: TaskId, now: u64) -> bool {
+        if !sched_feat(SchedFeature::WakeupPreemption) {
+            return false;
+        }
+        let mut rq = self.rqs.get(cpu).lock();
+        rq.update_curr(now);
+        self.check_preempt_woken(&mut rq, task)
+    }
+
+    /// As `check_preempt_wakeup`, but leaving the runqueue untouched: the
+    /// running task is charged up to `now` on a snapshot only, and no
+    /// buddies are set
+    pub fn would_preempt_wakeup(&self, cpu: CpuId, task: TaskId, now: u64) -> bool {
+        if !sched_feat(SchedFeature::WakeupPreemption) {
+            return false;
+        }
+        let mut rq = self.rqs.get(cpu).lock().clone();
+        rq.update_curr(now);
+        rq.check_preempt_wakeup(task, self.min_granularity(), self.wakeup_granularity())
+    }
+
+    /// Set the latency nice value of a task (-20..=19)
+    ///
+    /// A higher value makes the task more eager to preempt the running
+    /// task when it wakes, a lower value makes it more willing to wait.
+    pub fn set_latency_nice(&self, task: &Task, latency_nice: i8) -> KernelResult<()> {
+        if !(-20..=19).contains(&latency_nice) {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        self.latency_nice.write().insert(task.id(), latency_nice);
+        self.rqs.get(task.current_cpu()).lock().set_latency_nice(task.id(), latency_nice);
+        Ok(())
+    }
+
+    /// Latency nice value of a task (0 unless set)
+    pub fn latency_nice(&self, task: TaskId) -> i8 {
+        self.latency_nice.read().get(&task).copied().unwrap_or(0)
+    }
+
+    /// Queued (not running) tasks of a CPU in pick order
+    pub fn queued_tasks(&self, cpu: CpuId) -> Vec<TaskId> {
+        self.rqs.get(cpu).lock().queued()
+    }
+
+    /// Fair task running on a CPU, if any
+    pub fn curr_task(&self, cpu: CpuId) -> Option<TaskId> {
+        self.rqs.get(cpu).lock().curr()
+    }
+
+    /// Queued tasks of a CPU with their vruntime, in pick order
+    pub fn timeline(&self, cpu: CpuId) -> Vec<(TaskId, u64)> {
+        self.rqs.get(cpu).lock().timeline()
+    }
+
+    /// `min_vruntime` of a CPU's runqueue
+    pub fn min_vruntime(&self, cpu: CpuId) -> u64 {
+        self.rqs.get(cpu).lock().min_vruntime()
+    }
+
+    /// Sum of the weights of runnable fair tasks on a CPU
+    pub fn load_weight(&self, cpu: CpuId) -> u64 {
+        self.rqs.get(cpu).lock().load_weight()
+    }
+
+    /// Number of runnable fair tasks on a CPU
+    pub fn nr_running(&self, cpu: CpuId) -> usize {
+        self.rqs.get(cpu).lock().nr_running()
+    }
+
+    /// Check whether a CPU runs nothing but `SCHED_IDLE` tasks
+    pub fn sched_idle_cpu(&self, cpu: CpuId) -> bool {
+        self.rqs.get(cpu).lock().only_idle_tasks()
+    }
+
+    /// vruntime of a task on a CPU
+    pub fn task_vruntime(&self, cpu: CpuId, task: TaskId) -> Option<u64> {
+        self.rqs.get(cpu).lock().entity(task).map(|se| se.vruntime)
+    }
+
+    /// Record a memory fault of a task on a NUMA node
+    pub fn record_numa_fault(&self, task: &Task, node: NodeId) {
+        let mut numa = self.numa.write();
+        let stats = numa.entry(task.id()).or_default();
+        *stats.window_faults.entry(node).or_insert(0) += 1;
+    }
+
+    /// Periodic NUMA placement evaluation for a running task
+    ///
+    /// Closes the task's fault window once per scan period. A node becomes
+    /// preferred only after it attracted the majority of faults for several
+    /// consecutive windows, so short phases don't cause migrations.
+    /// Returns the preferred node, if any.
+    pub fn numa_tick(&self, task: &Task) -> Option<NodeId> {
+        let now = Timestamp::now().as_nanos();
+        let mut numa = self.numa.write();
+        let stats = numa.get_mut(&task.id())?;
+
+        if now.saturating_sub(stats.last_scan) >= NUMA_SCAN_PERIOD_NS {
+            stats.last_scan = now;
+            let previous = stats.preferred_node;
+            stats.close_window();
+            if stats.preferred_node != previous {
+                kernel_debug!("Task {} preferred NUMA node -> {:?}",
+                             task.id().as_u64(), stats.preferred_node.map(|n| n.as_u32()));
+            }
+        }
+        stats.preferred_node
+    }
+
+    /// Preferred NUMA node of a task
+    pub fn preferred_node(&self, task: TaskId) -> Option<NodeId> {
+        self.numa.read().get(&task).and_then(|s| s.preferred_node)
+    }
+
+    /// NUMA fault statistics of a task
+    pub fn numa_fault_stats(&self, task: TaskId) -> Option<NumaFaultStats> {
+        self.numa.read().get(&task).cloned()
+    }
+
+    /// Forget an exiting task
+    pub fn remove_task(&self, task: &Task) {
+        self.rqs.get(task.current_cpu()).lock().remove(task.id());
+        self.numa.write().remove(&task.id());
+        self.latency_nice.write().remove(&task.id());
+        let group = self.task_groups.write().remove(&task.id());
+        if let Some(group) = group {
+            if let Some(info) = self.groups.write().get_mut(&group) {
+                info.nr_tasks -= 1;
+            }
+        }
+    }
+
+    /// Log fair scheduler state
+    pub fn print_fair_info(&self) -> KernelResult<()> {
+        kernel_info!("CFS mode: {:?}, timeslice: {} us, target latency: {} ns, min granularity: {} ns, wakeup granularity: {} ns",
+                    self.mode(), self.timeslice_us(), self.target_latency(), self.min_granularity(),
+                    self.wakeup_granularity());
+        for (gid, info) in self.groups.read().iter() {
+            kernel_info!("Group {}: parent {}, weight {}, {} tasks, {} children",
+                        gid, info.group.parent, info.group.weight, info.nr_tasks, info.nr_children);
+        }
+        for (gid, bw) in self.bandwidth.lock().iter() {
+            kernel_info!("Group {}: quota {} us / period {} us, {:?}",
+                        gid, bw.config.quota_us, bw.config.period_us, bw.stats);
+        }
+        Ok(())
+    }
+
+    /// Log the NUMA preference and fault statistics of every tracked task
+    pub fn print_numa_info(&self) {
+        for (task, stats) in self.numa.read().iter() {
+            kernel_info!("Task {}: preferred node {:?}, faults {:?}",
+                        task.as_u64(), stats.preferred_node.map(|n| n.as_u32()),
+                        stats.total_faults.iter().map(|(n, c)| (n.as_u32(), *c)).collect::<Vec<_>>());
+        }
+    }
+
+    /// Enqueue a task on the runqueue of its CPU
+    fn enqueue(&self, task: &Task, batch: bool, group: TaskGroup) -> KernelResult<()> {
+        self.enqueue_weighted(task, batch, None, group)
+    }
+
+    /// Enqueue a task on the runqueue of its CPU, as a `SCHED_IDLE` task
+    /// with `idle_weight` if given, otherwise weighted by its nice value
+    fn enqueue_weighted(&self, task: &Task, batch: bool, idle_weight: Option<u32>,
+                        group: TaskGroup) -> KernelResult<()> {
+        let nice = task.nice();
+        if !(-20..=19).contains(&nice) {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        let weight = idle_weight.unwrap_or_else(|| nice_to_weight(nice));
+        let start_debit = self.start_debit(weight);
+        let latency_nice = self.latency_nice(task.id());
+        let limited = self.bandwidth.lock().contains_key(&group.id);
+        let sleeper_credit = self.sleeper_credit();
+        let mut rq = self.rqs.get(task.current_cpu()).lock();
+        rq.set_sleeper_credit(sleeper_credit);
+        rq.enqueue(task.id(), weight, batch, start_debit, group);
+        rq.set_idle(task.id(), idle_weight.is_some());
+        rq.set_group_bandwidth(group.id, limited);
+        rq.set_latency_nice(task.id(), latency_nice);
+        Ok(())
+    }
+
+    /// Virtual runtime a new task of `weight` starts behind (`START_DEBIT`)
+    fn start_debit(&self, weight: u32) -> u64 {
+        if sched_feat(SchedFeature::StartDebit) {
+            calc_delta_fair(self.timeslice_us() * 1_000, weight)
+        } else {
+            0
+        }
+    }
+
+    /// Virtual runtime a waking task may be placed behind `min_vruntime`:
+    /// one target latency, halved with `GENTLE_FAIR_SLEEPERS`
+    fn sleeper_credit(&self) -> u64 {
+        if sched_feat(SchedFeature::GentleFairSleepers) {
+            self.target_latency() / 2
+        } else {
+            self.target_latency()
+        }
+    }
+
+    /// Tick preemption check on an up to date runqueue
+    fn tick_preempts(&self, rq: &CfsRq) -> bool {
+        let min_gran = self.min_granularity();
+        let slice = calc_sched_slice(self.target_latency(), min_gran, rq.nr_running());
+        rq.curr_throttled() || rq.check_preempt_tick(slice, min_gran)
+    }
+
+    /// Wakeup preemption check on a locked, up to date runqueue, setting
+    /// the buddies the features ask for
+    fn check_preempt_woken(&self, rq: &mut CfsRq, task: TaskId) -> bool {
+        if sched_feat(SchedFeature::NextBuddy) {
+            rq.set_next_buddy(task);
+        }
+        let preempt = rq.check_preempt_wakeup(task, self.min_granularity(), self.wakeup_granularity());
+        if preempt && sched_feat(SchedFeature::LastBuddy) {
+            if let Some(curr) = rq.curr() {
+                rq.set_last_buddy(curr);
+            }
+        }
+        preempt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kernel::scheduler::wait::WakeBatch;
+
+    #[test]
+    fn test_latency_nice_lets_sensitive_task_preempt_batch_task() {
+        let mut rq = CfsRq::new();
+        let (batch, sensitive) = (TaskId::new(1), TaskId::new(2));
+        rq.enqueue(batch, NICE_0_LOAD, true, 0, TaskGroup::ROOT);
+        rq.enqueue(sensitive, NICE_0_LOAD, false, 0, TaskGroup::ROOT);
+        rq.set_curr(batch, 0);
+
+        // The batch task leads by 0.5ms of vruntime: within the 1ms granularity
+        rq.update_curr(500_000);
+        assert!(!rq.wakeup_preempt(sensitive, DEFAULT_WAKEUP_GRANULARITY_NS));
+
+        rq.set_latency_nice(sensitive, 19);
+        assert!(rq.wakeup_preempt(sensitive, DEFAULT_WAKEUP_GRANULARITY_NS));
+
+        rq.set_latency_nice(sensitive, -20);
+        assert!(!rq.wakeup_preempt(sensitive, DEFAULT_WAKEUP_GRANULARITY_NS));
+    }
+
+    #[test]
+    fn test_task_blocking_while_throttled_is_not_counted_on_unthrottle() {
+        let mut rq = CfsRq::new();
+        let group = TaskGroup { id: 7, parent: ROOT_TASK_GROUP, weight: NICE_0_LOAD };
+        for id in 1..=3 {
+            rq.enqueue(TaskId::new(id), NICE_0_LOAD, false, 0, group);
+        }
+        rq.enqueue(TaskId::new(4), NICE_0_LOAD, false, 0, TaskGroup::ROOT);
+        rq.set_group_bandwidth(group.id, true);
+        rq.set_curr(TaskId::new(1), 0);
+
+        // Exhaust the runtime: the group is throttled and can't be picked
+        rq.update_curr(1_000);
+        assert!(rq.throttle_group(group.id, 1_000));
+        assert!(rq.curr_throttled());
+        rq.put_prev(TaskId::new(1), 1_000);
+        assert_eq!(rq.nr_running(), 1);
+        assert_eq!(rq.leftmost(), Some(TaskId::new(4)));
+
+        // A task of the throttled group blocks
+        rq.dequeue(TaskId::new(2));
+        assert_eq!(rq.nr_running(), 1);
+
+        rq.add_group_runtime(group.id, 5_000_000);
+        assert_eq!(rq.unthrottle_group(group.id, 11_000), 10_000);
+        assert_eq!(rq.nr_running(), 3);
+        assert!(!rq.queued().contains(&TaskId::new(2)));
+    }
+
+    #[test]
+    fn test_nested_groups_share_cpu_hierarchically() {
+        let mut rq = CfsRq::new();
+        let a = TaskGroup { id: 10, parent: ROOT_TASK_GROUP, weight: NICE_0_LOAD };
+        let a1 = TaskGroup { id: 11, parent: a.id, weight: NICE_0_LOAD };
+        let a2 = TaskGroup { id: 12, parent: a.id, weight: NICE_0_LOAD };
+        let b = TaskGroup { id: 20, parent: ROOT_TASK_GROUP, weight: NICE_0_LOAD };
+        for group in [a, a1, a2, b] {
+            rq.add_group(group);
+        }
+        for (id, group) in [(1, a1), (2, a2), (3, b)] {
+            rq.enqueue(TaskId::new(id), NICE_0_LOAD, false, 0, group);
+        }
+
+        let mut ran = BTreeMap::new();
+        let mut now = 0;
+        for _ in 0..1000 {
+            let next = rq.leftmost().unwrap();
+            rq.set_curr(next, now);
+            now += 1_000_000;
+            rq.put_prev(next, now);
+            *ran.entry(next.as_u64()).or_insert(0u64) += 1;
+        }
+        assert!(ran[&1].abs_diff(250) <= 10, "a1 got {} of 1000", ran[&1]);
+        assert!(ran[&2].abs_diff(250) <= 10, "a2 got {} of 1000", ran[&2]);
+        assert!(ran[&3].abs_diff(500) <= 10, "b got {} of 1000", ran[&3]);
+
+        // A group with runnable tasks or child groups can't be removed
+        assert!(!rq.remove_group(a.id));
+        assert!(!rq.remove_group(a1.id));
+        rq.dequeue(TaskId::new(1));
+        assert!(rq.remove_group(a1.id));
+        assert_eq!(rq.nr_running(), 2);
+    }
+
+    /// Simulate 1s of tasks running 5ms bursts between 1ms sleeps on one
+    /// runqueue; returns the number of context switches
+    fn context_switches(min_gran: u64) -> u64 {
+        const STEP_NS: u64 = 100_000;
+        const SLICE_NS: u64 = 1_000_000;
+        const BURST_NS: u64 = 5_000_000;
+        const SLEEP_NS: u64 = 1_000_000;
+        let tasks: Vec<TaskId> = (1..=8).map(TaskId::new).collect();
+        let mut burst_left: BTreeMap<TaskId, u64> = tasks.iter().map(|&t| (t, BURST_NS)).collect();
+        let mut sleep_until: BTreeMap<TaskId, u64> = BTreeMap::new();
+        let mut rq = CfsRq::new();
+        for &task in &tasks {
+            rq.enqueue(task, NICE_0_LOAD, false, 0, TaskGroup::ROOT);
+        }
+
+        let mut switches = 0;
+        for step in 0..10_000 {
+            let now = step * STEP_NS;
+            let mut resched = rq.curr().is_none() || rq.check_preempt_tick(SLICE_NS, min_gran);
+            let woken: Vec<TaskId> = sleep_until.iter()
+                .filter(|(_, &until)| now >= until)
+                .map(|(&task, _)| task)
+                .collect();
+            for task in woken {
+                sleep_until.remove(&task);
+                burst_left.insert(task, BURST_NS);
+                rq.enqueue(task, NICE_0_LOAD, false, 0, TaskGroup::ROOT);
+                resched |= rq.check_preempt_wakeup(task, min_gran, DEFAULT_WAKEUP_GRANULARITY_NS);
+            }
+            if resched {
+                let prev = rq.curr();
+                if let Some(prev) = prev {
+                    rq.put_prev(prev, now);
+                }
+                if let Some(next) = rq.leftmost() {
+                    rq.set_curr(next, now);
+                    switches += (Some(next) != prev) as u64;
+                }
+            }
+
+            if let Some(curr) = rq.curr() {
+                rq.update_curr(now + STEP_NS);
+                let left = burst_left.get_mut(&curr).unwrap();
+                *left -= STEP_NS;
+                if *left == 0 {
+                    rq.dequeue(curr);
+                    sleep_until.insert(curr, now + STEP_NS + SLEEP_NS);
+                }
+            }
+        }
+        switches
+    }
+
+    #[test]
+    fn test_raising_min_granularity_lowers_context_switch_rate() {
+        let default = context_switches(DEFAULT_MIN_GRANULARITY_NS);
+        let raised = context_switches(4_000_000);
+        assert!(raised * 2 < default, "{} switches at 4ms vs {} at default", raised, default);
+    }
+
+    /// Ping-pong a message between two tasks sharing a runqueue with two
+    /// CPU hogs; returns how often the woken partner did not run next
+    /// (and on SMP would be pulled away to another CPU instead)
+    fn pingpong_misses(buddies: bool) -> u64 {
+        const SLICE_NS: u64 = 1_000_000;
+        const WORK_NS: u64 = 100_000;
+        let (ping, pong) = (TaskId::new(3), TaskId::new(4));
+        let mut rq = CfsRq::new();
+        for id in [1, 2, 3] {
+            rq.enqueue(TaskId::new(id), NICE_0_LOAD, false, 0, TaskGroup::ROOT);
+        }
+        let pick = |rq: &CfsRq| if buddies {
+            rq.pick_next(DEFAULT_WAKEUP_GRANULARITY_NS)
+        } else {
+            rq.leftmost()
+        };
+
+        let mut now = 0;
+        let mut misses = 0;
+        let mut runner = ping;
+        for _ in 0..1000 {
+            // Hogs run a full slice each until the message holder is picked
+            loop {
+                let next = pick(&rq).unwrap();
+                rq.set_curr(next, now);
+                if next == runner {
+                    break;
+                }
+                now += SLICE_NS;
+                rq.put_prev(next, now);
+            }
+            // Handle the message, wake the partner and block
+            now += WORK_NS;
+            rq.update_curr(now);
+            let partner = if runner == ping { pong } else { ping };
+            rq.enqueue(partner, NICE_0_LOAD, false, 0, TaskGroup::ROOT);
+            if buddies {
+                rq.set_next_buddy(partner);
+            }
+            rq.dequeue(runner);
+            misses += (pick(&rq) != Some(partner)) as u64;
+            runner = partner;
+        }
+        misses
+    }
+
+    /// Run two tasks in 10us bursts after the second one got 3ms ahead;
+    /// returns the first six picks
+    fn yield_picks(first_yields: bool) -> Vec<TaskId> {
+        let (a, b) = (TaskId::new(1), TaskId::new(2));
+        let mut rq = CfsRq::new();
+        rq.enqueue(a, NICE_0_LOAD, false, 0, TaskGroup::ROOT);
+        rq.enqueue(b, NICE_0_LOAD, false, 0, TaskGroup::ROOT);
+        rq.set_curr(b, 0);
+        rq.put_prev(b, 3_000_000);
+
+        let mut now = 3_000_000;
+        let mut picks = Vec::new();
+        for _ in 0..6 {
+            let next = rq.pick_next(DEFAULT_WAKEUP_GRANULARITY_NS).unwrap();
+            rq.set_curr(next, now);
+            now += 10_000;
+            if next == a && first_yields {
+                rq.yield_curr(now);
+            }
+            rq.put_prev(next, now);
+            picks.push(next);
+        }
+        picks
+    }
+
+    #[test]
+    fn test_yielding_tasks_alternate() {
+        let (a, b) = (TaskId::new(1), TaskId::new(2));
+        assert_eq!(yield_picks(false), [a; 6]);
+        assert_eq!(yield_picks(true), [a, b, a, b, a, b]);
+    }
+
+    #[test]
+    fn test_yield_alone_keeps_vruntime_and_penalty_is_capped() {
+        let (a, b) = (TaskId::new(1), TaskId::new(2));
+        let mut rq = CfsRq::new();
+        rq.enqueue(a, NICE_0_LOAD, false, 0, TaskGroup::ROOT);
+        rq.set_curr(a, 0);
+        assert!(!rq.yield_curr(1_000_000));
+        assert_eq!(rq.entities[&a].vruntime, 1_000_000);
+        rq.put_prev(a, 1_000_000);
+        assert_eq!(rq.pick_next(DEFAULT_WAKEUP_GRANULARITY_NS), Some(a));
+
+        // A peer far to the right costs at most one slice
+        rq.enqueue(b, NICE_0_LOAD, false, 0, TaskGroup::ROOT);
+        rq.set_curr(b, 1_000_000);
+        rq.put_prev(b, 101_000_000);
+        rq.set_curr(a, 101_000_000);
+        assert!(rq.yield_curr(101_000_000));
+        assert_eq!(rq.entities[&a].vruntime, 1_000_000 + DEFAULT_BASE_SLICE_NS);
+    }
+
+    #[test]
+    fn test_migrated_task_keeps_its_share_across_wraparound() {
+        let (migrated, x, y) = (TaskId::new(1), TaskId::new(2), TaskId::new(3));
+
+        // The source runqueue's vruntimes wrap around while the task runs
+        let mut src = CfsRq::new();
+        src.groups.get_mut(&ROOT_TASK_GROUP).unwrap().min_vruntime = u64::MAX - 500_000;
+        src.enqueue(migrated, NICE_0_LOAD, false, 0, TaskGroup::ROOT);
+        src.set_curr(migrated, 0);
+        src.put_prev(migrated, 2_000_000);
+        assert_eq!(src.min_vruntime(), 1_499_999);
+
+        // The destination's tasks have run for 10ms each
+        let mut dst = CfsRq::new();
+        for task in [x, y] {
+            dst.enqueue(task, NICE_0_LOAD, false, 0, TaskGroup::ROOT);
+        }
+        for task in [x, y] {
+            dst.set_curr(task, 0);
+            dst.put_prev(task, 10_000_000);
+        }
+        let se = src.detach(migrated).unwrap();
+        dst.attach(migrated, se);
+
+        // Round-robin in 1ms bursts: a third of the picks each
+        let mut now = 10_000_000;
+        let mut picks = Vec::new();
+        for _ in 0..12 {
+            let next = dst.pick_next(DEFAULT_WAKEUP_GRANULARITY_NS).unwrap();
+            dst.set_curr(next, now);
+            now += 1_000_000;
+            dst.put_prev(next, now);
+            picks.push(next);
+        }
+        let runs = picks.iter().filter(|&&task| task == migrated).count();
+        assert!((3..=5).contains(&runs), "picks: {:?}", picks);
+        assert!(picks[..3].contains(&migrated));
+    }
+
+    #[test]
+    fn test_long_sleeper_gets_bounded_credit() {
+        let (sleeper, hog) = (TaskId::new(1), TaskId::new(2));
+        let mut rq = CfsRq::new();
+        rq.set_sleeper_credit(SCHED_LATENCY_NS / 2);
+        rq.enqueue(sleeper, NICE_0_LOAD, false, 0, TaskGroup::ROOT);
+        rq.enqueue(hog, NICE_0_LOAD, false, 0, TaskGroup::ROOT);
+        rq.dequeue(sleeper);
+
+        // The hog runs alone for 5s, then the sleeper wakes and preempts it
+        rq.set_curr(hog, 0);
+        rq.update_curr(5_000_000_000);
+        rq.enqueue(sleeper, NICE_0_LOAD, false, 0, TaskGroup::ROOT);
+        assert!(rq.wakeup_preempt(sleeper, DEFAULT_WAKEUP_GRANULARITY_NS));
+        rq.put_prev(hog, 5_000_000_000);
+
+        // The sleeper runs in 0.5ms bursts until the hog is due again
+        let mut now = 5_000_000_000;
+        let mut ran = 0;
+        while rq.pick_next(DEFAULT_WAKEUP_GRANULARITY_NS) == Some(sleeper) {
+            rq.set_curr(sleeper, now);
+            now += 500_000;
+            ran += 500_000;
+            rq.put_prev(sleeper, now);
+            assert!(ran <= SCHED_LATENCY_NS / 2 + 500_000, "sleeper ran {}ns", ran);
+        }
+        assert!(ran >= SCHED_LATENCY_NS / 2);
+    }
+
+    #[test]
+    fn test_light_pick_stays_within_granularity() {
+        let mut rq = CfsRq::new();
+        let (heavy, light, lighter, leftmost) = (TaskId::new(1), TaskId::new(2), TaskId::new(3), TaskId::new(4));
+        for task in [heavy, light, lighter, leftmost] {
+            rq.enqueue(task, NICE_0_LOAD, false, 0, TaskGroup::ROOT);
+        }
+        // Vruntimes: leftmost 0, light and heavy within the granularity,
+        // lighter 5ms behind
+        for (task, ran) in [(heavy, 300_000), (light, 600_000), (lighter, 5_000_000)] {
+            rq.set_curr(task, 0);
+            rq.put_prev(task, ran);
+        }
+        let util = |task: TaskId| match task.as_u64() {
+            1 => 900,
+            2 => 100,
+            3 => 10,
+            _ => 500,
+        };
+        assert_eq!(rq.pick_next(DEFAULT_WAKEUP_GRANULARITY_NS), Some(leftmost));
+        assert_eq!(rq.pick_light(DEFAULT_WAKEUP_GRANULARITY_NS, util), Some(light));
+        // Equal utilization keeps the fair pick
+        assert_eq!(rq.pick_light(DEFAULT_WAKEUP_GRANULARITY_NS, |_| 100), Some(leftmost));
+    }
+
+    #[test]
+    fn test_next_buddy_keeps_pingpong_pair_together() {
+        let without = pingpong_misses(false);
+        let with = pingpong_misses(true);
+        assert!(with * 3 < without * 2, "{} misses with buddies vs {} without", with, without);
+    }
+
+    /// Simulate 1s of four CPU hogs sharing a runqueue with four
+    /// latency-sensitive tasks waking every 4ms for 0.3ms bursts; returns
+    /// the 99th percentile of the time from wakeup until first run
+    fn wakeup_latency_p99(mode: FairMode) -> u64 {
+        const STEP_NS: u64 = 50_000;
+        const SLICE_NS: u64 = 3_000_000;
+        const BURST_NS: u64 = 300_000;
+        const PERIOD_NS: u64 = 4_000_000;
+        let mut rq = CfsRq::with_mode(mode, SLICE_NS);
+        for id in 1..=4 {
+            rq.enqueue(TaskId::new(id), NICE_0_LOAD, false, 0, TaskGroup::ROOT);
+        }
+        let mut wake_at: BTreeMap<TaskId, u64> = (5..=8)
+            .map(|id| (TaskId::new(id), (id - 5) * PERIOD_NS / 4))
+            .collect();
+        let mut woken_at = BTreeMap::new();
+        let mut burst_left = BTreeMap::new();
+        let mut latencies = Vec::new();
+
+        for step in 0..20_000 {
+            let now = step * STEP_NS;
+            let mut resched = rq.curr().is_none() || rq.check_preempt_tick(SLICE_NS, DEFAULT_MIN_GRANULARITY_NS);
+            let woken: Vec<TaskId> = wake_at.iter()
+                .filter(|(_, &at)| now >= at)
+                .map(|(&task, _)| task)
+                .collect();
+            for task in woken {
+                wake_at.remove(&task);
+                woken_at.insert(task, now);
+                burst_left.insert(task, BURST_NS);
+                rq.enqueue(task, NICE_0_LOAD, false, 0, TaskGroup::ROOT);
+                rq.set_latency_nice(task, 19);
+                resched |= rq.check_preempt_wakeup(task, DEFAULT_MIN_GRANULARITY_NS, DEFAULT_WAKEUP_GRANULARITY_NS);
+            }
+            if resched {
+                if let Some(prev) = rq.curr() {
+                    rq.put_prev(prev, now);
+                }
+                if let Some(next) = rq.pick_next(DEFAULT_WAKEUP_GRANULARITY_NS) {
+                    rq.set_curr(next, now);
+                    if let Some(at) = woken_at.remove(&next) {
+                        latencies.push(now - at);
+                    }
+                }
+            }
+
+            if let Some(curr) = rq.curr() {
+                rq.update_curr(now + STEP_NS);
+                if let Some(left) = burst_left.get_mut(&curr) {
+                    *left -= STEP_NS;
+                    if *left == 0 {
+                        burst_left.remove(&curr);
+                        rq.dequeue(curr);
+                        wake_at.insert(curr, now + STEP_NS + PERIOD_NS - BURST_NS);
+                    }
+                }
+            }
+        }
+        latencies.sort_unstable();
+        latencies[latencies.len() * 99 / 100]
+    }
+
+    #[test]
+    fn test_eevdf_lowers_tail_wakeup_latency_of_short_requests() {
+        let cfs = wakeup_latency_p99(FairMode::Cfs);
+        let eevdf = wakeup_latency_p99(FairMode::Eevdf);
+        assert!(eevdf * 2 < cfs, "p99 wakeup latency {} ns with EEVDF vs {} ns with CFS", eevdf, cfs);
+    }
+
+    #[test]
+    fn test_slices_split_target_latency_until_min_granularity() {
+        const STEP_NS: u64 = 10_000;
+        let (target, min_gran) = (SCHED_LATENCY_NS, DEFAULT_MIN_GRANULARITY_NS);
+        let (a, b) = (TaskId::new(1), TaskId::new(2));
+        let mut rq = CfsRq::new();
+        rq.enqueue(a, NICE_0_LOAD, false, 0, TaskGroup::ROOT);
+        rq.enqueue(b, NICE_0_LOAD, false, 0, TaskGroup::ROOT);
+        rq.set_curr(a, 0);
+
+        // With two tasks the running one is preempted after half the target
+        let slice = calc_sched_slice(target, min_gran, rq.nr_running());
+        assert_eq!(slice, target / 2);
+        let mut now = 0;
+        while !rq.check_preempt_tick(slice, min_gran) {
+            now += STEP_NS;
+            rq.update_curr(now);
+        }
+        assert_eq!(now, target / 2);
+        assert_eq!(calc_sched_period(target, min_gran, 2), target);
+
+        // With 100 tasks slices stay at min_granularity and the period grows
+        assert_eq!(calc_sched_slice(target, min_gran, 100), min_gran);
+        assert_eq!(calc_sched_period(target, min_gran, 100), min_gran * 100);
+    }
+
+    #[test]
+    fn test_sched_idle_task_runs_only_while_normal_task_sleeps() {
+        const TICK_NS: u64 = 1_000_000;
+        let (gran, min_gran) = (DEFAULT_WAKEUP_GRANULARITY_NS, DEFAULT_MIN_GRANULARITY_NS);
+        let (hog, idle) = (TaskId::new(1), TaskId::new(2));
+        let mut rq = CfsRq::new();
+        rq.enqueue(idle, WEIGHT_IDLEPRIO, false, 0, TaskGroup::ROOT);
+        rq.set_idle(idle, true);
+        rq.enqueue(hog, NICE_0_LOAD, false, 0, TaskGroup::ROOT);
+        assert!(!rq.only_idle_tasks());
+
+        // A CPU-bound normal task starves the idle task, whose vruntime
+        // stays far behind
+        let mut now = 0;
+        let mut curr = rq.pick_next(gran).unwrap();
+        rq.set_curr(curr, now);
+        for _ in 0..1_000 {
+            now += TICK_NS;
+            rq.update_curr(now);
+            assert_eq!(curr, hog);
+            let slice = calc_sched_slice(SCHED_LATENCY_NS, min_gran, rq.nr_running());
+            if rq.check_preempt_tick(slice, min_gran) {
+                rq.put_prev(curr, now);
+                curr = rq.pick_next(gran).unwrap();
+                rq.set_curr(curr, now);
+            }
+        }
+        assert_eq!(rq.entity(idle).unwrap().sum_exec_runtime, 0);
+
+        // Once the normal task sleeps the idle task runs
+        rq.dequeue(hog);
+        assert!(rq.only_idle_tasks());
+        assert_eq!(rq.pick_next(gran), Some(idle));
+        rq.set_curr(idle, now);
+        now += TICK_NS / 10;
+        rq.update_curr(now);
+        assert!(rq.entity(idle).unwrap().sum_exec_runtime > 0);
+
+        // and the normal task takes the CPU back the moment it wakes
+        rq.enqueue(hog, NICE_0_LOAD, false, 0, TaskGroup::ROOT);
+        assert!(rq.check_preempt_wakeup(hog, min_gran, gran));
+        assert!(!rq.wakeup_preempt(idle, gran));
+    }
+
+    #[test]
+    fn test_nr_running_drops_when_the_running_task_sleeps() {
+        let mut rq = CfsRq::new();
+        let (sleeper, other) = (TaskId::new(1), TaskId::new(2));
+        rq.enqueue(other, NICE_0_LOAD, false, 0, TaskGroup::ROOT);
+        for round in 0..3u64 {
+            let now = round * 4_000_000;
+            rq.enqueue(sleeper, NICE_0_LOAD, false, 0, TaskGroup::ROOT);
+            assert_eq!(rq.nr_running(), 2);
+            rq.set_curr(sleeper, now);
+            assert_eq!(rq.nr_running(), 2);
+
+            // Switched out asleep: requeued with its runtime charged, then dequeued
+            rq.put_prev(sleeper, now + 1_000_000);
+            rq.dequeue(sleeper);
+            assert_eq!(rq.nr_running(), 1);
+            assert_eq!(rq.queued(), [other]);
+            assert_eq!(rq.load_weight(), NICE_0_LOAD as u64);
+        }
+        assert_eq!(rq.entity(sleeper).unwrap().sum_exec_runtime, 3_000_000);
+    }
+
+    #[test]
+    fn test_woken_batch_is_enqueued_with_one_enqueue_per_cpu() {
+        const CPUS: u64 = 4;
+        const WAITERS: u64 = 64;
+        let fair = FairScheduler::with_timeslice(4_000);
+        let cpu_of = |id: u64| CpuId::new((id % CPUS) as u32);
+
+        let mut batch = WakeBatch::new();
+        for id in 0..WAITERS {
+            batch.add(cpu_of(id), (TaskId::new(id), NICE_0_LOAD, TaskGroup::ROOT, id % 2 == 1));
+        }
+        let mut enqueues = Vec::new();
+        let woken = batch.flush(|cpu, entries| {
+            enqueues.push(cpu);
+            (entries.len(), fair.enqueue_entries(cpu, entries, 0))
+        }, |_| {});
+
+        assert_eq!(woken, WAITERS as usize);
+        assert_eq!(enqueues, (0..CPUS).map(cpu_of).collect::<Vec<_>>());
+        for cpu in 0..CPUS {
+            let mut queued = fair.queued_tasks(cpu_of(cpu));
+            queued.sort();
+            let expected: Vec<TaskId> = (0..WAITERS).filter(|&id| id % CPUS == cpu).map(TaskId::new).collect();
+            assert_eq!(queued, expected);
+            assert_eq!(fair.nr_running(cpu_of(cpu)), (WAITERS / CPUS) as usize);
+        }
+    }
+
+    #[test]
+    fn test_speculative_preempt_checks_leave_the_runqueue_untouched() {
+        let fair = FairScheduler::with_timeslice(4_000);
+        let cpu = CpuId::new(0);
+        let (curr, woken) = (TaskId::new(1), TaskId::new(2));
+        {
+            let mut rq = fair.rqs.get(cpu).lock();
+            rq.enqueue(curr, NICE_0_LOAD, false, 0, TaskGroup::ROOT);
+            rq.enqueue(woken, NICE_0_LOAD, false, 0, TaskGroup::ROOT);
+            rq.set_curr(curr, 0);
+        }
+        let state = || {
+            let rq = fair.rqs.get(cpu).lock();
+            (rq.curr_runtime(), rq.buddies(), rq.min_vruntime(), rq.timeline())
+        };
+        let before = state();
+
+        // 50ms into its slice the running task is due on both counts
+        let now = 50_000_000;
+        assert!(fair.would_preempt_tick(cpu, now));
+        assert!(fair.would_preempt_wakeup(cpu, woken, now));
+        assert_eq!(state(), before);
+
+        // while the real checks charge it and set the buddies
+        assert!(fair.check_preempt_tick(cpu, now));
+        assert!(fair.check_preempt_wakeup(cpu, woken, now));
+        let after = state();
+        assert_eq!(after.0, Some(now));
+        assert_eq!(after.1.0, Some(woken));
+    }
+}
@@ -0,0 +1,186 @@
+//! # Scheduler Features
+//!
+//! This module holds the runtime switches for individual scheduling
+//! heuristics, the equivalent of `/sys/kernel/debug/sched/features`. The
+//! switches are global so that hot paths can test them with `sched_feat()`
+//! without holding any scheduler reference.
+//!
+//! ## Features
+//! - Named, individually toggleable heuristics
+//! - Lock-free `sched_feat()` check for hot paths
+//! - Listing of all features and their state
+//! - Reset to compiled-in defaults
+//!
+//! ## Usage
+//! ```rust
+//! use crate::kernel::scheduler::features::{sched_feat, FeaturesScheduler, SchedFeature};
+//!
+//! features.set_feature("START_DEBIT", false)?;
+//!
+//! if sched_feat(SchedFeature::WakeupPreemption) {
+//!     // ...
+//! }
+//! ```
+
+use crate::kernel::error::{KernelResult, SchedulerError};
+use crate::kernel::log::kernel_info;
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// A toggleable scheduling heuristic
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum SchedFeature {
+    /// A waking task may preempt the running task
+    WakeupPreemption = 0,
+    /// New tasks start one slice behind `min_vruntime`
+    StartDebit = 1,
+    /// Prefer the task that was just woken as the next pick
+    NextBuddy = 2,
+    /// Prefer the task that was just preempted as the next pick
+    LastBuddy = 3,
+    /// Don't use the last buddy if it is cache-hot on another CPU
+    CacheHotBuddy = 4,
+    /// A yield reschedules at once instead of only requeueing the task
+    YieldReschedule = 5,
+    /// Waking tasks keep at most half a latency period of sleeper credit
+    /// instead of a full one
+    GentleFairSleepers = 6,
+}
+
+impl SchedFeature {
+    /// Every feature, in bit order
+    pub const ALL: [SchedFeature; 7] = [
+        SchedFeature::WakeupPreemption,
+        SchedFeature::StartDebit,
+        SchedFeature::NextBuddy,
+        SchedFeature::LastBuddy,
+        SchedFeature::CacheHotBuddy,
+        SchedFeature::YieldReschedule,
+        SchedFeature::GentleFairSleepers,
+    ];
+
+    /// Feature name as shown to users
+    pub fn name(&self) -> &'static str {
+        match self {
+            SchedFeature::WakeupPreemption => "WAKEUP_PREEMPTION",
+            SchedFeature::StartDebit => "START_DEBIT",
+            SchedFeature::NextBuddy => "NEXT_BUDDY",
+            SchedFeature::LastBuddy => "LAST_BUDDY",
+            SchedFeature::CacheHotBuddy => "CACHE_HOT_BUDDY",
+            SchedFeature::YieldReschedule => "YIELD_RESCHEDULE",
+            SchedFeature::GentleFairSleepers => "GENTLE_FAIR_SLEEPERS",
+        }
+    }
+
+    /// Look up a feature by name
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|f| f.name() == name)
+    }
+
+    /// Whether the feature is enabled by default
+    pub const fn default_enabled(&self) -> bool {
+        !matches!(self, SchedFeature::NextBuddy)
+    }
+
+    const fn bit(&self) -> u32 {
+        1 << (*self as u32)
+    }
+}
+
+/// Compiled-in feature defaults
+const DEFAULT_FEATURES: u32 = {
+    let mut mask = 0;
+    let mut i = 0;
+    while i < SchedFeature::ALL.len() {
+        if SchedFeature::ALL[i].default_enabled() {
+            mask |= SchedFeature::ALL[i].bit();
+        }
+        i += 1;
+    }
+    mask
+};
+
+/// Currently enabled features
+static SCHED_FEATURES: AtomicU32 = AtomicU32::new(DEFAULT_FEATURES);
+
+/// Check whether a feature is enabled
+#[inline]
+pub fn sched_feat(feature: SchedFeature) -> bool {
+    SCHED_FEATURES.load(Ordering::Relaxed) & feature.bit() != 0
+}
+
+/// Features scheduler component
+pub struct FeaturesScheduler;
+
+impl FeaturesScheduler {
+    /// Create the features component
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Enable or disable a feature by name
+    ///
+    /// # Returns
+    /// - `Ok(())` if the feature exists
+    /// - `Err(SchedulerError::InvalidParameter)` for an unknown name
+    pub fn set_feature(&self, name: &str, enabled: bool) -> KernelResult<()> {
+        let feature = SchedFeature::from_name(name).ok_or(SchedulerError::InvalidParameter)?;
+        if enabled {
+            SCHED_FEATURES.fetch_or(feature.bit(), Ordering::Relaxed);
+        } else {
+            SCHED_FEATURES.fetch_and(!feature.bit(), Ordering::Relaxed);
+        }
+        kernel_info!("Sched feature {} {}", name, if enabled { "enabled" } else { "disabled" });
+        Ok(())
+    }
+
+    /// Check whether a feature is enabled by name (`false` if unknown)
+    pub fn is_enabled(&self, name: &str) -> bool {
+        SchedFeature::from_name(name).map_or(false, sched_feat)
+    }
+
+    /// All features with their current state
+    pub fn list_features(&self) -> Vec<(&'static str, bool)> {
+        SchedFeature::ALL.iter().map(|f| (f.name(), sched_feat(*f))).collect()
+    }
+
+    /// Restore the compiled-in defaults
+    pub fn reset_defaults(&self) {
+        SCHED_FEATURES.store(DEFAULT_FEATURES, Ordering::Relaxed);
+    }
+
+    /// Log every feature, `NO_`-prefixed when disabled
+    pub fn print_features_info(&self) {
+        for (name, enabled) in self.list_features() {
+            kernel_info!("{}{}", if enabled { "" } else { "NO_" }, name);
+        }
+    }
+}
+
+impl Default for FeaturesScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_toggle_and_list_features() {
+        let features = FeaturesScheduler::new();
+        assert!(features.set_feature("NO_SUCH_FEATURE", true).is_err());
+        assert!(!features.is_enabled("NO_SUCH_FEATURE"));
+
+        features.set_feature("NEXT_BUDDY", true).unwrap();
+        assert!(sched_feat(SchedFeature::NextBuddy));
+        assert!(features.list_features().contains(&("NEXT_BUDDY", true)));
+
+        features.set_feature("NEXT_BUDDY", false).unwrap();
+        assert!(!features.is_enabled("NEXT_BUDDY"));
+        assert_eq!(features.list_features().len(), SchedFeature::ALL.len());
+    }
+}
@@ -0,0 +1,313 @@
+//! # CPU Isolation
+//!
+//! This module keeps general-purpose work off selected CPUs, the runtime
+//! equivalent of the `isolcpus=` boot parameter. An isolated CPU only runs
+//! tasks that were explicitly affined to isolated CPUs; everything else is
+//! placed on, balanced between, and migrated to the remaining housekeeping
+//! CPUs.
+//!
+//! CPUs can additionally run in adaptive-tick (`nohz_full`) mode: their
+//! scheduler tick stops while a single task runs. RT bandwidth throttling
+//! is not enforced on such CPUs, since there is no tick to notice that an
+//! RT task exceeded its runtime.
+//!
+//! Groups of tasks can also be confined to a cpuset: a set of CPUs and
+//! NUMA memory nodes, as with the cgroup `cpuset` controller. A child
+//! cpuset may only narrow its parent's sets. A task's affinity always stays
+//! within the CPUs of its cpuset.
+//!
+//! ## Features
+//! - Runtime isolation and de-isolation of CPUs
+//! - Isolated and housekeeping CPU masks
+//! - Placement check used by wakeup, scheduling and load balancing
+//! - Per-CPU `nohz_full` (adaptive tick) mode
+//! - Nested cpusets confining tasks to CPUs and memory nodes
+//!
+//! ## Usage
+//! ```rust
+//! use crate::kernel::scheduler::isolation::IsolationScheduler;
+//!
+//! let isolation = IsolationScheduler::new();
+//! isolation.isolate_cpu(CpuId::new(3))?;
+//!
+//! assert!(isolation.is_isolated(CpuId::new(3)));
+//!
+//! let container = isolation.create_cpuset(cpus, nodes);
+//! let affinity = isolation.attach_task(container, task.id(), &task.cpu_affinity())?;
+//! ```
+
+use crate::kernel::scheduler::topology::NodeMask;
+use crate::kernel::task::TaskId;
+use crate::kernel::cpu::{CpuId, CpuMask};
+use crate::kernel::error::{KernelResult, SchedulerError};
+use crate::kernel::sync::RwLock;
+use crate::kernel::log::{kernel_info, kernel_debug};
+
+use alloc::collections::BTreeMap;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Cpuset identifier
+pub type CpusetId = u64;
+
+/// CPUs and memory nodes a group of tasks is confined to
+#[derive(Debug, Clone)]
+struct Cpuset {
+    parent: Option<CpusetId>,
+    cpus: CpuMask,
+    nodes: NodeMask,
+}
+
+/// Isolation scheduler component
+pub struct IsolationScheduler {
+    isolated: RwLock<CpuMask>,
+    nohz_full: RwLock<CpuMask>,
+    cpusets: RwLock<BTreeMap<CpusetId, Cpuset>>,
+    /// Cpuset of every confined task
+    task_cpusets: RwLock<BTreeMap<TaskId, CpusetId>>,
+    next_cpuset: AtomicU64,
+}
+
+impl IsolationScheduler {
+    /// Create an isolation scheduler with no isolated CPUs
+    pub fn new() -> Self {
+        Self {
+            isolated: RwLock::new(CpuMask::empty()),
+            nohz_full: RwLock::new(CpuMask::empty()),
+            cpusets: RwLock::new(BTreeMap::new()),
+            task_cpusets: RwLock::new(BTreeMap::new()),
+            next_cpuset: AtomicU64::new(1),
+        }
+    }
+
+    /// Isolate a CPU from general scheduling
+    ///
+    /// Fails if the CPU is offline or if it is the last housekeeping CPU.
+    /// Moving the CPU's current tasks away is up to the caller.
+    pub fn isolate_cpu(&self, cpu: CpuId) -> KernelResult<()> {
+        if !CpuMask::online().contains(cpu) {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        let mut isolated = self.isolated.write();
+        if isolated.contains(cpu) {
+            return Ok(());
+        }
+        let housekeeping = CpuMask::online().iter()
+            .filter(|&c| c != cpu && !isolated.contains(c))
+            .count();
+        if housekeeping == 0 {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        isolated.set(cpu);
+        kernel_info!("CPU {} isolated", cpu.as_u32());
+        Ok(())
+    }
+
+    /// Return a CPU to general scheduling
+    pub fn unisolate_cpu(&self, cpu: CpuId) -> KernelResult<()> {
+        let mut isolated = self.isolated.write();
+        if !isolated.contains(cpu) {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        isolated.clear(cpu);
+        kernel_info!("CPU {} returned to housekeeping", cpu.as_u32());
+        Ok(())
+    }
+
+    /// Check whether a CPU is isolated
+    pub fn is_isolated(&self, cpu: CpuId) -> bool {
+        self.isolated.read().contains(cpu)
+    }
+
+    /// Mask of isolated CPUs
+    pub fn isolated_mask(&self) -> CpuMask {
+        self.isolated.read().clone()
+    }
+
+    /// Mask of online CPUs available for general scheduling
+    pub fn housekeeping_mask(&self) -> CpuMask {
+        let isolated = self.isolated.read();
+        let mut mask = CpuMask::online();
+        for cpu in isolated.iter() {
+            mask.clear(cpu);
+        }
+        mask
+    }
+
+    /// Check whether a task with the given affinity may be placed on a CPU
+    ///
+    /// Isolated CPUs only accept tasks whose affinity contains no
+    /// housekeeping CPU, i.e. tasks explicitly pinned to isolated CPUs.
+    pub fn task_allowed_on(&self, affinity: &CpuMask, cpu: CpuId) -> bool {
+        let isolated = self.isolated.read();
+        !isolated.contains(cpu) || affinity.iter().all(|c| isolated.contains(c))
+    }
+
+    /// Enable or disable adaptive-tick (`nohz_full`) mode on a CPU
+    ///
+    /// The tick of a `nohz_full` CPU stops while exactly one task is
+    /// runnable there. RT bandwidth throttling is disabled on such CPUs.
+    /// At least one online CPU must keep its tick to do housekeeping such
+    /// as load average accounting for the tickless ones.
+    pub fn set_nohz_full(&self, cpu: CpuId, enabled: bool) -> KernelResult<()> {
+        if !CpuMask::online().contains(cpu) {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        let mut nohz_full = self.nohz_full.write();
+        if enabled {
+            let ticking = CpuMask::online().iter()
+                .filter(|&c| c != cpu && !nohz_full.contains(c))
+                .count();
+            if ticking == 0 {
+                return Err(SchedulerError::InvalidParameter.into());
+            }
+            nohz_full.set(cpu);
+        } else {
+            nohz_full.clear(cpu);
+        }
+        kernel_info!("CPU {} nohz_full {}", cpu.as_u32(), if enabled { "enabled" } else { "disabled" });
+        Ok(())
+    }
+
+    /// Check whether a CPU runs in `nohz_full` mode
+    pub fn is_nohz_full(&self, cpu: CpuId) -> bool {
+        self.nohz_full.read().contains(cpu)
+    }
+
+    /// Mask of `nohz_full` CPUs
+    pub fn nohz_full_mask(&self) -> CpuMask {
+        self.nohz_full.read().clone()
+    }
+
+    /// Create a top-level cpuset
+    pub fn create_cpuset(&self, cpus: CpuMask, nodes: NodeMask) -> CpusetId {
+        let id = self.next_cpuset.fetch_add(1, Ordering::Relaxed);
+        self.cpusets.write().insert(id, Cpuset { parent: None, cpus, nodes });
+        kernel_debug!("Cpuset {} created", id);
+        id
+    }
+
+    /// Create a cpuset nested in `parent`
+    ///
+    /// # Returns
+    /// - `Ok(id)` of the new cpuset
+    /// - `Err(SchedulerError::InvalidParameter)` if the parent does not exist
+    ///   or the sets are not within the parent's
+    pub fn create_child_cpuset(&self, parent: CpusetId, cpus: CpuMask, nodes: NodeMask) -> KernelResult<CpusetId> {
+        let mut cpusets = self.cpusets.write();
+        let parent_set = cpusets.get(&parent).ok_or(SchedulerError::InvalidParameter)?;
+        if !cpus.iter().all(|cpu| parent_set.cpus.contains(cpu)) || !nodes.is_subset_of(&parent_set.nodes) {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        let id = self.next_cpuset.fetch_add(1, Ordering::Relaxed);
+        cpusets.insert(id, Cpuset { parent: Some(parent), cpus, nodes });
+        kernel_debug!("Cpuset {} created in cpuset {}", id, parent);
+        Ok(id)
+    }
+
+    /// Remove a cpuset without tasks or children
+    ///
+    /// # Returns
+    /// - `Ok(())` if the cpuset was removed
+    /// - `Err(SchedulerError::InvalidParameter)` if it does not exist
+    /// - `Err(SchedulerError::Busy)` if tasks or child cpusets remain in it
+    pub fn remove_cpuset(&self, id: CpusetId) -> KernelResult<()> {
+        let mut cpusets = self.cpusets.write();
+        if !cpusets.contains_key(&id) {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        let has_children = cpusets.values().any(|set| set.parent == Some(id));
+        if has_children || self.task_cpusets.read().values().any(|&set| set == id) {
+            return Err(SchedulerError::Busy.into());
+        }
+        cpusets.remove(&id);
+        Ok(())
+    }
+
+    /// Confine a task to a cpuset
+    ///
+    /// The task's affinity is narrowed to the cpuset's CPUs; the caller
+    /// applies the returned affinity.
+    ///
+    /// # Returns
+    /// - `Ok(affinity)` with the task's new affinity
+    /// - `Err(SchedulerError::InvalidParameter)` if the cpuset does not exist
+    /// - `Err(SchedulerError::AffinityViolation)` if the affinity has no
+    ///   online CPU in common with the cpuset
+    pub fn attach_task(&self, cpuset: CpusetId, task: TaskId, affinity: &CpuMask) -> KernelResult<CpuMask> {
+        let cpus = self.cpusets.read().get(&cpuset)
+            .map(|set| set.cpus.clone())
+            .ok_or(SchedulerError::InvalidParameter)?;
+        let allowed = intersect(affinity, &cpus);
+        if !allowed.iter().any(|cpu| CpuMask::online().contains(cpu)) {
+            return Err(SchedulerError::AffinityViolation.into());
+        }
+        self.task_cpusets.write().insert(task, cpuset);
+        Ok(allowed)
+    }
+
+    /// Release an exiting task from its cpuset
+    pub fn detach_task(&self, task: TaskId) {
+        self.task_cpusets.write().remove(&task);
+    }
+
+    /// Cpuset a task is confined to, if any
+    pub fn cpuset_of(&self, task: TaskId) -> Option<CpusetId> {
+        self.task_cpusets.read().get(&task).copied()
+    }
+
+    /// CPUs of a cpuset
+    pub fn cpuset_cpus(&self, cpuset: CpusetId) -> Option<CpuMask> {
+        self.cpusets.read().get(&cpuset).map(|set| set.cpus.clone())
+    }
+
+    /// Memory nodes of a cpuset
+    pub fn cpuset_nodes(&self, cpuset: CpusetId) -> Option<NodeMask> {
+        self.cpusets.read().get(&cpuset).map(|set| set.nodes)
+    }
+
+    /// Narrow a requested affinity to the CPUs of the task's cpuset
+    ///
+    /// Returns the mask unchanged for a task outside any cpuset.
+    pub fn constrain_affinity(&self, task: TaskId, mask: &CpuMask) -> CpuMask {
+        match self.cpuset_of(task).and_then(|set| self.cpuset_cpus(set)) {
+            Some(cpus) => intersect(mask, &cpus),
+            None => mask.clone(),
+        }
+    }
+
+    /// Check whether a task's cpuset allows it to run on a CPU
+    pub fn cpuset_allows_cpu(&self, task: TaskId, cpu: CpuId) -> bool {
+        self.cpuset_of(task)
+            .and_then(|set| self.cpuset_cpus(set))
+            .map_or(true, |cpus| cpus.contains(cpu))
+    }
+
+    /// Memory nodes a task may allocate from, `None` if unconstrained
+    pub fn task_nodes(&self, task: TaskId) -> Option<NodeMask> {
+        self.cpuset_of(task).and_then(|set| self.cpuset_nodes(set))
+    }
+
+    /// Log the isolated and `nohz_full` CPUs and the cpusets
+    pub fn print_isolation_info(&self) {
+        kernel_info!("Isolated CPUs: {:?}", *self.isolated.read());
+        kernel_info!("nohz_full CPUs: {:?}", *self.nohz_full.read());
+        for (id, set) in self.cpusets.read().iter() {
+            kernel_info!("Cpuset {}: CPUs {:?}, nodes {:?}", id, set.cpus, set.nodes);
+        }
+    }
+}
+
+impl Default for IsolationScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// CPUs present in both masks
+fn intersect(a: &CpuMask, b: &CpuMask) -> CpuMask {
+    let mut mask = CpuMask::empty();
+    for cpu in a.iter().filter(|&cpu| b.contains(cpu)) {
+        mask.set(cpu);
+    }
+    mask
+}
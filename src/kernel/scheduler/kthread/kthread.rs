@@ -0,0 +1,236 @@
+//! # Per-CPU Kernel Threads
+//!
+//! This module tracks per-CPU kernel threads: kernel threads bound to one
+//! CPU for their whole life because they manage that CPU's state (softirq
+//! processing, per-CPU workers). Moving one to another CPU would have it
+//! work on the wrong CPU's data, so load balancing, explicit migration and
+//! affinity changes leave them where they are.
+//!
+//! A CPU can only go offline once its kernel threads are parked. Parking
+//! is cooperative: `request_park` asks a thread to stop, the thread sees
+//! `should_park` at a point where it holds no per-CPU state and parks
+//! itself, and `unpark` lets it run again once the CPU is back.
+//!
+//! ## Features
+//! - Per-CPU kernel thread registry with the CPU each thread is bound to
+//! - Migration checks for the balancer and explicit moves
+//! - Cooperative park/unpark handshake
+//! - Offline check failing with `SchedulerError::PinnedTaskBlocking`
+//!   while a thread of the CPU is not parked
+//!
+//! ## Usage
+//! ```rust
+//! use crate::kernel::scheduler::kthread::KthreadScheduler;
+//!
+//! kthread.register(task.id(), cpu)?;
+//!
+//! // Before offlining the CPU
+//! kthread.request_park(task.id())?;
+//! // ... the thread calls `park_self` once it sees `should_park` ...
+//! kthread.check_offline(cpu)?;
+//! ```
+
+use crate::kernel::task::TaskId;
+use crate::kernel::cpu::CpuId;
+use crate::kernel::error::{KernelResult, SchedulerError};
+use crate::kernel::sync::RwLock;
+use crate::kernel::log::{kernel_info, kernel_debug};
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// Park state of a per-CPU kernel thread
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KthreadState {
+    /// Running its CPU's work
+    Running,
+    /// Asked to park, not parked yet
+    ParkRequested,
+    /// Parked until unparked
+    Parked,
+}
+
+/// A registered per-CPU kernel thread
+#[derive(Debug, Clone, Copy)]
+struct PerCpuKthread {
+    /// CPU the thread is bound to
+    cpu: CpuId,
+    state: KthreadState,
+}
+
+/// Per-CPU kernel thread scheduler component
+pub struct KthreadScheduler {
+    kthreads: RwLock<BTreeMap<TaskId, PerCpuKthread>>,
+}
+
+impl KthreadScheduler {
+    /// Create an empty per-CPU kernel thread registry
+    pub fn new() -> Self {
+        Self {
+            kthreads: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// Register a per-CPU kernel thread bound to `cpu`
+    ///
+    /// Fails with `SchedulerError::InvalidParameter` if the task is
+    /// already registered.
+    pub fn register(&self, task: TaskId, cpu: CpuId) -> KernelResult<()> {
+        let mut kthreads = self.kthreads.write();
+        if kthreads.contains_key(&task) {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        kthreads.insert(task, PerCpuKthread { cpu, state: KthreadState::Running });
+        kernel_debug!("Task {} is a per-CPU kthread of CPU {}", task.as_u64(), cpu.as_u32());
+        Ok(())
+    }
+
+    /// Forget a per-CPU kernel thread, e.g. because it exited
+    pub fn unregister(&self, task: TaskId) {
+        self.kthreads.write().remove(&task);
+    }
+
+    /// Check whether a task is a per-CPU kernel thread
+    pub fn is_per_cpu(&self, task: TaskId) -> bool {
+        self.kthreads.read().contains_key(&task)
+    }
+
+    /// CPU a per-CPU kernel thread is bound to
+    pub fn bound_cpu(&self, task: TaskId) -> Option<CpuId> {
+        self.kthreads.read().get(&task).map(|k| k.cpu)
+    }
+
+    /// Check whether a task may run on `cpu`: any task may, except a
+    /// per-CPU kernel thread on a CPU other than its own
+    pub fn may_run_on(&self, task: TaskId, cpu: CpuId) -> bool {
+        self.bound_cpu(task).map_or(true, |bound| bound == cpu)
+    }
+
+    /// Park state of a per-CPU kernel thread
+    pub fn state(&self, task: TaskId) -> Option<KthreadState> {
+        self.kthreads.read().get(&task).map(|k| k.state)
+    }
+
+    /// Ask a per-CPU kernel thread to park
+    ///
+    /// # Returns
+    /// - `Ok(true)` if the thread is already parked
+    /// - `Ok(false)` if it still has to park itself
+    /// - `Err(SchedulerError::TaskNotFound)` if it is not registered
+    pub fn request_park(&self, task: TaskId) -> KernelResult<bool> {
+        let mut kthreads = self.kthreads.write();
+        let kthread = kthreads.get_mut(&task).ok_or(SchedulerError::TaskNotFound)?;
+        if kthread.state == KthreadState::Running {
+            kthread.state = KthreadState::ParkRequested;
+        }
+        Ok(kthread.state == KthreadState::Parked)
+    }
+
+    /// Check whether a per-CPU kernel thread was asked to park
+    pub fn should_park(&self, task: TaskId) -> bool {
+        self.state(task) == Some(KthreadState::ParkRequested)
+    }
+
+    /// Park a per-CPU kernel thread that was asked to park
+    ///
+    /// Called by the thread itself. Returns whether it parked; a thread
+    /// that was not asked to, or was unparked meanwhile, keeps running.
+    pub fn park_self(&self, task: TaskId) -> bool {
+        let mut kthreads = self.kthreads.write();
+        match kthreads.get_mut(&task) {
+            Some(kthread) if kthread.state == KthreadState::ParkRequested => {
+                kthread.state = KthreadState::Parked;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Let a per-CPU kernel thread run again, withdrawing a pending park
+    /// request
+    ///
+    /// # Returns
+    /// - `Ok(true)` if the thread was parked and must be woken
+    /// - `Ok(false)` if it was not parked
+    /// - `Err(SchedulerError::TaskNotFound)` if it is not registered
+    pub fn unpark(&self, task: TaskId) -> KernelResult<bool> {
+        let mut kthreads = self.kthreads.write();
+        let kthread = kthreads.get_mut(&task).ok_or(SchedulerError::TaskNotFound)?;
+        let was_parked = kthread.state == KthreadState::Parked;
+        kthread.state = KthreadState::Running;
+        Ok(was_parked)
+    }
+
+    /// Check whether a per-CPU kernel thread is parked
+    pub fn is_parked(&self, task: TaskId) -> bool {
+        self.state(task) == Some(KthreadState::Parked)
+    }
+
+    /// Per-CPU kernel threads of a CPU that are not parked
+    pub fn unparked_on(&self, cpu: CpuId) -> Vec<TaskId> {
+        self.kthreads.read().iter()
+            .filter(|(_, k)| k.cpu == cpu && k.state != KthreadState::Parked)
+            .map(|(&task, _)| task)
+            .collect()
+    }
+
+    /// Check that a CPU can go offline as far as its kernel threads go
+    ///
+    /// Fails with `SchedulerError::PinnedTaskBlocking` while one of them is
+    /// not parked.
+    pub fn check_offline(&self, cpu: CpuId) -> KernelResult<()> {
+        let blocking = self.unparked_on(cpu);
+        if let Some(task) = blocking.first() {
+            kernel_debug!("CPU {} offline blocked by {} unparked kthreads, first {}",
+                         cpu.as_u32(), blocking.len(), task.as_u64());
+            return Err(SchedulerError::PinnedTaskBlocking.into());
+        }
+        Ok(())
+    }
+
+    /// Log every per-CPU kernel thread and its park state
+    pub fn print_kthread_info(&self) {
+        for (task, kthread) in self.kthreads.read().iter() {
+            kernel_info!("Kthread {} on CPU {}: {:?}", task.as_u64(), kthread.cpu.as_u32(), kthread.state);
+        }
+    }
+}
+
+impl Default for KthreadScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offline_is_blocked_until_kthread_parks() {
+        let kthread = KthreadScheduler::new();
+        let (cpu, other) = (CpuId::new(1), CpuId::new(2));
+        let worker = TaskId::new(10);
+        kthread.register(worker, cpu).unwrap();
+        assert!(kthread.register(worker, other).is_err());
+
+        assert!(kthread.check_offline(cpu).is_err());
+        assert!(kthread.check_offline(other).is_ok());
+        assert!(!kthread.may_run_on(worker, other));
+        assert!(kthread.may_run_on(TaskId::new(11), other));
+
+        // Requested but not yet parked still blocks
+        assert!(!kthread.request_park(worker).unwrap());
+        assert!(kthread.should_park(worker));
+        assert!(kthread.check_offline(cpu).is_err());
+
+        assert!(kthread.park_self(worker));
+        assert!(kthread.request_park(worker).unwrap());
+        assert!(kthread.check_offline(cpu).is_ok());
+
+        // Unparking blocks offlining again
+        assert!(kthread.unpark(worker).unwrap());
+        assert!(!kthread.park_self(worker));
+        assert!(kthread.check_offline(cpu).is_err());
+    }
+}
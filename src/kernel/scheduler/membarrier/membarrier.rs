@@ -0,0 +1,189 @@
+//! # Membarrier
+//!
+//! This module implements the `membarrier()` system call, which lets a
+//! user-space thread issue a memory barrier on every CPU running a sibling
+//! thread. User-space RCU and similar libraries use it to replace a full
+//! barrier on their fast path with a compiler barrier, paying for it on
+//! the (rare) slow path instead.
+//!
+//! Expedited commands only interrupt CPUs that are currently running a
+//! thread of the caller's thread group. Every CPU publishes the thread
+//! group it runs on each context switch, and the caller scans those after a
+//! full barrier, so a CPU that is not interrupted is guaranteed to pass a
+//! barrier before it next runs one of the group's threads.
+//!
+//! ## Features
+//! - `Global`: barrier on every online CPU
+//! - `PrivateExpedited`: barrier on CPUs running the caller's thread group
+//! - `PrivateExpeditedSyncCore`: additionally serializes instruction
+//!   fetch on those CPUs (for JIT code modification)
+//! - `Query`: bitmask of supported commands
+//!
+//! ## Usage
+//! ```rust
+//! use crate::kernel::scheduler::membarrier::{MembarrierCmd, MembarrierScheduler};
+//!
+//! membarrier.membarrier(MembarrierCmd::RegisterPrivateExpedited, &task)?;
+//! membarrier.membarrier(MembarrierCmd::PrivateExpedited, &task)?;
+//! ```
+
+use crate::kernel::task::{Task, TaskId};
+use crate::kernel::cpu::{CpuId, CpuMask};
+use crate::kernel::error::{KernelResult, SchedulerError};
+use crate::kernel::sync::RwLock;
+use crate::kernel::log::{kernel_info, kernel_debug};
+use crate::kernel::memory::percpu::PerCpu;
+use crate::arch::cpu::{current_cpu_id, sync_core};
+use crate::arch::smp::smp_call_function_many;
+
+use alloc::collections::BTreeMap;
+use core::sync::atomic::{fence, AtomicU64, Ordering};
+
+/// Marker for a CPU not running any thread group
+const NO_GROUP: u64 = u64::MAX;
+
+/// Membarrier commands (values match the Linux ABI)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum MembarrierCmd {
+    /// Return the bitmask of supported commands
+    Query = 0,
+    /// Barrier on every online CPU
+    Global = 1 << 0,
+    /// Barrier on every CPU running a thread of the caller's group
+    PrivateExpedited = 1 << 3,
+    /// Register the caller's group for `PrivateExpedited`
+    RegisterPrivateExpedited = 1 << 4,
+    /// `PrivateExpedited` plus core serialization
+    PrivateExpeditedSyncCore = 1 << 5,
+    /// Register the caller's group for `PrivateExpeditedSyncCore`
+    RegisterPrivateExpeditedSyncCore = 1 << 6,
+}
+
+/// Commands reported by `Query`
+pub const MEMBARRIER_SUPPORTED: u32 = MembarrierCmd::Global as u32
+    | MembarrierCmd::PrivateExpedited as u32
+    | MembarrierCmd::RegisterPrivateExpedited as u32
+    | MembarrierCmd::PrivateExpeditedSyncCore as u32
+    | MembarrierCmd::RegisterPrivateExpeditedSyncCore as u32;
+
+/// IPI handler: full memory barrier
+fn ipi_mb() {
+    fence(Ordering::SeqCst);
+}
+
+/// IPI handler: full memory barrier and core serialization
+fn ipi_sync_core() {
+    fence(Ordering::SeqCst);
+    sync_core();
+}
+
+/// Membarrier scheduler component
+pub struct MembarrierScheduler {
+    /// Thread group running on each CPU
+    running: PerCpu<AtomicU64>,
+    /// Registered commands of each thread group
+    registered: RwLock<BTreeMap<TaskId, u32>>,
+    ipis_sent: AtomicU64,
+}
+
+impl MembarrierScheduler {
+    /// Create a membarrier component
+    pub fn new() -> Self {
+        Self {
+            running: PerCpu::new(AtomicU64::new(NO_GROUP)),
+            registered: RwLock::new(BTreeMap::new()),
+            ipis_sent: AtomicU64::new(0),
+        }
+    }
+
+    /// Publish the thread group now running on a CPU
+    ///
+    /// Must be called on every context switch, after the previous task
+    /// stopped touching user memory.
+    pub fn on_task_switch(&self, cpu: CpuId, next: &Task) {
+        self.running.get(cpu).store(next.tgid().as_u64(), Ordering::SeqCst);
+    }
+
+    /// Execute a membarrier command on behalf of a task
+    ///
+    /// # Returns
+    /// - The supported command bitmask for `Query`, 0 otherwise
+    /// - `Err(SchedulerError::PermissionDenied)` for an expedited command
+    ///   the caller's thread group has not registered for
+    pub fn membarrier(&self, cmd: MembarrierCmd, caller: &Task) -> KernelResult<u32> {
+        let tgid = caller.tgid();
+        match cmd {
+            MembarrierCmd::Query => return Ok(MEMBARRIER_SUPPORTED),
+            MembarrierCmd::Global => {
+                let mut targets = CpuMask::online();
+                targets.clear(current_cpu_id());
+                self.barrier_on(&targets, ipi_mb);
+            }
+            MembarrierCmd::RegisterPrivateExpedited | MembarrierCmd::RegisterPrivateExpeditedSyncCore => {
+                *self.registered.write().entry(tgid).or_insert(0) |= cmd as u32;
+            }
+            MembarrierCmd::PrivateExpedited => {
+                self.check_registered(tgid, MembarrierCmd::RegisterPrivateExpedited)?;
+                self.barrier_on(&self.group_cpus(tgid), ipi_mb);
+            }
+            MembarrierCmd::PrivateExpeditedSyncCore => {
+                self.check_registered(tgid, MembarrierCmd::RegisterPrivateExpeditedSyncCore)?;
+                self.barrier_on(&self.group_cpus(tgid), ipi_sync_core);
+                sync_core();
+            }
+        }
+        Ok(0)
+    }
+
+    /// Forget the registrations of an exiting thread group
+    pub fn remove_group(&self, tgid: TaskId) {
+        self.registered.write().remove(&tgid);
+    }
+
+    /// Log membarrier state
+    pub fn print_membarrier_info(&self) {
+        kernel_info!("Membarrier registered groups: {}, IPIs sent: {}",
+                    self.registered.read().len(), self.ipis_sent.load(Ordering::Relaxed));
+    }
+
+    /// Fail unless a thread group registered for a command
+    fn check_registered(&self, tgid: TaskId, register: MembarrierCmd) -> KernelResult<()> {
+        let flags = self.registered.read().get(&tgid).copied().unwrap_or(0);
+        if flags & register as u32 == 0 {
+            return Err(SchedulerError::PermissionDenied.into());
+        }
+        Ok(())
+    }
+
+    /// Other CPUs currently running a thread of a group
+    fn group_cpus(&self, tgid: TaskId) -> CpuMask {
+        // Order the caller's prior accesses before reading the running groups
+        fence(Ordering::SeqCst);
+        let this_cpu = current_cpu_id();
+        let mut targets = CpuMask::empty();
+        for cpu in CpuMask::online().iter() {
+            if cpu != this_cpu && self.running.get(cpu).load(Ordering::SeqCst) == tgid.as_u64() {
+                targets.set(cpu);
+            }
+        }
+        targets
+    }
+
+    /// Run a barrier handler on a set of CPUs and wait for completion
+    fn barrier_on(&self, targets: &CpuMask, handler: fn()) {
+        fence(Ordering::SeqCst);
+        if !targets.is_empty() {
+            kernel_debug!("Membarrier IPI to {} CPUs", targets.weight());
+            smp_call_function_many(targets, handler);
+            self.ipis_sent.fetch_add(targets.weight() as u64, Ordering::Relaxed);
+        }
+        fence(Ordering::SeqCst);
+    }
+}
+
+impl Default for MembarrierScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,959 @@
+//! # Task Migration and Load Balancing
+//!
+//! This module decides which tasks move between CPUs. Balancing runs per
+//! scheduling domain: the groups of the domain are compared by load per
+//! unit of capacity, and the balancing CPU pulls work from the busiest
+//! group when the imbalance exceeds the configured threshold.
+//!
+//! ## Features
+//! - Capacity-aware imbalance calculation for asymmetric systems
+//! - Misfit task detection: tasks too big for a little core move to a big one
+//! - Cross-node moves penalized proportionally to NUMA distance
+//! - NUMA balancing: tasks are pulled toward, never away from, their
+//!   preferred node
+//! - Migration cost model: tasks that ran on their CPU within
+//!   `migration_cost_ns` are cache hot and stay put unless the imbalance
+//!   is severe
+//! - Active balancing: when only the running task could move, the busy
+//!   CPU's stopper pushes it to the idle balancing CPU, at most once per
+//!   balance interval per CPU
+//! - Migration throttling: a task that moved within
+//!   `min_migration_interval_ns` is not moved again, so that balancing
+//!   passes can't bounce it between CPUs
+//! - Newly idle balancing: a CPU about to idle first tries to pull work,
+//!   for no longer than it expects to stay idle
+//! - Pending migration queue: moves that could not happen yet (the task
+//!   moved too recently) wait for their target CPU to drain them, which
+//!   newly idle balancing does first
+//! - Bounded number of migrations per balance pass
+//! - Migration statistics
+//!
+//! ## Usage
+//! ```rust
+//! use crate::kernel::scheduler::migration::MigrationScheduler;
+//!
+//! let migration = MigrationScheduler::with_config(LoadBalanceConfig::default());
+//! for domain in domains.domains_of(cpu) {
+//!     migration.balance_domain(cpu, &domain, &config, &scheduler)?;
+//! }
+//! ```
+
+use crate::kernel::scheduler::core::LoadBalanceConfig;
+use crate::kernel::scheduler::domains::{SchedDomain, SD_NUMA};
+use crate::kernel::task::{Task, TaskId};
+use crate::kernel::cpu::{CpuId, CpuMask};
+use crate::kernel::error::{KernelResult, SchedulerError};
+use crate::kernel::sync::{SpinLock, RwLock};
+use crate::kernel::log::{kernel_debug, kernel_info};
+use crate::kernel::memory::percpu::PerCpu;
+use crate::kernel::time::Timestamp;
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Capacity of the biggest CPU
+const SCHED_CAPACITY_SCALE: u64 = 1024;
+
+/// Margin a task must leave on a CPU to fit there (~20%)
+const CAPACITY_MARGIN: u64 = 1280;
+
+/// NUMA distance of a CPU to its own node
+const LOCAL_DISTANCE: u64 = 10;
+
+/// Default time since a task last ran during which its cache is warm
+pub const DEFAULT_MIGRATION_COST_NS: u64 = 500_000; // 0.5ms
+
+/// Largest accepted migration cost
+const MAX_MIGRATION_COST_NS: u64 = 1_000_000_000;
+
+/// Weight of a new sample in the average idle duration (1/8)
+const AVG_IDLE_SHIFT: u32 = 3;
+
+/// Busiest-to-local load ratio (in percent) from which cache hot tasks
+/// are pulled anyway
+const SEVERE_IMBALANCE_PCT: u64 = 200;
+
+/// Utilization drop over the tick history (0-1000 scale) after which an
+/// imbalance on the busiest CPU is treated as transient
+const TRANSIENT_UTIL_DROP: i32 = 100;
+
+/// Whether a task may be pulled to a CPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationDecision {
+    /// The task may move
+    Allowed,
+    /// The task ran on another CPU within the migration cost
+    CacheHot,
+    /// The task's affinity excludes the destination
+    AffinityBlocked,
+    /// The task migrated less than the minimum migration interval ago
+    Throttled,
+}
+
+/// Where and when a task last ran
+#[derive(Debug, Clone, Copy)]
+struct LastRan {
+    cpu: CpuId,
+    at: u64,
+}
+
+/// A task that could be pulled from a runqueue
+#[derive(Debug, Clone, Copy)]
+pub struct MigrationCandidate {
+    /// Task identifier
+    pub task: TaskId,
+    /// Load contributed by the task
+    pub load: u64,
+    /// Utilization of the task (0-1024)
+    pub util: u32,
+}
+
+/// Per-CPU view of the system needed to balance load
+///
+/// Implemented by the core scheduler, which owns the runqueues.
+pub trait BalanceSource {
+    /// Runnable load of a CPU
+    ///
+    /// Only the tasks competing for the CPU count: load left behind by
+    /// tasks that went to sleep must not make a CPU look worth pulling from.
+    fn cpu_load(&self, cpu: CpuId) -> u64;
+    /// Compute capacity of a CPU (1024 for the biggest core)
+    fn cpu_capacity(&self, cpu: CpuId) -> u32;
+    /// Number of runnable tasks on a CPU
+    fn nr_running(&self, cpu: CpuId) -> u32;
+    /// Utilization change of a CPU over its recent ticks (0-1000 scale,
+    /// negative when falling)
+    fn util_trend(&self, _cpu: CpuId) -> i32 {
+        0
+    }
+    /// Queued (not currently running) tasks of a CPU that may be pulled
+    fn candidates(&self, cpu: CpuId) -> Vec<MigrationCandidate>;
+    /// Check whether a task's affinity allows it to run on a CPU
+    fn can_run_on(&self, task: TaskId, cpu: CpuId) -> bool;
+    /// Move a queued task to another CPU
+    fn move_task(&self, task: TaskId, dst: CpuId) -> KernelResult<()>;
+    /// NUMA distance between the nodes of two CPUs (10 = same node)
+    fn cpu_distance(&self, _from: CpuId, _to: CpuId) -> u8 {
+        LOCAL_DISTANCE as u8
+    }
+    /// Whether a CPU is on the task's preferred NUMA node (`None` if it has none)
+    fn prefers_cpu(&self, _task: TaskId, _cpu: CpuId) -> Option<bool> {
+        None
+    }
+    /// Whether a CPU shares a last-level cache with the other tasks of the
+    /// task's cache affinity group (`None` if it has no group peers)
+    fn shares_cache_group(&self, _task: TaskId, _cpu: CpuId) -> Option<bool> {
+        None
+    }
+    /// Task running on a CPU that active balancing may push away
+    fn running_task(&self, _cpu: CpuId) -> Option<TaskId> {
+        None
+    }
+    /// Queue work on `cpu`'s stopper that moves its running task to `dst`
+    ///
+    /// Must not wait for the work to run.
+    fn push_running_task(&self, _task: TaskId, _cpu: CpuId, _dst: CpuId) -> KernelResult<()> {
+        Err(SchedulerError::MigrationNotAllowed.into())
+    }
+}
+
+/// Load statistics of one balancing group
+#[derive(Debug, Clone, Copy, Default)]
+struct GroupStats {
+    load: u64,
+    capacity: u64,
+    nr_running: u32,
+}
+
+impl GroupStats {
+    /// Load per unit of capacity, scaled to `SCHED_CAPACITY_SCALE`
+    fn avg_load(&self) -> u64 {
+        if self.capacity == 0 {
+            return 0;
+        }
+        self.load * SCHED_CAPACITY_SCALE / self.capacity
+    }
+}
+
+/// Migration statistics
+#[derive(Debug, Default)]
+pub struct MigrationStats {
+    /// Tasks moved by load balancing
+    pub balance_migrations: AtomicU64,
+    /// Misfit tasks moved to a bigger CPU
+    pub misfit_migrations: AtomicU64,
+    /// Explicit task migrations
+    pub task_migrations: AtomicU64,
+    /// Cache hot tasks load balancing left in place
+    pub cache_hot_skips: AtomicU64,
+    /// Running tasks pushed away by active balancing
+    pub active_balance_count: AtomicU64,
+    /// Newly idle balances that pulled a task
+    pub newidle_balance_success: AtomicU64,
+    /// Newly idle balances that were skipped or pulled nothing
+    pub newidle_balance_fail: AtomicU64,
+    /// Migrations refused because the task moved too recently
+    pub throttled_migrations: AtomicU64,
+    /// Balances skipped because the busiest CPU was already draining
+    pub transient_skips: AtomicU64,
+    /// Migrations queued because they could not happen yet
+    pub deferred_migrations: AtomicU64,
+    /// Queued migrations carried out by draining the queue
+    pub drained_migrations: AtomicU64,
+    /// Largest number of migrations ever pending at once
+    pub pending_high_water: AtomicU64,
+}
+
+/// Migration scheduler component
+pub struct MigrationScheduler {
+    config: RwLock<LoadBalanceConfig>,
+    stats: MigrationStats,
+    migration_cost_ns: AtomicU64,
+    /// Last CPU and time each task ran
+    last_ran: RwLock<BTreeMap<TaskId, LastRan>>,
+    /// Time each task last migrated
+    last_migrated: RwLock<BTreeMap<TaskId, u64>>,
+    /// Time of the last active balance away from each CPU
+    last_active_balance: PerCpu<AtomicU64>,
+    /// Average length of each CPU's idle periods
+    avg_idle: PerCpu<AtomicU64>,
+    /// Pending migrations: the CPU each deferred task should move to
+    pending: SpinLock<BTreeMap<TaskId, CpuId>>,
+}
+
+impl MigrationScheduler {
+    /// Create a migration scheduler with a load balancing configuration
+    pub fn with_config(config: LoadBalanceConfig) -> Self {
+        Self {
+            config: RwLock::new(config),
+            stats: MigrationStats::default(),
+            migration_cost_ns: AtomicU64::new(DEFAULT_MIGRATION_COST_NS),
+            last_ran: RwLock::new(BTreeMap::new()),
+            last_migrated: RwLock::new(BTreeMap::new()),
+            last_active_balance: PerCpu::new(AtomicU64::new(0)),
+            avg_idle: PerCpu::new(AtomicU64::new(2 * DEFAULT_MIGRATION_COST_NS)),
+            pending: SpinLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// Update the load balancing configuration
+    pub fn set_config(&self, config: LoadBalanceConfig) {
+        *self.config.write() = config;
+    }
+
+    /// Migration statistics
+    pub fn stats(&self) -> &MigrationStats {
+        &self.stats
+    }
+
+    /// Set the time since a task last ran during which moving it is
+    /// considered to lose a warm cache (`sched_migration_cost`, at most 1s)
+    ///
+    /// 0 treats every task as cache cold.
+    pub fn set_migration_cost(&self, cost_ns: u64) -> KernelResult<()> {
+        if cost_ns > MAX_MIGRATION_COST_NS {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        self.migration_cost_ns.store(cost_ns, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Time since a task last ran during which its cache is warm
+    pub fn migration_cost(&self) -> u64 {
+        self.migration_cost_ns.load(Ordering::Relaxed)
+    }
+
+    /// Record that a task stopped running on a CPU at `now`
+    pub fn record_task_run(&self, task: TaskId, cpu: CpuId, now: u64) {
+        self.last_ran.write().insert(task, LastRan { cpu, at: now });
+    }
+
+    /// Forget the run history of an exiting task
+    pub fn remove_task(&self, task: TaskId) {
+        self.last_ran.write().remove(&task);
+        self.last_migrated.write().remove(&task);
+        self.pending.lock().remove(&task);
+    }
+
+    /// Queue the move of a task to `dst` that cannot happen yet
+    ///
+    /// A later deferral of the same task replaces the target; any actual
+    /// migration of the task drops the entry.
+    pub fn defer_migration(&self, task: TaskId, dst: CpuId) {
+        let depth = {
+            let mut pending = self.pending.lock();
+            pending.insert(task, dst);
+            pending.len() as u64
+        };
+        self.stats.deferred_migrations.fetch_add(1, Ordering::Relaxed);
+        self.stats.pending_high_water.fetch_max(depth, Ordering::Relaxed);
+        kernel_debug!("Migration of task {} to CPU {} deferred", task.as_u64(), dst.as_u32());
+    }
+
+    /// Pending migrations as (task, target CPU)
+    pub fn pending_migrations(&self) -> Vec<(TaskId, CpuId)> {
+        self.pending.lock().iter().map(|(&task, &cpu)| (task, cpu)).collect()
+    }
+
+    /// Carry out the pending migrations to a CPU; returns how many tasks
+    /// moved
+    ///
+    /// Tasks that still moved too recently stay queued. Tasks that may no
+    /// longer run on the CPU, or can no longer be moved (e.g. they are
+    /// running or asleep), are dropped.
+    pub fn drain_migrations(&self, cpu: CpuId, src: &dyn BalanceSource) -> usize {
+        let queued: Vec<TaskId> = {
+            let mut pending = self.pending.lock();
+            let queued: Vec<TaskId> = pending.iter()
+                .filter(|&(_, &dst)| dst == cpu)
+                .map(|(&task, _)| task)
+                .collect();
+            for task in &queued {
+                pending.remove(task);
+            }
+            queued
+        };
+
+        let now = Timestamp::now().as_nanos();
+        let mut moved = 0;
+        for task in queued {
+            if !src.can_run_on(task, cpu) {
+                continue;
+            }
+            if self.is_throttled(task, now) {
+                self.pending.lock().entry(task).or_insert(cpu);
+                continue;
+            }
+            if src.move_task(task, cpu).is_ok() {
+                self.record_migration(task);
+                moved += 1;
+            }
+        }
+        if moved > 0 {
+            self.stats.drained_migrations.fetch_add(moved as u64, Ordering::Relaxed);
+            kernel_debug!("Drained {} pending migrations to CPU {}", moved, cpu.as_u32());
+        }
+        moved
+    }
+
+    /// Check whether a task may be pulled to `dst`
+    ///
+    /// Affinity is checked first, then the minimum migration interval. A
+    /// task is cache hot if it last ran on a CPU other than `dst` less than
+    /// the migration cost ago.
+    pub fn can_migrate_task(&self, task: TaskId, dst: CpuId, src: &dyn BalanceSource) -> MigrationDecision {
+        if !src.can_run_on(task, dst) {
+            return MigrationDecision::AffinityBlocked;
+        }
+        let now = Timestamp::now().as_nanos();
+        if self.is_throttled(task, now) {
+            return MigrationDecision::Throttled;
+        }
+        if self.is_cache_hot(task, dst, now) {
+            return MigrationDecision::CacheHot;
+        }
+        MigrationDecision::Allowed
+    }
+
+    /// Check whether a task migrated less than the minimum migration
+    /// interval ago, counting the refused migration if so
+    ///
+    /// For explicit migrations; callers moving tasks off a CPU that goes
+    /// offline skip the check.
+    pub fn migration_throttled(&self, task: TaskId) -> bool {
+        let throttled = self.is_throttled(task, Timestamp::now().as_nanos());
+        if throttled {
+            self.stats.throttled_migrations.fetch_add(1, Ordering::Relaxed);
+        }
+        throttled
+    }
+
+    /// Check whether a task's utilization is too big for a CPU's capacity
+    pub fn is_misfit(util: u32, capacity: u32) -> bool {
+        util as u64 * CAPACITY_MARGIN > capacity as u64 * SCHED_CAPACITY_SCALE
+    }
+
+    /// Balance one scheduling domain by pulling work to `this_cpu`
+    ///
+    /// Groups are compared by load per capacity so that a busy little core
+    /// offloads to an idle big core even if their raw loads are equal.
+    /// Misfit tasks on smaller CPUs are pulled first when `this_cpu` is
+    /// bigger. If no queued task could be pulled to an idle `this_cpu`,
+    /// the busiest CPU is asked to push its running task instead; such a
+    /// push completes asynchronously and is not counted. Returns the number
+    /// of tasks moved.
+    pub fn balance_domain(&self, this_cpu: CpuId, domain: &SchedDomain, config: &LoadBalanceConfig,
+                          src: &dyn BalanceSource) -> KernelResult<usize> {
+        if !domain.span.contains(this_cpu) {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+
+        let max_moves = config.max_migrations_per_balance as usize;
+        let mut moved = self.pull_misfit_task(this_cpu, &domain.span, src)?;
+        if moved >= max_moves {
+            return Ok(moved);
+        }
+
+        let local_group = match domain.groups.iter().find(|g| g.contains(this_cpu)) {
+            Some(group) => group,
+            None => return Ok(moved),
+        };
+        let local = Self::group_stats(local_group, src);
+
+        // Find the busiest group by load per capacity
+        let busiest = domain.groups.iter()
+            .filter(|g| !g.contains(this_cpu))
+            .map(|g| (g, Self::group_stats(g, src)))
+            .filter(|(_, stats)| stats.nr_running > 0)
+            .max_by_key(|(_, stats)| stats.avg_load());
+        let (busiest_group, busiest) = match busiest {
+            Some(found) => found,
+            None => return Ok(moved),
+        };
+
+        let threshold = 100 + config.imbalance_threshold as u64;
+        if busiest.avg_load() * 100 <= local.avg_load() * threshold {
+            return Ok(moved);
+        }
+        let severe = busiest.avg_load() * 100 > local.avg_load() * SEVERE_IMBALANCE_PCT;
+
+        // Move enough load to meet in the middle, expressed in local capacity
+        let domain_avg = (busiest.load + local.load) * SCHED_CAPACITY_SCALE
+            / (busiest.capacity + local.capacity).max(1);
+        let excess = busiest.avg_load().saturating_sub(domain_avg);
+        let deficit = domain_avg.saturating_sub(local.avg_load());
+        let mut imbalance = excess.min(deficit) * local.capacity / SCHED_CAPACITY_SCALE;
+
+        // Pull from the busiest CPU of the busiest group
+        let busiest_cpu = busiest_group.iter()
+            .filter(|&cpu| src.nr_running(cpu) > 1)
+            .max_by_key(|&cpu| src.cpu_load(cpu) * SCHED_CAPACITY_SCALE / src.cpu_capacity(cpu).max(1) as u64);
+        let busiest_cpu = match busiest_cpu {
+            Some(cpu) => cpu,
+            None => return Ok(moved),
+        };
+
+        // A spike that is already draining does not warrant a migration
+        if !severe && src.util_trend(busiest_cpu) <= -TRANSIENT_UTIL_DROP {
+            self.stats.transient_skips.fetch_add(1, Ordering::Relaxed);
+            return Ok(moved);
+        }
+
+        // Remote moves cost more: weigh each task's load by the distance
+        let distance = (src.cpu_distance(busiest_cpu, this_cpu) as u64).max(LOCAL_DISTANCE);
+
+        // Never pull a task away from its preferred NUMA node, and try the
+        // tasks this move brings closer to their memory first, then those
+        // it does not take away from their cache affinity group
+        let mut candidates = src.candidates(busiest_cpu);
+        candidates.retain(|c| !(src.prefers_cpu(c.task, busiest_cpu) == Some(true)
+                                && src.prefers_cpu(c.task, this_cpu) == Some(false)));
+        candidates.sort_by_key(|c| (src.prefers_cpu(c.task, this_cpu) != Some(true),
+                                    src.shares_cache_group(c.task, this_cpu) == Some(false)));
+
+        let mut pulled = 0;
+        for candidate in candidates {
+            if moved >= max_moves || imbalance == 0 {
+                break;
+            }
+            match self.can_migrate_task(candidate.task, this_cpu, src) {
+                MigrationDecision::Allowed => {}
+                MigrationDecision::CacheHot if severe => {}
+                MigrationDecision::CacheHot => {
+                    self.stats.cache_hot_skips.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+                MigrationDecision::Throttled => {
+                    self.stats.throttled_migrations.fetch_add(1, Ordering::Relaxed);
+                    self.defer_migration(candidate.task, this_cpu);
+                    continue;
+                }
+                MigrationDecision::AffinityBlocked => continue,
+            }
+            // Don't overshoot by moving a task bigger than twice the imbalance
+            let cost = candidate.load * distance / LOCAL_DISTANCE;
+            if cost > imbalance * 2 {
+                continue;
+            }
+            src.move_task(candidate.task, this_cpu)?;
+            self.record_migration(candidate.task);
+            imbalance = imbalance.saturating_sub(candidate.load);
+            moved += 1;
+            pulled += 1;
+            self.stats.balance_migrations.fetch_add(1, Ordering::Relaxed);
+        }
+
+        if pulled == 0 && src.nr_running(this_cpu) == 0 {
+            let interval_ns = domain.balance_interval_ms * 1_000_000;
+            self.active_balance(busiest_cpu, this_cpu, interval_ns, src)?;
+        }
+
+        if moved > 0 {
+            kernel_debug!("Balanced {} domain: pulled {} tasks from CPU {} to CPU {}",
+                         domain.level.as_str(), moved, busiest_cpu.as_u32(), this_cpu.as_u32());
+        }
+        Ok(moved)
+    }
+
+    /// Account an idle period of a CPU that just ended
+    ///
+    /// Feeds the idle duration estimate bounding newly idle balancing.
+    pub fn update_avg_idle(&self, cpu: CpuId, idle_ns: u64) {
+        let avg_idle = self.avg_idle.get(cpu);
+        let avg = avg_idle.load(Ordering::Relaxed);
+        let avg = if idle_ns >= avg {
+            avg + ((idle_ns - avg) >> AVG_IDLE_SHIFT)
+        } else {
+            avg - ((avg - idle_ns) >> AVG_IDLE_SHIFT)
+        };
+        avg_idle.store(avg, Ordering::Relaxed);
+    }
+
+    /// Expected length of a CPU's next idle period
+    pub fn avg_idle(&self, cpu: CpuId) -> u64 {
+        self.avg_idle.get(cpu).load(Ordering::Relaxed)
+    }
+
+    /// Try to pull work to a CPU that is about to go idle
+    ///
+    /// Pending migrations to the CPU are drained first, as they need no
+    /// search. Balancing is skipped if the CPU is expected to idle for less
+    /// than the migration cost, since a pulled task would arrive cache cold
+    /// for nothing. Otherwise the CPU's domains are balanced bottom-up
+    /// until a task is pulled or the time spent exceeds the expected idle
+    /// duration. Returns whether a task was pulled.
+    pub fn newidle_balance(&self, cpu: CpuId, domains: &[SchedDomain],
+                           src: &dyn BalanceSource) -> KernelResult<bool> {
+        let avg_idle = self.avg_idle(cpu);
+        let mut pulled = self.drain_migrations(cpu, src) > 0;
+        if !pulled && avg_idle >= self.migration_cost() {
+            let config = self.config.read().clone();
+            let start = Timestamp::now().as_nanos();
+            for domain in domains {
+                if domain.has_flag(SD_NUMA) && !config.numa_aware {
+                    continue;
+                }
+                if Timestamp::now().as_nanos().saturating_sub(start) >= avg_idle {
+                    break;
+                }
+                if self.balance_domain(cpu, domain, &config, src)? > 0 {
+                    pulled = true;
+                    break;
+                }
+            }
+        }
+
+        let counter = if pulled {
+            &self.stats.newidle_balance_success
+        } else {
+            &self.stats.newidle_balance_fail
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        Ok(pulled)
+    }
+
+    /// Record and perform the move of a task to another CPU
+    pub fn migrate_task_safe(&self, task: &Task, target_cpu: CpuId) -> KernelResult<()> {
+        task.set_cpu(target_cpu)?;
+        self.record_migration(task.id());
+        self.stats.task_migrations.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Log migration statistics
+    pub fn print_migration_info(&self) {
+        kernel_info!("Balance migrations: {}", self.stats.balance_migrations.load(Ordering::Relaxed));
+        kernel_info!("Misfit migrations: {}", self.stats.misfit_migrations.load(Ordering::Relaxed));
+        kernel_info!("Task migrations: {}", self.stats.task_migrations.load(Ordering::Relaxed));
+        kernel_info!("Cache hot skips: {} (migration cost {}ns)",
+                    self.stats.cache_hot_skips.load(Ordering::Relaxed), self.migration_cost());
+        kernel_info!("Active balances: {}", self.stats.active_balance_count.load(Ordering::Relaxed));
+        kernel_info!("Transient imbalances skipped: {}", self.stats.transient_skips.load(Ordering::Relaxed));
+        kernel_info!("Throttled migrations: {} (min interval {}ns)",
+                    self.stats.throttled_migrations.load(Ordering::Relaxed),
+                    self.config.read().min_migration_interval_ns);
+        kernel_info!("Newly idle balances: {} pulled, {} failed",
+                    self.stats.newidle_balance_success.load(Ordering::Relaxed),
+                    self.stats.newidle_balance_fail.load(Ordering::Relaxed));
+        kernel_info!("Pending migrations: {} (high water {}), {} deferred, {} drained",
+                    self.pending.lock().len(),
+                    self.stats.pending_high_water.load(Ordering::Relaxed),
+                    self.stats.deferred_migrations.load(Ordering::Relaxed),
+                    self.stats.drained_migrations.load(Ordering::Relaxed));
+    }
+
+    /// Have `busiest_cpu`'s stopper push its running task to `this_cpu`
+    ///
+    /// Fires at most once per `interval_ns` per busiest CPU. Returns
+    /// whether the push was queued.
+    fn active_balance(&self, busiest_cpu: CpuId, this_cpu: CpuId, interval_ns: u64,
+                      src: &dyn BalanceSource) -> KernelResult<bool> {
+        let now = Timestamp::now().as_nanos();
+        let task = match src.running_task(busiest_cpu) {
+            Some(task) if src.can_run_on(task, this_cpu) && !self.is_throttled(task, now) => task,
+            _ => return Ok(false),
+        };
+        let last = self.last_active_balance.get(busiest_cpu);
+        let prev = last.load(Ordering::Relaxed);
+        if prev != 0 && now.saturating_sub(prev) < interval_ns {
+            return Ok(false);
+        }
+        if last.compare_exchange(prev, now, Ordering::AcqRel, Ordering::Relaxed).is_err() {
+            return Ok(false);
+        }
+        src.push_running_task(task, busiest_cpu, this_cpu)?;
+        self.record_migration(task);
+        self.stats.active_balance_count.fetch_add(1, Ordering::Relaxed);
+        kernel_debug!("Active balance: pushing running task {} from CPU {} to CPU {}",
+                     task.as_u64(), busiest_cpu.as_u32(), this_cpu.as_u32());
+        Ok(true)
+    }
+
+    /// Record that a task migrated now, dropping its pending migration
+    fn record_migration(&self, task: TaskId) {
+        self.last_migrated.write().insert(task, Timestamp::now().as_nanos());
+        self.pending.lock().remove(&task);
+    }
+
+    /// Check whether a task migrated within the minimum migration interval
+    fn is_throttled(&self, task: TaskId, now: u64) -> bool {
+        let interval = self.config.read().min_migration_interval_ns;
+        if interval == 0 {
+            return false;
+        }
+        match self.last_migrated.read().get(&task) {
+            Some(&at) => now.saturating_sub(at) < interval,
+            None => false,
+        }
+    }
+
+    /// Check whether a task last ran on a CPU other than `dst` within the
+    /// migration cost
+    fn is_cache_hot(&self, task: TaskId, dst: CpuId, now: u64) -> bool {
+        let cost = self.migration_cost();
+        if cost == 0 {
+            return false;
+        }
+        match self.last_ran.read().get(&task) {
+            Some(last) => last.cpu != dst && now.saturating_sub(last.at) < cost,
+            None => false,
+        }
+    }
+
+    /// Pull one misfit task from a smaller CPU of the span onto `this_cpu`
+    fn pull_misfit_task(&self, this_cpu: CpuId, span: &CpuMask, src: &dyn BalanceSource) -> KernelResult<usize> {
+        let this_capacity = src.cpu_capacity(this_cpu);
+        for cpu in span.iter().filter(|&cpu| cpu != this_cpu) {
+            let capacity = src.cpu_capacity(cpu);
+            if capacity >= this_capacity {
+                continue;
+            }
+            let now = Timestamp::now().as_nanos();
+            let misfit = src.candidates(cpu).into_iter()
+                .filter(|c| Self::is_misfit(c.util, capacity))
+                .filter(|c| src.can_run_on(c.task, this_cpu) && !self.is_throttled(c.task, now))
+                .max_by_key(|c| c.util);
+            if let Some(candidate) = misfit {
+                src.move_task(candidate.task, this_cpu)?;
+                self.record_migration(candidate.task);
+                self.stats.misfit_migrations.fetch_add(1, Ordering::Relaxed);
+                kernel_debug!("Misfit task {} moved from CPU {} (cap {}) to CPU {} (cap {})",
+                             candidate.task.as_u64(), cpu.as_u32(), capacity,
+                             this_cpu.as_u32(), this_capacity);
+                return Ok(1);
+            }
+        }
+        Ok(0)
+    }
+
+    /// Aggregate load statistics of a group
+    fn group_stats(group: &CpuMask, src: &dyn BalanceSource) -> GroupStats {
+        group.iter().fold(GroupStats::default(), |mut stats, cpu| {
+            stats.load += src.cpu_load(cpu);
+            stats.capacity += src.cpu_capacity(cpu) as u64;
+            stats.nr_running += src.nr_running(cpu);
+            stats
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kernel::scheduler::domains::{DomainLevel, SD_ASYM_CPUCAPACITY};
+    use alloc::collections::BTreeMap;
+    use core::cell::RefCell;
+
+    /// Two CPUs: CPU 0 is a little core (capacity 512), CPU 1 a big one
+    #[derive(Default)]
+    struct MockSystem {
+        queues: RefCell<BTreeMap<u32, Vec<MigrationCandidate>>>,
+        running: RefCell<BTreeMap<u32, MigrationCandidate>>,
+        pinned: Vec<TaskId>,
+        trends: RefCell<BTreeMap<u32, i32>>,
+    }
+
+    impl BalanceSource for MockSystem {
+        fn cpu_load(&self, cpu: CpuId) -> u64 {
+            let queued: u64 = self.queues.borrow().get(&cpu.as_u32())
+                .map(|q| q.iter().map(|c| c.load).sum()).unwrap_or(0);
+            queued + self.running.borrow().get(&cpu.as_u32()).map_or(0, |c| c.load)
+        }
+        fn cpu_capacity(&self, cpu: CpuId) -> u32 {
+            if cpu.as_u32() == 0 { 512 } else { 1024 }
+        }
+        fn nr_running(&self, cpu: CpuId) -> u32 {
+            let queued = self.queues.borrow().get(&cpu.as_u32()).map(|q| q.len() as u32).unwrap_or(0);
+            queued + self.running.borrow().contains_key(&cpu.as_u32()) as u32
+        }
+        fn util_trend(&self, cpu: CpuId) -> i32 {
+            self.trends.borrow().get(&cpu.as_u32()).copied().unwrap_or(0)
+        }
+        fn candidates(&self, cpu: CpuId) -> Vec<MigrationCandidate> {
+            self.queues.borrow().get(&cpu.as_u32()).cloned().unwrap_or_default()
+        }
+        fn can_run_on(&self, task: TaskId, _cpu: CpuId) -> bool {
+            !self.pinned.contains(&task)
+        }
+        fn move_task(&self, task: TaskId, dst: CpuId) -> KernelResult<()> {
+            let mut queues = self.queues.borrow_mut();
+            let mut found = None;
+            for queue in queues.values_mut() {
+                if let Some(pos) = queue.iter().position(|c| c.task == task) {
+                    found = Some(queue.remove(pos));
+                }
+            }
+            queues.entry(dst.as_u32()).or_default().push(found.unwrap());
+            Ok(())
+        }
+        fn running_task(&self, cpu: CpuId) -> Option<TaskId> {
+            self.running.borrow().get(&cpu.as_u32()).map(|c| c.task)
+        }
+        fn push_running_task(&self, _task: TaskId, cpu: CpuId, dst: CpuId) -> KernelResult<()> {
+            let running = self.running.borrow_mut().remove(&cpu.as_u32()).unwrap();
+            self.queues.borrow_mut().entry(dst.as_u32()).or_default().push(running);
+            Ok(())
+        }
+    }
+
+    fn two_cpu_domain() -> SchedDomain {
+        let mut span = CpuMask::empty();
+        span.set(CpuId::new(0));
+        span.set(CpuId::new(1));
+        let groups = span.iter().map(|cpu| {
+            let mut g = CpuMask::empty();
+            g.set(cpu);
+            g
+        }).collect();
+        SchedDomain {
+            level: DomainLevel::Mc,
+            span,
+            groups,
+            balance_interval_ms: 2,
+            balancer: CpuId::new(0),
+            last_balance: 0,
+            flags: SD_ASYM_CPUCAPACITY,
+        }
+    }
+
+    /// Pile pinned load onto the CPU task 1 is queued on and balance toward
+    /// the other CPU, `rounds` times; returns the number of tasks moved
+    fn bounce(migration: &MigrationScheduler, config: &LoadBalanceConfig, rounds: usize) -> usize {
+        let task = TaskId::new(1);
+        let system = MockSystem { pinned: vec![TaskId::new(2)], ..Default::default() };
+        system.queues.borrow_mut().insert(0, vec![MigrationCandidate { task, load: 100, util: 100 }]);
+
+        let mut moved = 0;
+        for _ in 0..rounds {
+            let from = system.queues.borrow().iter()
+                .find(|(_, q)| q.iter().any(|c| c.task == task))
+                .map(|(&cpu, _)| cpu).unwrap();
+            let hog = MigrationCandidate { task: TaskId::new(2), load: if from == 0 { 200 } else { 600 }, util: 100 };
+            {
+                let mut queues = system.queues.borrow_mut();
+                queues.values_mut().for_each(|q| q.retain(|c| c.task != hog.task));
+                queues.get_mut(&from).unwrap().push(hog);
+            }
+            moved += migration.balance_domain(CpuId::new(1 - from), &two_cpu_domain(), config, &system).unwrap();
+        }
+        moved
+    }
+
+    #[test]
+    fn test_busy_little_core_offloads_to_idle_big_core() {
+        let candidate = |id| MigrationCandidate { task: TaskId::new(id), load: 300, util: 300 };
+        let mut queues = BTreeMap::new();
+        queues.insert(0, vec![candidate(1), candidate(2)]);
+        let system = MockSystem { queues: RefCell::new(queues), ..Default::default() };
+
+        let migration = MigrationScheduler::with_config(LoadBalanceConfig::default());
+        let moved = migration.balance_domain(CpuId::new(1), &two_cpu_domain(),
+                                             &LoadBalanceConfig::default(), &system).unwrap();
+        assert_eq!(moved, 1);
+        assert_eq!(system.nr_running(CpuId::new(1)), 1);
+    }
+
+    #[test]
+    fn test_misfit_task_moves_to_big_core() {
+        let mut queues = BTreeMap::new();
+        queues.insert(0, vec![MigrationCandidate { task: TaskId::new(7), load: 500, util: 480 }]);
+        queues.insert(1, vec![MigrationCandidate { task: TaskId::new(8), load: 100, util: 100 }]);
+        let system = MockSystem { queues: RefCell::new(queues), ..Default::default() };
+
+        let migration = MigrationScheduler::with_config(LoadBalanceConfig::default());
+        migration.balance_domain(CpuId::new(1), &two_cpu_domain(),
+                                 &LoadBalanceConfig::default(), &system).unwrap();
+        assert_eq!(system.nr_running(CpuId::new(0)), 0);
+        assert_eq!(migration.stats().misfit_migrations.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_draining_cpu_is_left_alone() {
+        let candidate = |id| MigrationCandidate { task: TaskId::new(id), load: 200, util: 200 };
+        let mut queues = BTreeMap::new();
+        queues.insert(0, vec![candidate(1), candidate(2)]);
+        queues.insert(1, vec![MigrationCandidate { task: TaskId::new(3), load: 450, util: 450 }]);
+        let system = MockSystem { queues: RefCell::new(queues), ..Default::default() };
+        let migration = MigrationScheduler::with_config(LoadBalanceConfig::default());
+        let config = LoadBalanceConfig::default();
+
+        // 800 vs 450 per capacity, but CPU 0 is shedding utilization fast
+        system.trends.borrow_mut().insert(0, -150);
+        assert_eq!(migration.balance_domain(CpuId::new(1), &two_cpu_domain(), &config, &system).unwrap(), 0);
+        assert_eq!(migration.stats().transient_skips.load(Ordering::Relaxed), 1);
+
+        // A slow decline is not a transient spike
+        system.trends.borrow_mut().insert(0, -50);
+        assert_eq!(migration.balance_domain(CpuId::new(1), &two_cpu_domain(), &config, &system).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_cache_hot_tasks_stay_unless_imbalance_is_severe() {
+        let candidate = |id| MigrationCandidate { task: TaskId::new(id), load: 200, util: 200 };
+        let mut queues = BTreeMap::new();
+        queues.insert(0, vec![candidate(1), candidate(2)]);
+        queues.insert(1, vec![MigrationCandidate { task: TaskId::new(3), load: 450, util: 450 }]);
+        let system = MockSystem { queues: RefCell::new(queues), ..Default::default() };
+
+        let migration = MigrationScheduler::with_config(LoadBalanceConfig::default());
+        let now = Timestamp::now().as_nanos();
+        migration.record_task_run(TaskId::new(1), CpuId::new(0), now);
+        migration.record_task_run(TaskId::new(2), CpuId::new(0), now);
+        assert_eq!(migration.can_migrate_task(TaskId::new(1), CpuId::new(1), &system),
+                   MigrationDecision::CacheHot);
+        assert_eq!(migration.can_migrate_task(TaskId::new(1), CpuId::new(0), &system),
+                   MigrationDecision::Allowed);
+
+        // 800 vs 450 per capacity: imbalanced, but not severely
+        let config = LoadBalanceConfig::default();
+        assert_eq!(migration.balance_domain(CpuId::new(1), &two_cpu_domain(), &config, &system).unwrap(), 0);
+        assert_eq!(migration.stats().cache_hot_skips.load(Ordering::Relaxed), 2);
+
+        // With the big core idle the imbalance is severe
+        system.queues.borrow_mut().remove(&1);
+        assert_eq!(migration.balance_domain(CpuId::new(1), &two_cpu_domain(), &config, &system).unwrap(), 1);
+
+        migration.set_migration_cost(0).unwrap();
+        assert_eq!(migration.can_migrate_task(TaskId::new(2), CpuId::new(1), &system),
+                   MigrationDecision::Allowed);
+    }
+
+    #[test]
+    fn test_running_task_is_pushed_once_per_interval() {
+        let candidate = |id| MigrationCandidate { task: TaskId::new(id), load: 100, util: 100 };
+        let mut queues = BTreeMap::new();
+        queues.insert(0, vec![candidate(2)]);
+        let mut running = BTreeMap::new();
+        running.insert(0, candidate(1));
+        let system = MockSystem {
+            queues: RefCell::new(queues),
+            running: RefCell::new(running),
+            pinned: vec![TaskId::new(2)],
+            ..Default::default()
+        };
+
+        // The queued task is pinned, so only the running one can move
+        let migration = MigrationScheduler::with_config(LoadBalanceConfig::default());
+        let config = LoadBalanceConfig::default();
+        assert_eq!(migration.balance_domain(CpuId::new(1), &two_cpu_domain(), &config, &system).unwrap(), 0);
+        assert_eq!(system.running_task(CpuId::new(0)), None);
+        assert_eq!(system.nr_running(CpuId::new(1)), 1);
+        assert_eq!(migration.stats().active_balance_count.load(Ordering::Relaxed), 1);
+
+        // A second push from the same CPU within the interval is refused
+        system.queues.borrow_mut().remove(&1);
+        system.running.borrow_mut().insert(0, candidate(3));
+        migration.balance_domain(CpuId::new(1), &two_cpu_domain(), &config, &system).unwrap();
+        assert_eq!(system.running_task(CpuId::new(0)), Some(TaskId::new(3)));
+        assert_eq!(migration.stats().active_balance_count.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_newidle_balance_skips_short_idle() {
+        let candidate = |id| MigrationCandidate { task: TaskId::new(id), load: 300, util: 300 };
+        let mut queues = BTreeMap::new();
+        queues.insert(0, vec![candidate(1), candidate(2)]);
+        let system = MockSystem { queues: RefCell::new(queues), ..Default::default() };
+        let domains = [two_cpu_domain()];
+
+        // Idle periods shorter than the migration cost aren't worth a pull
+        let migration = MigrationScheduler::with_config(LoadBalanceConfig::default());
+        for _ in 0..64 {
+            migration.update_avg_idle(CpuId::new(1), 10_000);
+        }
+        assert!(migration.avg_idle(CpuId::new(1)) < migration.migration_cost());
+        assert!(!migration.newidle_balance(CpuId::new(1), &domains, &system).unwrap());
+        assert_eq!(system.nr_running(CpuId::new(1)), 0);
+
+        for _ in 0..64 {
+            migration.update_avg_idle(CpuId::new(1), 10_000_000);
+        }
+        assert!(migration.newidle_balance(CpuId::new(1), &domains, &system).unwrap());
+        assert_eq!(system.nr_running(CpuId::new(1)), 1);
+        assert_eq!(migration.stats().newidle_balance_success.load(Ordering::Relaxed), 1);
+        assert_eq!(migration.stats().newidle_balance_fail.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_min_migration_interval_stops_bouncing() {
+        // Without a minimum interval the task follows every load shift
+        let unthrottled = LoadBalanceConfig { min_migration_interval_ns: 0, ..Default::default() };
+        let migration = MigrationScheduler::with_config(unthrottled.clone());
+        assert_eq!(bounce(&migration, &unthrottled, 4), 4);
+        assert_eq!(migration.stats().throttled_migrations.load(Ordering::Relaxed), 0);
+
+        let throttled = LoadBalanceConfig { min_migration_interval_ns: 1_000_000_000, ..Default::default() };
+        let migration = MigrationScheduler::with_config(throttled.clone());
+        assert_eq!(bounce(&migration, &throttled, 4), 1);
+        assert_eq!(migration.stats().throttled_migrations.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn test_pending_migrations_drain_to_their_target() {
+        let candidate = |id| MigrationCandidate { task: TaskId::new(id), load: 100, util: 100 };
+        let mut queues = BTreeMap::new();
+        queues.insert(0, vec![candidate(1), candidate(2), candidate(3), candidate(4)]);
+        let system = MockSystem { queues: RefCell::new(queues), pinned: vec![TaskId::new(3)], ..Default::default() };
+        let config = LoadBalanceConfig { min_migration_interval_ns: 1_000_000_000, ..Default::default() };
+        let migration = MigrationScheduler::with_config(config);
+        let (cpu0, cpu1) = (CpuId::new(0), CpuId::new(1));
+
+        // Task 4 just moved, so it has to wait for its interval
+        migration.record_migration(TaskId::new(4));
+        migration.defer_migration(TaskId::new(1), cpu0);
+        migration.defer_migration(TaskId::new(1), cpu1);
+        migration.defer_migration(TaskId::new(2), cpu0);
+        migration.defer_migration(TaskId::new(3), cpu1);
+        migration.defer_migration(TaskId::new(4), cpu1);
+        assert_eq!(migration.pending_migrations(),
+                   vec![(TaskId::new(1), cpu1), (TaskId::new(2), cpu0), (TaskId::new(3), cpu1), (TaskId::new(4), cpu1)]);
+
+        // The pinned task is dropped, the throttled one stays queued
+        assert_eq!(migration.drain_migrations(cpu1, &system), 1);
+        assert_eq!(system.nr_running(cpu1), 1);
+        assert_eq!(migration.pending_migrations(), vec![(TaskId::new(2), cpu0), (TaskId::new(4), cpu1)]);
+        assert_eq!(migration.stats().pending_high_water.load(Ordering::Relaxed), 4);
+        assert_eq!(migration.stats().drained_migrations.load(Ordering::Relaxed), 1);
+    }
+}
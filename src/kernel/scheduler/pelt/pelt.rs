@@ -0,0 +1,789 @@
+//! # Per-Entity Load Tracking (PELT)
+//!
+//! This module tracks the load and utilization of every scheduling entity
+//! (tasks and per-CPU runqueues) as a geometric series of 1024us periods,
+//! where the contribution of a period decays by `y` such that `y^32 = 0.5`
+//! (a 32ms half-life by default).
+//! The resulting averages drive load balancing, frequency selection and
+//! energy-aware placement.
+//!
+//! ## Features
+//! - Geometric load, runnable and utilization sums per entity
+//! - Frequency invariance: time spent at a lower frequency contributes less
+//! - CPU capacity invariance for asymmetric (big.LITTLE) systems
+//! - Estimated utilization (util_est) surviving sleep-time decay
+//! - Runqueue load split into runnable load (tasks competing for the CPU)
+//!   and blocked load (the decaying contribution of tasks that slept)
+//! - Configurable half-life (8-64ms) for faster or smoother tracking
+//! - Integer-only arithmetic suitable for interrupt context
+//!
+//! ## Usage
+//! ```rust
+//! use crate::kernel::scheduler::pelt::PeltScheduler;
+//!
+//! let pelt = PeltScheduler::new();
+//!
+//! // Report that CPU 2 now runs at half of its maximum frequency
+//! pelt.set_freq_scale(CpuId::new(2), 512)?;
+//!
+//! // Account a running task on the scheduler tick
+//! pelt.update_task_load(task.id(), CpuId::new(2), now_ns, 1024, true, true);
+//! let util = pelt.task_util(task.id());
+//! ```
+
+use crate::kernel::task::{Task, TaskId};
+use crate::kernel::cpu::{CpuId, CpuMask};
+use crate::kernel::error::{KernelResult, SchedulerError};
+use crate::kernel::sync::{SpinLock, RwLock};
+use crate::kernel::log::kernel_debug;
+use crate::kernel::memory::percpu::PerCpu;
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// Capacity of the biggest CPU running at its maximum frequency
+pub const SCHED_CAPACITY_SCALE: u32 = 1024;
+
+/// Shift matching `SCHED_CAPACITY_SCALE`
+const SCHED_CAPACITY_SHIFT: u32 = 10;
+
+/// Length of a PELT period in microseconds
+const PELT_PERIOD_US: u64 = 1024;
+
+/// Default number of periods after which a contribution is halved
+pub const PELT_DEFAULT_HALF_LIFE_MS: u32 = 32;
+
+/// Valid range of the half-life (in milliseconds / periods)
+pub const PELT_MIN_HALF_LIFE_MS: u32 = 8;
+pub const PELT_MAX_HALF_LIFE_MS: u32 = 64;
+
+/// Weight of a new sample in the util_est EWMA (1/4)
+const UTIL_EST_WEIGHT_SHIFT: u32 = 2;
+
+/// Margin a task must leave on a CPU to fit there (~20%)
+const CAPACITY_MARGIN: u64 = 1280;
+
+/// Decay parameters derived from the configured half-life
+///
+/// `y` is chosen such that `y^half_life = 0.5`; `yn_inv[n]` holds
+/// `y^n * 2^32` for `n < half_life` and `load_avg_max` is the value of a
+/// fully saturated series `1024 * (1 + y + y^2 + ...)`.
+#[derive(Debug, Clone)]
+pub struct PeltDecay {
+    half_life: u64,
+    yn_inv: Vec<u32>,
+    load_avg_max: u64,
+}
+
+impl PeltDecay {
+    /// Compute the decay parameters for a half-life in milliseconds
+    pub fn new(half_life_ms: u32) -> KernelResult<Self> {
+        if !(PELT_MIN_HALF_LIFE_MS..=PELT_MAX_HALF_LIFE_MS).contains(&half_life_ms) {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        let half_life = half_life_ms as u64;
+
+        // Largest y (Q32) with y^half_life <= 0.5
+        let (mut lo, mut hi) = (1u64 << 31, 1u64 << 32);
+        while hi - lo > 1 {
+            let mid = (lo + hi) / 2;
+            if q32_pow(mid, half_life) <= 1 << 31 {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        let y = lo;
+
+        let yn_inv = (0..half_life)
+            .map(|n| q32_pow(y, n).min(u32::MAX as u64) as u32)
+            .collect();
+
+        // Sum the series until it stops growing
+        let mut load_avg_max = PELT_PERIOD_US;
+        loop {
+            let next = ((load_avg_max as u128 * y as u128) >> 32) as u64 + PELT_PERIOD_US;
+            if next == load_avg_max {
+                break;
+            }
+            load_avg_max = next;
+        }
+
+        Ok(Self { half_life, yn_inv, load_avg_max })
+    }
+
+    /// Half-life in milliseconds
+    pub fn half_life_ms(&self) -> u32 {
+        self.half_life as u32
+    }
+
+    /// Maximum value of a fully saturated series
+    pub fn load_avg_max(&self) -> u64 {
+        self.load_avg_max
+    }
+
+    /// Decay `val` by `y^n`
+    fn decay_load(&self, mut val: u64, n: u64) -> u64 {
+        if n > self.half_life * 63 {
+            return 0;
+        }
+        let mut local_n = n;
+        if local_n >= self.half_life {
+            val >>= local_n / self.half_life;
+            local_n %= self.half_life;
+        }
+        ((val as u128 * self.yn_inv[local_n as usize] as u128) >> 32) as u64
+    }
+
+    /// Sum of the contributions of `periods` elapsed periods
+    ///
+    /// `d1` is the remainder of the period that was in progress, `d3` the
+    /// part of the current period that has elapsed so far.
+    fn accumulate_segments(&self, periods: u64, d1: u64, d3: u64) -> u64 {
+        let c1 = self.decay_load(d1, periods);
+        let c2 = self.load_avg_max - self.decay_load(self.load_avg_max, periods) - PELT_PERIOD_US;
+        c1 + c2 + d3
+    }
+}
+
+impl Default for PeltDecay {
+    fn default() -> Self {
+        Self::new(PELT_DEFAULT_HALF_LIFE_MS).expect("default PELT half-life is valid")
+    }
+}
+
+/// Raise a Q32 fixed-point value to an integer power
+fn q32_pow(y: u64, n: u64) -> u64 {
+    let mut result = 1u64 << 32;
+    for _ in 0..n {
+        result = ((result as u128 * y as u128) >> 32) as u64;
+    }
+    result
+}
+
+/// Load tracking state of one scheduling entity
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SchedAvg {
+    /// Last time the sums were updated (nanoseconds)
+    pub last_update_time: u64,
+    /// Weighted runnable time
+    pub load_sum: u64,
+    /// Runnable time scaled by capacity
+    pub runnable_sum: u64,
+    /// Running time scaled by frequency and capacity
+    pub util_sum: u64,
+    /// Microseconds already accumulated in the current period
+    pub period_contrib: u32,
+    /// Average load
+    pub load_avg: u64,
+    /// Average runnable utilization (0-1024)
+    pub runnable_avg: u64,
+    /// Average invariant utilization (0-1024)
+    pub util_avg: u64,
+}
+
+/// Estimated utilization of a task
+///
+/// PELT decays a task's utilization while it sleeps, so a periodic task
+/// looks small on wakeup. util_est remembers the utilization seen at the
+/// last dequeue and smooths it over activations.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UtilEst {
+    /// Utilization observed at the last dequeue
+    pub enqueued: u32,
+    /// Moving average of the dequeue-time utilization
+    pub ewma: u32,
+}
+
+impl UtilEst {
+    /// Estimated utilization (0-1024)
+    pub fn value(&self) -> u32 {
+        self.enqueued.max(self.ewma)
+    }
+
+    /// Fold a dequeue-time utilization sample into the estimate
+    pub fn update(&mut self, util: u32) {
+        let util = util.min(SCHED_CAPACITY_SCALE);
+        self.enqueued = util;
+        if self.ewma <= util {
+            // Ramp up immediately, decay slowly
+            self.ewma = util;
+        } else {
+            let diff = self.ewma - util;
+            self.ewma -= diff >> UTIL_EST_WEIGHT_SHIFT;
+        }
+    }
+}
+
+/// Load left on a runqueue by tasks that went to sleep on it
+///
+/// It decays like a task's load while the tasks sleep, and a task's
+/// (equally decayed) load is taken out again when it wakes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BlockedLoad {
+    /// Decayed load of the sleeping tasks
+    pub load_avg: u64,
+    /// Time `load_avg` was last decayed to (nanoseconds)
+    pub last_decay: u64,
+}
+
+/// Per-CPU PELT state
+#[derive(Debug)]
+pub struct PeltRq {
+    /// Current frequency relative to the maximum frequency (0-1024)
+    pub freq_scale: AtomicU32,
+    /// Compute capacity relative to the biggest CPU (1-1024)
+    pub capacity_scale: AtomicU32,
+    /// Aggregate load of the runqueue
+    pub avg: SpinLock<SchedAvg>,
+    /// Sum of util_est of the tasks enqueued on this CPU
+    pub util_est_enqueued: AtomicU32,
+    /// Tasks runnable on this CPU, whose load is its runnable load
+    pub runnable: SpinLock<BTreeSet<TaskId>>,
+    /// Load of the tasks that went to sleep on this CPU
+    pub blocked: SpinLock<BlockedLoad>,
+}
+
+impl Default for PeltRq {
+    fn default() -> Self {
+        Self {
+            freq_scale: AtomicU32::new(SCHED_CAPACITY_SCALE),
+            capacity_scale: AtomicU32::new(SCHED_CAPACITY_SCALE),
+            avg: SpinLock::new(SchedAvg::default()),
+            util_est_enqueued: AtomicU32::new(0),
+            runnable: SpinLock::new(BTreeSet::new()),
+            blocked: SpinLock::new(BlockedLoad::default()),
+        }
+    }
+}
+
+/// PELT scheduler component
+pub struct PeltScheduler {
+    per_cpu: PerCpu<PeltRq>,
+    tasks: RwLock<BTreeMap<TaskId, SchedAvg>>,
+    util_est: RwLock<BTreeMap<TaskId, UtilEst>>,
+    /// CPU whose blocked load each sleeping task is part of
+    sleeping: RwLock<BTreeMap<TaskId, CpuId>>,
+    decay: RwLock<PeltDecay>,
+}
+
+impl PeltScheduler {
+    /// Create a new PELT instance with all CPUs at full capacity
+    pub fn new() -> Self {
+        Self {
+            per_cpu: PerCpu::new(PeltRq::default()),
+            tasks: RwLock::new(BTreeMap::new()),
+            util_est: RwLock::new(BTreeMap::new()),
+            sleeping: RwLock::new(BTreeMap::new()),
+            decay: RwLock::new(PeltDecay::default()),
+        }
+    }
+
+    /// Create a PELT instance with a custom half-life
+    ///
+    /// A shorter half-life reacts faster to load changes at the cost of
+    /// noisier averages. Valid values are 8 to 64 milliseconds.
+    pub fn with_half_life(ms: u32) -> KernelResult<Self> {
+        let pelt = Self::new();
+        *pelt.decay.write() = PeltDecay::new(ms)?;
+        kernel_debug!("PELT: half-life set to {} ms", ms);
+        Ok(pelt)
+    }
+
+    /// Change the half-life at runtime
+    ///
+    /// Sums accumulated under the old decay are meaningless under the new
+    /// one, so all existing task and runqueue averages are reset.
+    pub fn set_half_life(&self, ms: u32) -> KernelResult<()> {
+        let mut decay = self.decay.write();
+        *decay = PeltDecay::new(ms)?;
+        let mut tasks = self.tasks.write();
+
+        for sa in tasks.values_mut() {
+            *sa = SchedAvg { last_update_time: sa.last_update_time, ..SchedAvg::default() };
+        }
+        for cpu in CpuMask::online().iter() {
+            let mut sa = self.per_cpu.get(cpu).avg.lock();
+            *sa = SchedAvg { last_update_time: sa.last_update_time, ..SchedAvg::default() };
+        }
+
+        kernel_debug!("PELT: half-life changed to {} ms, averages reset", ms);
+        Ok(())
+    }
+
+    /// Current half-life in milliseconds
+    pub fn half_life_ms(&self) -> u32 {
+        self.decay.read().half_life_ms()
+    }
+
+    /// Set the current frequency scale of a CPU (1024 = maximum frequency)
+    pub fn set_freq_scale(&self, cpu: CpuId, scale: u32) -> KernelResult<()> {
+        if scale > SCHED_CAPACITY_SCALE {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        self.per_cpu.get(cpu).freq_scale.store(scale, Ordering::Release);
+        Ok(())
+    }
+
+    /// Set the compute capacity of a CPU (1024 = biggest CPU)
+    pub fn set_capacity_scale(&self, cpu: CpuId, cap: u32) -> KernelResult<()> {
+        if cap == 0 || cap > SCHED_CAPACITY_SCALE {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        self.per_cpu.get(cpu).capacity_scale.store(cap, Ordering::Release);
+        kernel_debug!("PELT: CPU {} capacity set to {}", cpu.as_u32(), cap);
+        Ok(())
+    }
+
+    /// Get the current frequency scale of a CPU
+    pub fn freq_scale(&self, cpu: CpuId) -> u32 {
+        self.per_cpu.get(cpu).freq_scale.load(Ordering::Acquire)
+    }
+
+    /// Get the compute capacity of a CPU
+    pub fn capacity_scale(&self, cpu: CpuId) -> u32 {
+        self.per_cpu.get(cpu).capacity_scale.load(Ordering::Acquire)
+    }
+
+    /// Capacity currently available on a CPU given its frequency
+    pub fn current_capacity(&self, cpu: CpuId) -> u32 {
+        cap_scale(self.capacity_scale(cpu) as u64, self.freq_scale(cpu)) as u32
+    }
+
+    /// Update the load of a task that is runnable and/or running on `cpu`
+    pub fn update_task_load(&self, task: TaskId, cpu: CpuId, now: u64, weight: u64,
+                            runnable: bool, running: bool) {
+        let freq = self.freq_scale(cpu);
+        let cap = self.capacity_scale(cpu);
+        let decay = self.decay.read();
+        let mut tasks = self.tasks.write();
+        let sa = tasks.entry(task).or_insert_with(|| SchedAvg {
+            last_update_time: now,
+            ..SchedAvg::default()
+        });
+        if update_load_sum(&decay, sa, now, weight, runnable, running, freq, cap) {
+            update_load_avg(&decay, sa, weight);
+        }
+    }
+
+    /// Update the aggregate load of a CPU runqueue
+    pub fn update_cpu_load(&self, cpu: CpuId, now: u64, weight: u64, running: bool) {
+        let rq = self.per_cpu.get(cpu);
+        let freq = rq.freq_scale.load(Ordering::Acquire);
+        let cap = rq.capacity_scale.load(Ordering::Acquire);
+        let decay = self.decay.read();
+        let mut sa = rq.avg.lock();
+        if update_load_sum(&decay, &mut sa, now, weight, weight > 0, running, freq, cap) {
+            update_load_avg(&decay, &mut sa, weight);
+        }
+    }
+
+    /// Invariant utilization of a task (0-1024)
+    pub fn task_util(&self, task: TaskId) -> u32 {
+        self.tasks.read().get(&task).map(|sa| sa.util_avg as u32).unwrap_or(0)
+    }
+
+    /// Load average of a task
+    pub fn task_load(&self, task: TaskId) -> u64 {
+        self.tasks.read().get(&task).map(|sa| sa.load_avg).unwrap_or(0)
+    }
+
+    /// Utilization of a task relative to the capacity currently offered by `cpu`
+    pub fn task_util_normalized(&self, task: TaskId, cpu: CpuId) -> u32 {
+        normalize_util(self.task_util(task), self.current_capacity(cpu))
+    }
+
+    /// Estimated utilization of a task: `max(pelt_util, util_est)`
+    ///
+    /// Used for wakeup placement and frequency selection, where the
+    /// sleep-decayed PELT value underestimates periodic tasks.
+    pub fn task_util_est(&self, task: &Task) -> u32 {
+        let id = task.id();
+        let est = self.util_est.read().get(&id).map(UtilEst::value).unwrap_or(0);
+        self.task_util(id).max(est)
+    }
+
+    /// Account a task's util_est on the runqueue it is enqueued on
+    pub fn util_est_enqueue(&self, task: TaskId, cpu: CpuId) {
+        let est = self.util_est.read().get(&task).map(UtilEst::value).unwrap_or(0);
+        self.per_cpu.get(cpu).util_est_enqueued.fetch_add(est, Ordering::Relaxed);
+    }
+
+    /// Record the task's utilization at dequeue and remove it from the runqueue sum
+    ///
+    /// Must be called by the dequeue path when a task goes to sleep.
+    pub fn util_est_dequeue(&self, task: TaskId, cpu: CpuId) {
+        let util = self.task_util(task);
+        let mut estimates = self.util_est.write();
+        let est = estimates.entry(task).or_default();
+
+        let rq_est = &self.per_cpu.get(cpu).util_est_enqueued;
+        let _ = rq_est.fetch_update(Ordering::Relaxed, Ordering::Relaxed,
+                                    |v| Some(v.saturating_sub(est.value())));
+        est.update(util);
+    }
+
+    /// Check whether a utilization fits on a CPU with ~20% headroom
+    pub fn task_fits_cpu(&self, util: u32, cpu: CpuId) -> bool {
+        util as u64 * CAPACITY_MARGIN < self.capacity_scale(cpu) as u64 * SCHED_CAPACITY_SCALE as u64
+    }
+
+    /// Invariant utilization of a CPU runqueue (0-1024)
+    pub fn cpu_util(&self, cpu: CpuId) -> u32 {
+        self.per_cpu.get(cpu).avg.lock().util_avg as u32
+    }
+
+    /// Estimated utilization of a CPU: `max(cpu_util, sum of enqueued util_est)`
+    pub fn cpu_util_est(&self, cpu: CpuId) -> u32 {
+        let enqueued = self.per_cpu.get(cpu).util_est_enqueued.load(Ordering::Relaxed);
+        self.cpu_util(cpu).max(enqueued).min(SCHED_CAPACITY_SCALE)
+    }
+
+    /// Load average of a CPU runqueue
+    ///
+    /// This is the aggregate of runnable and recently blocked load; load
+    /// balancing should compare `runnable_avg` instead.
+    pub fn cpu_load(&self, cpu: CpuId) -> u64 {
+        self.per_cpu.get(cpu).avg.lock().load_avg
+    }
+
+    /// A task became runnable on a CPU
+    ///
+    /// If it was asleep, its load, decayed over the sleep, leaves the
+    /// blocked load of the CPU it slept on.
+    pub fn enqueue_load(&self, task: TaskId, cpu: CpuId, now: u64) {
+        if let Some(slept_on) = self.sleeping.write().remove(&task) {
+            let decay = self.decay.read();
+            let load = self.tasks.read().get(&task)
+                .map_or(0, |sa| decay.decay_load(sa.load_avg, pelt_periods(sa.last_update_time, now)));
+            let mut blocked = self.per_cpu.get(slept_on).blocked.lock();
+            decay_blocked_load(&decay, &mut blocked, now);
+            blocked.load_avg = blocked.load_avg.saturating_sub(load);
+        }
+        self.per_cpu.get(cpu).runnable.lock().insert(task);
+    }
+
+    /// A task stopped being runnable on a CPU
+    ///
+    /// If it went to sleep (rather than exiting), its load joins the
+    /// blocked load of the CPU.
+    pub fn dequeue_load(&self, task: TaskId, cpu: CpuId, now: u64, sleeping: bool) {
+        let rq = self.per_cpu.get(cpu);
+        if !rq.runnable.lock().remove(&task) || !sleeping {
+            return;
+        }
+        let load = self.task_load(task);
+        let mut blocked = rq.blocked.lock();
+        decay_blocked_load(&self.decay.read(), &mut blocked, now);
+        blocked.load_avg += load;
+        self.sleeping.write().insert(task, cpu);
+    }
+
+    /// A runnable task moved to another CPU
+    ///
+    /// Its util_est moves from the runqueue sum of `from` to that of `to`.
+    pub fn migrate_load(&self, task: TaskId, from: CpuId, to: CpuId) {
+        if !self.per_cpu.get(from).runnable.lock().remove(&task) {
+            return;
+        }
+        self.per_cpu.get(to).runnable.lock().insert(task);
+        let est = self.util_est.read().get(&task).map(UtilEst::value).unwrap_or(0);
+        let _ = self.per_cpu.get(from).util_est_enqueued.fetch_update(Ordering::Relaxed, Ordering::Relaxed,
+                                                                      |v| Some(v.saturating_sub(est)));
+        self.per_cpu.get(to).util_est_enqueued.fetch_add(est, Ordering::Relaxed);
+    }
+
+    /// Decay the blocked load of a CPU up to `now`
+    ///
+    /// Called on the idle path, where no task updates the CPU's load.
+    pub fn decay_blocked(&self, cpu: CpuId, now: u64) {
+        let decay = self.decay.read();
+        decay_blocked_load(&decay, &mut self.per_cpu.get(cpu).blocked.lock(), now);
+    }
+
+    /// Runnable load of a CPU: the load of the tasks competing for it
+    pub fn runnable_avg(&self, cpu: CpuId) -> u64 {
+        let tasks = self.tasks.read();
+        self.per_cpu.get(cpu).runnable.lock().iter()
+            .filter_map(|task| tasks.get(task))
+            .map(|sa| sa.load_avg)
+            .sum()
+    }
+
+    /// Blocked load of a CPU: the decaying load of tasks that slept on it,
+    /// as of its last decay
+    pub fn blocked_avg(&self, cpu: CpuId) -> u64 {
+        self.per_cpu.get(cpu).blocked.lock().load_avg
+    }
+
+    /// Forget the load tracking state of an exiting task
+    pub fn remove_task(&self, task: TaskId) {
+        self.tasks.write().remove(&task);
+        self.util_est.write().remove(&task);
+        self.sleeping.write().remove(&task);
+    }
+}
+
+impl Default for PeltScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Scale `value` by `scale / SCHED_CAPACITY_SCALE`
+#[inline]
+fn cap_scale(value: u64, scale: u32) -> u64 {
+    (value * scale as u64) >> SCHED_CAPACITY_SHIFT
+}
+
+/// Express `util` relative to `capacity`, saturating at 1024
+#[inline]
+fn normalize_util(util: u32, capacity: u32) -> u32 {
+    if capacity == 0 {
+        return SCHED_CAPACITY_SCALE;
+    }
+    ((util as u64 * SCHED_CAPACITY_SCALE as u64) / capacity as u64)
+        .min(SCHED_CAPACITY_SCALE as u64) as u32
+}
+
+/// Number of PELT periods between two times (nanoseconds)
+#[inline]
+fn pelt_periods(from: u64, to: u64) -> u64 {
+    (to.saturating_sub(from) >> 10) / PELT_PERIOD_US
+}
+
+/// Decay a blocked load over the whole periods elapsed up to `now`
+fn decay_blocked_load(decay: &PeltDecay, blocked: &mut BlockedLoad, now: u64) {
+    let periods = pelt_periods(blocked.last_decay, now);
+    if periods > 0 {
+        blocked.load_avg = decay.decay_load(blocked.load_avg, periods);
+        blocked.last_decay += (periods * PELT_PERIOD_US) << 10;
+    }
+}
+
+/// Accumulate the time elapsed since the last update into the sums
+///
+/// The contribution of the elapsed time is scaled by the current frequency
+/// so that running at half speed accrues half the utilization, and the
+/// utilization sum is additionally scaled by the CPU capacity. This is the
+/// equivalent of Linux's `__update_load_sum`, using the configured decay.
+///
+/// Returns `true` if at least one period boundary was crossed and the
+/// averages need to be recomputed.
+pub fn update_load_sum(decay: &PeltDecay, sa: &mut SchedAvg, now: u64, weight: u64,
+                       runnable: bool, running: bool, freq_scale: u32,
+                       cap_scale_cpu: u32) -> bool {
+    let delta_ns = now.saturating_sub(sa.last_update_time);
+    let mut delta = delta_ns >> 10; // ~microseconds
+    if delta == 0 {
+        return false;
+    }
+    sa.last_update_time += delta << 10;
+
+    let (runnable, running) = if weight == 0 { (false, false) } else { (runnable, running) };
+
+    let mut contrib = delta;
+    delta += sa.period_contrib as u64;
+    let periods = delta / PELT_PERIOD_US;
+
+    if periods > 0 {
+        sa.load_sum = decay.decay_load(sa.load_sum, periods);
+        sa.runnable_sum = decay.decay_load(sa.runnable_sum, periods);
+        sa.util_sum = decay.decay_load(sa.util_sum, periods);
+
+        delta %= PELT_PERIOD_US;
+        contrib = decay.accumulate_segments(periods,
+                                            PELT_PERIOD_US - sa.period_contrib as u64,
+                                            delta);
+    }
+    sa.period_contrib = delta as u32;
+
+    let scaled = cap_scale(contrib, freq_scale);
+    if weight > 0 {
+        sa.load_sum += weight * scaled;
+    }
+    if runnable {
+        sa.runnable_sum += scaled * cap_scale_cpu as u64;
+    }
+    if running {
+        sa.util_sum += scaled * cap_scale_cpu as u64;
+    }
+
+    periods > 0
+}
+
+/// Recompute the averages from the sums
+pub fn update_load_avg(decay: &PeltDecay, sa: &mut SchedAvg, weight: u64) {
+    let divider = decay.load_avg_max - PELT_PERIOD_US + sa.period_contrib as u64;
+    sa.load_avg = if weight > 0 { sa.load_sum / divider } else { 0 };
+    sa.runnable_avg = sa.runnable_sum / divider;
+    sa.util_avg = sa.util_sum / divider;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run a fully busy entity for `ms` milliseconds in 1ms steps
+    fn run_busy(decay: &PeltDecay, sa: &mut SchedAvg, ms: u64, freq: u32, cap: u32) {
+        let start = sa.last_update_time;
+        for step in 1..=ms {
+            if update_load_sum(decay, sa, start + step * 1_000_000, 1024, true, true, freq, cap) {
+                update_load_avg(decay, sa, 1024);
+            }
+        }
+    }
+
+    /// Milliseconds a busy entity needs to reach 90% utilization
+    fn convergence_ms(half_life_ms: u32) -> u64 {
+        let decay = PeltDecay::new(half_life_ms).unwrap();
+        let mut sa = SchedAvg::default();
+        for ms in 1..=1000 {
+            if update_load_sum(&decay, &mut sa, ms * 1_000_000, 1024, true, true, 1024, 1024) {
+                update_load_avg(&decay, &mut sa, 1024);
+            }
+            if sa.util_avg >= 921 {
+                return ms;
+            }
+        }
+        u64::MAX
+    }
+
+    #[test]
+    fn test_decay_halves_after_period() {
+        let decay = PeltDecay::default();
+        assert_eq!(decay.decay_load(1024, 0), 1023);
+        let halved = decay.decay_load(1 << 20, 32);
+        assert!(halved <= 1 << 19 && halved >= (1 << 19) - 64);
+    }
+
+    #[test]
+    fn test_default_load_avg_max() {
+        let decay = PeltDecay::default();
+        assert!((47700..=47742).contains(&decay.load_avg_max()));
+    }
+
+    #[test]
+    fn test_half_life_range() {
+        assert!(PeltDecay::new(7).is_err());
+        assert!(PeltDecay::new(65).is_err());
+        assert!(PeltDecay::new(8).is_ok());
+        assert!(PeltDecay::new(64).is_ok());
+    }
+
+    #[test]
+    fn test_shorter_half_life_converges_faster() {
+        let fast = convergence_ms(8);
+        let slow = convergence_ms(32);
+        assert!(fast < slow);
+        // Convergence time scales with the half-life
+        assert!(fast * 3 < slow, "8ms: {} ms, 32ms: {} ms", fast, slow);
+    }
+
+    #[test]
+    fn test_full_frequency_saturates() {
+        let decay = PeltDecay::default();
+        let mut sa = SchedAvg::default();
+        run_busy(&decay, &mut sa, 1000, SCHED_CAPACITY_SCALE, SCHED_CAPACITY_SCALE);
+        assert!(sa.util_avg >= 1000 && sa.util_avg <= 1024);
+    }
+
+    #[test]
+    fn test_half_frequency_is_invariant() {
+        let decay = PeltDecay::default();
+        let mut sa = SchedAvg::default();
+        run_busy(&decay, &mut sa, 1000, 512, SCHED_CAPACITY_SCALE);
+
+        // Raw (invariant) utilization: ~50% of the biggest CPU at max frequency
+        let raw_percent = sa.util_avg * 100 / SCHED_CAPACITY_SCALE as u64;
+        assert!((48..=51).contains(&raw_percent), "raw util {}%", raw_percent);
+
+        // Normalized to the capacity offered at half frequency: ~100%
+        let normalized = normalize_util(sa.util_avg as u32, 512);
+        let normalized_percent = normalized * 100 / SCHED_CAPACITY_SCALE;
+        assert!(normalized_percent >= 97, "normalized util {}%", normalized_percent);
+    }
+
+    #[test]
+    fn test_blocked_load_is_not_runnable_load() {
+        let pelt = PeltScheduler::new();
+        let (idle_cpu, busy_cpu) = (CpuId::new(0), CpuId::new(1));
+
+        // Four tasks run for 100ms on one CPU, then all go to sleep
+        for id in 1..=4 {
+            pelt.enqueue_load(TaskId::new(id), idle_cpu, 0);
+        }
+        for ms in 1..=100 {
+            for id in 1..=4 {
+                pelt.update_task_load(TaskId::new(id), idle_cpu, ms * 1_000_000, 1024, true, true);
+            }
+        }
+        for id in 1..=4 {
+            pelt.dequeue_load(TaskId::new(id), idle_cpu, 100_000_000, true);
+        }
+
+        // A single task keeps running on the other CPU
+        pelt.enqueue_load(TaskId::new(5), busy_cpu, 0);
+        for ms in 1..=110 {
+            pelt.update_task_load(TaskId::new(5), busy_cpu, ms * 1_000_000, 1024, true, true);
+        }
+        pelt.decay_blocked(idle_cpu, 110_000_000);
+
+        // The idle CPU has more load, all of it blocked: nothing to pull
+        assert!(pelt.blocked_avg(idle_cpu) > pelt.runnable_avg(busy_cpu));
+        assert_eq!(pelt.runnable_avg(idle_cpu), 0);
+        let busiest = [idle_cpu, busy_cpu].into_iter().max_by_key(|&cpu| pelt.runnable_avg(cpu));
+        assert_eq!(busiest, Some(busy_cpu));
+
+        // A waking task takes its load back; the rest decays away
+        let before = pelt.blocked_avg(idle_cpu);
+        pelt.enqueue_load(TaskId::new(1), busy_cpu, 110_000_000);
+        assert!(pelt.blocked_avg(idle_cpu) < before * 4 / 5);
+        pelt.decay_blocked(idle_cpu, 2_000_000_000);
+        assert!(pelt.blocked_avg(idle_cpu) < before / 1000);
+    }
+
+    #[test]
+    fn test_little_core_capacity_scales_util() {
+        let decay = PeltDecay::default();
+        let mut sa = SchedAvg::default();
+        run_busy(&decay, &mut sa, 1000, SCHED_CAPACITY_SCALE, 256);
+        let percent = sa.util_avg * 100 / SCHED_CAPACITY_SCALE as u64;
+        assert!((23..=26).contains(&percent), "little core util {}%", percent);
+    }
+
+    #[test]
+    fn test_util_est_survives_sleep_and_follows_migration() {
+        let pelt = PeltScheduler::new();
+        let (task, from, to) = (TaskId::new(1), CpuId::new(0), CpuId::new(1));
+
+        // Runs 100ms, then sleeps 200ms
+        pelt.util_est_enqueue(task, from);
+        pelt.enqueue_load(task, from, 0);
+        for ms in 1..=100 {
+            pelt.update_task_load(task, from, ms * 1_000_000, 1024, true, true);
+        }
+        let awake = pelt.task_util(task);
+        pelt.util_est_dequeue(task, from);
+        pelt.dequeue_load(task, from, 100_000_000, true);
+        pelt.update_task_load(task, from, 300_000_000, 1024, false, false);
+        assert_eq!(pelt.per_cpu.get(from).util_est_enqueued.load(Ordering::Relaxed), 0);
+
+        // The estimate remembers the utilization before the sleep
+        let est = pelt.util_est.read()[&task].value();
+        assert_eq!(est, awake);
+        assert!(pelt.task_util(task) < est / 2);
+
+        pelt.util_est_enqueue(task, from);
+        pelt.enqueue_load(task, from, 300_000_000);
+        assert_eq!(pelt.per_cpu.get(from).util_est_enqueued.load(Ordering::Relaxed), est);
+        pelt.migrate_load(task, from, to);
+        assert_eq!(pelt.per_cpu.get(from).util_est_enqueued.load(Ordering::Relaxed), 0);
+        assert_eq!(pelt.per_cpu.get(to).util_est_enqueued.load(Ordering::Relaxed), est);
+        assert!(pelt.cpu_util_est(to) >= est);
+
+        pelt.util_est_dequeue(task, to);
+        assert_eq!(pelt.per_cpu.get(to).util_est_enqueued.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,252 @@
+//! # Preemption Control
+//!
+//! This module tracks whether the task running on a CPU may be preempted.
+//! Every CPU has a preemption counter: code that must not be switched away
+//! from (for example while holding a spinlock) raises it with
+//! `preempt_disable()` and lowers it with `preempt_enable()`. A reschedule
+//! requested while the counter is raised is remembered and performed when
+//! the outermost `preempt_enable()` drops the counter back to zero.
+//!
+//! ## Features
+//! - Nesting per-CPU preemption counter
+//! - Pending reschedule (`need_resched`) flag
+//! - Voluntary preemption points (`cond_resched`) for long kernel loops
+//! - Global preemption enable switch
+//! - Per-task notifiers run when the task is switched in or out
+//! - Preemption statistics
+//!
+//! ## Usage
+//! ```rust
+//! use crate::kernel::scheduler::preempt::PreemptScheduler;
+//!
+//! preempt.preempt_disable();
+//! // ... critical section, no task switch on this CPU ...
+//! if preempt.preempt_enable() {
+//!     scheduler.schedule()?;
+//! }
+//! ```
+
+use crate::kernel::task::{Task, TaskId};
+use crate::kernel::cpu::CpuId;
+use crate::kernel::error::{KernelResult, SchedulerError};
+use crate::kernel::sync::RwLock;
+use crate::kernel::log::{kernel_info, kernel_debug};
+use crate::kernel::memory::percpu::PerCpu;
+use crate::arch::cpu::current_cpu_id;
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+
+/// Callbacks run when a task is switched in or out of a CPU
+///
+/// Both run in the middle of a context switch with preemption disabled:
+/// they must be cheap and must not sleep.
+#[derive(Debug, Clone, Copy)]
+pub struct PreemptNotifierOps {
+    /// The task is about to run on `cpu`
+    pub sched_in: fn(task: TaskId, cpu: CpuId),
+    /// The task stops running on `cpu`
+    pub sched_out: fn(task: TaskId, cpu: CpuId),
+}
+
+/// Handle identifying a registered preempt notifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PreemptNotifierHandle(u64);
+
+/// Per-CPU preemption state
+#[derive(Debug, Default)]
+struct PreemptState {
+    /// Nesting depth of `preempt_disable()`
+    count: AtomicU32,
+    /// A reschedule is pending
+    need_resched: AtomicBool,
+}
+
+/// Preemption scheduler component
+pub struct PreemptScheduler {
+    enabled: AtomicBool,
+    per_cpu: PerCpu<PreemptState>,
+    notifiers: RwLock<BTreeMap<TaskId, Vec<(PreemptNotifierHandle, PreemptNotifierOps)>>>,
+    next_notifier: AtomicU64,
+    preemptions: AtomicU64,
+    deferred_reschedules: AtomicU64,
+    voluntary_reschedules: AtomicU64,
+}
+
+impl PreemptScheduler {
+    /// Create a preemption controller; `enabled` is the global preemption switch
+    pub fn with_enabled(enabled: bool) -> Self {
+        Self {
+            enabled: AtomicBool::new(enabled),
+            per_cpu: PerCpu::new(PreemptState::default()),
+            notifiers: RwLock::new(BTreeMap::new()),
+            next_notifier: AtomicU64::new(1),
+            preemptions: AtomicU64::new(0),
+            deferred_reschedules: AtomicU64::new(0),
+            voluntary_reschedules: AtomicU64::new(0),
+        }
+    }
+
+    /// Disable preemption on the current CPU (nests)
+    pub fn preempt_disable(&self) {
+        self.per_cpu.get(current_cpu_id()).count.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Re-enable preemption on the current CPU
+    ///
+    /// # Returns
+    /// `true` if this was the outermost enable and a reschedule became
+    /// pending meanwhile; the caller should then call `schedule()`.
+    pub fn preempt_enable(&self) -> bool {
+        let state = self.per_cpu.get(current_cpu_id());
+        let previous = state.count.fetch_sub(1, Ordering::AcqRel);
+        debug_assert!(previous > 0, "preempt_enable() without matching preempt_disable()");
+        previous == 1 && state.need_resched.load(Ordering::Acquire)
+    }
+
+    /// Preemption nesting depth of the current CPU
+    pub fn preempt_count(&self) -> u32 {
+        self.preempt_count_on(current_cpu_id())
+    }
+
+    /// Preemption nesting depth of a CPU
+    pub fn preempt_count_on(&self, cpu: CpuId) -> u32 {
+        self.per_cpu.get(cpu).count.load(Ordering::Acquire)
+    }
+
+    /// Check whether the current task may be preempted right now
+    pub fn preemptible(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed) && self.preempt_count() == 0
+    }
+
+    /// Enable or disable preemption globally
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Ask for a reschedule of the current CPU
+    pub fn request_reschedule(&self) -> KernelResult<()> {
+        self.per_cpu.get(current_cpu_id()).need_resched.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// Remember a reschedule that was refused because preemption is disabled
+    pub fn defer_reschedule(&self) {
+        self.deferred_reschedules.fetch_add(1, Ordering::Relaxed);
+        self.per_cpu.get(current_cpu_id()).need_resched.store(true, Ordering::Release);
+    }
+
+    /// Check whether a reschedule is pending on the current CPU
+    pub fn need_resched(&self) -> bool {
+        self.per_cpu.get(current_cpu_id()).need_resched.load(Ordering::Acquire)
+    }
+
+    /// Check whether the current task should reschedule at a voluntary
+    /// preemption point: a reschedule is pending and preemption is not
+    /// disabled on this CPU
+    ///
+    /// Two atomic loads, cheap enough for every iteration of a long loop.
+    /// The global enable switch does not matter: voluntary preemption
+    /// points are what keeps latency down when preemption is off.
+    pub fn should_resched(&self) -> bool {
+        let state = self.per_cpu.get(current_cpu_id());
+        state.count.load(Ordering::Acquire) == 0 && state.need_resched.load(Ordering::Acquire)
+    }
+
+    /// Account a reschedule taken at a voluntary preemption point
+    pub fn account_voluntary_reschedule(&self) {
+        self.voluntary_reschedules.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Account the preemption of a task and clear the pending reschedule
+    pub fn handle_task_preemption(&self, task: &Task) -> KernelResult<()> {
+        let state = self.per_cpu.get(current_cpu_id());
+        if state.need_resched.swap(false, Ordering::AcqRel) {
+            self.preemptions.fetch_add(1, Ordering::Relaxed);
+            kernel_debug!("Task {} preempted", task.id().as_u64());
+        }
+        Ok(())
+    }
+
+    /// Register callbacks run whenever `task` is switched in or out
+    pub fn register_notifier(&self, task: &Task, ops: PreemptNotifierOps) -> PreemptNotifierHandle {
+        let handle = PreemptNotifierHandle(self.next_notifier.fetch_add(1, Ordering::Relaxed));
+        self.notifiers.write().entry(task.id()).or_default().push((handle, ops));
+        kernel_debug!("Preempt notifier {} registered for task {}", handle.0, task.id().as_u64());
+        handle
+    }
+
+    /// Remove a preempt notifier of a task
+    pub fn unregister_notifier(&self, task: &Task, handle: PreemptNotifierHandle) -> KernelResult<()> {
+        let mut notifiers = self.notifiers.write();
+        let list = notifiers.get_mut(&task.id()).ok_or(SchedulerError::InvalidParameter)?;
+        let before = list.len();
+        list.retain(|(h, _)| *h != handle);
+        if list.len() == before {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        if list.is_empty() {
+            notifiers.remove(&task.id());
+        }
+        Ok(())
+    }
+
+    /// Drop all notifiers of an exiting task
+    pub fn remove_task(&self, task: TaskId) {
+        self.notifiers.write().remove(&task);
+    }
+
+    /// Run the notifiers of a task being switched out
+    pub fn fire_sched_out(&self, task: TaskId, cpu: CpuId) {
+        if let Some(list) = self.notifiers.read().get(&task) {
+            for (_, ops) in list {
+                (ops.sched_out)(task, cpu);
+            }
+        }
+    }
+
+    /// Run the notifiers of a task being switched in
+    pub fn fire_sched_in(&self, task: TaskId, cpu: CpuId) {
+        if let Some(list) = self.notifiers.read().get(&task) {
+            for (_, ops) in list {
+                (ops.sched_in)(task, cpu);
+            }
+        }
+    }
+
+    /// Check that the current CPU may sleep (debug builds only)
+    pub fn assert_may_sleep(&self) {
+        debug_assert_eq!(self.preempt_count(), 0, "task sleeping with preemption disabled");
+    }
+
+    /// Log preemption statistics
+    pub fn print_preempt_info(&self) {
+        kernel_info!("Preemption enabled: {}", self.enabled.load(Ordering::Relaxed));
+        kernel_info!("Preemptions: {}", self.preemptions.load(Ordering::Relaxed));
+        kernel_info!("Deferred reschedules: {}", self.deferred_reschedules.load(Ordering::Relaxed));
+        kernel_info!("Voluntary reschedules: {}", self.voluntary_reschedules.load(Ordering::Relaxed));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cond_resched_yields_only_to_pending_reschedule() {
+        // No preemption: voluntary points are the only relief
+        let preempt = PreemptScheduler::with_enabled(false);
+        assert!(!preempt.should_resched());
+
+        preempt.request_reschedule().unwrap();
+        preempt.preempt_disable();
+        assert!(!preempt.should_resched());
+        assert!(preempt.preempt_enable());
+        assert!(preempt.should_resched());
+
+        // Taking the reschedule clears it
+        preempt.per_cpu.get(current_cpu_id()).need_resched.store(false, Ordering::Release);
+        assert!(!preempt.should_resched());
+    }
+}
@@ -2,10 +2,25 @@
 /// This module is responsible for managing the PSI metrics
 /// and providing insights into system pressure for scheduling decisions.
 /// This file is part of the kernel's scheduler subsystem.
+///
+/// Besides the system-wide totals, stall time can be attributed to task
+/// groups (like cgroup v2 `*.pressure` files): a task's stall counts for
+/// its group and every ancestor group.
+///
+/// Sustained critical `full` memory pressure (every runnable task stalled
+/// on memory) produces an OOM hint before the allocator hits hard OOM.
+///
+/// Pressures are stored and computed as per-mille integers (0..=1000) so
+/// that no floating point is needed in kernel context and severity
+/// classification is deterministic. The percentage (`f64`) getters are
+/// thin wrappers for reporting.
 
 use std::time::{Duration, Instant};
 use std::collections::HashMap;
 
+use crate::kernel::task::TaskId;
+use crate::kernel::error::{KernelResult, SchedulerError};
+
 // Import PSI-related modules
 use crate::kernel::scheduler::psi::metrics::PSIMetrics;
 use crate::kernel::scheduler::psi::pressure::Pressure;
@@ -16,22 +31,50 @@ use crate::kernel::scheduler::psi::pressure_tracker::{
     PressureTrackerConfig, PressureTrackerMetrics
 };
 
-/// PSI pressure thresholds for different severity levels
+/// Pressure in per-mille of wall time (0..=1000)
+pub type Permille = u32;
+
+/// Full scale of a per-mille pressure
+pub const PERMILLE_MAX: Permille = 1000;
+
+/// Convert a percentage to per-mille, rounding to the nearest value
+pub fn permille_from_percent(percent: f64) -> Permille {
+    (percent.clamp(0.0, 100.0) * 10.0 + 0.5) as Permille
+}
+
+/// Convert per-mille to a percentage
+pub fn permille_to_percent(permille: Permille) -> f64 {
+    permille as f64 / 10.0
+}
+
+/// PSI pressure thresholds for different severity levels (per-mille)
 #[derive(Debug, Clone, Copy)]
 pub struct PSIThresholds {
-    pub low: f64,
-    pub medium: f64,
-    pub high: f64,
-    pub critical: f64,
+    pub low: Permille,
+    pub medium: Permille,
+    pub high: Permille,
+    pub critical: Permille,
+}
+
+impl PSIThresholds {
+    /// Build thresholds from percentages
+    pub fn from_percent(low: f64, medium: f64, high: f64, critical: f64) -> Self {
+        Self {
+            low: permille_from_percent(low),
+            medium: permille_from_percent(medium),
+            high: permille_from_percent(high),
+            critical: permille_from_percent(critical),
+        }
+    }
 }
 
 impl Default for PSIThresholds {
     fn default() -> Self {
         Self {
-            low: 10.0,      // 10% pressure
-            medium: 30.0,   // 30% pressure
-            high: 60.0,     // 60% pressure
-            critical: 90.0, // 90% pressure
+            low: 100,      // 10% pressure
+            medium: 300,   // 30% pressure
+            high: 600,     // 60% pressure
+            critical: 900, // 90% pressure
         }
     }
 }
@@ -53,6 +96,8 @@ pub struct PSIConfig {
     pub update_interval: Duration,
     pub thresholds: PSIThresholds,
     pub history_size: usize,
+    /// How long full memory pressure must stay critical for an OOM hint
+    pub oom_sustain: Duration,
 }
 
 impl Default for PSIConfig {
@@ -62,6 +107,7 @@ impl Default for PSIConfig {
             update_interval: Duration::from_millis(100), // 100ms update interval
             thresholds: PSIThresholds::default(),
             history_size: 60, // Keep 60 measurements (6 seconds at 100ms intervals)
+            oom_sustain: Duration::from_secs(10),
         }
     }
 }
@@ -70,12 +116,74 @@ impl Default for PSIConfig {
 #[derive(Debug, Clone)]
 pub struct PSIHistoryEntry {
     pub timestamp: Instant,
-    pub cpu_pressure: f64,
-    pub memory_pressure: f64,
-    pub io_pressure: f64,
+    pub cpu_pressure: Permille,
+    pub memory_pressure: Permille,
+    pub io_pressure: Permille,
     pub severity: PSISeverity,
 }
 
+/// Identifier of a PSI accounting group
+pub type PSIGroupId = u64;
+
+/// The root group, whose metrics are the system-wide totals
+pub const ROOT_PSI_GROUP: PSIGroupId = 0;
+
+/// Resource a task stalled on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StallResource {
+    Cpu,
+    Memory,
+    Io,
+}
+
+/// Sustained memory pressure reported ahead of hard OOM
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OomHint {
+    /// Current full memory pressure (per-mille)
+    pub full_pressure: Permille,
+    /// How long full memory pressure has been critical
+    pub sustained: Duration,
+}
+
+/// Callback fired once per sustained OOM pressure episode
+pub type OomCallback = fn(OomHint);
+
+/// Index of a resource in per-resource stall arrays
+fn resource_index(resource: StallResource) -> usize {
+    match resource {
+        StallResource::Cpu => 0,
+        StallResource::Memory => 1,
+        StallResource::Io => 2,
+    }
+}
+
+/// Share of `period` spent stalled, in per-mille
+fn stall_pressure(stall: Duration, period: Duration) -> Permille {
+    if period.is_zero() {
+        return 0;
+    }
+    let permille = stall.as_nanos() * PERMILLE_MAX as u128 / period.as_nanos();
+    permille.min(PERMILLE_MAX as u128) as Permille
+}
+
+/// Pressure accounting of one registered group
+#[derive(Debug)]
+struct GroupPressure {
+    parent: PSIGroupId,
+    /// Stall time per resource (cpu, memory, io) in the current period
+    stall: [Duration; 3],
+    metrics: PSIMetrics,
+}
+
+impl GroupPressure {
+    /// Turn the stall time of the period into pressures and start a new one
+    fn update(&mut self, period: Duration) {
+        let [cpu, memory, io] = self.stall.map(|stall| stall_pressure(stall, period));
+        self.metrics.update_with_pressures(cpu, memory, io);
+        self.stall = [Duration::ZERO; 3];
+    }
+}
+
 /// Main PSI scheduler structure
 #[derive(Debug)]
 pub struct PSIScheduler {
@@ -85,6 +193,17 @@ pub struct PSIScheduler {
     history: Vec<PSIHistoryEntry>,
     last_update: Instant,
     pressure_events: HashMap<PressureType, u64>,
+    groups: HashMap<PSIGroupId, GroupPressure>,
+    task_groups: HashMap<TaskId, PSIGroupId>,
+    /// System-wide `full` stall time per resource in the current period
+    full_stall: [Duration; 3],
+    /// System-wide `full` pressure per resource over the last period
+    full_pressure: [Permille; 3],
+    /// Since when full memory pressure has been critical
+    memory_critical_since: Option<Instant>,
+    /// Whether the current OOM pressure episode was already reported
+    oom_reported: bool,
+    oom_callbacks: Vec<OomCallback>,
 }
 
 impl PSIScheduler {
@@ -102,9 +221,22 @@ impl PSIScheduler {
             history: Vec::new(),
             last_update: Instant::now(),
             pressure_events: HashMap::new(),
+            groups: HashMap::new(),
+            task_groups: HashMap::new(),
+            full_stall: [Duration::ZERO; 3],
+            full_pressure: [0; 3],
+            memory_critical_since: None,
+            oom_reported: false,
+            oom_callbacks: Vec::new(),
         }
     }
 
+    /// Check whether `update_interval` has passed since the last update,
+    /// so that `update_metrics` would update
+    pub fn update_due(&self) -> bool {
+        Instant::now().duration_since(self.last_update) >= self.config.update_interval
+    }
+
     /// Update PSI metrics and perform pressure analysis
     pub fn update_metrics(&mut self) {
         let now = Instant::now();
@@ -118,9 +250,9 @@ impl PSIScheduler {
         self.pressure_tracker.update();
         
         // Get current pressure measurements
-        let cpu_pressure = self.pressure_tracker.get_cpu_pressure();
-        let memory_pressure = self.pressure_tracker.get_memory_pressure();
-        let io_pressure = self.pressure_tracker.get_io_pressure();
+        let cpu_pressure = self.pressure_tracker.get_cpu_pressure_permille();
+        let memory_pressure = self.pressure_tracker.get_memory_pressure_permille();
+        let io_pressure = self.pressure_tracker.get_io_pressure_permille();
 
         // Determine severity level
         let max_pressure = cpu_pressure.max(memory_pressure).max(io_pressure);
@@ -142,11 +274,160 @@ impl PSIScheduler {
         };
         
         self.add_history_entry(entry);
+        
+        // Per-group pressures over the same period
+        let period = now.duration_since(self.last_update);
+        for group in self.groups.values_mut() {
+            group.update(period);
+        }
+        self.full_pressure = self.full_stall.map(|stall| stall_pressure(stall, period));
+        self.full_stall = [Duration::ZERO; 3];
         self.last_update = now;
+        
+        self.update_oom_pressure(now);
+    }
+
+    /// Track sustained critical full memory pressure and fire OOM callbacks
+    fn update_oom_pressure(&mut self, now: Instant) {
+        if self.get_full_pressure_permille(StallResource::Memory) < self.config.thresholds.critical {
+            self.memory_critical_since = None;
+            self.oom_reported = false;
+            return;
+        }
+        self.memory_critical_since.get_or_insert(now);
+        
+        if self.oom_reported {
+            return;
+        }
+        if let Some(hint) = self.memory_oom_pressure() {
+            self.oom_reported = true;
+            for cb in &self.oom_callbacks {
+                cb(hint);
+            }
+        }
+    }
+
+    /// Account system-wide `full` stall time, during which every runnable
+    /// task was stalled on the resource
+    pub fn record_full_stall(&mut self, resource: StallResource, stall: Duration) {
+        self.full_stall[resource_index(resource)] += stall;
+    }
+
+    /// System-wide `full` pressure of a resource over the last period
+    pub fn get_full_pressure_permille(&self, resource: StallResource) -> Permille {
+        self.full_pressure[resource_index(resource)]
+    }
+
+    /// System-wide `full` pressure of a resource in percent
+    pub fn get_full_pressure(&self, resource: StallResource) -> f64 {
+        permille_to_percent(self.get_full_pressure_permille(resource))
+    }
+
+    /// OOM hint if full memory pressure has been critical for at least
+    /// `oom_sustain`
+    pub fn memory_oom_pressure(&self) -> Option<OomHint> {
+        let since = self.memory_critical_since?;
+        let sustained = Instant::now().duration_since(since);
+        if sustained < self.config.oom_sustain {
+            return None;
+        }
+        Some(OomHint {
+            full_pressure: self.get_full_pressure_permille(StallResource::Memory),
+            sustained,
+        })
+    }
+
+    /// Register a callback fired once per sustained OOM pressure episode
+    ///
+    /// An episode ends when full memory pressure drops below critical.
+    pub fn on_oom_pressure(&mut self, cb: OomCallback) {
+        self.oom_callbacks.push(cb);
+    }
+
+    /// Register an accounting group below `parent`
+    ///
+    /// Fails with `InvalidParameter` if `id` is the root group or already
+    /// registered, or if `parent` is neither the root nor registered.
+    pub fn register_group(&mut self, id: PSIGroupId, parent: PSIGroupId) -> KernelResult<()> {
+        if id == ROOT_PSI_GROUP || self.groups.contains_key(&id) {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        if parent != ROOT_PSI_GROUP && !self.groups.contains_key(&parent) {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        self.groups.insert(id, GroupPressure {
+            parent,
+            stall: [Duration::ZERO; 3],
+            metrics: PSIMetrics::new(),
+        });
+        Ok(())
+    }
+
+    /// Unregister an accounting group
+    ///
+    /// Tasks of the group fall back to the root group. Fails with `Busy` if
+    /// the group still has child groups.
+    pub fn unregister_group(&mut self, id: PSIGroupId) -> KernelResult<()> {
+        if !self.groups.contains_key(&id) {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        if self.groups.values().any(|g| g.parent == id) {
+            return Err(SchedulerError::Busy.into());
+        }
+        self.groups.remove(&id);
+        self.task_groups.retain(|_, group| *group != id);
+        Ok(())
+    }
+
+    /// Account a task's future stalls to a group
+    pub fn attach_task(&mut self, task: TaskId, group: PSIGroupId) -> KernelResult<()> {
+        if group == ROOT_PSI_GROUP {
+            self.task_groups.remove(&task);
+            return Ok(());
+        }
+        if !self.groups.contains_key(&group) {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        self.task_groups.insert(task, group);
+        Ok(())
+    }
+
+    /// Forget an exiting task
+    pub fn detach_task(&mut self, task: TaskId) {
+        self.task_groups.remove(&task);
+    }
+
+    /// Account a task's stall on a resource to its group and all ancestors
+    pub fn record_stall(&mut self, task: TaskId, resource: StallResource, stall: Duration) {
+        let index = resource_index(resource);
+        let mut group = self.task_groups.get(&task).copied().unwrap_or(ROOT_PSI_GROUP);
+        while let Some(pressure) = self.groups.get_mut(&group) {
+            pressure.stall[index] += stall;
+            group = pressure.parent;
+        }
+    }
+
+    /// Account a task's uninterruptible sleep as a stall
+    ///
+    /// A task in uninterruptible sleep waits for a resource rather than an
+    /// event: for I/O if `iowait`, otherwise for memory (reclaim, swap-in).
+    pub fn record_uninterruptible_sleep(&mut self, task: TaskId, iowait: bool, slept: Duration) {
+        let resource = if iowait { StallResource::Io } else { StallResource::Memory };
+        self.record_stall(task, resource, slept);
+    }
+
+    /// Get the PSI metrics of a group
+    ///
+    /// The root group reports the system-wide metrics.
+    pub fn get_group_metrics(&self, id: PSIGroupId) -> Option<&PSIMetrics> {
+        if id == ROOT_PSI_GROUP {
+            return Some(&self.metrics);
+        }
+        self.groups.get(&id).map(|g| &g.metrics)
     }
 
     /// Calculate PSI severity based on pressure value
-    fn calculate_severity(&self, pressure: f64) -> PSISeverity {
+    fn calculate_severity(&self, pressure: Permille) -> PSISeverity {
         let thresholds = &self.config.thresholds;
         
         if pressure >= thresholds.critical {
@@ -200,29 +481,38 @@ impl PSIScheduler {
             .unwrap_or(PSISeverity::None)
     }
 
-    /// Get average pressure over the last N entries
-    pub fn get_average_pressure(&self, entries: usize) -> (f64, f64, f64) {
+    /// Get average pressure over the last N entries (per-mille)
+    ///
+    /// The average is rounded to the nearest per-mille.
+    pub fn get_average_pressure_permille(&self, entries: usize) -> (Permille, Permille, Permille) {
         let count = entries.min(self.history.len());
         if count == 0 {
-            return (0.0, 0.0, 0.0);
+            return (0, 0, 0);
         }
 
         let start_idx = self.history.len() - count;
         let recent_entries = &self.history[start_idx..];
 
         let (cpu_sum, mem_sum, io_sum) = recent_entries.iter().fold(
-            (0.0, 0.0, 0.0),
+            (0u64, 0u64, 0u64),
             |(cpu_acc, mem_acc, io_acc), entry| {
                 (
-                    cpu_acc + entry.cpu_pressure,
-                    mem_acc + entry.memory_pressure,
-                    io_acc + entry.io_pressure,
+                    cpu_acc + entry.cpu_pressure as u64,
+                    mem_acc + entry.memory_pressure as u64,
+                    io_acc + entry.io_pressure as u64,
                 )
             },
         );
 
-        let count_f64 = count as f64;
-        (cpu_sum / count_f64, mem_sum / count_f64, io_sum / count_f64)
+        let count = count as u64;
+        let average = |sum: u64| ((sum + count / 2) / count) as Permille;
+        (average(cpu_sum), average(mem_sum), average(io_sum))
+    }
+
+    /// Get average pressure over the last N entries (percent)
+    pub fn get_average_pressure(&self, entries: usize) -> (f64, f64, f64) {
+        let (cpu, memory, io) = self.get_average_pressure_permille(entries);
+        (permille_to_percent(cpu), permille_to_percent(memory), permille_to_percent(io))
     }
 
     /// Check if system is under pressure
@@ -243,12 +533,33 @@ impl PSIScheduler {
         &self.pressure_events
     }
 
+    /// Leave an interval the system spent suspended out of the current
+    /// period
+    ///
+    /// The period and any sustained OOM pressure episode are moved back by
+    /// the suspended time, so it counts neither as stall nor as productive
+    /// time.
+    pub fn account_suspend(&mut self, suspended: Duration) {
+        self.last_update += suspended;
+        if let Some(since) = self.memory_critical_since.as_mut() {
+            *since += suspended;
+        }
+    }
+
     /// Reset all PSI metrics and history
     pub fn reset(&mut self) {
         self.metrics.reset();
         self.pressure_tracker.reset();
         self.history.clear();
         self.pressure_events.clear();
+        for group in self.groups.values_mut() {
+            group.stall = [Duration::ZERO; 3];
+            group.metrics.reset();
+        }
+        self.full_stall = [Duration::ZERO; 3];
+        self.full_pressure = [0; 3];
+        self.memory_critical_since = None;
+        self.oom_reported = false;
         self.last_update = Instant::now();
     }
 
@@ -275,17 +586,17 @@ impl PSIScheduler {
         
         if let Some(last_entry) = self.history.last() {
             println!("Latest Pressures:");
-            println!("  CPU: {:.2}%", last_entry.cpu_pressure);
-            println!("  Memory: {:.2}%", last_entry.memory_pressure);
-            println!("  I/O: {:.2}%", last_entry.io_pressure);
+            println!("  CPU: {:.1}%", permille_to_percent(last_entry.cpu_pressure));
+            println!("  Memory: {:.1}%", permille_to_percent(last_entry.memory_pressure));
+            println!("  I/O: {:.1}%", permille_to_percent(last_entry.io_pressure));
         }
 
         // Show averages
         let (avg_cpu, avg_mem, avg_io) = self.get_average_pressure(10);
         println!("10-Sample Averages:");
-        println!("  CPU: {:.2}%", avg_cpu);
-        println!("  Memory: {:.2}%", avg_mem);
-        println!("  I/O: {:.2}%", avg_io);
+        println!("  CPU: {:.1}%", avg_cpu);
+        println!("  Memory: {:.1}%", avg_mem);
+        println!("  I/O: {:.1}%", avg_io);
 
         // Show pressure events
         println!("Pressure Events:");
@@ -329,36 +640,56 @@ impl PSIMetrics {
     /// Create new PSI metrics instance
     pub fn new() -> Self {
         Self {
-            cpu_pressure: 0.0,
-            memory_pressure: 0.0,
-            io_pressure: 0.0,
+            cpu_pressure: 0,
+            memory_pressure: 0,
+            io_pressure: 0,
             last_updated: Instant::now(),
         }
     }
 
-    /// Update metrics with specific pressure values
-    pub fn update_with_pressures(&mut self, cpu: f64, memory: f64, io: f64) {
+    /// Update metrics with specific pressure values (per-mille)
+    pub fn update_with_pressures(&mut self, cpu: Permille, memory: Permille, io: Permille) {
         self.cpu_pressure = cpu;
         self.memory_pressure = memory;
         self.io_pressure = io;
         self.last_updated = Instant::now();
     }
 
-    /// Get the maximum pressure across all types
-    pub fn get_max_pressure(&self) -> f64 {
+    /// Get the maximum pressure across all types (per-mille)
+    pub fn max_pressure_permille(&self) -> Permille {
         self.cpu_pressure.max(self.memory_pressure).max(self.io_pressure)
     }
 
-    /// Check if any pressure exceeds threshold
+    /// Get the maximum pressure across all types (percent)
+    pub fn get_max_pressure(&self) -> f64 {
+        permille_to_percent(self.max_pressure_permille())
+    }
+
+    /// Check if any pressure exceeds a threshold given in percent
     pub fn exceeds_threshold(&self, threshold: f64) -> bool {
-        self.get_max_pressure() > threshold
+        self.max_pressure_permille() > permille_from_percent(threshold)
+    }
+
+    /// CPU pressure in percent
+    pub fn cpu_pressure_percent(&self) -> f64 {
+        permille_to_percent(self.cpu_pressure)
+    }
+
+    /// Memory pressure in percent
+    pub fn memory_pressure_percent(&self) -> f64 {
+        permille_to_percent(self.memory_pressure)
+    }
+
+    /// I/O pressure in percent
+    pub fn io_pressure_percent(&self) -> f64 {
+        permille_to_percent(self.io_pressure)
     }
 
     /// Reset all metrics to zero
     pub fn reset(&mut self) {
-        self.cpu_pressure = 0.0;
-        self.memory_pressure = 0.0;
-        self.io_pressure = 0.0;
+        self.cpu_pressure = 0;
+        self.memory_pressure = 0;
+        self.io_pressure = 0;
         self.last_updated = Instant::now();
     }
 
@@ -382,11 +713,14 @@ mod tests {
     #[test]
     fn test_severity_calculation() {
         let psi = PSIScheduler::new();
-        assert_eq!(psi.calculate_severity(5.0), PSISeverity::None);
-        assert_eq!(psi.calculate_severity(15.0), PSISeverity::Low);
-        assert_eq!(psi.calculate_severity(45.0), PSISeverity::Medium);
-        assert_eq!(psi.calculate_severity(75.0), PSISeverity::High);
-        assert_eq!(psi.calculate_severity(95.0), PSISeverity::Critical);
+        assert_eq!(psi.calculate_severity(50), PSISeverity::None);
+        assert_eq!(psi.calculate_severity(150), PSISeverity::Low);
+        assert_eq!(psi.calculate_severity(450), PSISeverity::Medium);
+        assert_eq!(psi.calculate_severity(750), PSISeverity::High);
+        assert_eq!(psi.calculate_severity(950), PSISeverity::Critical);
+        // Exactly at a threshold classifies as that level
+        assert_eq!(psi.calculate_severity(899), PSISeverity::High);
+        assert_eq!(psi.calculate_severity(900), PSISeverity::Critical);
     }
 
     #[test]
@@ -395,4 +729,76 @@ mod tests {
         // Test would require mocking pressure values
         assert_eq!(psi.get_scheduling_hint(), SchedulingHint::Normal);
     }
+
+    #[test]
+    fn test_group_stall_propagates_to_ancestors_only() {
+        let mut psi = PSIScheduler::with_config(PSIConfig {
+            update_interval: Duration::ZERO,
+            ..PSIConfig::default()
+        });
+        psi.register_group(1, ROOT_PSI_GROUP).unwrap();
+        psi.register_group(2, 1).unwrap();
+        psi.register_group(3, 1).unwrap();
+        assert!(psi.register_group(4, 99).is_err());
+        psi.attach_task(TaskId::new(10), 2).unwrap();
+
+        std::thread::sleep(Duration::from_millis(10));
+        psi.record_stall(TaskId::new(10), StallResource::Memory, Duration::from_millis(5));
+        psi.update_metrics();
+
+        let memory = |id| psi.get_group_metrics(id).unwrap().memory_pressure;
+        assert!(memory(2) > 0);
+        assert_eq!(memory(1), memory(2));
+        assert_eq!(memory(3), 0);
+        assert!(psi.unregister_group(1).is_err());
+    }
+
+    #[test]
+    fn test_permille_conversions_round_trip() {
+        assert_eq!(permille_from_percent(89.96), 900);
+        assert_eq!(permille_from_percent(150.0), PERMILLE_MAX);
+        assert_eq!(permille_to_percent(permille_from_percent(42.3)), 42.3);
+        assert_eq!(PSIThresholds::from_percent(10.0, 30.0, 60.0, 90.0).critical,
+                   PSIThresholds::default().critical);
+        assert_eq!(stall_pressure(Duration::from_millis(1), Duration::from_millis(3)), 333);
+    }
+
+    #[test]
+    fn test_oom_pressure_fires_once_per_episode() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static FIRED: AtomicUsize = AtomicUsize::new(0);
+        fn on_oom(_: OomHint) {
+            FIRED.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let mut psi = PSIScheduler::with_config(PSIConfig {
+            update_interval: Duration::ZERO,
+            oom_sustain: Duration::from_millis(20),
+            ..PSIConfig::default()
+        });
+        psi.on_oom_pressure(on_oom);
+        fn tick(psi: &mut PSIScheduler, full: bool) {
+            std::thread::sleep(Duration::from_millis(15));
+            if full {
+                psi.record_full_stall(StallResource::Memory, Duration::from_millis(50));
+            }
+            psi.update_metrics();
+        }
+
+        tick(&mut psi, true);
+        assert!(psi.memory_oom_pressure().is_none());
+        tick(&mut psi, true);
+        tick(&mut psi, true);
+        let hint = psi.memory_oom_pressure().unwrap();
+        assert!(hint.sustained >= Duration::from_millis(20));
+        tick(&mut psi, true);
+        assert_eq!(FIRED.load(Ordering::Relaxed), 1);
+
+        tick(&mut psi, false);
+        assert!(psi.memory_oom_pressure().is_none());
+        for _ in 0..3 {
+            tick(&mut psi, true);
+        }
+        assert_eq!(FIRED.load(Ordering::Relaxed), 2);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,186 @@
+//! # CPU Activity Report
+//!
+//! This module produces a turbostat-style report of what every CPU did
+//! since the previous report: how busy it was, how long it spent in each
+//! idle state and at what average frequency it ran. It joins the per-CPU
+//! idle accounting of `cpuidle`, the frequency residency of `cpufreq` and
+//! the scheduler's per-CPU utilization, which otherwise live apart.
+//!
+//! Frequency is scaled for all CPUs together, so every CPU of a report
+//! shows the same average frequency. An idle period still in progress is
+//! accounted when it ends.
+//!
+//! ## Features
+//! - Busy share per CPU: 1 - idle time / interval, injected idle included
+//! - Time spent in each idle state over the interval
+//! - Residency-weighted average frequency over the interval
+//! - Scheduler utilization alongside for comparison
+//!
+//! ## Usage
+//! ```rust
+//! use crate::kernel::scheduler::report::ReportScheduler;
+//!
+//! let report = ReportScheduler::new();
+//! for cpu in report.snapshot_report(|cpu| utilization_of(cpu)) {
+//!     kernel_info!("CPU {}: {}.{}% busy, {} Hz", cpu.cpu.as_u32(),
+//!                  cpu.busy / 10, cpu.busy % 10, cpu.average_frequency);
+//! }
+//! ```
+
+use crate::kernel::scheduler::cpufreq::{get_current_frequency, get_frequency_stats};
+use crate::kernel::scheduler::cpuidle::{get_all_idle_statistics, CpuIdleStats};
+use crate::kernel::cpu::{CpuId, CpuMask};
+use crate::kernel::time::Timestamp;
+use crate::kernel::sync::SpinLock;
+use crate::kernel::log::kernel_info;
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// Activity of one CPU over a report interval
+#[derive(Debug, Clone)]
+pub struct CpuReport {
+    /// CPU reported on
+    pub cpu: CpuId,
+    /// Length of the interval in microseconds
+    pub interval_us: u64,
+    /// Busy share of the interval (0-1000 for 0-100.0%)
+    pub busy: u32,
+    /// Time spent in each idle state over the interval (state_id, time_us)
+    pub state_time: Vec<(u64, u64)>,
+    /// Residency-weighted average frequency in Hz (0 if unknown)
+    pub average_frequency: u64,
+    /// Scheduler utilization at the end of the interval (0-1000)
+    pub utilization: u32,
+}
+
+/// Cumulative counters a report is the delta of
+#[derive(Debug, Clone, Default)]
+struct Snapshot {
+    timestamp_us: u64,
+    idle: BTreeMap<CpuId, CpuIdleStats>,
+    frequency_time: Vec<(u64, u64)>,
+}
+
+impl Snapshot {
+    /// Read the current counters
+    fn take() -> Self {
+        Self {
+            timestamp_us: Timestamp::now().as_nanos() / 1_000,
+            idle: get_all_idle_statistics().into_iter().collect(),
+            frequency_time: get_frequency_stats().map(|s| s.frequency_time).unwrap_or_default(),
+        }
+    }
+}
+
+/// Report scheduler component
+pub struct ReportScheduler {
+    /// Counters at the previous report
+    last: SpinLock<Snapshot>,
+}
+
+impl ReportScheduler {
+    /// Create a reporter; the first report covers the time since creation
+    pub fn new() -> Self {
+        Self {
+            last: SpinLock::new(Snapshot::take()),
+        }
+    }
+
+    /// Report the activity of every online CPU since the previous report
+    ///
+    /// `utilization` gives the scheduler's utilization of a CPU (0-1000).
+    pub fn snapshot_report(&self, utilization: impl Fn(CpuId) -> u32) -> Vec<CpuReport> {
+        let now = Snapshot::take();
+        let mut last = self.last.lock();
+        let interval_us = now.timestamp_us.saturating_sub(last.timestamp_us);
+        let average_frequency = average_frequency(&last.frequency_time, &now.frequency_time)
+            .unwrap_or_else(|| get_current_frequency().unwrap_or(0));
+
+        let empty = CpuIdleStats::default();
+        let reports = CpuMask::online().iter().map(|cpu| {
+            let before = last.idle.get(&cpu).unwrap_or(&empty);
+            let after = now.idle.get(&cpu).unwrap_or(&empty);
+            let idle_us = (after.total_idle_time + after.injected_idle_time)
+                .saturating_sub(before.total_idle_time + before.injected_idle_time);
+            CpuReport {
+                cpu,
+                interval_us,
+                busy: busy_share(idle_us, interval_us),
+                state_time: delta_pairs(&before.state_usage_time, &after.state_usage_time),
+                average_frequency,
+                utilization: utilization(cpu),
+            }
+        }).collect();
+        *last = now;
+        reports
+    }
+
+    /// Log a report of every online CPU since the previous report
+    pub fn print_report(&self, utilization: impl Fn(CpuId) -> u32) {
+        for report in self.snapshot_report(utilization) {
+            kernel_info!("CPU {}: busy {}.{}%, util {}.{}%, avg {} Hz, idle states {:?}",
+                        report.cpu.as_u32(), report.busy / 10, report.busy % 10,
+                        report.utilization / 10, report.utilization % 10,
+                        report.average_frequency, report.state_time);
+        }
+    }
+}
+
+impl Default for ReportScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Per-key growth of cumulative (key, value) counters
+///
+/// Keys missing before count from zero; counters that went backwards
+/// (a statistics reset) count as unchanged.
+fn delta_pairs(before: &[(u64, u64)], after: &[(u64, u64)]) -> Vec<(u64, u64)> {
+    after.iter().map(|&(key, value)| {
+        let previous = before.iter().find(|&&(k, _)| k == key).map_or(0, |&(_, v)| v);
+        (key, value.saturating_sub(previous))
+    }).collect()
+}
+
+/// Average of the frequencies run at between two residency snapshots,
+/// weighted by the time spent at each; `None` if no time was accounted
+fn average_frequency(before: &[(u64, u64)], after: &[(u64, u64)]) -> Option<u64> {
+    let deltas = delta_pairs(before, after);
+    let total: u128 = deltas.iter().map(|&(_, time)| time as u128).sum();
+    if total == 0 {
+        return None;
+    }
+    let weighted: u128 = deltas.iter().map(|&(freq, time)| freq as u128 * time as u128).sum();
+    Some((weighted / total) as u64)
+}
+
+/// Busy share of an interval given the idle time in it (0-1000)
+fn busy_share(idle_us: u64, interval_us: u64) -> u32 {
+    if interval_us == 0 {
+        return 0;
+    }
+    let idle = (idle_us.min(interval_us) as u128 * 1000 / interval_us as u128) as u32;
+    1000 - idle
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_deltas_weight_frequency_by_residency() {
+        let before = [(1_000_000_000, 500), (2_000_000_000, 100)];
+        let after = [(1_000_000_000, 800), (2_000_000_000, 700), (3_000_000_000, 100)];
+        assert_eq!(delta_pairs(&before, &after),
+                   [(1_000_000_000, 300), (2_000_000_000, 600), (3_000_000_000, 100)]);
+        // (1 GHz * 300 + 2 GHz * 600 + 3 GHz * 100) / 1000
+        assert_eq!(average_frequency(&before, &after), Some(1_800_000_000));
+        assert_eq!(average_frequency(&after, &after), None);
+
+        assert_eq!(busy_share(250, 1_000), 750);
+        assert_eq!(busy_share(2_000, 1_000), 0);
+        assert_eq!(busy_share(0, 0), 0);
+    }
+}
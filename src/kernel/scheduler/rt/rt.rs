@@ -0,0 +1,1063 @@
+//! # Real-Time Scheduler
+//!
+//! This module implements the `Fifo` and `RoundRobin` scheduling classes.
+//! Each CPU keeps one run list per RT priority; the highest non-empty
+//! priority always runs first, and tasks of equal priority run in FIFO
+//! order. A preempted RT task stays at the head of its list so it resumes
+//! before its peers, while one that yields goes to the tail.
+//!
+//! A task holding locks with a priority ceiling runs at the highest
+//! ceiling it holds, from the moment it acquires the lock (immediate
+//! priority ceiling). A lower priority task that could later block a
+//! higher one then never gets to run inside the critical section, so a
+//! task is blocked for at most one critical section and never by a chain
+//! of them.
+//!
+//! A `RoundRobin` task additionally goes to the tail when its time slice
+//! runs out. Every task can have its own slice; tasks without one use the
+//! scheduler's default. A preempted task keeps what is left of its slice.
+//!
+//! An optional stall watchdog flags an RT task that has run for longer
+//! than a threshold since it last yielded or slept. Being preempted does
+//! not end such a run: a spinning FIFO task interrupted by a higher
+//! priority one is still stalled when it resumes.
+//!
+//! ## Features
+//! - Per-CPU priority-indexed run lists (priorities 1-99, higher wins)
+//! - O(log n) pick of the highest priority task
+//! - Wakeup preemption of lower priority RT tasks
+//! - Priority changes of queued and running tasks
+//! - Immediate priority ceilings for lock holders
+//! - Yielding to the tail of the run list, or to a chosen peer
+//! - Per-task `RoundRobin` time slices
+//! - RT bandwidth limit (percent of CPU time)
+//! - Stall watchdog with a callback and optional forced reschedule
+//!
+//! ## Usage
+//! ```rust
+//! use crate::kernel::scheduler::rt::RtScheduler;
+//!
+//! let rt = RtScheduler::with_config(95, 10_000);
+//! rt.set_timeslice(&task, Duration::from_millis(50))?;
+//! rt.enqueue_task(&task)?;
+//!
+//! if rt.should_preempt_current(&task)? {
+//!     preempt.request_reschedule()?;
+//! }
+//!
+//! rt.set_stall_watchdog(Duration::from_millis(500));
+//! rt.set_stall_callback(Some(|task, cpu, ran_ns| {
+//!     kernel_warn!("RT task {} hogged CPU {} for {}ns", task.as_u64(), cpu.as_u32(), ran_ns);
+//! }));
+//! ```
+
+use crate::kernel::scheduler::core::SchedPolicy;
+use crate::kernel::task::{Task, TaskId};
+use crate::kernel::cpu::CpuId;
+use crate::kernel::error::{KernelResult, SchedulerError};
+use crate::kernel::sync::{SpinLock, RwLock};
+use crate::kernel::time::Duration;
+use crate::kernel::log::{kernel_info, kernel_warn};
+use crate::kernel::memory::percpu::PerCpu;
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+
+/// Lowest RT priority
+pub const MIN_RT_PRIO: u8 = 1;
+
+/// Highest RT priority
+pub const MAX_RT_PRIO: u8 = 99;
+
+/// Default `RoundRobin` time slice in microseconds
+pub const DEFAULT_RR_TIMESLICE_US: u64 = 100_000; // 100ms
+
+/// Allowed range of a `RoundRobin` time slice
+const RR_TIMESLICE_RANGE_NS: (u64, u64) = (100_000, 1_000_000_000); // 0.1ms..=1s
+
+/// Window over which the RT utilization of a CPU is measured
+const RT_UTIL_WINDOW_NS: u64 = 1_000_000_000; // 1s
+
+/// Called with a stalled RT task, the CPU it ran on and how long it has
+/// run without yielding (ns)
+pub type StallCallback = fn(task: TaskId, cpu: CpuId, ran_ns: u64);
+
+/// Run of an RT task since it last yielded or slept
+#[derive(Debug, Default, Clone, Copy)]
+struct RtRun {
+    /// Time run so far, up to the start of the current slice (ns)
+    ran_ns: u64,
+    /// Whether the watchdog already reported this run
+    reported: bool,
+}
+
+/// Time slice of a queued `RoundRobin` task
+#[derive(Debug, Clone, Copy)]
+struct RrSlice {
+    /// Full slice (ns)
+    slice: u64,
+    /// What is left of the current slice (ns)
+    left: u64,
+}
+
+/// Runqueue state of an RT task moving between CPUs
+#[derive(Debug, Clone, Copy)]
+struct RtMove {
+    prio: u8,
+    rr: Option<RrSlice>,
+    run: Option<RtRun>,
+}
+
+/// What the RT tick found on a CPU
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RtTick {
+    /// The running task was flagged by the stall watchdog
+    pub stalled: bool,
+    /// The CPU must reschedule
+    pub resched: bool,
+}
+
+/// Per-CPU RT runqueue
+#[derive(Debug, Default)]
+struct RtRq {
+    /// Queued tasks per priority, in run order
+    queues: BTreeMap<u8, VecDeque<TaskId>>,
+    /// Priority of every queued or running task
+    prio: BTreeMap<TaskId, u8>,
+    /// Currently running RT task
+    curr: Option<TaskId>,
+    /// Whether the running task yielded and requeues at the tail
+    curr_yielded: bool,
+    /// Task clock when the running task was switched in
+    curr_start: u64,
+    /// Run of the running task
+    curr_run: RtRun,
+    /// Runs of preempted tasks that have not yielded or slept since
+    runs: BTreeMap<TaskId, RtRun>,
+    /// Time slices of queued `RoundRobin` tasks
+    rr: BTreeMap<TaskId, RrSlice>,
+    /// Task clock up to which the running task's slice was charged
+    curr_charged: u64,
+    /// RT runtime in the current utilization window
+    rt_time: u64,
+    /// Start of the current utilization window (task clock)
+    window_start: u64,
+    /// RT utilization over the last complete window (per mille)
+    window_util: u32,
+}
+
+impl RtRq {
+    /// Queue a task at the tail of its priority's run list with a full
+    /// slice if it is a `RoundRobin` task; returns false if already queued
+    fn enqueue(&mut self, task: TaskId, prio: u8, rr_slice: Option<u64>) -> bool {
+        if self.prio.contains_key(&task) {
+            return false;
+        }
+        self.prio.insert(task, prio);
+        self.queues.entry(prio).or_default().push_back(task);
+        if let Some(slice) = rr_slice {
+            self.rr.insert(task, RrSlice { slice, left: slice });
+        }
+        true
+    }
+
+    /// Forget a task that stopped being runnable
+    fn dequeue(&mut self, task: TaskId) {
+        self.unlink(task);
+        self.prio.remove(&task);
+        self.runs.remove(&task);
+        self.rr.remove(&task);
+        if self.curr == Some(task) {
+            self.curr = None;
+            self.curr_yielded = false;
+        }
+    }
+
+    /// Take a task off this runqueue with the state it keeps on another
+    /// CPU; a running task stops running
+    fn detach(&mut self, task: TaskId) -> Option<RtMove> {
+        let prio = self.prio.get(&task).copied()?;
+        let run = if self.curr == Some(task) {
+            self.curr = None;
+            self.curr_yielded = false;
+            Some(core::mem::take(&mut self.curr_run))
+        } else {
+            self.runs.remove(&task)
+        };
+        self.unlink(task);
+        self.prio.remove(&task);
+        Some(RtMove { prio, rr: self.rr.remove(&task), run })
+    }
+
+    /// Queue a task taken off another CPU at the tail of its run list
+    fn attach(&mut self, task: TaskId, moved: RtMove) {
+        self.prio.insert(task, moved.prio);
+        self.queues.entry(moved.prio).or_default().push_back(task);
+        if let Some(rr) = moved.rr {
+            self.rr.insert(task, rr);
+        }
+        if let Some(run) = moved.run {
+            self.runs.insert(task, run);
+        }
+    }
+
+    /// Start running a queued task
+    fn set_curr(&mut self, task: TaskId, now: u64) {
+        if self.prio.contains_key(&task) {
+            self.unlink(task);
+            self.curr = Some(task);
+            self.curr_yielded = false;
+            self.curr_start = now;
+            self.curr_charged = now;
+            self.curr_run = self.runs.remove(&task).unwrap_or_default();
+        }
+    }
+
+    /// Stop running a task; if still runnable it resumes first among its
+    /// peers, or last if it yielded
+    fn put_prev(&mut self, task: TaskId, now: u64) {
+        if self.curr != Some(task) {
+            return;
+        }
+        self.charge_curr(now);
+        self.curr = None;
+        let yielded = core::mem::take(&mut self.curr_yielded);
+        let mut run = core::mem::take(&mut self.curr_run);
+        if !yielded && self.prio.contains_key(&task) {
+            run.ran_ns += now.saturating_sub(self.curr_start);
+            self.runs.insert(task, run);
+        }
+        if let Some(prio) = self.prio.get(&task).copied() {
+            let queue = self.queues.entry(prio).or_default();
+            if yielded {
+                queue.push_back(task);
+            } else {
+                queue.push_front(task);
+            }
+        }
+    }
+
+    /// Charge the running `RoundRobin` task's slice up to `now`
+    fn charge_curr(&mut self, now: u64) {
+        let delta = now.saturating_sub(self.curr_charged);
+        self.curr_charged = self.curr_charged.max(now);
+        if self.curr.is_some() {
+            self.rt_time += delta;
+        }
+        if let Some(rr) = self.curr.and_then(|curr| self.rr.get_mut(&curr)) {
+            rr.left = rr.left.saturating_sub(delta);
+        }
+    }
+
+    /// Charge the running task and expire its `RoundRobin` slice
+    ///
+    /// An expired slice is refilled; the task then yields if a peer of its
+    /// priority is queued. Returns whether it must make room for that peer.
+    fn rr_tick(&mut self, now: u64) -> bool {
+        self.charge_curr(now);
+        let curr = match self.curr {
+            Some(curr) => curr,
+            None => return false,
+        };
+        match self.rr.get_mut(&curr) {
+            Some(rr) if rr.left == 0 => rr.left = rr.slice,
+            _ => return false,
+        }
+        let peers = self.prio.get(&curr)
+            .and_then(|prio| self.queues.get(prio))
+            .map_or(false, |queue| !queue.is_empty());
+        self.curr_yielded |= peers;
+        peers
+    }
+
+    /// Close the utilization window once it has run its length
+    fn roll_window(&mut self, now: u64) {
+        let elapsed = now.saturating_sub(self.window_start);
+        if elapsed < RT_UTIL_WINDOW_NS {
+            return;
+        }
+        self.charge_curr(now);
+        self.window_util = (self.rt_time * 1000 / elapsed).min(1000) as u32;
+        self.rt_time = 0;
+        self.window_start = now;
+    }
+
+    /// Move a task to another priority
+    ///
+    /// A queued task goes to the tail of its new run list; the running task
+    /// keeps running. Returns whether the CPU must reschedule: the task now
+    /// outranks the running one, or the running task fell below a queued one.
+    fn change_prio(&mut self, task: TaskId, prio: u8) -> bool {
+        if !self.prio.contains_key(&task) {
+            return false;
+        }
+        if self.curr == Some(task) {
+            self.prio.insert(task, prio);
+            return self.highest().map_or(false, |(highest, _)| highest > prio);
+        }
+        self.unlink(task);
+        self.prio.insert(task, prio);
+        self.queues.entry(prio).or_default().push_back(task);
+        self.curr_prio().map_or(true, |curr_prio| prio > curr_prio)
+    }
+
+    /// Priority of the running task
+    fn curr_prio(&self) -> Option<u8> {
+        self.curr.and_then(|curr| self.prio.get(&curr).copied())
+    }
+
+    /// Check whether a queued task should replace the running one: it has
+    /// a higher priority, or the running task yielded
+    fn check_preempt(&self, task: TaskId) -> bool {
+        let prio = match self.prio.get(&task) {
+            Some(&prio) => prio,
+            None => return false,
+        };
+        match self.curr_prio() {
+            Some(curr_prio) => prio > curr_prio || (self.curr_yielded && prio == curr_prio),
+            None => true,
+        }
+    }
+
+    /// Highest priority queued task
+    fn highest(&self) -> Option<(u8, TaskId)> {
+        self.queues.iter().next_back()
+            .and_then(|(&prio, queue)| queue.front().map(|&task| (prio, task)))
+    }
+
+    /// Remove a task from its run list
+    fn unlink(&mut self, task: TaskId) {
+        if let Some(prio) = self.prio.get(&task).copied() {
+            if let Some(queue) = self.queues.get_mut(&prio) {
+                queue.retain(|&t| t != task);
+                if queue.is_empty() {
+                    self.queues.remove(&prio);
+                }
+            }
+        }
+    }
+}
+
+/// Real-time scheduler component
+pub struct RtScheduler {
+    rqs: PerCpu<SpinLock<RtRq>>,
+    bandwidth_percent: AtomicU32,
+    /// `RoundRobin` time slice of tasks without their own (ns)
+    timeslice_ns: AtomicU64,
+    /// Per-task `RoundRobin` time slices (ns)
+    timeslices: RwLock<BTreeMap<TaskId, u64>>,
+    /// Run time after which an RT task counts as stalled (ns, 0 = off)
+    stall_threshold_ns: AtomicU64,
+    /// Whether a stalled task is sent to the tail of its run list
+    stall_resched: AtomicBool,
+    stall_callback: SpinLock<Option<StallCallback>>,
+    stall_events: AtomicU64,
+    /// Priority ceilings of the locks every task holds, in acquisition order
+    ceilings: SpinLock<BTreeMap<TaskId, Vec<u8>>>,
+}
+
+impl RtScheduler {
+    /// Create an RT scheduler limited to a percentage of CPU time
+    pub fn with_bandwidth(bandwidth_percent: u32) -> Self {
+        Self::with_config(bandwidth_percent, DEFAULT_RR_TIMESLICE_US)
+    }
+
+    /// Create an RT scheduler limited to a percentage of CPU time, with a
+    /// default `RoundRobin` time slice in microseconds
+    pub fn with_config(bandwidth_percent: u32, timeslice_us: u64) -> Self {
+        let (min, max) = RR_TIMESLICE_RANGE_NS;
+        Self {
+            rqs: PerCpu::new(SpinLock::new(RtRq::default())),
+            bandwidth_percent: AtomicU32::new(bandwidth_percent.min(100)),
+            timeslice_ns: AtomicU64::new((timeslice_us * 1_000).clamp(min, max)),
+            timeslices: RwLock::new(BTreeMap::new()),
+            stall_threshold_ns: AtomicU64::new(0),
+            stall_resched: AtomicBool::new(false),
+            stall_callback: SpinLock::new(None),
+            stall_events: AtomicU64::new(0),
+            ceilings: SpinLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// RT bandwidth limit in percent
+    pub fn bandwidth_percent(&self) -> u32 {
+        self.bandwidth_percent.load(Ordering::Relaxed)
+    }
+
+    /// Make an RT task runnable at the tail of its priority's run list
+    pub fn enqueue_task(&self, task: &Task) -> KernelResult<()> {
+        if !(MIN_RT_PRIO..=MAX_RT_PRIO).contains(&task.rt_priority()) {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        let prio = self.effective_priority(task);
+        let rr_slice = self.rr_slice(task);
+        self.rqs.get(task.current_cpu()).lock().enqueue(task.id(), prio, rr_slice);
+        Ok(())
+    }
+
+    /// Make woken RT tasks of one CPU runnable under a single runqueue lock
+    ///
+    /// Fails without enqueuing anything if a priority is out of range.
+    /// Returns whether one of them should preempt the running RT task.
+    pub fn enqueue_tasks(&self, cpu: CpuId, tasks: &[&Task]) -> KernelResult<bool> {
+        if tasks.iter().any(|task| !(MIN_RT_PRIO..=MAX_RT_PRIO).contains(&task.rt_priority())) {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        let entries: Vec<(TaskId, u8, Option<u64>)> = tasks.iter()
+            .map(|task| (task.id(), self.effective_priority(task), self.rr_slice(task)))
+            .collect();
+        Ok(self.enqueue_entries(cpu, &entries))
+    }
+
+    /// Queue woken tasks of one CPU, each with its priority and
+    /// `RoundRobin` slice, under a single runqueue lock
+    ///
+    /// Returns whether one of them should preempt the running RT task.
+    fn enqueue_entries(&self, cpu: CpuId, tasks: &[(TaskId, u8, Option<u64>)]) -> bool {
+        let mut rq = self.rqs.get(cpu).lock();
+        let curr_prio = rq.curr_prio();
+        let mut preempt = false;
+        for &(task, prio, rr_slice) in tasks {
+            preempt |= curr_prio.map_or(true, |curr_prio| prio > curr_prio);
+            rq.enqueue(task, prio, rr_slice);
+        }
+        preempt
+    }
+
+    /// Remove an RT task from its CPU (sleep, exit or migration)
+    pub fn dequeue_task(&self, task: &Task) -> KernelResult<()> {
+        self.rqs.get(task.current_cpu()).lock().dequeue(task.id());
+        Ok(())
+    }
+
+    /// Move an RT task's runqueue state between CPUs
+    ///
+    /// It keeps its priority, the rest of its `RoundRobin` slice and its
+    /// watchdog run, and is queued at the tail of its run list on `to`; a
+    /// running task arrives queued. Does nothing if it is not queued on
+    /// `from`.
+    pub fn migrate_task(&self, task: TaskId, from: CpuId, to: CpuId) {
+        if from == to {
+            return;
+        }
+        let moved = self.rqs.get(from).lock().detach(task);
+        if let Some(moved) = moved {
+            self.rqs.get(to).lock().attach(task, moved);
+        }
+    }
+
+    /// Peek at the highest priority queued RT task of a CPU
+    pub fn pick_next_task(&self, cpu: CpuId) -> KernelResult<Option<Arc<Task>>> {
+        let next = self.rqs.get(cpu).lock().highest().map(|(_, task)| task);
+        Ok(next.and_then(Task::get_by_id))
+    }
+
+    /// Check whether a woken RT task should preempt the RT task running on its CPU
+    pub fn should_preempt_current(&self, task: &Task) -> KernelResult<bool> {
+        let curr_prio = self.rqs.get(task.current_cpu()).lock().curr_prio();
+        Ok(match curr_prio {
+            Some(curr_prio) => self.effective_priority(task) > curr_prio,
+            None => true,
+        })
+    }
+
+    /// Check whether a queued RT task should replace the RT task running on
+    /// a CPU, comparing their priorities
+    pub fn check_preempt_curr(&self, cpu: CpuId, task: TaskId) -> bool {
+        self.rqs.get(cpu).lock().check_preempt(task)
+    }
+
+    /// Change the RT priority of a task
+    ///
+    /// A queued task moves to the tail of its new priority's run list. A
+    /// task holding priority ceilings keeps running at least at the
+    /// highest of them.
+    ///
+    /// # Returns
+    /// - `Ok(true)` if the task's CPU must reschedule
+    /// - `Ok(false)` if the running task is unaffected
+    /// - `Err(SchedulerError::InvalidParameter)` if the priority is outside 1..=99
+    pub fn set_priority(&self, task: &Task, prio: u8) -> KernelResult<bool> {
+        if !(MIN_RT_PRIO..=MAX_RT_PRIO).contains(&prio) {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        task.set_rt_priority(prio);
+        let prio = self.ceilings.lock().get(&task.id()).map_or(prio, |held| Self::ceiling_prio(prio, held));
+        Ok(self.rqs.get(task.current_cpu()).lock().change_prio(task.id(), prio))
+    }
+
+    /// Priority a task runs at: its RT priority, raised to the highest
+    /// priority ceiling of the locks it holds
+    pub fn effective_priority(&self, task: &Task) -> u8 {
+        let prio = task.rt_priority();
+        self.ceilings.lock().get(&task.id()).map_or(prio, |held| Self::ceiling_prio(prio, held))
+    }
+
+    /// Raise a task to the priority ceiling of a lock it acquires
+    ///
+    /// Ceilings nest: the task runs at the highest one it holds until it
+    /// releases them with `release_ceiling`, in reverse order.
+    ///
+    /// # Returns
+    /// - `Ok(true)` if the task's CPU must reschedule
+    /// - `Ok(false)` if the running task is unaffected
+    /// - `Err(SchedulerError::InvalidParameter)` if the ceiling is outside
+    ///   1..=99 or below the task's own RT priority
+    pub fn acquire_ceiling(&self, task: &Task, ceiling: u8) -> KernelResult<bool> {
+        self.push_ceiling(task.current_cpu(), task.id(), task.rt_priority(), ceiling)
+    }
+
+    /// Drop the priority ceiling a task acquired last, when it releases
+    /// the lock
+    ///
+    /// # Returns
+    /// - `Ok(true)` if the task's CPU must reschedule, typically because
+    ///   the task fell below a queued task
+    /// - `Ok(false)` if the running task is unaffected
+    /// - `Err(SchedulerError::InvalidParameter)` if the task holds no ceiling
+    pub fn release_ceiling(&self, task: &Task) -> KernelResult<bool> {
+        self.pop_ceiling(task.current_cpu(), task.id(), task.rt_priority())
+    }
+
+    /// Number of priority ceilings a task holds
+    pub fn ceilings_held(&self, task: TaskId) -> usize {
+        self.ceilings.lock().get(&task).map_or(0, |held| held.len())
+    }
+
+    /// Push a ceiling of a task with RT priority `base` on `cpu`
+    fn push_ceiling(&self, cpu: CpuId, task: TaskId, base: u8, ceiling: u8) -> KernelResult<bool> {
+        if !(MIN_RT_PRIO..=MAX_RT_PRIO).contains(&ceiling) || ceiling < base {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        let prio = {
+            let mut ceilings = self.ceilings.lock();
+            let held = ceilings.entry(task).or_default();
+            held.push(ceiling);
+            Self::ceiling_prio(base, held)
+        };
+        Ok(self.rqs.get(cpu).lock().change_prio(task, prio))
+    }
+
+    /// Pop the last ceiling of a task with RT priority `base` on `cpu`
+    fn pop_ceiling(&self, cpu: CpuId, task: TaskId, base: u8) -> KernelResult<bool> {
+        let prio = {
+            let mut ceilings = self.ceilings.lock();
+            let held = match ceilings.get_mut(&task) {
+                Some(held) => held,
+                None => return Err(SchedulerError::InvalidParameter.into()),
+            };
+            held.pop();
+            let prio = Self::ceiling_prio(base, held);
+            if held.is_empty() {
+                ceilings.remove(&task);
+            }
+            prio
+        };
+        Ok(self.rqs.get(cpu).lock().change_prio(task, prio))
+    }
+
+    /// `base` raised to the highest of the ceilings `held`
+    fn ceiling_prio(base: u8, held: &[u8]) -> u8 {
+        held.iter().copied().fold(base, u8::max)
+    }
+
+    /// Start running a queued RT task on a CPU
+    ///
+    /// `now` is the task clock.
+    pub fn set_curr_task(&self, cpu: CpuId, task: TaskId, now: u64) {
+        self.rqs.get(cpu).lock().set_curr(task, now);
+    }
+
+    /// Stop running an RT task; if still runnable it resumes first among
+    /// its peers, or last if it yielded
+    ///
+    /// `now` is the task clock.
+    pub fn put_prev_task(&self, cpu: CpuId, task: TaskId, now: u64) {
+        self.rqs.get(cpu).lock().put_prev(task, now);
+    }
+
+    /// Have the running RT task of a CPU requeue at the tail of its
+    /// priority's run list when it is switched out
+    ///
+    /// `now` is the task clock; the yield ends the task's run for the stall
+    /// watchdog. Returns whether another task of the same priority is
+    /// queued; without one the task simply keeps running.
+    pub fn yield_task(&self, cpu: CpuId, task: TaskId, now: u64) -> bool {
+        let mut rq = self.rqs.get(cpu).lock();
+        if rq.curr != Some(task) {
+            return false;
+        }
+        rq.curr_yielded = true;
+        rq.curr_start = now;
+        rq.curr_run = RtRun::default();
+        rq.prio.get(&task)
+            .and_then(|prio| rq.queues.get(prio))
+            .map_or(false, |queue| !queue.is_empty())
+    }
+
+    /// Yield the running RT task of a CPU to a queued task of the same
+    /// priority, which moves to the head of the run list
+    ///
+    /// The running task yields either way. Returns whether the target was
+    /// moved.
+    pub fn yield_to(&self, cpu: CpuId, task: TaskId, target: TaskId) -> bool {
+        let mut rq = self.rqs.get(cpu).lock();
+        if rq.curr != Some(task) {
+            return false;
+        }
+        rq.curr_yielded = true;
+        let prio = match rq.prio.get(&task).copied() {
+            Some(prio) => prio,
+            None => return false,
+        };
+        match rq.queues.get_mut(&prio) {
+            Some(queue) if queue.contains(&target) => {
+                queue.retain(|&t| t != target);
+                queue.push_front(target);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Give a task its own `RoundRobin` time slice
+    ///
+    /// A queued task gets the new slice when its current one runs out.
+    ///
+    /// # Returns
+    /// - `Ok(())` if the slice was set
+    /// - `Err(SchedulerError::InvalidParameter)` if the slice is outside
+    ///   0.1ms..=1s
+    pub fn set_timeslice(&self, task: &Task, slice: Duration) -> KernelResult<()> {
+        let slice = slice.as_nanos();
+        let (min, max) = RR_TIMESLICE_RANGE_NS;
+        if !(min..=max).contains(&slice) {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        self.timeslices.write().insert(task.id(), slice);
+        if let Some(rr) = self.rqs.get(task.current_cpu()).lock().rr.get_mut(&task.id()) {
+            rr.slice = slice;
+        }
+        Ok(())
+    }
+
+    /// `RoundRobin` time slice of a task in nanoseconds
+    pub fn timeslice(&self, task: TaskId) -> u64 {
+        self.timeslices.read().get(&task).copied()
+            .unwrap_or_else(|| self.timeslice_ns.load(Ordering::Relaxed))
+    }
+
+    /// Forget the time slice and priority ceilings of an exiting task
+    pub fn remove_task(&self, task: TaskId) {
+        self.timeslices.write().remove(&task);
+        self.ceilings.lock().remove(&task);
+    }
+
+    /// Flag RT tasks that run for longer than `threshold` without
+    /// yielding or sleeping; a zero threshold turns the watchdog off
+    pub fn set_stall_watchdog(&self, threshold: Duration) {
+        self.stall_threshold_ns.store(threshold.as_nanos(), Ordering::Relaxed);
+    }
+
+    /// Set the function called when a stalled task is flagged
+    pub fn set_stall_callback(&self, callback: Option<StallCallback>) {
+        *self.stall_callback.lock() = callback;
+    }
+
+    /// Choose whether a flagged task is forced off the CPU in favour of
+    /// its peers, as if it had yielded
+    pub fn set_stall_resched(&self, resched: bool) {
+        self.stall_resched.store(resched, Ordering::Relaxed);
+    }
+
+    /// Number of stalls flagged so far
+    pub fn stall_events(&self) -> u64 {
+        self.stall_events.load(Ordering::Relaxed)
+    }
+
+    /// Charge the running RT task of a CPU on the scheduler tick
+    ///
+    /// `now` is the task clock. A `RoundRobin` task whose slice ran out
+    /// goes behind its peers. The task is also checked against the stall
+    /// watchdog, which flags a run once; if the task is then forced to
+    /// yield, its next run starts afresh.
+    pub fn task_tick(&self, cpu: CpuId, now: u64) -> RtTick {
+        let threshold = self.stall_threshold_ns.load(Ordering::Relaxed);
+        let stall_resched = self.stall_resched.load(Ordering::Relaxed);
+        let mut tick = RtTick::default();
+        let stall = {
+            let mut rq = self.rqs.get(cpu).lock();
+            tick.resched = rq.rr_tick(now);
+            rq.roll_window(now);
+            match rq.curr {
+                Some(task) if threshold > 0 && !rq.curr_run.reported => {
+                    let ran_ns = rq.curr_run.ran_ns + now.saturating_sub(rq.curr_start);
+                    if ran_ns >= threshold {
+                        rq.curr_run.reported = true;
+                        rq.curr_yielded |= stall_resched;
+                        Some((task, ran_ns))
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            }
+        };
+        if let Some((task, ran_ns)) = stall {
+            tick.stalled = true;
+            tick.resched |= stall_resched;
+            self.stall_events.fetch_add(1, Ordering::Relaxed);
+            kernel_warn!("RT task {} ran {}ns on CPU {} without yielding",
+                        task.as_u64(), ran_ns, cpu.as_u32());
+            let callback = *self.stall_callback.lock();
+            if let Some(callback) = callback {
+                callback(task, cpu, ran_ns);
+            }
+        }
+        tick
+    }
+
+    /// Queued RT tasks of a CPU per priority, highest priority first
+    pub fn run_lists(&self, cpu: CpuId) -> Vec<(u8, Vec<TaskId>)> {
+        self.rqs.get(cpu).lock().queues.iter().rev()
+            .map(|(&prio, queue)| (prio, queue.iter().copied().collect()))
+            .collect()
+    }
+
+    /// Runnable RT tasks of a CPU, the running one included, highest
+    /// priority first
+    pub fn queued_tasks(&self, cpu: CpuId) -> Vec<TaskId> {
+        let rq = self.rqs.get(cpu).lock();
+        let mut tasks: Vec<TaskId> = rq.queues.values().rev().flatten().copied().collect();
+        tasks.extend(rq.curr);
+        tasks
+    }
+
+    /// Number of runnable RT tasks on a CPU, including the running one
+    pub fn nr_running(&self, cpu: CpuId) -> usize {
+        self.rqs.get(cpu).lock().prio.len()
+    }
+
+    /// Share of a CPU's time RT tasks ran in the last second (per mille)
+    pub fn rt_utilization(&self, cpu: CpuId) -> u32 {
+        self.rqs.get(cpu).lock().window_util
+    }
+
+    /// Time slice a task is queued with, if it is a `RoundRobin` task
+    fn rr_slice(&self, task: &Task) -> Option<u64> {
+        matches!(task.sched_policy(), SchedPolicy::RoundRobin).then(|| self.timeslice(task.id()))
+    }
+
+    /// Log RT scheduler state
+    pub fn print_rt_info(&self) -> KernelResult<()> {
+        kernel_info!("RT bandwidth: {}%", self.bandwidth_percent());
+        kernel_info!("RR timeslice: {}ns, {} per-task overrides",
+                    self.timeslice_ns.load(Ordering::Relaxed), self.timeslices.read().len());
+        let threshold = self.stall_threshold_ns.load(Ordering::Relaxed);
+        if threshold > 0 {
+            kernel_info!("RT stall watchdog: {}ns, {} stalls", threshold, self.stall_events());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kernel::scheduler::wait::WakeBatch;
+
+    #[test]
+    fn test_rr_cpu_time_follows_timeslices() {
+        let (a, b) = (TaskId::new(1), TaskId::new(2));
+        let mut rq = RtRq::default();
+        rq.enqueue(a, 50, Some(2_000_000));
+        rq.enqueue(b, 50, Some(6_000_000));
+
+        // 800 ticks of 1ms, switching whenever a slice runs out
+        let mut ran: BTreeMap<TaskId, u64> = BTreeMap::new();
+        let mut curr = rq.highest().unwrap().1;
+        rq.set_curr(curr, 0);
+        for tick in 1..=800 {
+            let now = tick * 1_000_000;
+            *ran.entry(curr).or_default() += 1_000_000;
+            if rq.rr_tick(now) {
+                rq.put_prev(curr, now);
+                curr = rq.highest().unwrap().1;
+                rq.set_curr(curr, now);
+            }
+        }
+        assert_eq!(ran[&a], 200_000_000);
+        assert_eq!(ran[&b], 3 * ran[&a]);
+    }
+
+    #[test]
+    fn test_higher_rt_priorities_run_first() {
+        let (low, mid, high) = (TaskId::new(1), TaskId::new(2), TaskId::new(3));
+        let mut rq = RtRq::default();
+        rq.enqueue(mid, 50, None);
+        rq.enqueue(low, 10, None);
+        rq.set_curr(low, 0);
+        // A queued higher priority task preempts the running one
+        assert!(rq.check_preempt(mid));
+        rq.put_prev(low, 0);
+        rq.enqueue(high, 90, None);
+
+        let mut order = Vec::new();
+        let mut now = 0;
+        while let Some((_, next)) = rq.highest() {
+            now += 1_000_000;
+            rq.set_curr(next, now);
+            rq.dequeue(next);
+            order.push(next);
+        }
+        assert_eq!(order, [high, mid, low]);
+
+        // Raising a queued task above the running one calls for a reschedule
+        rq.enqueue(low, 10, None);
+        rq.enqueue(mid, 50, None);
+        rq.set_curr(mid, now);
+        assert!(!rq.check_preempt(low));
+        assert!(rq.change_prio(low, 90));
+        assert_eq!(rq.highest(), Some((90, low)));
+        assert!(rq.check_preempt(low));
+    }
+
+    #[test]
+    fn test_migrated_task_is_only_queued_on_its_new_cpu() {
+        let rt = RtScheduler::with_bandwidth(100);
+        let (src, dst) = (CpuId::new(0), CpuId::new(1));
+        let (a, b, c) = (TaskId::new(1), TaskId::new(2), TaskId::new(3));
+        rt.rqs.get(src).lock().enqueue(a, 50, Some(4_000_000));
+        rt.rqs.get(src).lock().enqueue(b, 50, None);
+        rt.rqs.get(dst).lock().enqueue(c, 50, None);
+        {
+            // `a` runs 1ms of its slice before its affinity changes
+            let mut rq = rt.rqs.get(src).lock();
+            rq.set_curr(a, 0);
+            rq.put_prev(a, 1_000_000);
+        }
+
+        rt.migrate_task(a, src, dst);
+        assert_eq!(rt.run_lists(src), [(50, vec![b])]);
+        assert_eq!(rt.nr_running(src), 1);
+        assert_eq!(rt.rqs.get(src).lock().highest(), Some((50, b)));
+        // Queued behind `c` with what is left of its slice and its run
+        assert_eq!(rt.run_lists(dst), [(50, vec![c, a])]);
+        assert_eq!(rt.nr_running(dst), 2);
+        assert_eq!(rt.rqs.get(dst).lock().rr[&a].left, 3_000_000);
+        assert_eq!(rt.rqs.get(dst).lock().runs[&a].ran_ns, 1_000_000);
+
+        // Moving again from a CPU it left does nothing
+        rt.migrate_task(a, src, dst);
+        assert_eq!(rt.nr_running(dst), 2);
+        rt.rqs.get(dst).lock().dequeue(a);
+        assert_eq!(rt.run_lists(dst), [(50, vec![c])]);
+    }
+
+    #[test]
+    fn test_woken_batch_is_queued_with_one_enqueue_per_cpu() {
+        const CPUS: u64 = 4;
+        const WAITERS: u64 = 64;
+        let rt = RtScheduler::with_bandwidth(100);
+        let cpu_of = |id: u64| CpuId::new((id % CPUS) as u32);
+        // CPU 0 already runs a task above every waiter
+        rt.rqs.get(cpu_of(0)).lock().enqueue(TaskId::new(1_000), 90, None);
+        rt.rqs.get(cpu_of(0)).lock().set_curr(TaskId::new(1_000), 0);
+
+        let mut batch = WakeBatch::new();
+        for id in 0..WAITERS {
+            batch.add(cpu_of(id), (TaskId::new(id), 10 + (id / CPUS % 2) as u8, None));
+        }
+        let mut enqueues = Vec::new();
+        let mut kicked = Vec::new();
+        let woken = batch.flush(|cpu, entries| {
+            enqueues.push(cpu);
+            (entries.len(), rt.enqueue_entries(cpu, entries))
+        }, |cpu| kicked.push(cpu));
+
+        assert_eq!(woken, WAITERS as usize);
+        assert_eq!(enqueues, (0..CPUS).map(cpu_of).collect::<Vec<_>>());
+        assert_eq!(kicked, (1..CPUS).map(cpu_of).collect::<Vec<_>>());
+        for cpu in 0..CPUS {
+            let queued: usize = rt.run_lists(cpu_of(cpu)).iter().map(|(_, queue)| queue.len()).sum();
+            assert_eq!(queued, (WAITERS / CPUS) as usize);
+        }
+        // Higher priority waiters first, each priority in wakeup order
+        assert_eq!(rt.run_lists(cpu_of(1))[0], (11, vec![TaskId::new(5), TaskId::new(13), TaskId::new(21),
+                                                         TaskId::new(29), TaskId::new(37), TaskId::new(45),
+                                                         TaskId::new(53), TaskId::new(61)]));
+    }
+
+    #[test]
+    fn test_rt_tasks_move_off_an_offlining_cpu() {
+        let rt = RtScheduler::with_bandwidth(100);
+        let (dying, other) = (CpuId::new(0), CpuId::new(1));
+        let (running, high, low) = (TaskId::new(1), TaskId::new(2), TaskId::new(3));
+        {
+            let mut rq = rt.rqs.get(dying).lock();
+            rq.enqueue(running, 50, None);
+            rq.enqueue(high, 60, None);
+            rq.enqueue(low, 10, None);
+            rq.set_curr(running, 0);
+        }
+
+        // What `cpu_offline` drains: queued tasks and the running one
+        let tasks = rt.queued_tasks(dying);
+        assert_eq!(tasks, [high, low, running]);
+        for task in tasks {
+            rt.migrate_task(task, dying, other);
+        }
+
+        assert_eq!(rt.nr_running(dying), 0);
+        assert_eq!(rt.rqs.get(dying).lock().highest(), None);
+        assert_eq!(rt.rqs.get(dying).lock().curr, None);
+        assert_eq!(rt.run_lists(other), [(60, vec![high]), (50, vec![running]), (10, vec![low])]);
+        assert_eq!(rt.nr_running(other), 3);
+    }
+
+    /// Step of a simulated task
+    #[derive(Debug, Clone, Copy)]
+    enum Step {
+        Lock(usize),
+        Run,
+        Unlock(usize),
+    }
+
+    /// Time from the arrival of a high priority task until it got through
+    /// two locks, each held by a lower priority task, on one CPU (ms)
+    ///
+    /// `low` (10) takes lock A at 0ms, `mid` (20) wakes at 1ms and wants
+    /// lock B, `high` (90) wakes at 2ms and needs A and then B. Critical
+    /// sections of the lower tasks take 3ms; `high` needs no CPU time of
+    /// its own. Without ceilings, lock owners inherit the priority of the
+    /// task they block.
+    fn high_blocking_ms(ceiling: bool) -> u64 {
+        const CEILING: u8 = 90;
+        let rt = RtScheduler::with_bandwidth(100);
+        let cpu = CpuId::new(0);
+        let (low, mid, high) = (TaskId::new(1), TaskId::new(2), TaskId::new(3));
+        let base: BTreeMap<TaskId, u8> = [(low, 10), (mid, 20), (high, 90)].into_iter().collect();
+        let arrivals = [(low, 0), (mid, 1), (high, 2)];
+        let section = |lock| [Step::Lock(lock), Step::Run, Step::Run, Step::Run, Step::Unlock(lock)];
+        let mut scripts: BTreeMap<TaskId, VecDeque<Step>> = BTreeMap::new();
+        scripts.insert(low, section(0).into_iter().collect());
+        scripts.insert(mid, section(1).into_iter().collect());
+        scripts.insert(high, [Step::Lock(0), Step::Unlock(0), Step::Lock(1), Step::Unlock(1)].into_iter().collect());
+        let mut owner: [Option<TaskId>; 2] = [None; 2];
+        let mut waiting: Vec<(TaskId, usize)> = Vec::new();
+
+        for now in 0..20 {
+            for &(task, at) in &arrivals {
+                if at == now {
+                    rt.rqs.get(cpu).lock().enqueue(task, base[&task], None);
+                }
+            }
+            // Instant steps run until a task spends the millisecond
+            loop {
+                let curr = {
+                    let mut rq = rt.rqs.get(cpu).lock();
+                    match (rq.curr, rq.highest()) {
+                        (Some(curr), Some((_, next))) if rq.check_preempt(next) => {
+                            rq.put_prev(curr, now);
+                            rq.set_curr(next, now);
+                        }
+                        (None, Some((_, next))) => rq.set_curr(next, now),
+                        _ => {}
+                    }
+                    match rq.curr {
+                        Some(curr) => curr,
+                        None => break,
+                    }
+                };
+                let step = scripts.get_mut(&curr).unwrap().pop_front();
+                match step {
+                    Some(Step::Run) => break,
+                    Some(Step::Lock(lock)) => match owner[lock] {
+                        None => {
+                            owner[lock] = Some(curr);
+                            if ceiling {
+                                rt.push_ceiling(cpu, curr, base[&curr], CEILING).unwrap();
+                            }
+                        }
+                        Some(holder) => {
+                            scripts.get_mut(&curr).unwrap().push_front(Step::Lock(lock));
+                            waiting.push((curr, lock));
+                            let mut rq = rt.rqs.get(cpu).lock();
+                            rq.dequeue(curr);
+                            rq.change_prio(holder, base[&curr]);
+                        }
+                    },
+                    Some(Step::Unlock(lock)) => {
+                        owner[lock] = None;
+                        if ceiling {
+                            rt.pop_ceiling(cpu, curr, base[&curr]).unwrap();
+                        } else {
+                            rt.rqs.get(cpu).lock().change_prio(curr, base[&curr]);
+                        }
+                        for (task, _) in waiting.iter().filter(|(_, l)| *l == lock) {
+                            rt.rqs.get(cpu).lock().enqueue(*task, base[task], None);
+                        }
+                        waiting.retain(|(_, l)| *l != lock);
+                    }
+                    None => {
+                        rt.rqs.get(cpu).lock().dequeue(curr);
+                        if curr == high {
+                            return now - 2;
+                        }
+                    }
+                }
+            }
+        }
+        panic!("high priority task never finished");
+    }
+
+    #[test]
+    fn test_ceiling_bounds_blocking_to_one_critical_section() {
+        // Inheritance: `mid` takes B while `low` holds A, so `high` waits
+        // for what is left of both sections
+        assert_eq!(high_blocking_ms(false), 4);
+        // Ceiling: `low` runs at 90 inside A, `mid` never gets to take B
+        assert_eq!(high_blocking_ms(true), 1);
+
+        // A ceiling below the task's own priority is refused
+        let rt = RtScheduler::with_bandwidth(100);
+        let (cpu, task) = (CpuId::new(0), TaskId::new(1));
+        assert!(rt.push_ceiling(cpu, task, 50, 40).is_err());
+        assert!(rt.push_ceiling(cpu, task, 50, 0).is_err());
+        assert!(rt.pop_ceiling(cpu, task, 50).is_err());
+
+        // Nested ceilings release in reverse order
+        rt.rqs.get(cpu).lock().enqueue(task, 50, None);
+        rt.push_ceiling(cpu, task, 50, 80).unwrap();
+        rt.push_ceiling(cpu, task, 50, 60).unwrap();
+        assert_eq!(rt.ceilings_held(task), 2);
+        assert_eq!(rt.rqs.get(cpu).lock().highest(), Some((80, task)));
+        rt.pop_ceiling(cpu, task, 50).unwrap();
+        assert_eq!(rt.rqs.get(cpu).lock().highest(), Some((80, task)));
+        rt.pop_ceiling(cpu, task, 50).unwrap();
+        assert_eq!(rt.rqs.get(cpu).lock().highest(), Some((50, task)));
+        assert_eq!(rt.ceilings_held(task), 0);
+    }
+
+    #[test]
+    fn test_rt_utilization_over_last_window() {
+        let task = TaskId::new(1);
+        let mut rq = RtRq::default();
+        rq.enqueue(task, 50, None);
+
+        // RT runs 600ms of the first second
+        rq.set_curr(task, 0);
+        rq.put_prev(task, 600_000_000);
+        rq.roll_window(900_000_000);
+        assert_eq!(rq.window_util, 0);
+        rq.roll_window(RT_UTIL_WINDOW_NS);
+        assert_eq!(rq.window_util, 600);
+
+        // and all of the next one
+        rq.set_curr(task, RT_UTIL_WINDOW_NS);
+        rq.roll_window(2 * RT_UTIL_WINDOW_NS);
+        assert_eq!(rq.window_util, 1_000);
+    }
+}
@@ -0,0 +1,463 @@
+//! # Scheduler Statistics
+//!
+//! This module accounts per-task scheduling statistics (schedstats): how
+//! long each task ran, how long it waited on a runqueue before running, and
+//! how long it slept. The accounting hooks run on every enqueue and context
+//! switch, so they are disabled unless schedstats are switched on.
+//!
+//! Tasks with a CPU quota are always accounted: the quota caps the share of
+//! one CPU a task may use within a window, and the task is throttled once
+//! its runtime in the current window exceeds that share.
+//!
+//! Wakeup latency, the time from a task's wakeup to its first run after
+//! it, is always measured: per task as a moving average, and for all tasks
+//! as a histogram.
+//!
+//! ## Features
+//! - Per-task run, wait, sleep and block time
+//! - Wait and migration counts
+//! - Runtime enable switch (off by default)
+//! - Per-task CPU quota with throttled time accounting
+//! - Per-task wakeup latency and a global wakeup latency histogram
+//!
+//! ## Usage
+//! ```rust
+//! use crate::kernel::scheduler::stats::StatsScheduler;
+//!
+//! let stats = StatsScheduler::with_enabled(true);
+//! stats.on_enqueue(task.id(), now);
+//! stats.on_switch_in(task.id(), now);
+//!
+//! let schedstats = stats.task_schedstats(task.id());
+//! ```
+
+use crate::kernel::task::{TaskId, TaskState};
+use crate::kernel::sync::{SpinLock, RwLock};
+use crate::kernel::log::kernel_info;
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Scheduling statistics of one task (nanoseconds unless noted)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TaskSchedStats {
+    /// Total time spent running
+    pub sum_exec_runtime: u64,
+    /// Total time spent runnable but waiting for a CPU
+    pub wait_sum: u64,
+    /// Number of waits on a runqueue
+    pub wait_count: u64,
+    /// Total time spent in interruptible sleep
+    pub sleep_sum: u64,
+    /// Total time spent blocked in uninterruptible sleep
+    pub block_sum: u64,
+    /// Number of migrations to another CPU
+    pub nr_migrations: u64,
+}
+
+/// Number of buckets of a `LatencyHistogram`
+pub const LATENCY_BUCKETS: usize = 24;
+
+/// Weight of a new sample in a task's wakeup latency average (1/8)
+const WAKEUP_LATENCY_SHIFT: u32 = 3;
+
+/// Histogram of latencies in power-of-two microsecond buckets
+///
+/// Bucket 0 counts latencies below 1us, bucket `i` those from `2^(i-1)` up
+/// to `2^i` microseconds; the last bucket also counts everything longer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    buckets: [u64; LATENCY_BUCKETS],
+}
+
+impl LatencyHistogram {
+    /// Count a latency (nanoseconds)
+    pub fn record(&mut self, latency_ns: u64) {
+        let us = latency_ns / 1_000;
+        let bucket = (u64::BITS - us.leading_zeros()) as usize;
+        self.buckets[bucket.min(LATENCY_BUCKETS - 1)] += 1;
+    }
+
+    /// Number of latencies counted
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// Counts per bucket
+    pub fn buckets(&self) -> &[u64; LATENCY_BUCKETS] {
+        &self.buckets
+    }
+
+    /// Upper bound (nanoseconds) of the bucket holding the `percent`th
+    /// percentile, or 0 if nothing was counted
+    pub fn percentile(&self, percent: u8) -> u64 {
+        let total = self.count();
+        if total == 0 {
+            return 0;
+        }
+        let rank = (total * percent.min(100) as u64).div_ceil(100).max(1);
+        let mut seen = 0;
+        for (bucket, &count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return (1u64 << bucket) * 1_000;
+            }
+        }
+        (1u64 << (LATENCY_BUCKETS - 1)) * 1_000
+    }
+
+    /// Add the counts of another histogram
+    pub fn merge(&mut self, other: &LatencyHistogram) {
+        for (bucket, count) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *bucket += count;
+        }
+    }
+}
+
+/// Accounting state of one task
+#[derive(Debug, Clone, Copy, Default)]
+struct TaskStatsState {
+    stats: TaskSchedStats,
+    /// Start of the current runqueue wait (0 if not waiting)
+    wait_start: u64,
+    /// Start of the current sleep (0 if not sleeping)
+    sleep_start: u64,
+    /// Start of the current uninterruptible block (0 if not blocked)
+    block_start: u64,
+    /// Start of the current run (0 if not running)
+    exec_start: u64,
+}
+
+impl TaskStatsState {
+    /// Total runtime including the run in progress
+    fn runtime(&self, now: u64) -> u64 {
+        let running = if self.exec_start != 0 { now.saturating_sub(self.exec_start) } else { 0 };
+        self.stats.sum_exec_runtime + running
+    }
+}
+
+/// CPU quota state of one task
+#[derive(Debug, Clone, Copy, Default)]
+struct QuotaState {
+    /// Runtime allowed per window
+    budget: u64,
+    /// Window length
+    window: u64,
+    /// Start of the current window
+    window_start: u64,
+    /// Task runtime at the start of the current window
+    window_runtime: u64,
+    /// Start of the current throttle (0 if not throttled)
+    throttled_at: u64,
+    /// Total time spent throttled in completed throttles
+    throttled_sum: u64,
+}
+
+/// Statistics scheduler component
+pub struct StatsScheduler {
+    enabled: AtomicBool,
+    tasks: RwLock<BTreeMap<TaskId, TaskStatsState>>,
+    quotas: RwLock<BTreeMap<TaskId, QuotaState>>,
+    /// Moving average of each task's wakeup latency (nanoseconds)
+    wakeup_latency: RwLock<BTreeMap<TaskId, u64>>,
+    wakeup_histogram: SpinLock<LatencyHistogram>,
+}
+
+impl StatsScheduler {
+    /// Create a statistics scheduler with schedstats disabled
+    pub fn new() -> Self {
+        Self::with_enabled(false)
+    }
+
+    /// Create a statistics scheduler
+    pub fn with_enabled(enabled: bool) -> Self {
+        Self {
+            enabled: AtomicBool::new(enabled),
+            tasks: RwLock::new(BTreeMap::new()),
+            quotas: RwLock::new(BTreeMap::new()),
+            wakeup_latency: RwLock::new(BTreeMap::new()),
+            wakeup_histogram: SpinLock::new(LatencyHistogram::default()),
+        }
+    }
+
+    /// Enable or disable per-task accounting
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Check whether per-task accounting is enabled
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// A task became runnable: close its sleep and start its wait
+    pub fn on_enqueue(&self, task: TaskId, now: u64) {
+        self.update(task, |state| {
+            if state.sleep_start != 0 {
+                state.stats.sleep_sum += now.saturating_sub(state.sleep_start);
+                state.sleep_start = 0;
+            }
+            if state.block_start != 0 {
+                state.stats.block_sum += now.saturating_sub(state.block_start);
+                state.block_start = 0;
+            }
+            if state.exec_start == 0 && state.wait_start == 0 {
+                state.wait_start = now;
+            }
+        });
+    }
+
+    /// A task starts running: close its wait
+    pub fn on_switch_in(&self, task: TaskId, now: u64) {
+        self.update(task, |state| {
+            if state.wait_start != 0 {
+                state.stats.wait_sum += now.saturating_sub(state.wait_start);
+                state.stats.wait_count += 1;
+                state.wait_start = 0;
+            }
+            state.exec_start = now;
+        });
+    }
+
+    /// A task stops running; `state` tells whether it waits, sleeps or blocks
+    pub fn on_switch_out(&self, task: TaskId, task_state: TaskState, now: u64) {
+        self.update(task, |state| {
+            if state.exec_start != 0 {
+                state.stats.sum_exec_runtime += now.saturating_sub(state.exec_start);
+                state.exec_start = 0;
+            }
+            match task_state {
+                TaskState::Running | TaskState::Runnable => state.wait_start = now,
+                TaskState::InterruptibleSleep | TaskState::UninterruptibleSleep => state.sleep_start = now,
+                _ => {}
+            }
+        });
+    }
+
+    /// A woken task runs for the first time since its wakeup, `latency_ns`
+    /// after it
+    pub fn on_wakeup_run(&self, task: TaskId, latency_ns: u64) {
+        self.wakeup_histogram.lock().record(latency_ns);
+        let mut averages = self.wakeup_latency.write();
+        let avg = averages.entry(task).or_insert(latency_ns);
+        *avg = *avg - (*avg >> WAKEUP_LATENCY_SHIFT) + (latency_ns >> WAKEUP_LATENCY_SHIFT);
+    }
+
+    /// Moving average of a task's wakeup latency (nanoseconds, 0 if the
+    /// task never ran after a wakeup)
+    pub fn task_wakeup_latency(&self, task: TaskId) -> u64 {
+        self.wakeup_latency.read().get(&task).copied().unwrap_or(0)
+    }
+
+    /// Wakeup latencies of all tasks
+    pub fn wakeup_latency_histogram(&self) -> LatencyHistogram {
+        *self.wakeup_histogram.lock()
+    }
+
+    /// A task moved to another CPU
+    pub fn on_migrate(&self, task: TaskId) {
+        self.update(task, |state| state.stats.nr_migrations += 1);
+    }
+
+    /// Statistics of a task (all zero if never accounted)
+    pub fn task_schedstats(&self, task: TaskId) -> TaskSchedStats {
+        self.tasks.read().get(&task).map(|state| state.stats).unwrap_or_default()
+    }
+
+    /// Cap a task at `percent` of one CPU within each `window_ns`
+    ///
+    /// The first window starts at `now`; a task that is `running` is
+    /// charged from `now` on even if schedstats are disabled.
+    pub fn set_task_quota(&self, task: TaskId, percent: u8, window_ns: u64, running: bool, now: u64) {
+        let window_runtime = {
+            let mut tasks = self.tasks.write();
+            let state = tasks.entry(task).or_default();
+            if running && state.exec_start == 0 {
+                state.exec_start = now;
+            }
+            state.runtime(now)
+        };
+        let mut quotas = self.quotas.write();
+        let quota = quotas.entry(task).or_default();
+        quota.budget = window_ns * percent as u64 / 100;
+        quota.window = window_ns;
+        quota.window_start = now;
+        quota.window_runtime = window_runtime;
+    }
+
+    /// Remove a task's quota; returns whether it was throttled
+    pub fn clear_task_quota(&self, task: TaskId) -> bool {
+        self.quotas.write().remove(&task).map_or(false, |q| q.throttled_at != 0)
+    }
+
+    /// Check a task's runtime against its quota
+    ///
+    /// Returns true if the task used up its share of the current window
+    /// and is newly throttled.
+    pub fn quota_tick(&self, task: TaskId, now: u64) -> bool {
+        let runtime = match self.tasks.read().get(&task) {
+            Some(state) => state.runtime(now),
+            None => return false,
+        };
+        let mut quotas = self.quotas.write();
+        let quota = match quotas.get_mut(&task) {
+            Some(quota) if quota.throttled_at == 0 => quota,
+            _ => return false,
+        };
+        if runtime.saturating_sub(quota.window_runtime) < quota.budget {
+            return false;
+        }
+        quota.throttled_at = now;
+        true
+    }
+
+    /// Start new windows for every quota whose window ended
+    ///
+    /// Returns the tasks that were throttled and may run again.
+    pub fn expire_quota_windows(&self, now: u64) -> Vec<TaskId> {
+        let tasks = self.tasks.read();
+        let mut restored = Vec::new();
+        for (&task, quota) in self.quotas.write().iter_mut() {
+            if now.saturating_sub(quota.window_start) < quota.window {
+                continue;
+            }
+            let windows = (now - quota.window_start) / quota.window;
+            quota.window_start += windows * quota.window;
+            quota.window_runtime = tasks.get(&task).map_or(0, |state| state.runtime(now));
+            if quota.throttled_at != 0 {
+                quota.throttled_sum += now.saturating_sub(quota.throttled_at);
+                quota.throttled_at = 0;
+                restored.push(task);
+            }
+        }
+        restored
+    }
+
+    /// Check whether a task is throttled by its quota
+    pub fn quota_throttled(&self, task: TaskId) -> bool {
+        self.quotas.read().get(&task).map_or(false, |q| q.throttled_at != 0)
+    }
+
+    /// Total time a task spent throttled by its quota
+    pub fn quota_throttled_time(&self, task: TaskId, now: u64) -> u64 {
+        self.quotas.read().get(&task).map_or(0, |q| {
+            let current = if q.throttled_at != 0 { now.saturating_sub(q.throttled_at) } else { 0 };
+            q.throttled_sum + current
+        })
+    }
+
+    /// Forget an exiting task
+    pub fn remove_task(&self, task: TaskId) {
+        self.tasks.write().remove(&task);
+        self.quotas.write().remove(&task);
+        self.wakeup_latency.write().remove(&task);
+    }
+
+    /// Log the statistics of every accounted task
+    pub fn print_stats_info(&self) {
+        kernel_info!("Schedstats enabled: {}", self.is_enabled());
+        let histogram = self.wakeup_latency_histogram();
+        kernel_info!("Wakeup latency: {} wakeups, p50 <{}ns, p99 <{}ns",
+                    histogram.count(), histogram.percentile(50), histogram.percentile(99));
+        for (task, state) in self.tasks.read().iter() {
+            let stats = &state.stats;
+            kernel_info!("Task {}: exec={} wait={}/{} sleep={} block={} migrations={}",
+                        task.as_u64(), stats.sum_exec_runtime, stats.wait_sum, stats.wait_count,
+                        stats.sleep_sum, stats.block_sum, stats.nr_migrations);
+        }
+    }
+
+    /// Apply an accounting update if schedstats are enabled or the task
+    /// has a quota
+    fn update(&self, task: TaskId, f: impl FnOnce(&mut TaskStatsState)) {
+        if !self.is_enabled() && !self.quotas.read().contains_key(&task) {
+            return;
+        }
+        f(self.tasks.write().entry(task).or_default());
+    }
+}
+
+impl Default for StatsScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wait_run_and_sleep_accounting() {
+        let stats = StatsScheduler::with_enabled(true);
+        let task = TaskId::new(1);
+
+        stats.on_enqueue(task, 100);
+        stats.on_switch_in(task, 150);
+        stats.on_switch_out(task, TaskState::InterruptibleSleep, 400);
+        stats.on_enqueue(task, 1_000);
+
+        let s = stats.task_schedstats(task);
+        assert_eq!(s.wait_sum, 50);
+        assert_eq!(s.wait_count, 1);
+        assert_eq!(s.sum_exec_runtime, 250);
+        assert_eq!(s.sleep_sum, 600);
+    }
+
+    #[test]
+    fn test_disabled_accounts_nothing() {
+        let stats = StatsScheduler::new();
+        let task = TaskId::new(1);
+
+        stats.on_enqueue(task, 100);
+        stats.on_switch_in(task, 150);
+        stats.on_migrate(task);
+
+        assert_eq!(stats.task_schedstats(task), TaskSchedStats::default());
+    }
+
+    #[test]
+    fn test_quota_throttles_until_window_ends() {
+        let stats = StatsScheduler::new();
+        let task = TaskId::new(1);
+        stats.set_task_quota(task, 50, 1_000, true, 100);
+
+        assert!(!stats.quota_tick(task, 500));
+        assert!(stats.quota_tick(task, 600));
+        assert!(stats.quota_throttled(task));
+        stats.on_switch_out(task, TaskState::Runnable, 600);
+
+        assert!(stats.expire_quota_windows(1_000).is_empty());
+        assert_eq!(stats.expire_quota_windows(1_100), [task]);
+        assert!(!stats.quota_throttled(task));
+        assert_eq!(stats.quota_throttled_time(task, 2_000), 500);
+
+        stats.on_switch_in(task, 1_200);
+        assert!(!stats.quota_tick(task, 1_600));
+        assert!(stats.quota_tick(task, 1_700));
+    }
+
+    #[test]
+    fn test_wakeup_latency_average_and_histogram() {
+        let stats = StatsScheduler::new();
+        let task = TaskId::new(1);
+        assert_eq!(stats.task_wakeup_latency(task), 0);
+
+        stats.on_wakeup_run(task, 80_000);
+        assert_eq!(stats.task_wakeup_latency(task), 80_000);
+        stats.on_wakeup_run(task, 160_000);
+        assert_eq!(stats.task_wakeup_latency(task), 90_000);
+        stats.on_wakeup_run(TaskId::new(2), 500);
+
+        let histogram = stats.wakeup_latency_histogram();
+        assert_eq!(histogram.count(), 3);
+        // 500ns < 1us; 80us in [64us, 128us); 160us in [128us, 256us)
+        assert_eq!(histogram.buckets()[0], 1);
+        assert_eq!(histogram.buckets()[7], 1);
+        assert_eq!(histogram.buckets()[8], 1);
+        assert_eq!(histogram.percentile(30), 1_000);
+        assert_eq!(histogram.percentile(50), 128_000);
+        assert_eq!(histogram.percentile(99), 256_000);
+    }
+}
@@ -0,0 +1,315 @@
+//! # Stop Task Scheduler
+//!
+//! This module implements the stop scheduling class and the CPU stopper
+//! work queues built on it. Every CPU has one stopper task that outranks
+//! all other classes; work queued for a CPU runs on its stopper, so
+//! nothing else can run on that CPU until the work is done. This is the
+//! primitive for CPU-pinned critical operations such as moving a running
+//! task between CPUs.
+//!
+//! ## Features
+//! - Per-CPU stop work queues executed by the stopper task
+//! - Stopper tasks preempt every other scheduling class
+//! - `stop_two_cpus` rendezvous with deadlock-free queueing order
+//!
+//! ## Usage
+//! ```rust
+//! use crate::kernel::scheduler::stop_task::StopTaskScheduler;
+//!
+//! stop_task.queue_stop_work(cpu, Box::new(|| {
+//!     kernel_info!("Running with the CPU stopped");
+//!     Ok(())
+//! }))?;
+//!
+//! stop_task.stop_two_cpus(src, dst, || move_task(task, src, dst))?;
+//! ```
+
+use crate::kernel::scheduler::completion::{Completion, CompletionScheduler};
+use crate::kernel::task::{Task, TaskId};
+use crate::kernel::cpu::{CpuId, CpuMask};
+use crate::kernel::error::{KernelResult, SchedulerError};
+use crate::kernel::sync::SpinLock;
+use crate::kernel::log::{kernel_info, kernel_warn};
+use crate::kernel::memory::percpu::PerCpu;
+use crate::arch::smp::send_reschedule_ipi;
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+
+/// Work executed by a CPU's stopper task
+pub type StopWork = Box<dyn FnOnce() -> KernelResult<()> + Send>;
+
+/// A queued stop work and the completion signalled when it has run
+struct StopWorkItem {
+    work: StopWork,
+    done: Option<Arc<Completion>>,
+}
+
+/// Stopper state of one CPU
+#[derive(Default)]
+struct CpuStopper {
+    /// Stopper task of the CPU, once started
+    task: Option<Arc<Task>>,
+    /// Pending work in queueing order
+    works: VecDeque<StopWorkItem>,
+    /// Running tasks to push to another CPU, as (task, target CPU)
+    pushes: VecDeque<(TaskId, CpuId)>,
+    /// Whether the stopper is executing work
+    running: bool,
+}
+
+/// Rendezvous state shared by the CPUs of a multi-CPU stop
+struct MultiStop {
+    /// The work, run by the first CPU once all CPUs arrived
+    work: SpinLock<Option<StopWork>>,
+    /// Result of the work
+    result: SpinLock<Option<KernelResult<()>>>,
+    /// CPUs taking part
+    nr_cpus: u32,
+    /// CPUs that reached the rendezvous
+    arrived: AtomicU32,
+    /// Set once the work has run
+    finished: AtomicBool,
+}
+
+impl MultiStop {
+    /// Stopper body of one participating CPU
+    ///
+    /// Every CPU spins until all have arrived; the leader then runs the
+    /// work while the others keep spinning, so all CPUs stay stopped for
+    /// the duration of the work.
+    fn run(&self, leader: bool) -> KernelResult<()> {
+        self.arrived.fetch_add(1, Ordering::AcqRel);
+        while self.arrived.load(Ordering::Acquire) < self.nr_cpus {
+            core::hint::spin_loop();
+        }
+        if leader {
+            let work = self.work.lock().take();
+            let result = work.map_or(Ok(()), |work| work());
+            *self.result.lock() = Some(result);
+            self.finished.store(true, Ordering::Release);
+        } else {
+            while !self.finished.load(Ordering::Acquire) {
+                core::hint::spin_loop();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Stop task scheduler component
+pub struct StopTaskScheduler {
+    stoppers: PerCpu<SpinLock<CpuStopper>>,
+    completion: CompletionScheduler,
+    works_run: AtomicU64,
+}
+
+impl StopTaskScheduler {
+    /// Create a stop task scheduler with no stopper tasks yet
+    pub fn new() -> Self {
+        Self {
+            stoppers: PerCpu::new(SpinLock::new(CpuStopper::default())),
+            completion: CompletionScheduler::new(),
+            works_run: AtomicU64::new(0),
+        }
+    }
+
+    /// Register the stopper task of a CPU
+    ///
+    /// The task's body must call `run_stop_works` for its CPU and carry
+    /// out the pushes from `take_pushes` whenever it is picked, then block.
+    pub fn register_stopper(&self, cpu: CpuId, task: Arc<Task>) {
+        self.stoppers.get(cpu).lock().task = Some(task);
+    }
+
+    /// Check whether a CPU's stopper task has been started
+    pub fn has_stopper(&self, cpu: CpuId) -> bool {
+        self.stoppers.get(cpu).lock().task.is_some()
+    }
+
+    /// Check whether a task is a CPU's stopper task
+    pub fn is_stopper(&self, cpu: CpuId, task: TaskId) -> bool {
+        self.stoppers.get(cpu).lock().task.as_ref().map_or(false, |stopper| stopper.id() == task)
+    }
+
+    /// Queue work to run on a CPU's stopper task
+    ///
+    /// Returns without waiting for the work to run.
+    ///
+    /// # Returns
+    /// - `Ok(())` if the work was queued
+    /// - `Err(SchedulerError::InvalidParameter)` if the CPU is offline
+    /// - `Err(SchedulerError::NotRunning)` if the CPU has no stopper task
+    pub fn queue_stop_work(&self, cpu: CpuId, work: StopWork) -> KernelResult<()> {
+        self.queue(cpu, |stopper| stopper.works.push_back(StopWorkItem { work, done: None }))
+    }
+
+    /// Have a CPU's stopper push its running task to `dst`
+    ///
+    /// The stopper preempts the task, so it can then be moved like a
+    /// queued one. Only the task and CPUs are queued; the stopper hands
+    /// them to the scheduler through `take_pushes`. Fails like
+    /// `queue_stop_work`.
+    pub fn queue_push(&self, cpu: CpuId, task: TaskId, dst: CpuId) -> KernelResult<()> {
+        self.queue(cpu, |stopper| stopper.pushes.push_back((task, dst)))
+    }
+
+    /// Take the pushes queued for a CPU; called by that CPU's stopper task
+    pub fn take_pushes(&self, cpu: CpuId) -> Vec<(TaskId, CpuId)> {
+        self.stoppers.get(cpu).lock().pushes.drain(..).collect()
+    }
+
+    /// Run work with two CPUs stopped
+    ///
+    /// Both stoppers rendezvous before the work runs on the lower CPU, and
+    /// neither CPU runs anything else until it is done. The work is queued
+    /// on both CPUs under both stopper locks, taken lower `CpuId` first, so
+    /// concurrent callers queue in the same order on every CPU and cannot
+    /// deadlock waiting on each other's rendezvous.
+    ///
+    /// Must be called from a task that may sleep; waits until the work
+    /// has run and returns its result.
+    pub fn stop_two_cpus<F>(&self, cpu1: CpuId, cpu2: CpuId, work: F) -> KernelResult<()>
+    where
+        F: FnOnce() -> KernelResult<()> + Send,
+    {
+        let online = CpuMask::online();
+        if !online.contains(cpu1) || !online.contains(cpu2) {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        Task::current().ok_or(SchedulerError::NotRunning)?;
+
+        let (first, second) = if cpu1 <= cpu2 { (cpu1, cpu2) } else { (cpu2, cpu1) };
+        let nr_cpus = if first == second { 1 } else { 2 };
+
+        let work: Box<dyn FnOnce() -> KernelResult<()> + Send + '_> = Box::new(work);
+        // SAFETY: this function does not return until every stopper has
+        // finished with the work (waited for below), so anything it
+        // borrows outlives its use.
+        let work: StopWork = unsafe { core::mem::transmute(work) };
+        let state = Arc::new(MultiStop {
+            work: SpinLock::new(Some(work)),
+            result: SpinLock::new(None),
+            nr_cpus,
+            arrived: AtomicU32::new(0),
+            finished: AtomicBool::new(false),
+        });
+        let done = Arc::new(Completion::new());
+
+        let item = |leader: bool| {
+            let state = state.clone();
+            StopWorkItem {
+                work: Box::new(move || state.run(leader)),
+                done: Some(done.clone()),
+            }
+        };
+        let tasks = {
+            let mut a = self.stoppers.get(first).lock();
+            if nr_cpus == 1 {
+                let task = a.task.clone().ok_or(SchedulerError::NotRunning)?;
+                a.works.push_back(item(true));
+                [Some(task), None]
+            } else {
+                let mut b = self.stoppers.get(second).lock();
+                let (task_a, task_b) = match (a.task.clone(), b.task.clone()) {
+                    (Some(task_a), Some(task_b)) => (task_a, task_b),
+                    _ => return Err(SchedulerError::NotRunning.into()),
+                };
+                a.works.push_back(item(true));
+                b.works.push_back(item(false));
+                [Some(task_a), Some(task_b)]
+            }
+        };
+        for (task, cpu) in tasks.iter().zip([first, second]) {
+            if let Some(task) = task {
+                Self::kick(task, cpu);
+            }
+        }
+
+        for _ in 0..nr_cpus {
+            // Cannot fail: the caller was checked to be a task above
+            while self.completion.wait_for_completion(&done).is_err() {
+                core::hint::spin_loop();
+            }
+        }
+        let result = state.result.lock().take();
+        result.unwrap_or(Ok(()))
+    }
+
+    /// Peek at the stopper task of a CPU if it has work to do
+    pub fn pick_next_task(&self, cpu: CpuId) -> KernelResult<Option<Arc<Task>>> {
+        let stopper = self.stoppers.get(cpu).lock();
+        if stopper.works.is_empty() && stopper.pushes.is_empty() && !stopper.running {
+            return Ok(None);
+        }
+        Ok(stopper.task.clone())
+    }
+
+    /// Run all queued work of a CPU; called by that CPU's stopper task
+    ///
+    /// Work queued while this runs is executed too, so the stopper only
+    /// yields once the queue is empty. Returns the number of works run.
+    pub fn run_stop_works(&self, cpu: CpuId) -> usize {
+        let mut count = 0;
+        loop {
+            let item = {
+                let mut stopper = self.stoppers.get(cpu).lock();
+                let item = stopper.works.pop_front();
+                stopper.running = item.is_some();
+                match item {
+                    Some(item) => item,
+                    None => break,
+                }
+            };
+            if let Err(e) = (item.work)() {
+                kernel_warn!("Stop work on CPU {} failed: {:?}", cpu.as_u32(), e);
+            }
+            if let Some(done) = item.done {
+                self.completion.complete(&done);
+            }
+            count += 1;
+        }
+        self.works_run.fetch_add(count as u64, Ordering::Relaxed);
+        count
+    }
+
+    /// Log stop task state
+    pub fn print_stop_task_info(&self) {
+        kernel_info!("Stop works run: {}", self.works_run.load(Ordering::Relaxed));
+        for cpu in CpuMask::online().iter() {
+            let stopper = self.stoppers.get(cpu).lock();
+            kernel_info!("CPU {} stopper: started={} pending={} pushes={}",
+                        cpu.as_u32(), stopper.task.is_some(), stopper.works.len(), stopper.pushes.len());
+        }
+    }
+
+    /// Queue work with `push` and kick the CPU's stopper
+    fn queue(&self, cpu: CpuId, push: impl FnOnce(&mut CpuStopper)) -> KernelResult<()> {
+        if !CpuMask::online().contains(cpu) {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        let task = {
+            let mut stopper = self.stoppers.get(cpu).lock();
+            let task = stopper.task.clone().ok_or(SchedulerError::NotRunning)?;
+            push(&mut stopper);
+            task
+        };
+        Self::kick(&task, cpu);
+        Ok(())
+    }
+
+    /// Make a stopper runnable and have its CPU reschedule
+    fn kick(task: &Task, cpu: CpuId) {
+        task.wake_up();
+        send_reschedule_ipi(cpu);
+    }
+}
+
+impl Default for StopTaskScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
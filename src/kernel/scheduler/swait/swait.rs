@@ -0,0 +1,260 @@
+//! # Simple Wait Queues
+//!
+//! This module implements simple wait queues: a FIFO of sleeping tasks
+//! without per-entry wake functions or exclusive flags. Every waiter is
+//! woken by making it runnable, which keeps a wakeup short and bounded,
+//! so simple wait queues can be woken from contexts where a regular wait
+//! queue's arbitrary wake functions are not acceptable.
+//!
+//! A wakeup either wakes the oldest waiter or all of them. Waits are
+//! uninterruptible, interruptible by signals, or bounded by a timeout,
+//! with the same lost-wakeup-free loop as `wait_event`.
+//!
+//! ## Features
+//! - FIFO waiters, woken one at a time or all at once
+//! - `swait_event`: sleep until a condition holds
+//! - Interruptible waits ending with `SchedulerError::Interrupted` on a
+//!   pending signal
+//! - Bounded waits
+//!
+//! ## Usage
+//! ```rust
+//! use crate::kernel::scheduler::swait::{SwaitQueue, SwaitScheduler};
+//!
+//! static QUEUE: SwaitQueue = SwaitQueue::new();
+//!
+//! // Waiting side
+//! swait.swait_event_interruptible(&QUEUE, || READY.load(Ordering::Acquire))?;
+//!
+//! // Waking side
+//! READY.store(true, Ordering::Release);
+//! swait.swake_up_one(&QUEUE);
+//! ```
+
+use crate::kernel::scheduler::wait::has_pending_signal;
+use crate::kernel::task::{Task, TaskId, TaskState};
+use crate::kernel::time::{Timestamp, Duration};
+use crate::kernel::error::{KernelResult, SchedulerError};
+use crate::kernel::sync::SpinLock;
+use crate::kernel::log::{kernel_info, kernel_debug};
+
+use alloc::collections::VecDeque;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// A simple wait queue
+pub struct SwaitQueue {
+    /// Waiting tasks, in arrival order
+    waiters: SpinLock<VecDeque<TaskId>>,
+}
+
+impl SwaitQueue {
+    /// Create an empty simple wait queue
+    pub const fn new() -> Self {
+        Self {
+            waiters: SpinLock::new(VecDeque::new()),
+        }
+    }
+
+    /// Queue a waiter at the tail unless it is already queued
+    pub fn add(&self, task: TaskId) {
+        let mut waiters = self.waiters.lock();
+        if !waiters.contains(&task) {
+            waiters.push_back(task);
+        }
+    }
+
+    /// Remove a waiter that stopped waiting
+    pub fn remove(&self, task: TaskId) {
+        self.waiters.lock().retain(|&t| t != task);
+    }
+
+    /// Check whether a task is queued
+    pub fn contains(&self, task: TaskId) -> bool {
+        self.waiters.lock().contains(&task)
+    }
+
+    /// Number of queued waiters
+    pub fn len(&self) -> usize {
+        self.waiters.lock().len()
+    }
+
+    /// Check whether nobody waits
+    pub fn is_empty(&self) -> bool {
+        self.waiters.lock().is_empty()
+    }
+}
+
+impl Default for SwaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Simple wait queue scheduler component
+pub struct SwaitScheduler {
+    wakeups: AtomicU64,
+    interrupted: AtomicU64,
+}
+
+impl SwaitScheduler {
+    /// Create a simple wait queue scheduler
+    pub fn new() -> Self {
+        Self {
+            wakeups: AtomicU64::new(0),
+            interrupted: AtomicU64::new(0),
+        }
+    }
+
+    /// Wake the oldest waiter; returns whether one was woken
+    ///
+    /// Waiters that no longer exist are dropped.
+    pub fn swake_up_one(&self, q: &SwaitQueue) -> bool {
+        loop {
+            let waiter = q.waiters.lock().pop_front();
+            match waiter {
+                Some(id) => {
+                    if let Some(task) = Task::get_by_id(id) {
+                        task.wake_up();
+                        self.wakeups.fetch_add(1, Ordering::Relaxed);
+                        return true;
+                    }
+                }
+                None => return false,
+            }
+        }
+    }
+
+    /// Wake every waiter; returns the number woken
+    pub fn swake_up_all(&self, q: &SwaitQueue) -> usize {
+        let waiters: VecDeque<TaskId> = core::mem::take(&mut *q.waiters.lock());
+        let mut woken = 0;
+        for task in waiters.into_iter().filter_map(Task::get_by_id) {
+            task.wake_up();
+            woken += 1;
+        }
+        self.wakeups.fetch_add(woken as u64, Ordering::Relaxed);
+        kernel_debug!("Simple wait queue wakeup: {} woken", woken);
+        woken
+    }
+
+    /// Sleep on a simple wait queue until `cond()` returns true
+    ///
+    /// The sleep is uninterruptible.
+    pub fn swait_event<F: Fn() -> bool>(&self, q: &SwaitQueue, cond: F) -> KernelResult<()> {
+        self.swait_common(q, &cond, None, false).map(|_| ())
+    }
+
+    /// Sleep on a simple wait queue until `cond()` returns true or a
+    /// signal arrives
+    ///
+    /// # Returns
+    /// - `Ok(())` if the condition became true
+    /// - `Err(SchedulerError::Interrupted)` if the waiting task got a signal
+    pub fn swait_event_interruptible<F: Fn() -> bool>(&self, q: &SwaitQueue, cond: F) -> KernelResult<()> {
+        self.swait_common(q, &cond, None, true).map(|_| ())
+    }
+
+    /// Sleep on a simple wait queue until `cond()` returns true or the
+    /// timeout expires
+    ///
+    /// # Returns
+    /// - `Ok(true)` if the condition became true
+    /// - `Ok(false)` if the timeout expired first
+    pub fn swait_event_timeout<F: Fn() -> bool>(&self, q: &SwaitQueue, cond: F,
+                                                timeout: Duration) -> KernelResult<bool> {
+        let deadline = Timestamp::now().as_nanos().saturating_add(timeout.as_nanos());
+        self.swait_common(q, &cond, Some(deadline), false)
+    }
+
+    /// Log simple wait queue statistics
+    pub fn print_swait_info(&self) {
+        kernel_info!("Simple wait queue wakeups: {}, interrupted waits: {}",
+                    self.wakeups.load(Ordering::Relaxed), self.interrupted.load(Ordering::Relaxed));
+    }
+
+    /// Common wait loop
+    ///
+    /// As in `wait_event`, the task marks itself sleeping and queues itself
+    /// before re-checking the condition, so a wakeup cannot be lost. After
+    /// every wakeup an interruptible waiter checks for a pending signal
+    /// before sleeping again.
+    fn swait_common(&self, q: &SwaitQueue, cond: &dyn Fn() -> bool,
+                    deadline: Option<u64>, interruptible: bool) -> KernelResult<bool> {
+        if cond() {
+            return Ok(true);
+        }
+        if Self::expired(deadline) {
+            return Ok(false);
+        }
+        let current = Task::current().ok_or(SchedulerError::NotRunning)?;
+        let id = current.id();
+
+        let sleep_state = if interruptible {
+            TaskState::InterruptibleSleep
+        } else {
+            TaskState::UninterruptibleSleep
+        };
+        let satisfied = loop {
+            current.set_state(sleep_state);
+            q.add(id);
+            if cond() {
+                break true;
+            }
+            if Self::expired(deadline) {
+                break false;
+            }
+            if interruptible && has_pending_signal(&current) {
+                current.set_state(TaskState::Running);
+                q.remove(id);
+                self.interrupted.fetch_add(1, Ordering::Relaxed);
+                return Err(SchedulerError::Interrupted.into());
+            }
+            Task::block_current(deadline);
+        };
+
+        current.set_state(TaskState::Running);
+        q.remove(id);
+        Ok(satisfied)
+    }
+
+    /// Check whether a wait deadline has passed
+    fn expired(deadline: Option<u64>) -> bool {
+        deadline.map_or(false, |d| Timestamp::now().as_nanos() >= d)
+    }
+}
+
+impl Default for SwaitScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_swait_event_returns_without_sleeping() {
+        let swait = SwaitScheduler::new();
+        let q = SwaitQueue::new();
+
+        assert!(swait.swait_event(&q, || true).is_ok());
+        assert!(swait.swait_event_interruptible(&q, || true).is_ok());
+        assert!(!swait.swait_event_timeout(&q, || false, Duration::from_nanos(0)).unwrap());
+        assert!(q.is_empty());
+    }
+
+    #[test]
+    fn test_waiters_queue_once_in_arrival_order() {
+        let q = SwaitQueue::new();
+        q.add(TaskId::new(2));
+        q.add(TaskId::new(1));
+        q.add(TaskId::new(2));
+        assert_eq!(q.len(), 2);
+        assert_eq!(q.waiters.lock().front(), Some(&TaskId::new(2)));
+
+        q.remove(TaskId::new(2));
+        assert!(!q.contains(TaskId::new(2)));
+        assert!(q.contains(TaskId::new(1)));
+    }
+}
@@ -0,0 +1,617 @@
+//! # CPU Topology
+//!
+//! This module records how logical CPUs relate to each other physically:
+//! which CPUs are SMT siblings of the same core, which cores share a
+//! package (and its last-level cache), and which NUMA node each CPU
+//! belongs to. The scheduling domain hierarchy and all topology-aware
+//! placement decisions are derived from this information.
+//!
+//! ## Features
+//! - Per-CPU core, package and NUMA node identifiers
+//! - Sibling, package and node CPU masks
+//! - Per-CPU compute capacity for asymmetric (big.LITTLE) systems
+//! - NUMA distance matrix (SLIT-style, 10 = local)
+//! - NUMA node masks
+//! - Registration from architecture code during boot or hotplug
+//! - Wakeup CPU selection strategies (wake-affine, spread, packing, thermal spread)
+//! - Exec placement on the least loaded node and package
+//! - Cache affinity group placement: cooperating tasks share a last-level
+//!   cache, antagonists stay off each other's SMT siblings
+//!
+//! ## Usage
+//! ```rust
+//! use crate::kernel::scheduler::topology::{TopologyScheduler, CpuTopology, NodeId};
+//!
+//! let topology = TopologyScheduler::new();
+//! topology.set_cpu_topology(CpuId::new(0), CpuTopology {
+//!     core_id: 0,
+//!     package_id: 0,
+//!     node: NodeId(0),
+//! });
+//!
+//! let siblings = topology.smt_siblings(CpuId::new(0));
+//! ```
+
+use crate::kernel::cpu::{CpuId, CpuMask};
+use crate::kernel::error::{KernelResult, SchedulerError};
+use crate::kernel::sync::RwLock;
+use crate::kernel::log::{kernel_info, kernel_debug};
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// NUMA node identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct NodeId(pub u32);
+
+impl NodeId {
+    /// Get the raw node number
+    pub fn as_u32(&self) -> u32 {
+        self.0
+    }
+}
+
+/// Set of NUMA nodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct NodeMask(u64);
+
+impl NodeMask {
+    /// Number of nodes a mask can hold
+    pub const MAX_NODES: u32 = 64;
+
+    /// Create a mask without nodes
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Add a node; nodes beyond `MAX_NODES` are ignored
+    pub fn set(&mut self, node: NodeId) {
+        if node.0 < Self::MAX_NODES {
+            self.0 |= 1 << node.0;
+        }
+    }
+
+    /// Remove a node
+    pub fn clear(&mut self, node: NodeId) {
+        if node.0 < Self::MAX_NODES {
+            self.0 &= !(1 << node.0);
+        }
+    }
+
+    /// Check whether a node is in the mask
+    pub fn contains(&self, node: NodeId) -> bool {
+        node.0 < Self::MAX_NODES && self.0 & (1 << node.0) != 0
+    }
+
+    /// Check whether the mask has no nodes
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Check whether every node of the mask is also in `other`
+    pub fn is_subset_of(&self, other: &NodeMask) -> bool {
+        self.0 & !other.0 == 0
+    }
+
+    /// Nodes of the mask in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = NodeId> + '_ {
+        (0..Self::MAX_NODES).filter(|&n| self.0 & (1 << n) != 0).map(NodeId)
+    }
+}
+
+/// Physical location of a logical CPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CpuTopology {
+    /// Core identifier, unique within the package
+    pub core_id: u32,
+    /// Physical package (socket) identifier
+    pub package_id: u32,
+    /// NUMA node the CPU belongs to
+    pub node: NodeId,
+}
+
+/// Capacity of the biggest CPU in the system
+pub const SCHED_CAPACITY_SCALE: u32 = 1024;
+
+/// Distance of a node to itself
+pub const NUMA_LOCAL_DISTANCE: u8 = 10;
+
+/// Default distance between two different nodes
+pub const NUMA_REMOTE_DISTANCE: u8 = 20;
+
+/// How the CPU of a waking task is chosen when nothing else decides
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WakeupStrategy {
+    /// Wake on the waker's CPU if it shares a cache with the task's
+    /// previous CPU and runs nothing but the waker
+    #[default]
+    WakeAffine,
+    /// Wake on an idle CPU, an idle core of the previous CPU's package first
+    SpreadFirst,
+    /// Wake on the busiest CPU the task still fits on, so that others can idle
+    Packing,
+    /// Wake on the coolest idle CPU, the one that idled longest among equals,
+    /// to spread heat across the package
+    SpreadThermal,
+}
+
+/// Load of a CPU as seen by wakeup placement
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CpuLoad {
+    /// Runnable tasks, including a running one
+    pub nr_running: usize,
+    /// Whether the waking task's utilization fits the CPU's capacity
+    pub fits: bool,
+    /// Accumulated idle time in microseconds (only needed by `SpreadThermal`)
+    pub idle_time: u64,
+    /// Temperature of the CPU's thermal zone in Celsius, if known (only
+    /// needed by `SpreadThermal`)
+    pub temperature: Option<u64>,
+}
+
+/// Topology scheduler component
+pub struct TopologyScheduler {
+    cpus: RwLock<BTreeMap<CpuId, CpuTopology>>,
+    capacities: RwLock<BTreeMap<CpuId, u32>>,
+    distances: RwLock<BTreeMap<(NodeId, NodeId), u8>>,
+}
+
+impl TopologyScheduler {
+    /// Create an empty topology; CPUs are added with `set_cpu_topology`
+    pub fn new() -> Self {
+        Self {
+            cpus: RwLock::new(BTreeMap::new()),
+            capacities: RwLock::new(BTreeMap::new()),
+            distances: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// Record the physical location of a CPU
+    pub fn set_cpu_topology(&self, cpu: CpuId, topo: CpuTopology) {
+        kernel_debug!("Topology: CPU {} -> package {} core {} node {}",
+                     cpu.as_u32(), topo.package_id, topo.core_id, topo.node.as_u32());
+        self.cpus.write().insert(cpu, topo);
+    }
+
+    /// Record every CPU of `cpus` whose topology is unknown as a core of
+    /// package 0 on node 0
+    ///
+    /// Used when the platform registered no topology, so that the CPUs
+    /// form a single flat scheduling domain.
+    pub fn add_flat_cpus(&self, cpus: &CpuMask) {
+        let mut known = self.cpus.write();
+        for cpu in cpus.iter() {
+            known.entry(cpu).or_insert(CpuTopology { core_id: cpu.as_u32(), package_id: 0, node: NodeId(0) });
+        }
+    }
+
+    /// Forget a CPU (e.g. when it is hot-removed)
+    pub fn remove_cpu(&self, cpu: CpuId) {
+        self.cpus.write().remove(&cpu);
+        self.capacities.write().remove(&cpu);
+    }
+
+    /// Set the compute capacity of a CPU, normalized to 1024 for the biggest core
+    pub fn set_cpu_capacity(&self, cpu: CpuId, capacity: u32) -> KernelResult<()> {
+        if capacity == 0 || capacity > SCHED_CAPACITY_SCALE {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        kernel_debug!("Topology: CPU {} capacity {}", cpu.as_u32(), capacity);
+        self.capacities.write().insert(cpu, capacity);
+        Ok(())
+    }
+
+    /// Compute capacity of a CPU (1024 unless set otherwise)
+    pub fn cpu_capacity(&self, cpu: CpuId) -> u32 {
+        self.capacities.read().get(&cpu).copied().unwrap_or(SCHED_CAPACITY_SCALE)
+    }
+
+    /// Check whether the CPUs of a mask have different capacities
+    pub fn is_asymmetric(&self, mask: &CpuMask) -> bool {
+        let mut capacities = mask.iter().map(|cpu| self.cpu_capacity(cpu));
+        match capacities.next() {
+            Some(first) => capacities.any(|cap| cap != first),
+            None => false,
+        }
+    }
+
+    /// Get the physical location of a CPU
+    pub fn cpu_topology(&self, cpu: CpuId) -> Option<CpuTopology> {
+        self.cpus.read().get(&cpu).copied()
+    }
+
+    /// All CPUs with a known topology
+    pub fn known_cpus(&self) -> CpuMask {
+        self.mask_where(|_| true)
+    }
+
+    /// CPUs sharing the same physical core as `cpu` (including itself)
+    pub fn smt_siblings(&self, cpu: CpuId) -> CpuMask {
+        match self.cpu_topology(cpu) {
+            Some(topo) => self.mask_where(|t| t.package_id == topo.package_id && t.core_id == topo.core_id),
+            None => Self::single(cpu),
+        }
+    }
+
+    /// CPUs sharing the same package (and last-level cache) as `cpu`
+    pub fn package_mask(&self, cpu: CpuId) -> CpuMask {
+        match self.cpu_topology(cpu) {
+            Some(topo) => self.mask_where(|t| t.package_id == topo.package_id),
+            None => Self::single(cpu),
+        }
+    }
+
+    /// Last-level cache domain of a CPU: the package it belongs to (`None`
+    /// if its topology is unknown)
+    pub fn llc_id(&self, cpu: CpuId) -> Option<u32> {
+        self.cpu_topology(cpu).map(|t| t.package_id)
+    }
+
+    /// CPUs on the same NUMA node as `cpu`
+    pub fn node_mask(&self, cpu: CpuId) -> CpuMask {
+        match self.cpu_topology(cpu) {
+            Some(topo) => self.mask_where(|t| t.node == topo.node),
+            None => Self::single(cpu),
+        }
+    }
+
+    /// NUMA node of a CPU (node 0 if unknown)
+    pub fn node_of_cpu(&self, cpu: CpuId) -> NodeId {
+        self.cpu_topology(cpu).map(|t| t.node).unwrap_or_default()
+    }
+
+    /// CPUs belonging to a NUMA node
+    pub fn cpus_on_node(&self, node: NodeId) -> CpuMask {
+        self.mask_where(|t| t.node == node)
+    }
+
+    /// Set the distance from one NUMA node to another
+    ///
+    /// Distances follow the ACPI SLIT convention: a node is at distance 10
+    /// from itself and any other node is strictly farther.
+    pub fn set_numa_distance(&self, from: NodeId, to: NodeId, distance: u8) -> KernelResult<()> {
+        let valid = if from == to {
+            distance == NUMA_LOCAL_DISTANCE
+        } else {
+            distance > NUMA_LOCAL_DISTANCE
+        };
+        if !valid {
+            return Err(SchedulerError::InvalidParameter.into());
+        }
+        kernel_debug!("Topology: node {} -> node {} distance {}", from.as_u32(), to.as_u32(), distance);
+        self.distances.write().insert((from, to), distance);
+        Ok(())
+    }
+
+    /// Distance from one NUMA node to another
+    pub fn numa_distance(&self, from: NodeId, to: NodeId) -> u8 {
+        if from == to {
+            return NUMA_LOCAL_DISTANCE;
+        }
+        self.distances.read().get(&(from, to)).copied().unwrap_or(NUMA_REMOTE_DISTANCE)
+    }
+
+    /// Distance between the nodes of two CPUs
+    pub fn cpu_distance(&self, from: CpuId, to: CpuId) -> u8 {
+        self.numa_distance(self.node_of_cpu(from), self.node_of_cpu(to))
+    }
+
+    /// Number of distinct NUMA nodes with at least one CPU
+    pub fn nr_nodes(&self) -> usize {
+        let cpus = self.cpus.read();
+        let mut nodes: Vec<NodeId> = cpus.values().map(|t| t.node).collect();
+        nodes.sort_unstable();
+        nodes.dedup();
+        nodes.len()
+    }
+
+    /// Choose the CPU a task wakes up on
+    ///
+    /// `allowed` are the CPUs the task may run on, `prev` the CPU it last
+    /// ran on and `waker` the CPU waking it. The previous CPU is kept when
+    /// the strategy finds nothing better.
+    pub fn select_wakeup_cpu(&self, strategy: WakeupStrategy, allowed: &CpuMask, prev: CpuId,
+                             waker: CpuId, load: impl Fn(CpuId) -> CpuLoad) -> CpuId {
+        let candidate = |cpu: CpuId| allowed.contains(cpu) && load(cpu).fits;
+        match strategy {
+            WakeupStrategy::WakeAffine => {
+                let affine = waker != prev && candidate(waker) && load(waker).nr_running <= 1
+                    && self.package_mask(prev).contains(waker);
+                if affine { waker } else { prev }
+            }
+            WakeupStrategy::SpreadFirst => {
+                if allowed.contains(prev) && load(prev).nr_running == 0 {
+                    return prev;
+                }
+                let idle = |cpu: &CpuId| candidate(*cpu) && load(*cpu).nr_running == 0;
+                let package = self.package_mask(prev);
+                let target = package.iter()
+                    .filter(idle)
+                    .find(|&cpu| self.smt_siblings(cpu).iter().all(|sibling| load(sibling).nr_running == 0))
+                    .or_else(|| package.iter().find(idle))
+                    .or_else(|| allowed.iter().find(idle));
+                target.unwrap_or(prev)
+            }
+            WakeupStrategy::Packing => {
+                // Ties go to the previous CPU, then to the lowest CPU
+                allowed.iter()
+                    .filter(|&cpu| load(cpu).fits)
+                    .max_by_key(|&cpu| (load(cpu).nr_running, cpu == prev, core::cmp::Reverse(cpu.as_u32())))
+                    .unwrap_or(prev)
+            }
+            WakeupStrategy::SpreadThermal => {
+                // CPUs without a temperature rank after those with one, so
+                // without thermal data the longest idle CPU wins; ties go
+                // to the previous CPU, then to the lowest CPU
+                allowed.iter()
+                    .map(|cpu| (cpu, load(cpu)))
+                    .filter(|(_, l)| l.fits && l.nr_running == 0)
+                    .min_by_key(|&(cpu, l)| (l.temperature.unwrap_or(u64::MAX), core::cmp::Reverse(l.idle_time), cpu != prev))
+                    .map_or(prev, |(cpu, _)| cpu)
+            }
+        }
+    }
+
+    /// Choose the CPU a task that just exec'd runs its new image on
+    ///
+    /// The task has no cache footprint, so cache affinity is ignored: the
+    /// node with the fewest runnable tasks per allowed CPU is chosen, then
+    /// the least loaded package in it, then its least loaded CPU, an idle
+    /// core first. The previous CPU is kept unless that CPU is busier.
+    pub fn select_exec_cpu(&self, allowed: &CpuMask, prev: CpuId, load: impl Fn(CpuId) -> CpuLoad) -> CpuId {
+        let mut candidates = CpuMask::empty();
+        for cpu in allowed.iter().filter(|&cpu| load(cpu).fits) {
+            candidates.set(cpu);
+        }
+        let node = self.least_loaded_group(&candidates, prev, &load, |cpu| self.node_mask(cpu));
+        let package = self.least_loaded_group(&node, prev, &load, |cpu| self.package_mask(cpu));
+        let idle_core = |cpu: CpuId| self.smt_siblings(cpu).iter().all(|sibling| load(sibling).nr_running == 0);
+        let best = match package.iter()
+            .min_by_key(|&cpu| (load(cpu).nr_running, !idle_core(cpu), cpu != prev, cpu.as_u32())) {
+            Some(cpu) => cpu,
+            None => return prev,
+        };
+        if candidates.contains(prev) && load(prev).nr_running <= load(best).nr_running {
+            prev
+        } else {
+            best
+        }
+    }
+
+    /// Choose a CPU for a task of a cache affinity group
+    ///
+    /// `members` are the CPUs of the group's other tasks. The task goes to
+    /// the last-level cache most of them share, ties going to the previous
+    /// CPU's, on an allowed CPU it fits whose SMT siblings run no task of
+    /// another group (`antagonist`) if possible, then the least loaded.
+    /// Returns `None` without members or without such a CPU.
+    pub fn select_cache_group_cpu(&self, allowed: &CpuMask, prev: CpuId, members: &[CpuId],
+                                  load: impl Fn(CpuId) -> CpuLoad,
+                                  antagonist: impl Fn(CpuId) -> bool) -> Option<CpuId> {
+        let mut llcs: BTreeMap<u32, usize> = BTreeMap::new();
+        for &cpu in members {
+            if let Some(llc) = self.llc_id(cpu) {
+                *llcs.entry(llc).or_default() += 1;
+            }
+        }
+        let prev_llc = self.llc_id(prev);
+        let (&llc, _) = llcs.iter()
+            .max_by_key(|&(&llc, &count)| (count, Some(llc) == prev_llc, core::cmp::Reverse(llc)))?;
+
+        let contended = |cpu: CpuId| self.smt_siblings(cpu).iter()
+            .any(|sibling| sibling != cpu && antagonist(sibling));
+        allowed.iter()
+            .filter(|&cpu| self.llc_id(cpu) == Some(llc) && load(cpu).fits)
+            .min_by_key(|&cpu| (contended(cpu), load(cpu).nr_running, cpu != prev, cpu.as_u32()))
+    }
+
+    /// Log the known topology
+    pub fn print_topology_info(&self) {
+        kernel_info!("=== CPU Topology ===");
+        for (cpu, topo) in self.cpus.read().iter() {
+            kernel_info!("CPU {}: package {} core {} node {} capacity {}",
+                        cpu.as_u32(), topo.package_id, topo.core_id, topo.node.as_u32(),
+                        self.cpu_capacity(*cpu));
+        }
+    }
+
+    /// Build a mask of the CPUs whose topology matches `pred`
+    fn mask_where(&self, pred: impl Fn(&CpuTopology) -> bool) -> CpuMask {
+        let mut mask = CpuMask::empty();
+        for (cpu, topo) in self.cpus.read().iter() {
+            if pred(topo) {
+                mask.set(*cpu);
+            }
+        }
+        mask
+    }
+
+    /// The group of `candidates` with the fewest runnable tasks per CPU,
+    /// where `group_of` gives the group of a CPU; ties go to the group of
+    /// `prev`, then to the group of the lowest CPU
+    fn least_loaded_group(&self, candidates: &CpuMask, prev: CpuId, load: &impl Fn(CpuId) -> CpuLoad,
+                          group_of: impl Fn(CpuId) -> CpuMask) -> CpuMask {
+        let mut seen = CpuMask::empty();
+        let mut best: Option<((u64, bool), CpuMask)> = None;
+        for cpu in candidates.iter() {
+            if seen.contains(cpu) {
+                continue;
+            }
+            let span = group_of(cpu);
+            let mut group = CpuMask::empty();
+            let (mut nr_cpus, mut nr_running) = (0, 0);
+            for member in candidates.iter().filter(|&member| span.contains(member)) {
+                group.set(member);
+                seen.set(member);
+                nr_cpus += 1;
+                nr_running += load(member).nr_running as u64;
+            }
+            let key = (nr_running * SCHED_CAPACITY_SCALE as u64 / nr_cpus, !group.contains(prev));
+            if best.as_ref().map_or(true, |(best_key, _)| key < *best_key) {
+                best = Some((key, group));
+            }
+        }
+        best.map_or_else(CpuMask::empty, |(_, group)| group)
+    }
+
+    /// Mask containing only `cpu`
+    fn single(cpu: CpuId) -> CpuMask {
+        let mut mask = CpuMask::empty();
+        mask.set(cpu);
+        mask
+    }
+}
+
+impl Default for TopologyScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two packages of two cores with two SMT threads each: CPUs 0-3 share
+    /// package 0 (cores 0-1 and 2-3), CPUs 4-7 package 1
+    fn two_package_topology() -> TopologyScheduler {
+        let topology = TopologyScheduler::new();
+        for cpu in 0..8 {
+            topology.set_cpu_topology(CpuId::new(cpu), CpuTopology {
+                core_id: cpu / 2,
+                package_id: cpu / 4,
+                node: NodeId(cpu / 4),
+            });
+        }
+        topology
+    }
+
+    /// Loads from runnable task counts by CPU, where every CPU fits the task
+    fn loads(nr_running: [usize; 8]) -> impl Fn(CpuId) -> CpuLoad {
+        move |cpu| CpuLoad { nr_running: nr_running[cpu.as_u32() as usize], fits: true, ..Default::default() }
+    }
+
+    #[test]
+    fn test_wake_affine_prefers_cache_sharing_waker() {
+        let topology = two_package_topology();
+        let all = topology.known_cpus();
+        let select = |waker, nr_running| topology.select_wakeup_cpu(
+            WakeupStrategy::WakeAffine, &all, CpuId::new(1), CpuId::new(waker), loads(nr_running));
+
+        // The waker's CPU shares the package and only runs the waker
+        assert_eq!(select(2, [1, 1, 1, 0, 0, 0, 0, 0]), CpuId::new(2));
+        // Overloaded waker
+        assert_eq!(select(2, [1, 1, 3, 0, 0, 0, 0, 0]), CpuId::new(1));
+        // Waker in the other package
+        assert_eq!(select(5, [1, 1, 0, 0, 0, 1, 0, 0]), CpuId::new(1));
+    }
+
+    #[test]
+    fn test_spread_first_prefers_idle_core_in_package() {
+        let topology = two_package_topology();
+        let all = topology.known_cpus();
+        let select = |nr_running| topology.select_wakeup_cpu(
+            WakeupStrategy::SpreadFirst, &all, CpuId::new(0), CpuId::new(0), loads(nr_running));
+
+        // Previous CPU idle
+        assert_eq!(select([0, 1, 0, 0, 0, 0, 0, 0]), CpuId::new(0));
+        // An idle core beats the idle SMT sibling of a busy thread
+        assert_eq!(select([1, 0, 0, 0, 0, 0, 0, 0]), CpuId::new(2));
+        // No idle core in the package: an idle thread in it
+        assert_eq!(select([1, 0, 1, 1, 0, 0, 0, 0]), CpuId::new(1));
+        // Package busy: the other package
+        assert_eq!(select([1, 1, 1, 1, 1, 0, 1, 1]), CpuId::new(5));
+        // Nothing idle
+        assert_eq!(select([1; 8]), CpuId::new(0));
+    }
+
+    #[test]
+    fn test_packing_fills_busiest_fitting_cpu() {
+        let topology = two_package_topology();
+        let all = topology.known_cpus();
+
+        let nr_running = [1, 0, 3, 0, 0, 2, 0, 0];
+        let select = |fits: fn(u32) -> bool| topology.select_wakeup_cpu(
+            WakeupStrategy::Packing, &all, CpuId::new(4), CpuId::new(4),
+            move |cpu| CpuLoad { nr_running: nr_running[cpu.as_u32() as usize], fits: fits(cpu.as_u32()), ..Default::default() });
+        assert_eq!(select(|_| true), CpuId::new(2));
+        // The busiest CPU has no room left
+        assert_eq!(select(|cpu| cpu != 2), CpuId::new(5));
+        // Nothing fits
+        assert_eq!(select(|_| false), CpuId::new(4));
+
+        // All idle: stay on the previous CPU
+        assert_eq!(topology.select_wakeup_cpu(WakeupStrategy::Packing, &all, CpuId::new(4),
+                                              CpuId::new(0), loads([0; 8])), CpuId::new(4));
+    }
+
+    #[test]
+    fn test_spread_thermal_prefers_coolest_then_longest_idle_cpu() {
+        let topology = two_package_topology();
+        let all = topology.known_cpus();
+        let nr_running = [1, 0, 0, 1, 0, 0, 1, 0];
+        let idle_time = [0, 500, 900, 0, 100, 300, 0, 200];
+        let select = |temperature: fn(u32) -> Option<u64>| topology.select_wakeup_cpu(
+            WakeupStrategy::SpreadThermal, &all, CpuId::new(1), CpuId::new(0),
+            move |cpu| {
+                let i = cpu.as_u32() as usize;
+                CpuLoad { nr_running: nr_running[i], fits: true, idle_time: idle_time[i],
+                          temperature: temperature(cpu.as_u32()) }
+            });
+
+        // Package 1 runs cooler: its longest idle CPU
+        assert_eq!(select(|cpu| Some(if cpu < 4 { 70 } else { 55 })), CpuId::new(5));
+        // The coolest CPU wins even if it idled less
+        assert_eq!(select(|cpu| Some(if cpu == 4 { 50 } else { 60 })), CpuId::new(4));
+        // No thermal data: the longest idle CPU
+        assert_eq!(select(|_| None), CpuId::new(2));
+        // A CPU with a known temperature beats those without one
+        assert_eq!(select(|cpu| if cpu == 7 { Some(80) } else { None }), CpuId::new(7));
+
+        // Nothing idle: stay on the previous CPU
+        assert_eq!(topology.select_wakeup_cpu(WakeupStrategy::SpreadThermal, &all, CpuId::new(1),
+                                              CpuId::new(0), loads([1; 8])), CpuId::new(1));
+    }
+
+    #[test]
+    fn test_exec_moves_task_from_busy_cpu_to_idle_package() {
+        let topology = two_package_topology();
+        let all = topology.known_cpus();
+        let select = |nr_running| topology.select_exec_cpu(&all, CpuId::new(0), loads(nr_running));
+
+        // Package 1 is less loaded; CPUs 4-5 form its idle core
+        assert_eq!(select([2, 1, 1, 1, 0, 0, 1, 0]), CpuId::new(4));
+        // Only the exec'd task runs: an idle package beats cache affinity
+        assert_eq!(select([1, 0, 0, 0, 0, 0, 0, 0]), CpuId::new(4));
+        // Nowhere less busy
+        assert_eq!(select([1; 8]), CpuId::new(0));
+
+        // Affinity limited to package 0: its idle core
+        let mut package0 = CpuMask::empty();
+        for cpu in 0..4 {
+            package0.set(CpuId::new(cpu));
+        }
+        assert_eq!(topology.select_exec_cpu(&package0, CpuId::new(0), loads([2, 1, 0, 0, 0, 0, 0, 0])),
+                   CpuId::new(2));
+    }
+
+    #[test]
+    fn test_cache_groups_share_llc_and_avoid_antagonist_siblings() {
+        let topology = two_package_topology();
+        let all = topology.known_cpus();
+        assert_eq!(topology.llc_id(CpuId::new(5)), Some(1));
+        let nr_running = [1, 0, 1, 0, 1, 1, 0, 1];
+        let select = |members: &[CpuId], antagonist: fn(u32) -> bool| topology.select_cache_group_cpu(
+            &all, CpuId::new(0), members, loads(nr_running), move |cpu| antagonist(cpu.as_u32()));
+
+        // Most of the group runs in package 1: its least loaded CPU
+        let members = [CpuId::new(4), CpuId::new(5), CpuId::new(1)];
+        assert_eq!(select(&members, |_| false), Some(CpuId::new(6)));
+        // CPU 7 runs another group: stay off its sibling 6
+        assert_eq!(select(&members, |cpu| cpu == 7), Some(CpuId::new(4)));
+        // A tie goes to the previous CPU's cache
+        assert_eq!(select(&[CpuId::new(1), CpuId::new(5)], |_| false), Some(CpuId::new(1)));
+        // No other member: no preference
+        assert_eq!(select(&[], |_| false), None);
+    }
+}
@@ -0,0 +1,493 @@
+//! # Wait Queues
+//!
+//! This module implements wait queues: lists of tasks sleeping until some
+//! condition changes. Waiters are either non-exclusive, and always woken,
+//! or exclusive (`WQ_FLAG_EXCLUSIVE`), in which case a wakeup only wakes
+//! as many of them as requested. Exclusive waiters avoid the thundering
+//! herd when a single resource becomes available.
+//!
+//! ## Features
+//! - Non-exclusive and exclusive waiters
+//! - Wake one, `nr`, or all exclusive waiters
+//! - Per-entry wake functions
+//! - `wait_event`: sleep until a condition holds, free of lost wakeups;
+//!   uninterruptible, or interruptible by signals with
+//!   `wait_event_interruptible`
+//! - Signal checks shared by every interruptible wait (wait queues, simple
+//!   wait queues, completions)
+//! - `WakeBatch`: wakeups grouped by target CPU, so that draining many
+//!   waiters takes each runqueue lock and sends each IPI once per CPU
+//!
+//! ## Usage
+//! ```rust
+//! use crate::kernel::scheduler::wait::{WaitQueue, WaitQueueEntry, WaitScheduler};
+//!
+//! static QUEUE: WaitQueue = WaitQueue::new();
+//!
+//! // Consumer
+//! QUEUE.add_wait_queue_exclusive(WaitQueueEntry::exclusive(current.id()));
+//!
+//! // Producer: one item, one consumer
+//! wait.wake_up_nr(&QUEUE, 1);
+//!
+//! // Sleep until a flag is set
+//! wait.wait_event(&QUEUE, || READY.load(Ordering::Acquire))?;
+//! ```
+
+use crate::kernel::task::{Task, TaskId, TaskState};
+use crate::kernel::cpu::CpuId;
+use crate::kernel::time::{Timestamp, Duration};
+use crate::kernel::error::{KernelResult, SchedulerError};
+use crate::kernel::sync::SpinLock;
+use crate::kernel::log::{kernel_info, kernel_debug};
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Waiter is woken only as part of a bounded number of exclusive wakeups
+pub const WQ_FLAG_EXCLUSIVE: u32 = 0x01;
+
+/// Wakes the task of a wait queue entry; returns whether it was woken
+pub type WakeFn = fn(TaskId) -> bool;
+
+/// Check whether a signal is pending for a task
+///
+/// Every interruptible wait loop checks this before sleeping and after
+/// each wakeup, and gives up with `SchedulerError::Interrupted` if it is.
+pub fn has_pending_signal(task: &Task) -> bool {
+    task.has_pending_signal()
+}
+
+/// Check whether a signal ends a sleep in `state`
+///
+/// Only interruptible sleepers are woken by signals; an uninterruptible
+/// sleeper keeps sleeping until its condition holds.
+pub fn signal_interrupts(state: TaskState) -> bool {
+    state == TaskState::InterruptibleSleep
+}
+
+/// Default wake function: make the task runnable
+pub fn default_wake_function(task: TaskId) -> bool {
+    match Task::get_by_id(task) {
+        Some(task) => {
+            task.wake_up();
+            true
+        }
+        None => false,
+    }
+}
+
+/// A task waiting on a wait queue
+#[derive(Debug, Clone, Copy)]
+pub struct WaitQueueEntry {
+    /// Waiting task
+    pub task: TaskId,
+    /// `WQ_FLAG_*` flags
+    pub flags: u32,
+    /// Function called to wake the task
+    pub func: WakeFn,
+}
+
+impl WaitQueueEntry {
+    /// Non-exclusive waiter
+    pub fn new(task: TaskId) -> Self {
+        Self {
+            task,
+            flags: 0,
+            func: default_wake_function,
+        }
+    }
+
+    /// Exclusive waiter
+    pub fn exclusive(task: TaskId) -> Self {
+        Self {
+            flags: WQ_FLAG_EXCLUSIVE,
+            ..Self::new(task)
+        }
+    }
+
+    /// Use a custom wake function
+    pub fn with_func(mut self, func: WakeFn) -> Self {
+        self.func = func;
+        self
+    }
+
+    /// Check whether the waiter is exclusive
+    pub fn is_exclusive(&self) -> bool {
+        self.flags & WQ_FLAG_EXCLUSIVE != 0
+    }
+}
+
+/// Queue of waiting tasks
+///
+/// Non-exclusive waiters are kept at the head and exclusive waiters at the
+/// tail, so a wakeup reaches all non-exclusive waiters before it starts
+/// counting exclusive ones.
+pub struct WaitQueue {
+    entries: SpinLock<VecDeque<WaitQueueEntry>>,
+}
+
+impl WaitQueue {
+    /// Create an empty wait queue
+    pub const fn new() -> Self {
+        Self {
+            entries: SpinLock::new(VecDeque::new()),
+        }
+    }
+
+    /// Add a non-exclusive waiter
+    pub fn add_wait_queue(&self, mut entry: WaitQueueEntry) {
+        entry.flags &= !WQ_FLAG_EXCLUSIVE;
+        self.entries.lock().push_front(entry);
+    }
+
+    /// Add an exclusive waiter
+    pub fn add_wait_queue_exclusive(&self, mut entry: WaitQueueEntry) {
+        entry.flags |= WQ_FLAG_EXCLUSIVE;
+        self.entries.lock().push_back(entry);
+    }
+
+    /// Remove the entries of a task
+    pub fn remove_wait_queue(&self, task: TaskId) {
+        self.entries.lock().retain(|e| e.task != task);
+    }
+
+    /// Check whether a task is queued
+    pub fn contains(&self, task: TaskId) -> bool {
+        self.entries.lock().iter().any(|e| e.task == task)
+    }
+
+    /// Number of waiters
+    pub fn len(&self) -> usize {
+        self.entries.lock().len()
+    }
+
+    /// Check whether nobody is waiting
+    pub fn is_empty(&self) -> bool {
+        self.entries.lock().is_empty()
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Wait queue scheduler component
+pub struct WaitScheduler {
+    wakeups: AtomicU64,
+}
+
+impl WaitScheduler {
+    /// Create a wait queue scheduler
+    pub fn new() -> Self {
+        Self {
+            wakeups: AtomicU64::new(0),
+        }
+    }
+
+    /// Wake all non-exclusive waiters and one exclusive waiter
+    pub fn wake_up(&self, q: &WaitQueue) -> usize {
+        self.wake_up_nr(q, 1)
+    }
+
+    /// Wake every waiter
+    pub fn wake_up_all(&self, q: &WaitQueue) -> usize {
+        self.wake_up_nr(q, 0)
+    }
+
+    /// Wake all non-exclusive waiters and at most `nr` exclusive waiters
+    ///
+    /// `nr == 0` wakes every exclusive waiter. Woken entries are removed
+    /// from the queue; entries whose wake function fails stay queued and
+    /// do not count against `nr`.
+    ///
+    /// # Returns
+    /// Number of waiters woken
+    pub fn wake_up_nr(&self, q: &WaitQueue, nr: usize) -> usize {
+        let mut entries = q.entries.lock();
+        let mut woken = 0;
+        let mut exclusive_woken = 0;
+
+        entries.retain(|entry| {
+            let exclusive = entry.is_exclusive();
+            if exclusive && nr != 0 && exclusive_woken >= nr {
+                return true;
+            }
+            if !(entry.func)(entry.task) {
+                return true;
+            }
+            woken += 1;
+            if exclusive {
+                exclusive_woken += 1;
+            }
+            false
+        });
+
+        self.wakeups.fetch_add(woken as u64, Ordering::Relaxed);
+        kernel_debug!("Wait queue wakeup: {} woken ({} exclusive)", woken, exclusive_woken);
+        woken
+    }
+
+    /// Sleep on a wait queue until `cond()` returns true
+    ///
+    /// The sleep is uninterruptible: signals are ignored, and the sleeper
+    /// counts toward the load average.
+    pub fn wait_event<F: Fn() -> bool>(&self, q: &WaitQueue, cond: F) -> KernelResult<()> {
+        self.wait_event_common(q, &cond, None, false).map(|_| ())
+    }
+
+    /// Sleep on a wait queue until `cond()` returns true or a signal arrives
+    ///
+    /// # Returns
+    /// - `Ok(())` if the condition became true
+    /// - `Err(SchedulerError::Interrupted)` if the waiting task got a signal
+    pub fn wait_event_interruptible<F: Fn() -> bool>(&self, q: &WaitQueue, cond: F) -> KernelResult<()> {
+        self.wait_event_common(q, &cond, None, true).map(|_| ())
+    }
+
+    /// Sleep on a wait queue until `cond()` returns true or the timeout expires
+    ///
+    /// # Returns
+    /// - `Ok(true)` if the condition became true
+    /// - `Ok(false)` if the timeout expired first
+    pub fn wait_event_timeout<F: Fn() -> bool>(&self, q: &WaitQueue, cond: F,
+                                               timeout: Duration) -> KernelResult<bool> {
+        let deadline = Timestamp::now().as_nanos().saturating_add(timeout.as_nanos());
+        self.wait_event_common(q, &cond, Some(deadline), false)
+    }
+
+    /// Log wait queue statistics
+    pub fn print_wait_info(&self) {
+        kernel_info!("Wait queue wakeups: {}", self.wakeups.load(Ordering::Relaxed));
+    }
+
+    /// Common `wait_event` loop
+    ///
+    /// The task marks itself blocked and queues itself *before* re-checking
+    /// the condition. A waker updates the condition before taking the queue
+    /// lock to wake, so either the re-check sees the update or the waker
+    /// finds the queued entry and makes the task runnable again; the wakeup
+    /// cannot fall in between. Spurious wakeups loop and requeue.
+    fn wait_event_common(&self, q: &WaitQueue, cond: &dyn Fn() -> bool,
+                         deadline: Option<u64>, interruptible: bool) -> KernelResult<bool> {
+        if cond() {
+            return Ok(true);
+        }
+        if Self::expired(deadline) {
+            return Ok(false);
+        }
+        let current = Task::current().ok_or(SchedulerError::NotRunning)?;
+        let id = current.id();
+
+        let sleep_state = if interruptible {
+            TaskState::InterruptibleSleep
+        } else {
+            TaskState::UninterruptibleSleep
+        };
+        let satisfied = loop {
+            current.set_state(sleep_state);
+            if !q.contains(id) {
+                q.add_wait_queue(WaitQueueEntry::new(id));
+            }
+            if cond() {
+                break true;
+            }
+            if Self::expired(deadline) {
+                break false;
+            }
+            if interruptible && has_pending_signal(&current) {
+                current.set_state(TaskState::Running);
+                q.remove_wait_queue(id);
+                return Err(SchedulerError::Interrupted.into());
+            }
+            Task::block_current(deadline);
+        };
+
+        current.set_state(TaskState::Running);
+        q.remove_wait_queue(id);
+        Ok(satisfied)
+    }
+
+    /// Check whether a wait deadline has passed
+    fn expired(deadline: Option<u64>) -> bool {
+        deadline.map_or(false, |d| Timestamp::now().as_nanos() >= d)
+    }
+}
+
+impl Default for WaitScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Tasks being woken, grouped by the CPU they are enqueued on
+///
+/// A waker releasing many tasks adds them all, then flushes the batch:
+/// every CPU's tasks are enqueued with one call, under one runqueue lock,
+/// and every CPU is kicked at most once.
+#[derive(Debug)]
+pub struct WakeBatch<T> {
+    per_cpu: BTreeMap<CpuId, Vec<T>>,
+}
+
+impl<T> WakeBatch<T> {
+    /// Create an empty batch
+    pub fn new() -> Self {
+        Self {
+            per_cpu: BTreeMap::new(),
+        }
+    }
+
+    /// Add a task to be enqueued on `cpu`
+    pub fn add(&mut self, cpu: CpuId, task: T) {
+        self.per_cpu.entry(cpu).or_default().push(task);
+    }
+
+    /// Number of tasks in the batch
+    pub fn len(&self) -> usize {
+        self.per_cpu.values().map(Vec::len).sum()
+    }
+
+    /// Check whether the batch is empty
+    pub fn is_empty(&self) -> bool {
+        self.per_cpu.is_empty()
+    }
+
+    /// Enqueue the batch
+    ///
+    /// `enqueue` is called once per CPU with all of its tasks and returns
+    /// how many it woke and whether the CPU must reschedule; `kick` is then
+    /// called for the CPUs that must.
+    ///
+    /// # Returns
+    /// Number of tasks woken
+    pub fn flush<E, K>(self, mut enqueue: E, mut kick: K) -> usize
+    where
+        E: FnMut(CpuId, &[T]) -> (usize, bool),
+        K: FnMut(CpuId),
+    {
+        let mut woken = 0;
+        for (cpu, tasks) in &self.per_cpu {
+            let (count, resched) = enqueue(*cpu, tasks);
+            woken += count;
+            if resched {
+                kick(*cpu);
+            }
+        }
+        woken
+    }
+}
+
+impl<T> Default for WakeBatch<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::AtomicUsize;
+
+    static EXCLUSIVE_RUNS: AtomicUsize = AtomicUsize::new(0);
+    static MIXED_RUNS: AtomicUsize = AtomicUsize::new(0);
+
+    fn run_exclusive(_task: TaskId) -> bool {
+        EXCLUSIVE_RUNS.fetch_add(1, Ordering::SeqCst);
+        true
+    }
+
+    fn run_mixed(_task: TaskId) -> bool {
+        MIXED_RUNS.fetch_add(1, Ordering::SeqCst);
+        true
+    }
+
+    #[test]
+    fn test_wake_up_nr_wakes_one_of_ten_exclusive() {
+        let wait = WaitScheduler::new();
+        let q = WaitQueue::new();
+        for id in 0..10 {
+            q.add_wait_queue_exclusive(WaitQueueEntry::exclusive(TaskId::new(id)).with_func(run_exclusive));
+        }
+
+        assert_eq!(wait.wake_up_nr(&q, 1), 1);
+        assert_eq!(EXCLUSIVE_RUNS.load(Ordering::SeqCst), 1);
+        assert_eq!(q.len(), 9);
+        // First come, first served
+        assert!(!q.contains(TaskId::new(0)));
+    }
+
+    #[test]
+    fn test_non_exclusive_waiters_always_woken() {
+        let wait = WaitScheduler::new();
+        let q = WaitQueue::new();
+        for id in 0..3 {
+            q.add_wait_queue_exclusive(WaitQueueEntry::exclusive(TaskId::new(id)).with_func(run_mixed));
+        }
+        for id in 10..12 {
+            q.add_wait_queue(WaitQueueEntry::new(TaskId::new(id)).with_func(run_mixed));
+        }
+
+        assert_eq!(wait.wake_up_nr(&q, 1), 3);
+        assert_eq!(MIXED_RUNS.load(Ordering::SeqCst), 3);
+        assert_eq!(q.len(), 2);
+    }
+
+    #[test]
+    fn test_wait_event_condition_already_true() {
+        let wait = WaitScheduler::new();
+        let q = WaitQueue::new();
+
+        assert!(wait.wait_event(&q, || true).is_ok());
+        assert!(wait.wait_event_timeout(&q, || true, Duration::from_nanos(0)).unwrap());
+        assert!(q.is_empty());
+    }
+
+    #[test]
+    fn test_signals_only_interrupt_interruptible_sleep() {
+        assert!(signal_interrupts(TaskState::InterruptibleSleep));
+        assert!(!signal_interrupts(TaskState::UninterruptibleSleep));
+        assert!(!signal_interrupts(TaskState::Runnable));
+        assert!(!signal_interrupts(TaskState::Running));
+    }
+
+    #[test]
+    fn test_wait_event_timeout_expires() {
+        let wait = WaitScheduler::new();
+        let q = WaitQueue::new();
+
+        assert!(!wait.wait_event_timeout(&q, || false, Duration::from_nanos(0)).unwrap());
+        assert!(q.is_empty());
+    }
+
+    #[test]
+    fn test_wake_batch_enqueues_each_cpu_once_and_kicks_preempted_ones() {
+        const WAITERS: u64 = 512;
+        const CPUS: u32 = 8;
+        let cpu_of = |id: u64| CpuId::new((id % CPUS as u64) as u32);
+
+        let mut batch = WakeBatch::new();
+        for id in 0..WAITERS {
+            batch.add(cpu_of(id), TaskId::new(id));
+        }
+        assert_eq!(batch.len(), WAITERS as usize);
+
+        // Waiters preempt only on even CPUs; CPU 7 fails to wake one of them
+        let mut enqueued: BTreeMap<CpuId, Vec<TaskId>> = BTreeMap::new();
+        let mut kicked = Vec::new();
+        let woken = batch.flush(|cpu, tasks| {
+            assert!(enqueued.insert(cpu, tasks.to_vec()).is_none());
+            let count = if cpu.as_u32() == 7 { tasks.len() - 1 } else { tasks.len() };
+            (count, cpu.as_u32() % 2 == 0)
+        }, |cpu| kicked.push(cpu));
+
+        assert_eq!(woken, WAITERS as usize - 1);
+        assert_eq!(enqueued.len(), CPUS as usize);
+        let expected: Vec<TaskId> = (0..WAITERS).filter(|id| id % CPUS as u64 == 3).map(TaskId::new).collect();
+        assert_eq!(enqueued[&CpuId::new(3)], expected);
+        assert_eq!(kicked, [0, 2, 4, 6].map(CpuId::new));
+    }
+}